@@ -1,19 +1,89 @@
 //! Module containing definitions for BGP
 
 use crate::{AsId, Prefix, RouterId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Path identifier of the ADD-PATH extension (RFC 7911). Sessions without ADD-PATH only use the
+/// path 0.
+pub type PathId = u32;
+
+/// Well-known community GRACEFUL_SHUTDOWN (65535:0, RFC 8326). Routes carrying this community
+/// are imported with local_pref 0, such that traffic is drained from the session before it is
+/// shut down.
+pub const COMMUNITY_GRACEFUL_SHUTDOWN: u32 = 0xFFFF_0000;
+
+/// Well-known community NO_EXPORT (65535:65281, RFC 1997). Routes carrying this community are not
+/// advertised to any eBGP peer.
+pub const COMMUNITY_NO_EXPORT: u32 = 0xFFFF_FF01;
+
+/// Well-known community NO_ADVERTISE (65535:65282, RFC 1997). Routes carrying this community are
+/// not advertised to any peer.
+pub const COMMUNITY_NO_ADVERTISE: u32 = 0xFFFF_FF02;
+
+/// Returns the informational community (`as_id:peer`) which marks routes that entered the AS
+/// `as_id` via the eBGP session with `peer`. Both the AS id and the router id must fit into 16 bits,
+/// otherwise, no community can identify the peer, and `None` is returned.
+pub fn ingress_community(as_id: AsId, peer: RouterId) -> Option<u32> {
+    let peer = peer.index() as u32;
+    if as_id.0 > 0xFFFF || peer > 0xFFFF {
+        None
+    } else {
+        Some((as_id.0 << 16) | peer)
+    }
+}
 
 /// Bgo Route
 /// The following attributes are omitted
-/// - ORIGIN: assumed to be always set to IGP
 /// - ATOMIC_AGGREGATE: not used
 /// - AGGREGATOR: not used
 #[derive(Debug, Clone)]
 pub struct BgpRoute {
+    /// IP prefix of the route
     pub prefix: Prefix,
+    /// Path identifier (ADD-PATH, RFC 7911), which is 0 on sessions without ADD-PATH
+    pub path_id: PathId,
+    /// AS path, starting with the AS of the neighbor
     pub as_path: Vec<AsId>,
+    /// BGP next hop
     pub next_hop: RouterId,
+    /// LOCAL_PREF attribute, which is only present on routes received over iBGP
     pub local_pref: Option<u32>,
+    /// MULTI_EXIT_DISC attribute
     pub med: Option<u32>,
+    /// Accumulated IGP metric (AIGP, RFC 7311). It is only present if the route originates in an
+    /// AIGP domain. Each router adds its IGP cost to the next hop when it receives the route.
+    pub aigp: Option<u64>,
+    /// BGP communities (RFC 1997), each encoded as a single 32 bit value.
+    pub communities: BTreeSet<u32>,
+    /// Link bandwidth extended community, in Mbps. It is attached to routes learned via eBGP, and
+    /// used to share the traffic among multiple paths proportionally to their bandwidth. It is
+    /// non-transitive, and removed when the route is advertised via eBGP.
+    pub link_bandwidth: Option<u64>,
+    /// CLUSTER_LIST (RFC 4456), containing the cluster ids of all route reflectors which have
+    /// reflected the route, with the last one first. It is removed when the route is advertised
+    /// via eBGP.
+    pub cluster_list: Vec<u32>,
+    /// ORIGINATOR_ID (RFC 4456), the router from which the first route reflector learned the
+    /// route. It is set when the route is reflected for the first time, and preserved by all
+    /// further route reflectors. It is removed when the route is advertised via eBGP.
+    pub originator_id: Option<RouterId>,
+    /// BGP identifier of the router which advertised the route, used as the final tiebreak of the
+    /// decision process. For routes learned via eBGP, this is the external peer. When the route is
+    /// advertised via iBGP, it is set to the border router which learned it, and preserved by all
+    /// route reflectors. It is removed when the route is advertised via eBGP.
+    pub router_id: Option<RouterId>,
+    /// Sequence number of the update, increased by the sender for every update it sends. This is
+    /// not a BGP attribute, but is used to detect updates which are received out of order. It is
+    /// ignored when comparing routes.
+    pub seq: Option<u64>,
+    /// Route target extended communities (RFC 4364), each encoded as `(ASN, value)`, like 100:1.
+    /// Routes carrying route targets are VPN routes, which are imported into the VRFs of a router
+    /// (see `policy::Vrf`). They are never advertised via eBGP, and never used for forwarding in
+    /// the global table.
+    pub route_targets: BTreeSet<(u32, u32)>,
+    /// ORIGIN attribute. Routes with a lower origin are preferred (IGP < EGP < Incomplete).
+    pub origin: Origin,
 }
 
 impl BgpRoute {
@@ -27,10 +97,20 @@ impl BgpRoute {
     pub fn clone_default(&self) -> Self {
         Self {
             prefix: self.prefix,
+            path_id: self.path_id,
             as_path: self.as_path.clone(),
             next_hop: self.next_hop,
             local_pref: Some(self.local_pref.unwrap_or(100)),
             med: Some(self.med.unwrap_or(0)),
+            aigp: self.aigp,
+            communities: self.communities.clone(),
+            link_bandwidth: self.link_bandwidth,
+            cluster_list: self.cluster_list.clone(),
+            originator_id: self.originator_id,
+            router_id: self.router_id,
+            seq: self.seq,
+            route_targets: self.route_targets.clone(),
+            origin: self.origin,
         }
     }
 }
@@ -40,27 +120,37 @@ impl PartialEq for BgpRoute {
         let s = self.clone_default();
         let o = other.clone_default();
         s.prefix == o.prefix
+            && s.path_id == o.path_id
             && s.as_path == other.as_path
             && s.next_hop == o.next_hop
             && s.local_pref == o.local_pref
             && s.med == o.med
+            && s.aigp == o.aigp
+            && s.communities == o.communities
+            && s.link_bandwidth == o.link_bandwidth
+            && s.cluster_list == o.cluster_list
+            && s.originator_id == o.originator_id
+            && s.router_id == o.router_id
+            && s.route_targets == o.route_targets
+            && s.origin == o.origin
     }
 }
 
+/// Type of a BGP session, seen from the router on which it is configured
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BgpSessionType {
+    /// iBGP session to a regular peer
     IBgpPeer,
+    /// iBGP session to a route reflector client
     IBgpClient,
+    /// eBGP session to a router in a different AS
     EBgp,
 }
 
 impl BgpSessionType {
     /// returns true if the session type is EBgp
     pub fn is_ebgp(&self) -> bool {
-        match self {
-            Self::EBgp => true,
-            _ => false,
-        }
+        matches!(self, Self::EBgp)
     }
 
     /// returns true if the session type is IBgp
@@ -69,8 +159,42 @@ impl BgpSessionType {
     }
 }
 
-#[derive(Debug, Clone)]
+/// BGP message exchanged between two routers
+#[derive(Debug, Clone, PartialEq)]
 pub enum BgpEvent {
+    /// Withdraw the route for a prefix
     Withdraw(Prefix),
+    /// Advertise a new route, replacing the previous one for the same prefix (and path id)
     Update(BgpRoute),
+    /// Withdraw a single path of a prefix, advertised over a session with ADD-PATH.
+    WithdrawPath(Prefix, PathId),
+    /// NOTIFICATION (Cease): the sender has closed the session, and the receiver closes it as
+    /// well.
+    Notification,
+}
+
+/// ORIGIN attribute of a BGP route, telling how the route was injected into BGP. The variants are
+/// ordered by preference, such that the lowest origin is preferred.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub enum Origin {
+    /// The route was learned from an interior protocol, or originated with a network statement.
+    #[default]
+    Igp,
+    /// The route was learned via EGP.
+    Egp,
+    /// The route was learned by other means, like redistribution.
+    Incomplete,
+}
+
+/// Result of the route origin validation (RPKI, RFC 6811) of a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpkiValidation {
+    /// A ROA exists for the prefix, and it authorizes the origin AS of the route.
+    Valid,
+    /// ROAs exist for the prefix, but none of them authorizes the origin AS of the route.
+    Invalid,
+    /// No ROA exists for the prefix.
+    NotFound,
 }
@@ -5,7 +5,7 @@ use crate::RouterId;
 use std::collections::VecDeque;
 
 /// Event to handle
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
     /// BGP Event from `#0` to `#1`
     Bgp(RouterId, RouterId, BgpEvent),
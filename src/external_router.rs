@@ -1,14 +1,32 @@
-use crate::bgp::{BgpEvent, BgpRoute};
+//! Module defining an external router, which advertises routes to the network
+
+use crate::bgp::{BgpEvent, BgpRoute, Origin};
 use crate::event::{Event, EventQueue};
 use crate::{AsId, DeviceError, NetworkDevice, Prefix, RouterId};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Additional path attributes of a route advertised by an external router. Attributes which are
+/// not set explicitly take their default value.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExternalRouteAttrs {
+    /// Communities attached to the route
+    pub communities: BTreeSet<u32>,
+    /// ORIGIN attribute of the route
+    pub origin: Origin,
+}
 
+/// Router of a neighboring AS, which advertises routes to the network
 #[derive(Debug, Clone)]
 pub struct ExternalRouter {
     name: &'static str,
     router_id: RouterId,
     as_id: AsId,
+    /// Internal routers with an eBGP session to this router
     pub neighbors: HashSet<RouterId>,
+    /// Routes currently advertised to all neighbors, mapping the prefix to the route
+    advertised_routes: HashMap<Prefix, BgpRoute>,
+    /// Sequence number of the last update sent by the router (see `BgpRoute::seq`).
+    update_seq: u64,
 }
 
 impl NetworkDevice for ExternalRouter {
@@ -19,11 +37,20 @@ impl NetworkDevice for ExternalRouter {
             router_id,
             as_id,
             neighbors: HashSet::new(),
+            advertised_routes: HashMap::new(),
+            update_seq: 0,
         }
     }
 
-    /// Handle an `Event` and produce the necessary result
-    fn handle_event(&mut self, _event: Event, _queue: &mut EventQueue) -> Result<(), DeviceError> {
+    /// Handle an `Event` and produce the necessary result. The external router only reacts to a
+    /// BGP NOTIFICATION, after which it stops advertising routes to the neighbor.
+    fn handle_event(&mut self, event: Event, _queue: &mut EventQueue) -> Result<(), DeviceError> {
+        if let Event::Bgp(from, to, BgpEvent::Notification) = event {
+            if to == self.router_id {
+                // the neighbor has closed the session
+                self.neighbors.remove(&from);
+            }
+        }
         Ok(())
     }
 
@@ -44,35 +71,110 @@ impl NetworkDevice for ExternalRouter {
 }
 
 impl ExternalRouter {
-    /// Send an BGP UPDATE to all neighbors with the new route
+    /// Send an BGP UPDATE to all neighbors with the new route, and remember the advertised route
     pub fn advertise_prefix(
-        &self,
+        &mut self,
         prefix: Prefix,
         as_path: Vec<AsId>,
         med: Option<u32>,
+        attrs: ExternalRouteAttrs,
         queue: &mut EventQueue,
     ) {
+        self.update_seq += 1;
         let route = BgpRoute {
             prefix,
+            path_id: 0,
             as_path,
             next_hop: self.router_id,
             local_pref: None,
             med,
+            aigp: None,
+            communities: attrs.communities,
+            link_bandwidth: None,
+            cluster_list: Vec::new(),
+            originator_id: None,
+            router_id: None,
+            seq: Some(self.update_seq),
+            route_targets: BTreeSet::new(),
+            origin: attrs.origin,
         };
+        self.advertised_routes.insert(prefix, route.clone());
         let bgp_event = BgpEvent::Update(route);
-        for neighbor in self.neighbors.iter() {
-            queue.push_back(Event::Bgp(self.router_id, *neighbor, bgp_event.clone()));
+        for neighbor in self.sorted_neighbors() {
+            queue.push_back(Event::Bgp(self.router_id, neighbor, bgp_event.clone()));
+        }
+    }
+
+    /// Change the AS path and the MED of an advertised route, and send a BGP UPDATE with the new
+    /// route to all neighbors (implicit update). All other attributes are kept. Returns false if
+    /// the prefix is not advertised, in which case nothing is sent.
+    pub fn update_prefix(
+        &mut self,
+        prefix: Prefix,
+        as_path: Vec<AsId>,
+        med: Option<u32>,
+        queue: &mut EventQueue,
+    ) -> bool {
+        let route = match self.advertised_routes.get_mut(&prefix) {
+            Some(route) => route,
+            None => return false,
+        };
+        self.update_seq += 1;
+        route.as_path = as_path;
+        route.med = med;
+        route.seq = Some(self.update_seq);
+        let bgp_event = BgpEvent::Update(route.clone());
+        for neighbor in self.sorted_neighbors() {
+            queue.push_back(Event::Bgp(self.router_id, neighbor, bgp_event.clone()));
         }
+        true
     }
 
-    /// Send a BGP WITHDRAW to all neighbors for the given prefix
-    pub fn widthdraw_prefix(&self, prefix: Prefix, queue: &mut EventQueue) {
-        for neighbor in self.neighbors.iter() {
+    /// Send a BGP WITHDRAW to all neighbors for the given prefix. If the prefix is not advertised,
+    /// nothing is sent.
+    pub fn widthdraw_prefix(&mut self, prefix: Prefix, queue: &mut EventQueue) {
+        if self.advertised_routes.remove(&prefix).is_none() {
+            println!(
+                "{} does not advertise prefix {}, nothing to withdraw",
+                self.name, prefix.0
+            );
+            return;
+        }
+        for neighbor in self.sorted_neighbors() {
             queue.push_back(Event::Bgp(
                 self.router_id,
-                *neighbor,
+                neighbor,
                 BgpEvent::Withdraw(prefix),
             ));
         }
     }
+
+    /// Send a BGP UPDATE with all currently advertised routes to a single neighbor, e.g., after
+    /// the session was re-established.
+    pub fn readvertise_to(&mut self, neighbor: RouterId, queue: &mut EventQueue) {
+        let mut prefixes: Vec<Prefix> = self.advertised_routes.keys().copied().collect();
+        prefixes.sort();
+        for prefix in prefixes {
+            self.update_seq += 1;
+            let mut route = self.advertised_routes[&prefix].clone();
+            route.seq = Some(self.update_seq);
+            queue.push_back(Event::Bgp(
+                self.router_id,
+                neighbor,
+                BgpEvent::Update(route),
+            ));
+        }
+    }
+
+    /// Returns all routes currently advertised by the router, mapping the prefix to the route.
+    pub fn advertised_prefixes(&self) -> &HashMap<Prefix, BgpRoute> {
+        &self.advertised_routes
+    }
+
+    /// Returns all neighbors, sorted, such that updates are always sent in the same order.
+    fn sorted_neighbors(&self) -> Vec<RouterId> {
+        let mut neighbors: Vec<RouterId> = self.neighbors.iter().copied().collect();
+        neighbors.sort();
+        neighbors
+    }
 }
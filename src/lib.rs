@@ -0,0 +1,21 @@
+//! Simple BGP Simulation
+
+#![deny(missing_docs)]
+#![allow(dead_code)]
+
+pub mod bgp;
+pub mod event;
+pub mod external_router;
+pub mod network;
+pub mod policy;
+pub mod router;
+pub mod scenario;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod types;
+
+pub use event::{Event, EventQueue};
+pub use types::*;
+
+#[cfg(test)]
+mod test;
@@ -1,20 +1,7 @@
 //! Simple BGP Simulation
 
-#![deny(missing_docs)]
-#![allow(dead_code)]
-
-mod bgp;
-mod event;
-mod external_router;
-mod network;
-mod router;
-mod types;
-
-pub use event::{EventQueue, Event};
-pub use types::*;
-
-#[cfg(test)]
-mod test;
+use bgpsim::network::Network;
+use bgpsim::{AsId, Prefix};
 
 /// main function
 fn main() {
@@ -24,7 +11,7 @@ fn main() {
 fn evil_twin_gadget() {
     // Evil twin gadget from L. Vanbever: Improving Network Agility with Seamless BGP
     // reconfigurations
-    let mut n = network::Network::new();
+    let mut n = Network::new();
 
     // router declaration
     let r1 = n.add_router("R1");
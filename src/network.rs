@@ -1,24 +1,67 @@
-use crate::bgp::{BgpEvent, BgpSessionType};
+//! Module defining the network, which contains all routers and executes the events
+
+use crate::bgp::{BgpEvent, BgpRoute, BgpSessionType};
 use crate::event::{Event, EventQueue};
-use crate::external_router::ExternalRouter;
-use crate::router::{RIBEntry, Router};
+use crate::external_router::{ExternalRouteAttrs, ExternalRouter};
+use crate::router::{DecisionStep, NextHop, RIBEntry, Router, TableSizes};
+use crate::scenario::{
+    Scenario, ScenarioAdvertisement, ScenarioLink, ScenarioRoute, ScenarioRouter,
+    ScenarioRouterConfig, ScenarioRouterKind,
+};
 use crate::{
-    AsId, DeviceError, IgpNetwork, LinkWeight, NetworkDevice, NetworkError, Prefix, RouterId,
+    AsId, ConfigError, ConvergenceResult, DeviceError, IgpNetwork, LinkId, LinkWeight,
+    NetworkDevice, NetworkError, Prefix, RouterId,
 };
-use std::collections::{HashMap, HashSet};
+use petgraph::algo::{bellman_ford, FloatMeasure};
+use petgraph::visit::EdgeRef;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
 static DEFAULT_STOP_AFTER: usize = 10_000;
+/// Default OSPF reference bandwidth in Mbps
+static DEFAULT_REFERENCE_BANDWIDTH: u64 = 100;
 
-#[derive(Debug)]
+/// Network of internal and external routers, together with the IGP topology and the queue of
+/// pending events
+#[derive(Debug, Clone)]
 pub struct Network {
     net: IgpNetwork,
     routers: HashMap<RouterId, Router>,
     external_routers: HashMap<RouterId, ExternalRouter>,
     queue: EventQueue,
     stop_after: Option<usize>,
+    max_queue_len: Option<usize>,
+    /// Seed for breaking exact ties in the decision process, applied to all routers, including
+    /// the ones added later (see `set_tiebreak_seed`).
+    tiebreak_seed: Option<u64>,
+    reference_bandwidth: u64,
+    trace: Option<Vec<Event>>,
+    /// Prefix whose propagation is recorded, together with the routers having a selected route
+    /// after each executed event (see `record_frontier`).
+    frontier: Option<(Prefix, Vec<HashSet<RouterId>>)>,
+    last_convergence_steps: usize,
+    failed_links: HashMap<(RouterId, RouterId), LinkWeight>,
+    /// Links (in both directions) which are not advertised into the IGP. They are part of the
+    /// topology, but ignored when computing the IGP forwarding tables.
+    non_igp_links: HashSet<(RouterId, RouterId)>,
+    igp_dirty: bool,
+    igp_computations: usize,
+    /// Number of BGP messages exchanged over each directed session, indexed by sender and receiver.
+    message_counts: HashMap<(RouterId, RouterId), usize>,
+    /// Routers whose BGP is administratively shut down, together with their BGP sessions before
+    /// the shutdown. Each session contains the peer, the type of the peer in relation to the
+    /// router, and the type of the router in relation to the peer (`None` for external peers).
+    bgp_shutdown: HashMap<RouterId, Vec<(RouterId, BgpSessionType, Option<BgpSessionType>)>>,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Network {
+    /// Create an empty network
     pub fn new() -> Self {
         Self {
             net: IgpNetwork::new(),
@@ -26,7 +69,160 @@ impl Network {
             external_routers: HashMap::new(),
             queue: EventQueue::new(),
             stop_after: Some(DEFAULT_STOP_AFTER),
+            max_queue_len: None,
+            tiebreak_seed: None,
+            reference_bandwidth: DEFAULT_REFERENCE_BANDWIDTH,
+            trace: None,
+            frontier: None,
+            last_convergence_steps: 0,
+            failed_links: HashMap::new(),
+            non_igp_links: HashSet::new(),
+            igp_dirty: true,
+            igp_computations: 0,
+            message_counts: HashMap::new(),
+            bgp_shutdown: HashMap::new(),
+        }
+    }
+
+    /// # Create a reproducible network
+    ///
+    /// Create an empty network, whose routers break exact ties in the decision process using the
+    /// seed (see `set_tiebreak_seed`). Apart from the tiebreak, the simulation does not depend on
+    /// the iteration order of hash maps: routers, prefixes, routes and neighbors are always
+    /// processed in the order of their ids. Hence, building the same network with the same seed
+    /// always results in the same sequence of events and the same forwarding state.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut net = Self::new();
+        net.tiebreak_seed = Some(seed);
+        net
+    }
+
+    /// # Build a network from an adjacency description
+    ///
+    /// Create a network with the internal `routers`, the external routers `externals` (with their
+    /// AS), and the links `edges` (`source`, `target`, `weight`), which are added with
+    /// `add_edge` (symmetric, and with an eBGP session for external routers). Routers are
+    /// referenced by their name. The network is returned together with the map of names to
+    /// router ids. The IGP forwarding tables are not yet written.
+    pub fn from_edges(
+        routers: &[&'static str],
+        externals: &[(&'static str, AsId)],
+        edges: &[(&'static str, &'static str, LinkWeight)],
+    ) -> Result<(Network, HashMap<&'static str, RouterId>), NetworkError> {
+        let mut net = Network::new();
+        let mut ids: HashMap<&'static str, RouterId> = HashMap::new();
+        for name in routers {
+            ids.insert(name, net.add_router(name));
+        }
+        for (name, as_id) in externals {
+            ids.insert(name, net.add_external_router(name, *as_id));
+        }
+        for (source, target, weight) in edges {
+            let source = *ids
+                .get(source)
+                .ok_or(NetworkError::DeviceNameNotFound(source))?;
+            let target = *ids
+                .get(target)
+                .ok_or(NetworkError::DeviceNameNotFound(target))?;
+            net.add_edge(source, target, *weight, None)?;
+        }
+        Ok((net, ids))
+    }
+
+    /// # Reconstruct a network from a scenario
+    ///
+    /// Create all routers, links and sessions of the scenario (see `to_scenario`), apply the
+    /// configuration, advertise all routes, and let the network converge. The forwarding state of
+    /// the scenario is not used, but it can be compared with the one of the reconstructed network.
+    pub fn from_scenario(scenario: &Scenario) -> Result<Network, NetworkError> {
+        let invalid = |msg: String| NetworkError::InvalidScenario(msg);
+        let mut net = Network::new();
+        for r in scenario.routers.iter() {
+            // router names are referenced during the whole lifetime of the network
+            let name: &'static str = Box::leak(r.name.clone().into_boxed_str());
+            let id = match r.kind {
+                ScenarioRouterKind::Internal => net.add_router(name),
+                ScenarioRouterKind::PRouter => net.add_p_router(name),
+                ScenarioRouterKind::External => net.add_external_router(name, AsId(r.as_id)),
+            };
+            if id.index() != r.id as usize {
+                return Err(invalid(format!(
+                    "router {} has an unexpected index",
+                    r.name
+                )));
+            }
+        }
+        // links are added in the order of their ids. Gaps in the ids are filled with placeholder
+        // links, which are removed again once all links are added.
+        let mut placeholders = Vec::new();
+        for link in scenario.links.iter() {
+            let (source, target) = (link.source.into(), link.target.into());
+            loop {
+                let id = net.add_parallel_edge(source, target, link.weight)?;
+                match id.index().cmp(&(link.id as usize)) {
+                    Ordering::Less => placeholders.push(id),
+                    Ordering::Equal => break,
+                    Ordering::Greater => {
+                        return Err(invalid(format!("link {} has an unexpected id", link.id)))
+                    }
+                }
+            }
+            if !link.in_igp {
+                net.non_igp_links.insert((source, target));
+            }
+        }
+        for id in placeholders {
+            net.net.remove_edge(id);
+        }
+        net.write_igp_fw_tables(false)?;
+        for (external, neighbor) in scenario.ebgp_sessions.iter() {
+            net.add_ebgp_session((*external).into(), (*neighbor).into(), false)?;
+        }
+        for (source, target, route_reflector) in scenario.ibgp_sessions.iter() {
+            net.add_ibgp_session((*source).into(), (*target).into(), *route_reflector, false)?;
+        }
+        for r in scenario.routers.iter() {
+            if r.kind == ScenarioRouterKind::External {
+                continue;
+            }
+            net.routers
+                .get_mut(&r.id.into())
+                .ok_or(NetworkError::DeviceNotFound(r.id.into()))?
+                .apply_scenario_config(&r.config, &mut net.queue)?;
+        }
+        for a in scenario.advertisements.iter() {
+            let as_path = a.as_path.iter().map(|as_id| AsId(*as_id)).collect();
+            let attrs = ExternalRouteAttrs {
+                communities: a.communities.iter().copied().collect(),
+                origin: a.origin,
+            };
+            net.advertise_external_route_with(
+                a.router.into(),
+                Prefix(a.prefix),
+                as_path,
+                a.med,
+                attrs,
+                false,
+            )?;
         }
+        net.do_queue()?;
+        Ok(net)
+    }
+
+    /// Reconstruct a network from a scenario in JSON format, see `from_scenario`.
+    pub fn from_scenario_json(json: &str) -> Result<Network, NetworkError> {
+        let scenario: Scenario =
+            serde_json::from_str(json).map_err(|e| NetworkError::InvalidScenario(e.to_string()))?;
+        Self::from_scenario(&scenario)
+    }
+
+    /// Reconstruct a network from a scenario in the compact binary format, see `from_scenario`
+    /// and `to_bytes`.
+    #[cfg(feature = "binary")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, NetworkError> {
+        let scenario: Scenario = bincode::deserialize(bytes)
+            .map_err(|e| NetworkError::InvalidScenario(e.to_string()))?;
+        Self::from_scenario(&scenario)
     }
 
     /// Configure the topology to pause the queue and return after a certain number of queue have
@@ -36,12 +232,96 @@ impl Network {
         self.stop_after = stop_after;
     }
 
+    /// Configure the maximum number of events in the queue. If executing an event causes the queue
+    /// to grow beyond this limit, `NetworkError::QueueOverflow` is returned, and the events stay
+    /// in the queue. This way, runaway scenarios are detected early, before the queue grows large.
+    /// If set to None (the default), the queue is not bounded.
+    pub fn set_max_queue_len(&mut self, max_queue_len: Option<usize>) {
+        self.max_queue_len = max_queue_len;
+    }
+
+    /// Enable or disable recording of all executed events. When enabled, the recorded trace starts
+    /// empty. The trace can be obtained with `take_trace`, and reproduced with `replay`.
+    pub fn record_trace(&mut self, record: bool) {
+        self.trace = if record { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns all events which were executed since recording was enabled, or since the last call
+    /// to `take_trace`. Recording continues with an empty trace. If recording is disabled, an
+    /// empty trace is returned.
+    pub fn take_trace(&mut self) -> Vec<Event> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Export the events recorded so far (see `record_trace`) as CSV, with one row per event and
+    /// the columns `time,from,to,type,prefix`. The time is the position of the event in the trace,
+    /// and the routers are referenced by their name. The type is one of `update`, `withdraw`,
+    /// `withdraw_path` and `notification`. Notifications have no prefix. The trace itself is not
+    /// changed.
+    pub fn export_trace_csv(&self) -> String {
+        let name = |router: RouterId| match self.get_router_name(router) {
+            Ok(name) => name.to_string(),
+            Err(_) => router.index().to_string(),
+        };
+        let mut csv = String::from("time,from,to,type,prefix\n");
+        for (time, event) in self.trace.iter().flatten().enumerate() {
+            let Event::Bgp(from, to, bgp_event) = event;
+            let (kind, prefix) = match bgp_event {
+                BgpEvent::Update(route) => ("update", Some(route.prefix)),
+                BgpEvent::Withdraw(prefix) => ("withdraw", Some(*prefix)),
+                BgpEvent::WithdrawPath(prefix, _) => ("withdraw_path", Some(*prefix)),
+                BgpEvent::Notification => ("notification", None),
+            };
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                time,
+                name(*from),
+                name(*to),
+                kind,
+                prefix.map(|p| p.0.to_string()).unwrap_or_default()
+            ));
+        }
+        csv
+    }
+
+    /// Enable recording of the propagation frontier of the prefix, or disable it with `None`.
+    /// While enabled, the set of internal routers which have selected a route for the prefix is
+    /// recorded after each executed event. The snapshots start empty, and can be obtained with
+    /// `take_frontier`.
+    pub fn record_frontier(&mut self, prefix: Option<Prefix>) {
+        self.frontier = prefix.map(|prefix| (prefix, Vec::new()));
+    }
+
+    /// Returns all snapshots of the propagation frontier which were recorded since recording was
+    /// enabled, or since the last call to `take_frontier`, one for each executed event. Recording
+    /// continues with no snapshots. If recording is disabled, nothing is returned.
+    pub fn take_frontier(&mut self) -> Vec<HashSet<RouterId>> {
+        self.frontier
+            .as_mut()
+            .map(|(_, snapshots)| std::mem::take(snapshots))
+            .unwrap_or_default()
+    }
+
     /// add a new router to the topology and return
     /// Own as is always set to 65001
     pub fn add_router(&mut self, name: &'static str) -> RouterId {
-        let new_router = Router::new(name, self.net.add_node(()), AsId(65001));
+        let mut new_router = Router::new(name, self.net.add_node(()), AsId(65001));
+        new_router.tiebreak_seed = self.tiebreak_seed;
+        let router_id = new_router.router_id();
+        self.routers.insert(router_id, new_router);
+        self.igp_dirty = true;
+        router_id
+    }
+
+    /// add a new P router to the topology and return its id. P routers only run the IGP, and
+    /// cannot have any BGP session. They forward the traffic towards the BGP next hop chosen by
+    /// the previous router on the path (label switching).
+    pub fn add_p_router(&mut self, name: &'static str) -> RouterId {
+        let mut new_router = Router::new_p_router(name, self.net.add_node(()), AsId(65001));
+        new_router.tiebreak_seed = self.tiebreak_seed;
         let router_id = new_router.router_id();
         self.routers.insert(router_id, new_router);
+        self.igp_dirty = true;
         router_id
     }
 
@@ -50,6 +330,7 @@ impl Network {
         let new_router = ExternalRouter::new(name, self.net.add_node(()), as_id);
         let router_id = new_router.router_id();
         self.external_routers.insert(router_id, new_router);
+        self.igp_dirty = true;
         router_id
     }
 
@@ -57,21 +338,88 @@ impl Network {
     ///
     /// create an edge between two routers. If `rev_w` is `None`, then the link is treated as
     /// symmetric. Else, the reverse path will have weight `rev_w`. Source and Target may be
-    /// external routers. For external routers, an eBGP connection is created.
+    /// external routers. For external routers, an eBGP connection is created, unless it already
+    /// exists (e.g., for parallel links). Use `add_edge_no_session` to create the link without the
+    /// eBGP session.
     pub fn add_edge(
         &mut self,
         source: RouterId,
         target: RouterId,
         weight: LinkWeight,
         rev_w: Option<LinkWeight>,
+    ) -> Result<(), NetworkError> {
+        // if source or target is an external router, add the ebgp connection
+        let ebgp = (self.external_routers.contains_key(&source)
+            || self.external_routers.contains_key(&target))
+            && !self.has_bgp_session(source, target);
+        // validate the session before changing the topology
+        if ebgp {
+            self.check_ebgp_session(source, target)?;
+        }
+        self.add_edge_no_session(source, target, weight, rev_w)?;
+        if ebgp {
+            self.add_ebgp_session(source, target, false)?;
+        }
+        Ok(())
+    }
+
+    /// # Create an edge without a BGP session
+    ///
+    /// create an edge between two routers, like `add_edge`, but never create an eBGP session,
+    /// even if source or target is an external router. The session can be added later with
+    /// `add_ebgp_session`.
+    pub fn add_edge_no_session(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        weight: LinkWeight,
+        rev_w: Option<LinkWeight>,
     ) -> Result<(), NetworkError> {
         // add forward link
         self.net.add_edge(source, target, weight);
         self.net.add_edge(target, source, rev_w.unwrap_or(weight));
-        // if source or target is an external router, add them to adjacency list and add ebgp
-        // connection
+        self.igp_dirty = true;
+        Ok(())
+    }
+
+    /// # Create a parallel link
+    ///
+    /// Add a single directed link from `source` to `target`, even if there are already links
+    /// between them, and return its id. Parallel links keep their own weight. The IGP uses the
+    /// link with the lowest weight, and all equal-cost links are reported by
+    /// `get_igp_ecmp_next_hops`. The reverse direction must be added separately. No BGP session
+    /// is created.
+    pub fn add_parallel_edge(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        weight: LinkWeight,
+    ) -> Result<LinkId, NetworkError> {
+        for router in [source, target] {
+            if !self.routers.contains_key(&router) && !self.external_routers.contains_key(&router) {
+                return Err(NetworkError::DeviceNotFound(router));
+            }
+        }
+        self.igp_dirty = true;
+        Ok(self.net.add_edge(source, target, weight))
+    }
+
+    /// # Add an eBGP session
+    ///
+    /// Adds an eBGP session between source and target. At least one of them must be an external
+    /// router. For external routers, the other router is added to the adjacency list, and for
+    /// internal routers, the eBGP session is established.
+    pub fn add_ebgp_session(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        self.check_ebgp_session(source, target)?;
         let source_external = self.external_routers.contains_key(&source);
         let target_external = self.external_routers.contains_key(&target);
+        let source_as = self.get_as_id(source)?;
+        let target_as = self.get_as_id(target)?;
         if source_external {
             // add connection from external source to (potentially extern) target
             self.external_routers
@@ -85,6 +433,10 @@ impl Network {
                     .get_mut(&target)
                     .ok_or(NetworkError::DeviceNotFound(target))?
                     .establish_bgp_session(source, BgpSessionType::EBgp)?;
+                self.routers
+                    .get_mut(&target)
+                    .ok_or(NetworkError::DeviceNotFound(target))?
+                    .set_ebgp_neighbor_as(source, source_as)?;
             }
         }
         if target_external {
@@ -100,9 +452,64 @@ impl Network {
                     .get_mut(&source)
                     .ok_or(NetworkError::DeviceNotFound(source))?
                     .establish_bgp_session(target, BgpSessionType::EBgp)?;
+                self.routers
+                    .get_mut(&source)
+                    .ok_or(NetworkError::DeviceNotFound(source))?
+                    .set_ebgp_neighbor_as(target, target_as)?;
             }
         }
-        Ok(())
+        if update {
+            if !source_external {
+                self.schedule_update_router(source)?;
+            }
+            if !target_external {
+                self.schedule_update_router(target)?;
+            }
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// Configure the reference bandwidth (in Mbps) used to compute the link weight of edges added
+    /// with `add_edge_bandwidth`. Edges that are already added are not changed.
+    pub fn set_reference_bandwidth(&mut self, reference_bandwidth: u64) {
+        self.reference_bandwidth = reference_bandwidth;
+    }
+
+    /// # Create an edge based on its bandwidth
+    ///
+    /// create an edge between two routers, where the weight is computed OSPF-style as the
+    /// reference bandwidth divided by the bandwidth of the link (in Mbps), rounded to the nearest
+    /// integer, but at least 1. If `rev_bw` is `None`, then the link is treated as symmetric.
+    /// Else, the reverse path will have the bandwidth `rev_bw`. See `add_edge` for details.
+    pub fn add_edge_bandwidth(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        bw_mbps: u64,
+        rev_bw: Option<u64>,
+    ) -> Result<(), NetworkError> {
+        let weight = self.bandwidth_to_weight(bw_mbps);
+        let rev_w = rev_bw.map(|bw| self.bandwidth_to_weight(bw));
+        self.add_edge(source, target, weight, rev_w)
+    }
+
+    /// Returns an error if the router is not an internal router of the network
+    fn check_internal_router(&self, router: RouterId) -> Result<(), NetworkError> {
+        if self.routers.contains_key(&router) {
+            Ok(())
+        } else if self.external_routers.contains_key(&router) {
+            Err(NetworkError::DeviceIsExternalRouter(router))
+        } else {
+            Err(NetworkError::DeviceNotFound(router))
+        }
+    }
+
+    /// Compute the OSPF link weight of a link with the given bandwidth (in Mbps)
+    fn bandwidth_to_weight(&self, bw_mbps: u64) -> LinkWeight {
+        let cost = (self.reference_bandwidth as f64 / bw_mbps as f64).round();
+        cost.max(1.0) as LinkWeight
     }
 
     /// update the weight of an edge
@@ -116,6 +523,89 @@ impl Network {
         self.net.update_edge(source, target, weight);
         self.net
             .update_edge(target, source, rev_w.unwrap_or(weight));
+        self.igp_dirty = true;
+    }
+
+    /// # Fail a shared risk link group (SRLG)
+    ///
+    /// Remove all links of the group (in both directions) at once, and update the IGP forwarding
+    /// tables only once all of them are removed. Hence, the network converges only once, as if all
+    /// links failed simultaneously. BGP sessions are not affected. The links can be restored with
+    /// `restore_srlg`. If any link does not exist, nothing is changed, and an error is returned.
+    pub fn fail_srlg(
+        &mut self,
+        links: &[(RouterId, RouterId)],
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        let mut edges = Vec::new();
+        for (a, b) in links.iter().flat_map(|(s, t)| vec![(*s, *t), (*t, *s)]) {
+            let edge = self
+                .net
+                .find_edge(a, b)
+                .ok_or(NetworkError::LinkNotFound(a, b))?;
+            edges.push((a, b, edge));
+        }
+        for (a, b, edge) in edges {
+            if let Some(weight) = self.net.remove_edge(edge) {
+                self.failed_links.insert((a, b), weight);
+            }
+        }
+        self.igp_dirty = true;
+        self.write_igp_fw_tables(update)
+    }
+
+    /// # Restore a shared risk link group (SRLG)
+    ///
+    /// Restore all links of the group, which were previously failed with `fail_srlg`, with their
+    /// original weights. Like `fail_srlg`, the network converges only once. If any link was not
+    /// failed before, nothing is changed, and an error is returned.
+    pub fn restore_srlg(
+        &mut self,
+        links: &[(RouterId, RouterId)],
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        let directed: Vec<(RouterId, RouterId)> = links
+            .iter()
+            .flat_map(|(s, t)| vec![(*s, *t), (*t, *s)])
+            .collect();
+        if let Some((a, b)) = directed.iter().find(|l| !self.failed_links.contains_key(l)) {
+            return Err(NetworkError::LinkNotFound(*a, *b));
+        }
+        for (a, b) in directed {
+            if let Some(weight) = self.failed_links.remove(&(a, b)) {
+                self.net.add_edge(a, b, weight);
+            }
+        }
+        self.igp_dirty = true;
+        self.write_igp_fw_tables(update)
+    }
+
+    /// # Exclude a link from the IGP
+    ///
+    /// Configure if the link between source and target (in both directions) is advertised into the
+    /// IGP. A link which is not advertised (like a management link) remains part of the topology,
+    /// but is ignored when computing the shortest paths. By default, all links are advertised.
+    pub fn set_link_in_igp(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        in_igp: bool,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        for (a, b) in [(source, target), (target, source)] {
+            if self.net.find_edge(a, b).is_none() {
+                return Err(NetworkError::LinkNotFound(a, b));
+            }
+        }
+        for link in [(source, target), (target, source)] {
+            if in_igp {
+                self.non_igp_links.remove(&link);
+            } else {
+                self.non_igp_links.insert(link);
+            }
+        }
+        self.igp_dirty = true;
+        self.write_igp_fw_tables(update)
     }
 
     /// # Add an iBGP session
@@ -130,6 +620,8 @@ impl Network {
         route_reflector: bool,
         update: bool,
     ) -> Result<bool, NetworkError> {
+        self.check_internal_router(source)?;
+        self.check_internal_router(target)?;
         if route_reflector {
             self.routers
                 .get_mut(&source)
@@ -137,7 +629,7 @@ impl Network {
                 .establish_bgp_session(target, BgpSessionType::IBgpClient)?;
             self.routers
                 .get_mut(&target)
-                .ok_or(NetworkError::DeviceNotFound(source))?
+                .ok_or(NetworkError::DeviceNotFound(target))?
                 .establish_bgp_session(source, BgpSessionType::IBgpPeer)?;
         } else {
             self.routers
@@ -146,7 +638,7 @@ impl Network {
                 .establish_bgp_session(target, BgpSessionType::IBgpPeer)?;
             self.routers
                 .get_mut(&target)
-                .ok_or(NetworkError::DeviceNotFound(source))?
+                .ok_or(NetworkError::DeviceNotFound(target))?
                 .establish_bgp_session(source, BgpSessionType::IBgpPeer)?;
         }
         if update {
@@ -158,6 +650,103 @@ impl Network {
         }
     }
 
+    /// # Add a route reflector cluster
+    ///
+    /// Configures all routers in `rrs` as route reflectors of the cluster `cluster_id`, serving
+    /// all routers in `clients`. The route reflectors are fully meshed with each other, and with
+    /// the route reflectors of all clusters added before, using regular iBGP sessions. Each
+    /// route reflector has a session with each client. Routes reflected within the cluster carry
+    /// the cluster id in their CLUSTER_LIST, such that they are not reflected back into it.
+    pub fn add_cluster(
+        &mut self,
+        cluster_id: u32,
+        rrs: &[RouterId],
+        clients: &[RouterId],
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        for router in rrs.iter().chain(clients.iter()) {
+            self.check_internal_router(*router)?;
+        }
+        // route reflectors of all other clusters
+        let mut other_rrs: Vec<RouterId> = self
+            .routers
+            .values()
+            .filter(|r| r.cluster_id.is_some() && !rrs.contains(&r.router_id()))
+            .map(|r| r.router_id())
+            .collect();
+        other_rrs.sort();
+
+        for (i, rr) in rrs.iter().enumerate() {
+            self.routers
+                .get_mut(rr)
+                .ok_or(NetworkError::DeviceNotFound(*rr))?
+                .cluster_id = Some(cluster_id);
+            for peer in rrs[i + 1..].iter().chain(other_rrs.iter()) {
+                self.add_ibgp_session(*rr, *peer, false, false)?;
+            }
+            for client in clients.iter() {
+                self.add_ibgp_session(*rr, *client, true, false)?;
+            }
+        }
+
+        if update {
+            for router in rrs.iter().chain(clients.iter()).chain(other_rrs.iter()) {
+                self.schedule_update_router(*router)?;
+            }
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// # Change the type of an iBGP session
+    ///
+    /// Reconfigures the existing iBGP session between source and target, without closing it
+    /// first. This way, the routes received over the session are kept, and no transient routing
+    /// gap is caused. `route_reflector` has the same meaning as in `add_ibgp_session`. An error
+    /// is returned if the session is an eBGP session.
+    pub fn change_ibgp_session_type(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        route_reflector: bool,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        self.check_internal_router(source)?;
+        self.check_internal_router(target)?;
+        // only iBGP sessions can be changed, an eBGP session is left untouched
+        for (router, peer) in [(source, target), (target, source)] {
+            let session_type = self
+                .routers
+                .get(&router)
+                .ok_or(NetworkError::DeviceNotFound(router))?
+                .get_bgp_session_type(peer)?;
+            if session_type.is_ebgp() {
+                return Err(NetworkError::NotAnIBgpSession(source, target));
+            }
+        }
+        let source_type = if route_reflector {
+            BgpSessionType::IBgpClient
+        } else {
+            BgpSessionType::IBgpPeer
+        };
+        self.routers
+            .get_mut(&source)
+            .ok_or(NetworkError::DeviceNotFound(source))?
+            .change_bgp_session_type(target, source_type)?;
+        self.routers
+            .get_mut(&target)
+            .ok_or(NetworkError::DeviceNotFound(target))?
+            .change_bgp_session_type(source, BgpSessionType::IBgpPeer)?;
+        if update {
+            self.schedule_update_router(source)?;
+            self.schedule_update_router(target)?;
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
     /// Remove an iBGP session
     pub fn remove_ibgp_session(
         &mut self,
@@ -165,6 +754,8 @@ impl Network {
         target: RouterId,
         update: bool,
     ) -> Result<bool, NetworkError> {
+        self.check_internal_router(source)?;
+        self.check_internal_router(target)?;
         self.routers
             .get_mut(&source)
             .ok_or(NetworkError::DeviceNotFound(source))?
@@ -182,21 +773,55 @@ impl Network {
         }
     }
 
-    /// Write the igp forwarding tables for all internal routers. As soon as this is done, recompute
-    /// the BGP table. and run the algorithm. This will happen all at once, in a very unpredictable
-    /// manner. If you want to do this more predictable, use `write_ibgp_fw_table`.
+    /// # Administratively shut down BGP on a router
     ///
-    /// The function returns Ok(true) if all events caused by the igp fw table write are handled
-    /// correctly. Returns Ok(false) if the max number of iterations is exceeded, and returns an
-    /// error if an event was not handled correctly.
-    pub fn write_igp_fw_tables(&mut self, update: bool) -> Result<bool, NetworkError> {
-        // update igp table
-        for r in self.routers.values_mut() {
-            r.write_igp_forwarding_table(&self.net)?;
+    /// All BGP sessions of the router are closed, such that all routes it advertised are
+    /// withdrawn, and all routes it learned are removed. The router keeps running the IGP, and
+    /// the sessions are remembered, such that they can be re-established with
+    /// `no_shutdown_router_bgp`. If BGP is already shut down on the router, nothing happens.
+    pub fn shutdown_router_bgp(
+        &mut self,
+        router: RouterId,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        self.check_internal_router(router)?;
+        if self.bgp_shutdown.contains_key(&router) {
+            return Ok(true);
+        }
+        let mut sessions = self
+            .routers
+            .get(&router)
+            .ok_or(NetworkError::DeviceNotFound(router))?
+            .get_bgp_sessions();
+        sessions.sort_by_key(|(peer, _)| *peer);
+
+        let mut shutdown = Vec::new();
+        for (peer, session_type) in sessions {
+            self.routers
+                .get_mut(&router)
+                .ok_or(NetworkError::DeviceNotFound(router))?
+                .close_bgp_session(peer)?;
+            let peer_type = if let Some(external) = self.external_routers.get_mut(&peer) {
+                external.neighbors.remove(&router);
+                None
+            } else {
+                let r = self
+                    .routers
+                    .get_mut(&peer)
+                    .ok_or(NetworkError::DeviceNotFound(peer))?;
+                let peer_type = r.get_bgp_session_type(router)?;
+                r.close_bgp_session(router)?;
+                Some(peer_type)
+            };
+            shutdown.push((peer, session_type, peer_type));
         }
+
+        let touched: Vec<RouterId> = std::iter::once(router)
+            .chain(shutdown.iter().filter(|s| s.2.is_some()).map(|s| s.0))
+            .collect();
+        self.bgp_shutdown.insert(router, shutdown);
         if update {
-            // update bgp
-            for r in self.routers.keys().cloned().collect::<Vec<RouterId>>() {
+            for r in touched {
                 self.schedule_update_router(r)?;
             }
             self.do_queue()
@@ -205,25 +830,56 @@ impl Network {
         }
     }
 
-    /// Write forwarding tables for the selected router
+    /// # Re-enable BGP on a router after an administrative shutdown
     ///
-    /// The function returns Ok(true) if all events caused by the igp fw table write are handled
-    /// correctly. Returns Ok(false) if the max number of iterations is exceeded, and returns an
-    /// error if an event was not handled correctly.
-    pub fn write_igp_fw_tables_order(
+    /// Re-establish all BGP sessions of the router which were closed by `shutdown_router_bgp`.
+    /// External neighbors advertise all their routes again. If BGP is not shut down on the
+    /// router, nothing happens.
+    pub fn no_shutdown_router_bgp(
         &mut self,
-        order: Vec<RouterId>,
+        router: RouterId,
         update: bool,
     ) -> Result<bool, NetworkError> {
-        for router in order.iter() {
+        self.check_internal_router(router)?;
+        let sessions = match self.bgp_shutdown.remove(&router) {
+            Some(sessions) => sessions,
+            None => return Ok(true),
+        };
+
+        for (peer, session_type, peer_type) in sessions.iter() {
             self.routers
                 .get_mut(&router)
-                .ok_or(NetworkError::DeviceNotFound(*router))?
-                .write_igp_forwarding_table(&self.net)?;
+                .ok_or(NetworkError::DeviceNotFound(router))?
+                .establish_bgp_session(*peer, *session_type)?;
+            match peer_type {
+                Some(peer_type) => {
+                    self.routers
+                        .get_mut(peer)
+                        .ok_or(NetworkError::DeviceNotFound(*peer))?
+                        .establish_bgp_session(router, *peer_type)?;
+                }
+                None => {
+                    let peer_as = self.get_as_id(*peer)?;
+                    self.routers
+                        .get_mut(&router)
+                        .ok_or(NetworkError::DeviceNotFound(router))?
+                        .set_ebgp_neighbor_as(*peer, peer_as)?;
+                    let external = self
+                        .external_routers
+                        .get_mut(peer)
+                        .ok_or(NetworkError::DeviceNotFound(*peer))?;
+                    external.neighbors.insert(router);
+                    external.readvertise_to(router, &mut self.queue);
+                }
+            }
         }
+
         if update {
-            for router in order.iter() {
-                self.schedule_update_router(*router)?;
+            self.schedule_update_router(router)?;
+            for (peer, _, peer_type) in sessions.iter() {
+                if peer_type.is_some() {
+                    self.schedule_update_router(*peer)?;
+                }
             }
             self.do_queue()
         } else {
@@ -231,26 +887,387 @@ impl Network {
         }
     }
 
-    /// Advertise an external route and let the network converge
-    /// The source must be a RouterId of an ExternalRouter
-    pub fn advertise_external_route(
+    /// # Clear a BGP session
+    ///
+    /// Reset the BGP session between the internal `router` and `neighbor` (`clear ip bgp
+    /// <neighbor>`), without changing its configuration. Both ends withdraw all routes sent over
+    /// the session, forget all routes received over it, and send all their best routes again (see
+    /// `Router::clear_bgp_neighbor`). An external neighbor advertises all its routes again.
+    /// Afterwards, the queue is executed until the network has converged.
+    /// Returns Ok(false) if max iterations is exceeded.
+    pub fn clear_bgp_neighbor(
+        &mut self,
+        router: RouterId,
+        neighbor: RouterId,
+    ) -> Result<bool, NetworkError> {
+        self.check_internal_router(router)?;
+        self.routers
+            .get_mut(&router)
+            .ok_or(NetworkError::DeviceNotFound(router))?
+            .clear_bgp_neighbor(neighbor, &mut self.queue)?;
+        if let Some(r) = self.routers.get_mut(&neighbor) {
+            r.clear_bgp_neighbor(router, &mut self.queue)?;
+        } else {
+            self.external_routers
+                .get_mut(&neighbor)
+                .ok_or(NetworkError::DeviceNotFound(neighbor))?
+                .readvertise_to(router, &mut self.queue);
+        }
+        self.do_queue()
+    }
+
+    /// # Gracefully shut down a BGP session
+    ///
+    /// Drain the traffic from the BGP session between source and target before it is removed
+    /// (RFC 8326). Internal routers tag all routes received over the session with the
+    /// GRACEFUL_SHUTDOWN community, and import them with local_pref 0. This way, the network
+    /// reconverges away from the session, while it is still established. The session may be an
+    /// iBGP or an eBGP session.
+    pub fn graceful_shutdown_session(
         &mut self,
         source: RouterId,
-        prefix: Prefix,
-        as_path: Vec<AsId>,
-        med: Option<u32>,
+        target: RouterId,
         update: bool,
     ) -> Result<bool, NetworkError> {
-        // initiate the advertisement
-        println!(
-            "\n*** Advertise prefix {} on {} ***\n",
+        if !self.routers.contains_key(&source) && !self.routers.contains_key(&target) {
+            return Err(NetworkError::DeviceIsExternalRouter(source));
+        }
+        for (router, peer) in [(source, target), (target, source)] {
+            if let Some(r) = self.routers.get_mut(&router) {
+                r.set_graceful_shutdown(peer, true)?;
+            }
+        }
+        if update {
+            for router in [source, target] {
+                if self.routers.contains_key(&router) {
+                    self.schedule_update_router(router)?;
+                }
+            }
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// Apply a single configuration change, see `ConfigChange`.
+    pub fn apply_config_change(
+        &mut self,
+        change: ConfigChange,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        match change {
+            ConfigChange::AddIbgpSession(source, target, route_reflector) => {
+                self.add_ibgp_session(source, target, route_reflector, update)
+            }
+            ConfigChange::RemoveIbgpSession(source, target) => {
+                self.remove_ibgp_session(source, target, update)
+            }
+            ConfigChange::ChangeIbgpSessionType(source, target, route_reflector) => {
+                self.change_ibgp_session_type(source, target, route_reflector, update)
+            }
+            ConfigChange::UpdateLinkWeight(source, target, weight) => {
+                let edge = self
+                    .net
+                    .find_edge(source, target)
+                    .ok_or(NetworkError::LinkNotFound(source, target))?;
+                self.net[edge] = weight;
+                self.igp_dirty = true;
+                self.write_igp_fw_tables(update)
+            }
+        }
+    }
+
+    /// Returns the current configuration of the iBGP sessions and the link weights, see
+    /// `NetworkConfig`.
+    pub fn get_config(&self) -> NetworkConfig {
+        let mut ibgp_sessions = HashMap::new();
+        for (id, r) in self.routers.iter() {
+            for (peer, session_type) in r.get_bgp_sessions() {
+                match session_type {
+                    BgpSessionType::IBgpClient => {
+                        ibgp_sessions.insert((*id, peer), true);
+                    }
+                    // the client also sees its route reflector as peer
+                    BgpSessionType::IBgpPeer
+                        if !matches!(
+                            self.routers.get(&peer).map(|p| p.get_bgp_session_type(*id)),
+                            Some(Ok(BgpSessionType::IBgpClient))
+                        ) =>
+                    {
+                        ibgp_sessions.insert((*id.min(&peer), *id.max(&peer)), false);
+                    }
+                    BgpSessionType::IBgpPeer => {}
+                    BgpSessionType::EBgp => {}
+                }
+            }
+        }
+        let link_weights = self
+            .net
+            .edge_indices()
+            .filter_map(|e| Some((self.net.edge_endpoints(e)?, self.net[e])))
+            .collect();
+        NetworkConfig {
+            ibgp_sessions,
+            link_weights,
+        }
+    }
+
+    /// # Plan a migration between two configurations
+    ///
+    /// Compute the configuration changes from `from` to `to` (see `NetworkConfig::changes_to`), and
+    /// search for an order in which they can be applied safely, i.e., the network converges after
+    /// each change, no forwarding loop occurs, and no router loses its route towards any known
+    /// prefix (see `find_safe_reconfig_order`). The network must be configured as `from`. Returns
+    /// None if no such order exists. The network itself is not changed.
+    pub fn plan_migration(
+        &self,
+        from: &NetworkConfig,
+        to: &NetworkConfig,
+    ) -> Option<MigrationPlan> {
+        self.find_safe_reconfig_order(from.changes_to(to))
+            .map(|changes| MigrationPlan { changes })
+    }
+
+    /// # Find a safe reconfiguration order
+    ///
+    /// Search for an order of the given configuration changes, such that the network converges
+    /// after each change, and the forwarding state is free of forwarding loops for all known
+    /// prefixes after each step. Further, no router may run into a black hole for a prefix it
+    /// could reach before the step. Every possible order is explored on a copy of the network, so
+    /// this should only be used with a small number of changes. Returns None if no such order
+    /// exists. The network itself is not changed.
+    pub fn find_safe_reconfig_order(
+        &self,
+        changes: Vec<ConfigChange>,
+    ) -> Option<Vec<ConfigChange>> {
+        let mut order = Vec::with_capacity(changes.len());
+        if self.search_safe_reconfig_order(changes, &mut order) {
+            Some(order)
+        } else {
+            None
+        }
+    }
+
+    /// Check if applying the configuration changes in the given order is safe, i.e., the network
+    /// converges after each change, and the forwarding state is free of forwarding loops and new
+    /// black holes after each step (see `find_safe_reconfig_order`). The network itself is not changed.
+    pub fn is_safe_reconfig_order(&self, changes: &[ConfigChange]) -> bool {
+        let mut net = self.clone();
+        changes
+            .iter()
+            .all(|change| net.apply_safe_config_change(*change))
+    }
+
+    /// Recursively try all orders of the remaining changes, and push the safe order to `order`.
+    fn search_safe_reconfig_order(
+        &self,
+        remaining: Vec<ConfigChange>,
+        order: &mut Vec<ConfigChange>,
+    ) -> bool {
+        if remaining.is_empty() {
+            return true;
+        }
+        for (i, change) in remaining.iter().enumerate() {
+            let mut net = self.clone();
+            if !net.apply_safe_config_change(*change) {
+                continue;
+            }
+            let mut next = remaining.clone();
+            next.remove(i);
+            order.push(*change);
+            if net.search_safe_reconfig_order(next, order) {
+                return true;
+            }
+            order.pop();
+        }
+        false
+    }
+
+    /// Apply the change, and return true if the network converges without any forwarding loop or
+    /// new black hole.
+    fn apply_safe_config_change(&mut self, change: ConfigChange) -> bool {
+        let black_holes = self.forwarding_black_holes();
+        self.apply_config_change(change, true) == Ok(true) && self.is_forwarding_safe(&black_holes)
+    }
+
+    /// Returns all pairs of internal routers and known prefixes, for which the traffic runs into a
+    /// black hole.
+    fn forwarding_black_holes(&self) -> HashSet<(RouterId, Prefix)> {
+        let prefixes = self.known_prefixes();
+        self.routers
+            .keys()
+            .flat_map(|router| prefixes.iter().map(move |prefix| (*router, *prefix)))
+            .filter(|(router, prefix)| {
+                matches!(
+                    self.get_route(*router, *prefix),
+                    Err(NetworkError::ForwardingBlackHole(..))
+                )
+            })
+            .collect()
+    }
+
+    /// Returns true if the forwarding state is free of forwarding loops, and all black holes are
+    /// contained in `black_holes`.
+    fn is_forwarding_safe(&self, black_holes: &HashSet<(RouterId, Prefix)>) -> bool {
+        let prefixes = self.known_prefixes();
+        self.routers.keys().all(|router| {
+            prefixes
+                .iter()
+                .all(|prefix| match self.get_route(*router, *prefix) {
+                    Err(NetworkError::ForwardingLoop(_)) => false,
+                    Err(NetworkError::ForwardingBlackHole(..)) => {
+                        black_holes.contains(&(*router, *prefix))
+                    }
+                    _ => true,
+                })
+        })
+    }
+
+    /// Write the igp forwarding tables for all internal routers. As soon as this is done, recompute
+    /// the BGP table. and run the algorithm. This will happen all at once, in a very unpredictable
+    /// manner. If you want to do this more predictable, use `write_ibgp_fw_table`.
+    ///
+    /// The forwarding tables are only recomputed if the topology has changed since they were last
+    /// written for all routers. Otherwise, they are already up to date.
+    ///
+    /// The function returns Ok(true) if all events caused by the igp fw table write are handled
+    /// correctly. Returns Ok(false) if the max number of iterations is exceeded, and returns an
+    /// error if an event was not handled correctly.
+    pub fn write_igp_fw_tables(&mut self, update: bool) -> Result<bool, NetworkError> {
+        // update igp table, if the topology has changed
+        if self.igp_dirty {
+            let igp_graph = self.igp_graph();
+            for r in self.routers.values_mut() {
+                r.write_igp_forwarding_table(&igp_graph)?;
+                self.igp_computations += 1;
+            }
+            self.igp_dirty = false;
+        }
+        if update {
+            // update bgp
+            let mut routers: Vec<RouterId> = self.routers.keys().cloned().collect();
+            routers.sort();
+            for r in routers {
+                self.schedule_update_router(r)?;
+            }
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// Write forwarding tables for the selected router
+    ///
+    /// The function returns Ok(true) if all events caused by the igp fw table write are handled
+    /// correctly. Returns Ok(false) if the max number of iterations is exceeded, and returns an
+    /// error if an event was not handled correctly.
+    pub fn write_igp_fw_tables_order(
+        &mut self,
+        order: Vec<RouterId>,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        let igp_graph = self.igp_graph();
+        for router in order.iter() {
+            self.routers
+                .get_mut(router)
+                .ok_or(NetworkError::DeviceNotFound(*router))?
+                .write_igp_forwarding_table(&igp_graph)?;
+            self.igp_computations += 1;
+        }
+        if update {
+            for router in order.iter() {
+                self.schedule_update_router(*router)?;
+            }
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// Originate a route on an internal router (network statement), and let the network converge.
+    /// The next hop of the route is set to the router itself. See `Router::originate_route`.
+    pub fn originate_route(
+        &mut self,
+        router: RouterId,
+        route: BgpRoute,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        self.check_internal_router(router)?;
+        self.routers
+            .get_mut(&router)
+            .ok_or(NetworkError::DeviceNotFound(router))?
+            .originate_route(route, &mut self.queue)?;
+        if update {
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// Originate a VPN route in a VRF of an internal router, and let the network converge. See
+    /// `Router::originate_vrf_route`.
+    pub fn originate_vrf_route(
+        &mut self,
+        router: RouterId,
+        vrf: &'static str,
+        route: BgpRoute,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        self.check_internal_router(router)?;
+        self.routers
+            .get_mut(&router)
+            .ok_or(NetworkError::DeviceNotFound(router))?
+            .originate_vrf_route(vrf, route, &mut self.queue)?;
+        if update {
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// Advertise an external route and let the network converge
+    /// The source must be a RouterId of an ExternalRouter
+    pub fn advertise_external_route(
+        &mut self,
+        source: RouterId,
+        prefix: Prefix,
+        as_path: Vec<AsId>,
+        med: Option<u32>,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        self.advertise_external_route_with(
+            source,
+            prefix,
+            as_path,
+            med,
+            ExternalRouteAttrs::default(),
+            update,
+        )
+    }
+
+    /// Advertise an external route with the additional path attributes `attrs`, and let the
+    /// network converge. The attributes contain the communities of the route, e.g.,
+    /// `COMMUNITY_NO_EXPORT` to keep it within the AS of the receiving routers, and the ORIGIN
+    /// attribute. See `advertise_external_route`.
+    pub fn advertise_external_route_with(
+        &mut self,
+        source: RouterId,
+        prefix: Prefix,
+        as_path: Vec<AsId>,
+        med: Option<u32>,
+        attrs: ExternalRouteAttrs,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        // initiate the advertisement
+        println!(
+            "\n*** Advertise prefix {} on {} ***\n",
             prefix.0,
             self.get_router_name(source)?
         );
         self.external_routers
-            .get(&source)
+            .get_mut(&source)
             .ok_or(NetworkError::DeviceNotFound(source))?
-            .advertise_prefix(prefix, as_path, med, &mut self.queue);
+            .advertise_prefix(prefix, as_path, med, attrs, &mut self.queue);
         if update {
             // run the queue
             self.do_queue()
@@ -259,6 +1276,100 @@ impl Network {
         }
     }
 
+    /// Advertise `prefix` on the external router `source` (with the AS path containing only its
+    /// own AS) and let the network converge, while tracing how the route propagates. Every event
+    /// which changes the selected route of an internal router for `prefix` is recorded in the
+    /// order of execution, together with the neighbor that sent the update, forming the
+    /// propagation tree rooted at `source`. The depth of `source` is 0. The queue is executed
+    /// for at most the number of events configured with `stop_after_queue`, and the remaining
+    /// events stay in the queue.
+    pub fn trace_route_propagation(
+        &mut self,
+        source: RouterId,
+        prefix: Prefix,
+    ) -> Result<Vec<PropagationStep>, NetworkError> {
+        let as_id = self
+            .external_routers
+            .get(&source)
+            .ok_or(NetworkError::DeviceNotFound(source))?
+            .as_id();
+        self.advertise_external_route(source, prefix, vec![as_id], None, false)?;
+
+        let mut depths: HashMap<RouterId, usize> = HashMap::new();
+        depths.insert(source, 0);
+        let mut steps = Vec::new();
+        let mut remaining_iter = self.stop_after;
+        while let Some(Event::Bgp(from, to, _)) = self.queue.front().cloned() {
+            if let Some(rem) = remaining_iter {
+                if rem == 0 {
+                    break;
+                }
+                remaining_iter = Some(rem - 1);
+            }
+            let before = self
+                .routers
+                .get(&to)
+                .and_then(|r| r.get_selected_bgp_route(prefix));
+            self.step()?;
+            let after = self
+                .routers
+                .get(&to)
+                .and_then(|r| r.get_selected_bgp_route(prefix));
+            if self.routers.contains_key(&to) && before != after {
+                let depth = depths.get(&from).copied().unwrap_or(0) + 1;
+                depths.insert(to, depth);
+                steps.push(PropagationStep {
+                    router: to,
+                    parent: from,
+                    depth,
+                });
+            }
+        }
+        Ok(steps)
+    }
+
+    /// Advertise an external route and let the network converge, but stop after `stop_after`
+    /// events, independent of the limit configured with `stop_after_queue`.
+    /// The source must be a RouterId of an ExternalRouter
+    pub fn advertise_external_route_capped(
+        &mut self,
+        source: RouterId,
+        prefix: Prefix,
+        as_path: Vec<AsId>,
+        med: Option<u32>,
+        stop_after: Option<usize>,
+    ) -> Result<bool, NetworkError> {
+        self.advertise_external_route(source, prefix, as_path, med, false)?;
+        self.do_queue_capped(stop_after)
+    }
+
+    /// Change the AS path and the MED of a route which is already advertised by the external
+    /// router `source`, and let the network converge. The new route replaces the old one (implicit
+    /// update), without withdrawing it first. If the prefix is not advertised by `source`,
+    /// `NetworkError::PrefixNotAdvertised` is returned.
+    pub fn update_external_route(
+        &mut self,
+        source: RouterId,
+        prefix: Prefix,
+        as_path: Vec<AsId>,
+        med: Option<u32>,
+        update: bool,
+    ) -> Result<bool, NetworkError> {
+        let updated = self
+            .external_routers
+            .get_mut(&source)
+            .ok_or(NetworkError::DeviceNotFound(source))?
+            .update_prefix(prefix, as_path, med, &mut self.queue);
+        if !updated {
+            return Err(NetworkError::PrefixNotAdvertised(source, prefix));
+        }
+        if update {
+            self.do_queue()
+        } else {
+            Ok(true)
+        }
+    }
+
     /// Retract an external route and let the network converge
     /// The source must be a RouterId of an ExternalRouter
     pub fn retract_external_route(
@@ -274,7 +1385,7 @@ impl Network {
         );
         // initiate the advertisement
         self.external_routers
-            .get(&source)
+            .get_mut(&source)
             .ok_or(NetworkError::DeviceNotFound(source))?
             .widthdraw_prefix(prefix, &mut self.queue);
         if update {
@@ -301,43 +1412,217 @@ impl Network {
     /// Returns Ok(false) if max iterations is exceeded
     /// Returns Ok(true) if everything was fine.
     pub fn do_queue(&mut self) -> Result<bool, NetworkError> {
-        let mut remaining_iter = self.stop_after;
-        while let Some(event) = self.queue.pop_front() {
-            if let Some(rem) = remaining_iter {
-                if rem == 0 {
-                    return Ok(false);
+        self.do_queue_capped(self.stop_after)
+    }
+
+    /// Execute the queue, but stop after `stop_after` events, instead of the limit configured
+    /// with `stop_after_queue`. The configured limit is not changed. If set to None, the queue
+    /// will continue running until converged.
+    /// Returns Ok(false) if max iterations is exceeded. The remaining events stay in the queue.
+    /// Returns Ok(true) if everything was fine.
+    pub fn do_queue_capped(&mut self, stop_after: Option<usize>) -> Result<bool, NetworkError> {
+        self.run_queue(stop_after, 1, |_| {})
+    }
+
+    /// Execute the queue until the network has converged, ignoring the limit configured with
+    /// `stop_after_queue` for this call only. The configured limit is not changed.
+    /// Returns Ok(true) once the network has converged.
+    pub fn do_queue_uncapped(&mut self) -> Result<bool, NetworkError> {
+        self.do_queue_capped(None)
+    }
+
+    /// # Execute the queue, and observe the transient states
+    ///
+    /// Execute the queue like `do_queue`, but call `observer` with the network after every
+    /// `every` events. This way, the forwarding state can be inspected during convergence, e.g.,
+    /// to find transient forwarding loops or black holes, which are no longer present once the
+    /// network has converged. The state after the last event is observed only if the number of
+    /// executed events is a multiple of `every`.
+    /// Returns Ok(false) if max iterations is exceeded. The remaining events stay in the queue.
+    /// Returns Ok(true) if everything was fine.
+    pub fn do_queue_observed<F>(&mut self, every: usize, observer: F) -> Result<bool, NetworkError>
+    where
+        F: FnMut(&Network),
+    {
+        self.run_queue(self.stop_after, every.max(1), observer)
+    }
+
+    /// Execute exactly one event from the queue, and return it. This way, the convergence can be
+    /// followed step by step, and the network can be inspected between two events. Returns
+    /// `Ok(None)` if the queue is empty, i.e., if the network has converged. If the queue grows
+    /// beyond the limit configured with `set_max_queue_len`, `NetworkError::QueueOverflow` is
+    /// returned after executing the event.
+    pub fn step(&mut self) -> Result<Option<Event>, NetworkError> {
+        match self.queue.pop_front() {
+            Some(event) => {
+                let Event::Bgp(from, to, _) = event;
+                *self.message_counts.entry((from, to)).or_default() += 1;
+                self.execute_event(event.clone())?;
+                match self.max_queue_len {
+                    Some(max) if self.queue.len() > max => Err(NetworkError::QueueOverflow {
+                        len: self.queue.len(),
+                    }),
+                    _ => Ok(Some(event)),
                 }
-                remaining_iter = Some(rem - 1);
             }
-            // print the job
-            self.print_event(&event)?;
-            // execute the event
-            let (working_router_id, event_result) = match event {
-                Event::Bgp(from, to, bgp_event) => (
-                    to,
-                    if let Some(r) = self.routers.get_mut(&to) {
-                        r.handle_event(Event::Bgp(from, to, bgp_event), &mut self.queue)
-                            .map_err(|e| NetworkError::DeviceError(e))
-                    } else if let Some(r) = self.external_routers.get_mut(&to) {
-                        r.handle_event(Event::Bgp(from, to, bgp_event), &mut self.queue)
-                            .map_err(|e| NetworkError::DeviceError(e))
-                    } else {
-                        Err(NetworkError::DeviceNotFound(to))
-                    },
-                ),
-            };
+            None => Ok(None),
+        }
+    }
 
-            match event_result {
-                Ok(()) => {}
-                Err(NetworkError::DeviceError(DeviceError::NoBgpSession(target))) => eprintln!(
-                    "No BGP session active between {} and  {}!",
-                    self.get_router_name(working_router_id)?,
-                    self.get_router_name(target)?
-                ),
-                Err(e) => return Err(e),
+    /// Replay a trace of events, recorded with `record_trace`, in exactly the same order. Events
+    /// produced by the routers while replaying are dropped, since they are part of the trace. The
+    /// events that were already in the queue before are not affected. Replaying the trace on an
+    /// identical network in the same initial state results in the same state as the recorded one.
+    pub fn replay(&mut self, trace: &[Event]) -> Result<(), NetworkError> {
+        let queue = std::mem::take(&mut self.queue);
+        let mut result = Ok(());
+        for event in trace {
+            result = self.execute_event(event.clone());
+            self.queue.clear();
+            if result.is_err() {
+                break;
             }
         }
-        Ok(true)
+        self.queue = queue;
+        result
+    }
+
+    /// # Apply a batch of changes, and converge once
+    ///
+    /// All changes done on the `Transaction` inside of `f` are applied without updating the
+    /// network (like calling the methods with `update` set to `false`). Once `f` returns, all
+    /// affected routers are updated, and the queue is executed until the network has converged.
+    /// If `f` returns an error, the queue is not executed, and the error is returned. Changes
+    /// done before the error are not undone.
+    pub fn transaction<F>(&mut self, f: F) -> Result<ConvergenceResult, NetworkError>
+    where
+        F: FnOnce(&mut Transaction) -> Result<(), NetworkError>,
+    {
+        let mut tx = Transaction {
+            net: self,
+            touched: Vec::new(),
+        };
+        f(&mut tx)?;
+        let touched = tx.touched;
+        for router in touched {
+            self.schedule_update_router(router)?;
+        }
+        if self.do_queue()? {
+            Ok(ConvergenceResult::Converged)
+        } else {
+            Ok(ConvergenceResult::MaxIterExceeded)
+        }
+    }
+
+    /// Returns the physical topology, containing all links with their weights in both directions.
+    pub fn get_topology(&self) -> &IgpNetwork {
+        &self.net
+    }
+
+    /// Returns all equal-cost next hops of the IGP shortest paths from `source` to `target`. The
+    /// next hop is listed once for each link on any shortest path, such that a neighbor appears
+    /// multiple times if it is reached via parallel links of the same weight. The next hops are
+    /// sorted, and the vector is empty if `target` is not reachable, or equal to `source`. Only
+    /// links advertised into the IGP are used.
+    pub fn get_igp_ecmp_next_hops(
+        &self,
+        source: RouterId,
+        target: RouterId,
+    ) -> Result<Vec<RouterId>, NetworkError> {
+        for router in [source, target] {
+            if !self.routers.contains_key(&router) && !self.external_routers.contains_key(&router) {
+                return Err(NetworkError::DeviceNotFound(router));
+            }
+        }
+        let graph = self.igp_graph();
+        let distance = |from: RouterId| -> LinkWeight {
+            bellman_ford(&graph, from)
+                .map(|(weights, _)| weights[target.index()])
+                .unwrap_or_else(|_| LinkWeight::infinite())
+        };
+        let total = distance(source);
+        if source == target || total == LinkWeight::infinite() {
+            return Ok(Vec::new());
+        }
+        let mut next_hops: Vec<RouterId> = graph
+            .edges(source)
+            .filter(|e| *e.weight() + distance(e.target()) == total)
+            .map(|e| e.target())
+            .collect();
+        next_hops.sort();
+        Ok(next_hops)
+    }
+
+    /// Returns the events which are scheduled, but not yet executed. The queue is empty once the
+    /// network has converged.
+    pub fn get_queue(&self) -> &EventQueue {
+        &self.queue
+    }
+
+    /// Returns the number of times any IGP forwarding table was computed, i.e., the number of
+    /// shortest path computations done so far.
+    pub fn igp_computations(&self) -> usize {
+        self.igp_computations
+    }
+
+    /// Returns the number of BGP messages sent over each directed session so far, indexed by the
+    /// sender and the receiver. Sessions without any message are not contained. Events replayed
+    /// with `replay` are not counted.
+    pub fn message_counts_per_session(&self) -> HashMap<(RouterId, RouterId), usize> {
+        self.message_counts.clone()
+    }
+
+    /// Returns the number of events executed during the last run of the queue, i.e., the number of
+    /// steps the last operation took to converge. Operations called with `update` set to `false`
+    /// do not run the queue, and do not change this number.
+    pub fn last_convergence_steps(&self) -> usize {
+        self.last_convergence_steps
+    }
+
+    /// Returns the sum of the BGP table sizes of all internal routers (see `Router::table_sizes`).
+    pub fn total_table_sizes(&self) -> TableSizes {
+        let mut sizes = TableSizes::default();
+        for r in self.routers.values() {
+            sizes += r.table_sizes();
+        }
+        sizes
+    }
+
+    /// Returns all updates which were received out of order by any internal router, such that an
+    /// older update replaced a newer one (see `Router::get_stale_updates`). Each entry contains the
+    /// receiving router, the sender and the prefix, sorted by the receiving router.
+    pub fn detect_stale_overwrites(&self) -> Vec<(RouterId, RouterId, Prefix)> {
+        let mut result: Vec<(RouterId, RouterId, Prefix)> = self
+            .routers
+            .values()
+            .flat_map(|r| {
+                r.get_stale_updates()
+                    .iter()
+                    .map(move |(from, prefix)| (r.router_id(), *from, *prefix))
+            })
+            .collect();
+        result.sort_by_key(|(router, _, _)| *router);
+        result
+    }
+
+    /// Break exact ties in the decision process of all internal routers randomly, using the seed
+    /// (see `Router::tiebreak_seed`). Runs with the same seed are reproducible. If `seed` is
+    /// `None`, ties are broken deterministically again. The seed is also used for all routers
+    /// added later. The change is applied on the next decision process.
+    pub fn set_tiebreak_seed(&mut self, seed: Option<u64>) {
+        self.tiebreak_seed = seed;
+        for r in self.routers.values_mut() {
+            r.tiebreak_seed = seed;
+        }
+    }
+
+    /// Add a route origin authorization (ROA) to all internal routers, authorizing `origin_as` to
+    /// originate the prefix. ROAs are only checked when routes are received, so they should be
+    /// added before advertising any route.
+    pub fn add_roa(&mut self, prefix: Prefix, origin_as: AsId) {
+        for r in self.routers.values_mut() {
+            r.add_roa(prefix, origin_as);
+        }
     }
 
     /// Get an immutable reference to a router
@@ -347,6 +1632,13 @@ impl Network {
             .ok_or(NetworkError::DeviceNotFound(router))
     }
 
+    /// Get an immutable reference to an external router
+    pub fn get_external_router(&self, router: RouterId) -> Result<&ExternalRouter, NetworkError> {
+        self.external_routers
+            .get(&router)
+            .ok_or(NetworkError::DeviceNotFound(router))
+    }
+
     /// Get a mutable reference to a router
     pub fn get_router_mut(&mut self, router: RouterId) -> Result<&mut Router, NetworkError> {
         self.routers
@@ -354,6 +1646,35 @@ impl Network {
             .ok_or(NetworkError::DeviceNotFound(router))
     }
 
+    /// Returns the set of all prefixes known by any internal router in the network
+    pub fn known_prefixes(&self) -> BTreeSet<Prefix> {
+        self.routers
+            .values()
+            .flat_map(|r| r.known_prefixes().iter().cloned())
+            .collect()
+    }
+
+    /// Returns the connectivity gaps in the network, mapping each internal router to the sorted
+    /// prefixes (out of `known_prefixes`) for which its traffic runs into a black hole or a
+    /// forwarding loop. Routers which can reach all prefixes are omitted.
+    pub fn get_unreachable_prefixes(&self) -> Result<HashMap<RouterId, Vec<Prefix>>, NetworkError> {
+        let prefixes = self.known_prefixes();
+        let mut result: HashMap<RouterId, Vec<Prefix>> = HashMap::new();
+        for router in self.routers.keys() {
+            for prefix in prefixes.iter() {
+                match self.get_route(*router, *prefix) {
+                    Ok(_) => {}
+                    Err(NetworkError::ForwardingLoop(_))
+                    | Err(NetworkError::ForwardingBlackHole(..)) => {
+                        result.entry(*router).or_default().push(*prefix)
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(result)
+    }
+
     /// return the route for the given prefix, starting at the source router.
     pub fn get_route(
         &self,
@@ -361,12 +1682,13 @@ impl Network {
         prefix: Prefix,
     ) -> Result<Vec<RouterId>, NetworkError> {
         // check if we are already at an external router
-        if let Some(_) = self.external_routers.get(&source) {
+        if self.external_routers.contains_key(&source) {
             return Err(NetworkError::DeviceIsExternalRouter(source));
         }
         let mut visited_routers: HashSet<RouterId> = HashSet::new();
         let mut result: Vec<RouterId> = Vec::new();
         let mut current_node = source;
+        let mut bgp_next_hop: Option<RouterId> = None;
         loop {
             if !(self.routers.contains_key(&current_node)
                 || self.external_routers.contains_key(&current_node))
@@ -385,14 +1707,17 @@ impl Network {
                             .collect(),
                     ));
                 }
-                current_node = match r.get_next_hop(prefix) {
-                    Some(router_id) => router_id,
-                    None => {
+                current_node = match self.get_next_hop_on_path(r, prefix, &mut bgp_next_hop) {
+                    // the prefix is originated by the router itself
+                    NextHop::Via(next_hops) if next_hops[0] == current_node => break,
+                    NextHop::Via(next_hops) => next_hops[0],
+                    reason => {
                         return Err(NetworkError::ForwardingBlackHole(
                             result
                                 .iter()
                                 .map(|r| self.routers.get(r).unwrap().name())
                                 .collect(),
+                            reason,
                         ))
                     }
                 };
@@ -403,6 +1728,518 @@ impl Network {
         Ok(result)
     }
 
+    /// # Explain a forwarding loop
+    ///
+    /// Follow the path of the traffic for the prefix, starting at `source`, like `get_route`. If
+    /// the traffic runs into a forwarding loop, return all routers on the loop (starting with the
+    /// first router of the loop which is reached from `source`), together with the BGP route each
+    /// of them has selected. P routers, which do not run BGP, have no selected route. If there is
+    /// no forwarding loop on the path, an empty vector is returned.
+    pub fn explain_loop(
+        &self,
+        source: RouterId,
+        prefix: Prefix,
+    ) -> Result<Vec<(RouterId, Option<BgpRoute>)>, NetworkError> {
+        if self.external_routers.contains_key(&source) {
+            return Err(NetworkError::DeviceIsExternalRouter(source));
+        }
+        if !self.routers.contains_key(&source) {
+            return Err(NetworkError::DeviceNotFound(source));
+        }
+        let mut path: Vec<RouterId> = Vec::new();
+        let mut current_node = source;
+        let mut bgp_next_hop: Option<RouterId> = None;
+        while let Some(r) = self.routers.get(&current_node) {
+            if let Some(pos) = path.iter().position(|x| *x == current_node) {
+                return Ok(path[pos..]
+                    .iter()
+                    .map(|router| {
+                        let route = self
+                            .routers
+                            .get(router)
+                            .and_then(|r| r.get_selected_bgp_route(prefix))
+                            .map(|entry| entry.route);
+                        (*router, route)
+                    })
+                    .collect());
+            }
+            path.push(current_node);
+            current_node = match self.get_next_hop_on_path(r, prefix, &mut bgp_next_hop) {
+                NextHop::Via(next_hops) if next_hops[0] == current_node => break,
+                NextHop::Via(next_hops) => next_hops[0],
+                NextHop::NoRoute | NextHop::Unreachable(_) => break,
+            };
+        }
+        Ok(Vec::new())
+    }
+
+    /// # Path diversity
+    ///
+    /// Returns the number of link-disjoint paths from `source` towards the BGP next hops of all
+    /// routes it has received for the prefix (including the ones which are not selected). This
+    /// approximates how well the router is protected against single link failures: with a
+    /// diversity of at least 2, the router can still reach the prefix after any link failure, once
+    /// BGP and the IGP have converged. Only links advertised into the IGP are used, and external
+    /// routers are never used as transit. If the router is itself a next hop, this route is not
+    /// counted. Returns 0 if the router knows no route for the prefix.
+    pub fn path_diversity(&self, source: RouterId, prefix: Prefix) -> Result<usize, NetworkError> {
+        if self.external_routers.contains_key(&source) {
+            return Err(NetworkError::DeviceIsExternalRouter(source));
+        }
+        let egresses: HashSet<RouterId> = self
+            .routers
+            .get(&source)
+            .ok_or(NetworkError::DeviceNotFound(source))?
+            .get_known_bgp_routes(prefix)?
+            .into_iter()
+            .map(|entry| entry.route.next_hop)
+            .filter(|next_hop| *next_hop != source)
+            .collect();
+
+        // unit capacity for each link, such that the maximum flow equals the number of
+        // link-disjoint paths
+        let graph = self.igp_graph();
+        let mut capacity: HashMap<(RouterId, RouterId), usize> = HashMap::new();
+        for e in graph.edge_indices() {
+            match graph.edge_endpoints(e) {
+                Some((a, b)) if !self.external_routers.contains_key(&a) => {
+                    *capacity.entry((a, b)).or_default() += 1
+                }
+                _ => {}
+            }
+        }
+
+        // augment the flow along the shortest path (in hops) to any egress, until none is left
+        let mut num_paths = 0;
+        loop {
+            let mut predecessors: HashMap<RouterId, RouterId> = HashMap::new();
+            let mut queue: VecDeque<RouterId> = VecDeque::from(vec![source]);
+            let mut egress: Option<RouterId> = None;
+            while let Some(node) = queue.pop_front() {
+                if egresses.contains(&node) {
+                    egress = Some(node);
+                    break;
+                }
+                for neighbor in graph.neighbors_undirected(node) {
+                    if neighbor != source
+                        && !predecessors.contains_key(&neighbor)
+                        && capacity.get(&(node, neighbor)).copied().unwrap_or(0) > 0
+                    {
+                        predecessors.insert(neighbor, node);
+                        queue.push_back(neighbor);
+                    }
+                }
+            }
+            let mut node = match egress {
+                Some(egress) => egress,
+                None => break,
+            };
+            while node != source {
+                let predecessor = predecessors[&node];
+                *capacity.entry((predecessor, node)).or_default() -= 1;
+                *capacity.entry((node, predecessor)).or_default() += 1;
+                node = predecessor;
+            }
+            num_paths += 1;
+        }
+        Ok(num_paths)
+    }
+
+    /// # Extract a subnetwork
+    ///
+    /// Returns a copy of the network, which only contains the given routers, together with all
+    /// external routers which have an eBGP session with any of the given internal routers. All
+    /// links and BGP sessions to other routers are removed, and the subnetwork is converged again.
+    /// The routers keep their ids and their configuration, and external routers keep advertising
+    /// their routes. The network itself is not changed. If the subnetwork does not converge within
+    /// the limit configured with `stop_after_queue`, `NetworkError::MaxIterExceeded` is returned.
+    pub fn subnetwork(&self, routers: &[RouterId]) -> Result<Network, NetworkError> {
+        let mut keep: HashSet<RouterId> = HashSet::new();
+        for router in routers {
+            if let Some(r) = self.routers.get(router) {
+                keep.extend(
+                    r.get_bgp_sessions()
+                        .into_iter()
+                        .map(|(peer, _)| peer)
+                        .filter(|peer| self.external_routers.contains_key(peer)),
+                );
+            } else if !self.external_routers.contains_key(router) {
+                return Err(NetworkError::DeviceNotFound(*router));
+            }
+            keep.insert(*router);
+        }
+
+        // disconnect the other routers, and let the network converge, such that all routes via
+        // the other routers are withdrawn before the links are removed
+        let mut net = self.clone();
+        for (id, r) in net.routers.iter_mut() {
+            let mut removed: Vec<RouterId> = r
+                .get_bgp_sessions()
+                .into_iter()
+                .map(|(peer, _)| peer)
+                .filter(|peer| keep.contains(id) != keep.contains(peer))
+                .collect();
+            removed.sort();
+            for peer in removed {
+                r.close_bgp_session(peer)?;
+            }
+        }
+        for (id, r) in net.external_routers.iter_mut() {
+            let kept = keep.contains(id);
+            r.neighbors
+                .retain(|neighbor| keep.contains(neighbor) == kept);
+        }
+        let mut ids: Vec<RouterId> = net.routers.keys().copied().collect();
+        ids.sort();
+        for id in ids {
+            net.schedule_update_router(id)?;
+        }
+        if !net.do_queue()? {
+            return Err(NetworkError::MaxIterExceeded);
+        }
+        net.net.retain_edges(|g, e| match g.edge_endpoints(e) {
+            Some((a, b)) => keep.contains(&a) == keep.contains(&b),
+            None => true,
+        });
+        net.igp_dirty = true;
+        if !net.write_igp_fw_tables(true)? {
+            return Err(NetworkError::MaxIterExceeded);
+        }
+
+        // remove the other routers
+        net.routers.retain(|id, _| keep.contains(id));
+        net.external_routers.retain(|id, _| keep.contains(id));
+        let removed: Vec<RouterId> = net
+            .net
+            .node_indices()
+            .filter(|node| !keep.contains(node))
+            .collect();
+        for node in removed {
+            net.net.remove_node(node);
+        }
+        net.failed_links
+            .retain(|(a, b), _| keep.contains(a) && keep.contains(b));
+        net.non_igp_links
+            .retain(|(a, b)| keep.contains(a) && keep.contains(b));
+        net.bgp_shutdown.retain(|router, _| keep.contains(router));
+        for sessions in net.bgp_shutdown.values_mut() {
+            sessions.retain(|(peer, _, _)| keep.contains(peer));
+        }
+        net.queue
+            .retain(|Event::Bgp(from, to, _)| keep.contains(from) && keep.contains(to));
+        net.igp_dirty = true;
+        net.write_igp_fw_tables(false)?;
+        Ok(net)
+    }
+
+    /// # Validate the configuration
+    ///
+    /// Check that the configuration is coherent, before running any simulation. All BGP sessions
+    /// must be configured on both ends, with compatible types (an iBGP client session on one end,
+    /// and a regular iBGP session on the other, or regular iBGP sessions on both ends). eBGP
+    /// sessions are only allowed between internal and external routers. Each external router must
+    /// have at least one neighbor, and the topology must be connected. All inconsistencies are
+    /// returned, sorted by the router ids.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors: Vec<ConfigError> = Vec::new();
+
+        let mut routers: Vec<&Router> = self.routers.values().collect();
+        routers.sort_by_key(|r| r.router_id());
+        for r in routers {
+            let id = r.router_id();
+            let mut sessions = r.get_bgp_sessions();
+            sessions.sort_by_key(|(peer, _)| *peer);
+            for (peer, session_type) in sessions {
+                if let Some(ext) = self.external_routers.get(&peer) {
+                    if !ext.neighbors.contains(&id) {
+                        errors.push(ConfigError::OneSidedSession(id, peer));
+                    }
+                    continue;
+                }
+                let peer_router = match self.routers.get(&peer) {
+                    Some(peer_router) => peer_router,
+                    None => continue,
+                };
+                if session_type.is_ebgp() {
+                    if id < peer {
+                        errors.push(ConfigError::InternalEbgpSession(id, peer));
+                    }
+                    continue;
+                }
+                match (session_type, peer_router.get_bgp_session_type(id)) {
+                    (_, Err(_)) => errors.push(ConfigError::OneSidedSession(id, peer)),
+                    (BgpSessionType::IBgpClient, Ok(BgpSessionType::IBgpPeer))
+                    | (BgpSessionType::IBgpPeer, Ok(BgpSessionType::IBgpClient))
+                    | (BgpSessionType::IBgpPeer, Ok(BgpSessionType::IBgpPeer)) => {}
+                    (_, Ok(_)) if id < peer => {
+                        errors.push(ConfigError::IncompatibleSessionTypes(id, peer))
+                    }
+                    (_, Ok(_)) => {}
+                }
+            }
+        }
+
+        let mut externals: Vec<&ExternalRouter> = self.external_routers.values().collect();
+        externals.sort_by_key(|r| r.router_id());
+        for ext in externals {
+            let id = ext.router_id();
+            if ext.neighbors.is_empty() {
+                errors.push(ConfigError::ExternalRouterWithoutNeighbor(id));
+            }
+            let mut neighbors: Vec<RouterId> = ext.neighbors.iter().copied().collect();
+            neighbors.sort();
+            for neighbor in neighbors {
+                let established = self
+                    .routers
+                    .get(&neighbor)
+                    .map(|r| r.get_bgp_session_type(id).is_ok())
+                    .unwrap_or(false);
+                if !established {
+                    errors.push(ConfigError::OneSidedSession(id, neighbor));
+                }
+            }
+        }
+
+        // all nodes must be reachable from the first one, ignoring the direction of the links
+        if let Some(first) = self.net.node_indices().min() {
+            let mut reached: HashSet<RouterId> = HashSet::new();
+            reached.insert(first);
+            let mut stack = vec![first];
+            while let Some(node) = stack.pop() {
+                for neighbor in self.net.neighbors_undirected(node) {
+                    if reached.insert(neighbor) {
+                        stack.push(neighbor);
+                    }
+                }
+            }
+            let mut disconnected: Vec<RouterId> = self
+                .net
+                .node_indices()
+                .filter(|node| !reached.contains(node))
+                .collect();
+            disconnected.sort();
+            errors.extend(disconnected.into_iter().map(ConfigError::Disconnected));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Returns the share of the traffic for the prefix, starting at `source`, which leaves the
+    /// network at each external router. Unlike `get_route`, all next hops are followed, and the
+    /// traffic is split at each router according to `Router::get_traffic_split`. If any part of
+    /// the traffic is dropped or caught in a forwarding loop, an error is returned.
+    pub fn get_traffic_split(
+        &self,
+        source: RouterId,
+        prefix: Prefix,
+    ) -> Result<HashMap<RouterId, f64>, NetworkError> {
+        if self.external_routers.contains_key(&source) {
+            return Err(NetworkError::DeviceIsExternalRouter(source));
+        }
+        let mut path: Vec<RouterId> = Vec::new();
+        let mut loads: HashMap<(RouterId, RouterId), f64> = HashMap::new();
+        self.traffic_split_recursive(source, prefix, None, 1.0, &mut path, &mut loads)?;
+        // the traffic leaves the network on the links towards external routers
+        let mut result: HashMap<RouterId, f64> = HashMap::new();
+        for ((_, target), load) in loads {
+            if self.external_routers.contains_key(&target) {
+                *result.entry(target).or_default() += load;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the load on each directed link, caused by the traffic `demands`. Each demand
+    /// `(source, prefix, amount)` sends `amount` of traffic from the internal router `source`
+    /// towards the prefix. The traffic is forwarded like in `get_traffic_split`, and split among
+    /// all next hops with BGP multipath. Links without any load are not part of the result. If
+    /// any part of a demand is dropped or caught in a forwarding loop, an error is returned.
+    pub fn compute_link_loads(
+        &self,
+        demands: &[(RouterId, Prefix, f64)],
+    ) -> Result<HashMap<(RouterId, RouterId), f64>, NetworkError> {
+        let mut loads: HashMap<(RouterId, RouterId), f64> = HashMap::new();
+        for (source, prefix, amount) in demands.iter() {
+            if self.external_routers.contains_key(source) {
+                return Err(NetworkError::DeviceIsExternalRouter(*source));
+            }
+            let mut path: Vec<RouterId> = Vec::new();
+            self.traffic_split_recursive(*source, *prefix, None, *amount, &mut path, &mut loads)?;
+        }
+        Ok(loads)
+    }
+
+    /// Returns all internal routers, sorted by their ID, whose traffic for the prefix leaves the
+    /// network via the `external` router, i.e., the route (see `get_route`) ends at `external`.
+    /// Routers whose traffic is dropped or caught in a forwarding loop are not returned.
+    pub fn ingress_routers_for_egress(
+        &self,
+        external: RouterId,
+        prefix: Prefix,
+    ) -> Result<Vec<RouterId>, NetworkError> {
+        if self.routers.contains_key(&external) {
+            return Err(NetworkError::DeviceIsInternalRouter(external));
+        }
+        if !self.external_routers.contains_key(&external) {
+            return Err(NetworkError::DeviceNotFound(external));
+        }
+        let mut result: Vec<RouterId> = self
+            .routers
+            .keys()
+            .filter(|r| {
+                self.get_route(**r, prefix)
+                    .map(|path| path.last() == Some(&external))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect();
+        result.sort();
+        Ok(result)
+    }
+
+    /// Predict the set of internal routers which may receive any BGP update for the prefix when
+    /// the external router `source` advertises, changes or withdraws its route, without executing
+    /// any event. The prediction walks the BGP sessions, starting at the neighbors of `source`,
+    /// following the export rules of route reflection: A router which learns the route from an
+    /// eBGP peer or from a client sends updates to all of its peers, while a router which learns
+    /// it from a non-client only sends updates to its clients and eBGP peers. However, a router
+    /// may also select a different route as a result, and send updates for that route. Therefore,
+    /// routers which know any route from an eBGP peer or a client, or which conditionally
+    /// advertise a prefix based on `prefix`, are treated as if they send updates to all peers.
+    /// Export policies are ignored, such that the result contains at least all routers which
+    /// actually receive an update.
+    pub fn predict_affected_routers(
+        &self,
+        source: RouterId,
+        prefix: Prefix,
+    ) -> Result<HashSet<RouterId>, NetworkError> {
+        let external = match self.external_routers.get(&source) {
+            Some(r) => r,
+            None if self.routers.contains_key(&source) => {
+                return Err(NetworkError::DeviceIsInternalRouter(source))
+            }
+            None => return Err(NetworkError::DeviceNotFound(source)),
+        };
+
+        // routers which send updates to all of their peers, and routers which only send updates
+        // to their clients and eBGP peers.
+        let mut export_all: HashSet<RouterId> = HashSet::new();
+        let mut affected: HashSet<RouterId> = HashSet::new();
+        let mut stack: Vec<(RouterId, BgpSessionType)> = external
+            .neighbors
+            .iter()
+            .filter(|r| self.routers.contains_key(r))
+            .map(|r| (*r, BgpSessionType::EBgp))
+            .collect();
+
+        while let Some((id, from_type)) = stack.pop() {
+            let r = self
+                .routers
+                .get(&id)
+                .ok_or(NetworkError::DeviceNotFound(id))?;
+            let all = from_type != BgpSessionType::IBgpPeer
+                || r.has_conditional_advertise(prefix)
+                || r.get_known_bgp_routes(prefix)?
+                    .iter()
+                    .any(|e| e.from_type != BgpSessionType::IBgpPeer);
+            // skip the router if it was already visited with the same or a stronger export
+            if export_all.contains(&id) || (!all && !affected.insert(id)) {
+                continue;
+            }
+            affected.insert(id);
+            if all {
+                export_all.insert(id);
+            }
+            for (peer, session_type) in r.get_bgp_sessions() {
+                if !all && session_type == BgpSessionType::IBgpPeer {
+                    continue;
+                }
+                if let Some(p) = self.routers.get(&peer) {
+                    stack.push((peer, p.get_bgp_session_type(id)?));
+                }
+            }
+        }
+        Ok(affected)
+    }
+
+    /// return the path taken by the traffic coming back from the destination of `source` for the
+    /// given prefix. The returning traffic starts at the external router, where the traffic from
+    /// `source` leaves the network (see `get_route`), enters the network at the same egress router,
+    /// and follows the IGP path back to `source`. Since links can be asymmetric, this is not
+    /// necessarily the reverse of the forward path. If the prefix is originated by an internal
+    /// router, the returning traffic starts at the originator.
+    pub fn get_return_path(
+        &self,
+        source: RouterId,
+        prefix: Prefix,
+    ) -> Result<Vec<RouterId>, NetworkError> {
+        let route = self.get_route(source, prefix)?;
+        match route.as_slice() {
+            // the route ends at the external router, and the router before is the egress router.
+            [.., egress, external] if self.external_routers.contains_key(external) => {
+                let mut result = vec![*external];
+                result.extend(self.igp_path(*egress, source)?);
+                Ok(result)
+            }
+            // the prefix is originated by the internal router at the end of the route.
+            [.., originator] if self.routers.contains_key(originator) => {
+                self.igp_path(*originator, source)
+            }
+            _ => Err(NetworkError::DeviceIsExternalRouter(source)),
+        }
+    }
+
+    /// return the shortest IGP path from the internal router `source` to `target`, reconstructed
+    /// from the IGP forwarding tables of the routers along the path. The path starts at `source`
+    /// and ends at `target`.
+    pub fn igp_path(
+        &self,
+        source: RouterId,
+        target: RouterId,
+    ) -> Result<Vec<RouterId>, NetworkError> {
+        let mut visited_routers: HashSet<RouterId> = HashSet::new();
+        let mut result: Vec<RouterId> = Vec::new();
+        let mut current_node = source;
+        loop {
+            result.push(current_node);
+            if current_node == target {
+                break;
+            }
+            let r = match self.routers.get(&current_node) {
+                Some(r) => r,
+                None if self.external_routers.contains_key(&current_node) => {
+                    return Err(NetworkError::DeviceIsExternalRouter(current_node))
+                }
+                None => return Err(NetworkError::DeviceNotFound(current_node)),
+            };
+            if !visited_routers.insert(current_node) {
+                return Err(NetworkError::ForwardingLoop(
+                    result
+                        .iter()
+                        .map(|r| self.get_router_name(*r).unwrap())
+                        .collect(),
+                ));
+            }
+            current_node = match r.igp_forwarding_table.get(&target) {
+                Some(Some((next_hop, _))) => *next_hop,
+                _ => {
+                    return Err(NetworkError::ForwardingBlackHole(
+                        result
+                            .iter()
+                            .map(|r| self.get_router_name(*r).unwrap())
+                            .collect(),
+                        NextHop::Unreachable(target),
+                    ))
+                }
+            };
+        }
+        Ok(result)
+    }
+
     /// Print the route of a routerID to the destination
     pub fn print_route(&self, source: RouterId, prefix: Prefix) -> Result<(), NetworkError> {
         match self.get_route(source, prefix) {
@@ -417,56 +2254,433 @@ impl Network {
                 print!("{}", path.join(" => "));
                 println!(" FORWARDING LOOP!");
             }
-            Err(NetworkError::ForwardingBlackHole(path)) => {
-                print!("{}", path.join(" => "));
-                println!(" BLACK HOLE!");
+            Err(NetworkError::ForwardingBlackHole(path, reason)) => {
+                print!("{}", path.join(" => "));
+                match reason {
+                    NextHop::Unreachable(next_hop) => println!(
+                        " BLACK HOLE! (next hop {} is unreachable)",
+                        self.get_router_name(next_hop)?
+                    ),
+                    _ => println!(" BLACK HOLE! (no route)"),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// print the selected egress hop for a BGP origin at a router
+    pub fn print_egress_hop(&self, source: RouterId, prefix: Prefix) -> Result<(), NetworkError> {
+        let r = self
+            .routers
+            .get(&source)
+            .ok_or(NetworkError::DeviceNotFound(source))?;
+        println!(
+            "{} has chosen {} for {:?}",
+            r.name(),
+            r.get_selected_bgp_route(prefix)
+                .map(|e| self.get_router_name(e.route.next_hop))
+                .unwrap_or(Ok("None"))?,
+            prefix
+        );
+        Ok(())
+    }
+
+    /// Get the bgp table (known and chosen routes) of a router for a given prefix. Each known
+    /// route is flagged if it is selected. If the selected route is not among the known routes,
+    /// the table is invalid, and the selected route is stored separately.
+    pub fn get_bgp_table(
+        &self,
+        source: RouterId,
+        prefix: Prefix,
+    ) -> Result<BgpTableDump, NetworkError> {
+        let r = self
+            .routers
+            .get(&source)
+            .ok_or(NetworkError::DeviceNotFound(source))?;
+        let selected_entry = r.get_selected_bgp_route(prefix);
+        let mut found = false;
+        let mut entries: Vec<(RIBEntry, bool)> = Vec::new();
+        for entry in r.get_known_bgp_routes(prefix)? {
+            let selected = selected_entry.as_ref() == Some(&entry);
+            found |= selected;
+            entries.push((entry, selected));
+        }
+        Ok(BgpTableDump {
+            entries,
+            invalid_selected: if found { None } else { selected_entry },
+        })
+    }
+
+    /// Returns the AS path of the route selected by `source` for the prefix, or `None` if the
+    /// router does not exist, or if it has not selected any route.
+    pub fn get_selected_as_path(&self, source: RouterId, prefix: Prefix) -> Option<Vec<AsId>> {
+        self.routers
+            .get(&source)?
+            .get_selected_bgp_route(prefix)
+            .map(|entry| entry.route.as_path)
+    }
+
+    /// Compare the forwarding state for the prefix with the one of the `other` network, and return
+    /// all internal routers whose next hop is different, sorted by their ID. Routers which only
+    /// exist in one of the two networks are reported as added or removed.
+    pub fn diff_forwarding(
+        &self,
+        other: &Network,
+        prefix: Prefix,
+    ) -> Vec<(RouterId, ForwardingChange)> {
+        let mut routers: Vec<RouterId> = self
+            .routers
+            .keys()
+            .chain(other.routers.keys())
+            .cloned()
+            .collect::<HashSet<RouterId>>()
+            .into_iter()
+            .collect();
+        routers.sort();
+        routers
+            .into_iter()
+            .filter_map(|router| {
+                let change = match (self.routers.get(&router), other.routers.get(&router)) {
+                    (Some(r), Some(o)) => {
+                        let (old, new) = (r.get_next_hop(prefix), o.get_next_hop(prefix));
+                        if old == new {
+                            return None;
+                        }
+                        ForwardingChange::Changed(old, new)
+                    }
+                    (Some(r), None) => ForwardingChange::Removed(r.get_next_hop(prefix)),
+                    (None, Some(o)) => ForwardingChange::Added(o.get_next_hop(prefix)),
+                    (None, None) => return None,
+                };
+                Some((router, change))
+            })
+            .collect()
+    }
+
+    /// Explain the route selection of a router for a prefix. For each known route which is not
+    /// selected (rival), the step of the decision process is returned, which decided that the
+    /// selected route is preferred.
+    pub fn explain_selection(
+        &self,
+        source: RouterId,
+        prefix: Prefix,
+    ) -> Result<SelectionExplanation, NetworkError> {
+        let r = self
+            .routers
+            .get(&source)
+            .ok_or(NetworkError::DeviceNotFound(source))?;
+        let selected = r.get_selected_bgp_route(prefix);
+        let rivals = match selected.as_ref() {
+            Some(selected) => r
+                .get_known_bgp_routes(prefix)?
+                .into_iter()
+                .filter(|entry| entry != selected)
+                .map(|entry| {
+                    let step = r.compare_routes(selected, &entry).1;
+                    (entry, step)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(SelectionExplanation { selected, rivals })
+    }
+
+    /// Check that the selected route of a router for the given prefix can be reconstructed from
+    /// the known routes (`bgp_rib_in`). If not, `NetworkError::RibInconsistency` is returned.
+    pub fn validate_rib(&self, source: RouterId, prefix: Prefix) -> Result<(), NetworkError> {
+        if self.get_bgp_table(source, prefix)?.is_valid() {
+            Ok(())
+        } else {
+            Err(NetworkError::RibInconsistency(source, prefix))
+        }
+    }
+
+    /// # Check iBGP correctness
+    ///
+    /// Compare the egress selected by each internal router with the egress it would choose if it
+    /// knew all routes for the prefix learned via eBGP by any router in the network. The routes are
+    /// ranked with the decision process of the router being checked (see `Router::compare_routes`),
+    /// as if they were received over iBGP from the border router, with the IGP cost of the router
+    /// to the egress (hot-potato routing). A violation is reported for each router whose selected
+    /// route is worse than the optimal one, which is caused by the route dissemination of iBGP.
+    /// Routes which only differ in the final tiebreak are equally good. The violations are sorted
+    /// by the router id. P routers and VPN routes are not checked.
+    pub fn check_ibgp_correctness(&self, prefix: Prefix) -> Result<Vec<Violation>, NetworkError> {
+        // all routes learned via eBGP, with the (processed) attributes of the border router
+        let mut egresses: Vec<RIBEntry> = Vec::new();
+        for r in self.routers.values() {
+            for entry in r.get_known_bgp_routes(prefix)? {
+                if entry.from_type.is_ebgp() && entry.route.route_targets.is_empty() {
+                    egresses.push(RIBEntry {
+                        route: entry.route.clone_default(),
+                        from_type: entry.from_type,
+                        from_id: r.router_id(),
+                        igp_cost: None,
+                    });
+                }
+            }
+        }
+
+        let mut violations: Vec<Violation> = Vec::new();
+        for r in self.routers.values().filter(|r| !r.is_p_router()) {
+            // the route as the router would know it, if it learned it from the border router
+            let candidates: Vec<RIBEntry> = egresses
+                .iter()
+                .filter_map(|egress| {
+                    let mut entry = egress.clone();
+                    if entry.from_id != r.router_id() {
+                        let cost = r
+                            .igp_forwarding_table
+                            .get(&entry.route.next_hop)
+                            .and_then(|entry| *entry)
+                            .map(|(_, cost)| cost)?;
+                        entry.from_type = BgpSessionType::IBgpPeer;
+                        entry.igp_cost = Some(cost);
+                        entry.route.aigp = entry.route.aigp.map(|aigp| aigp + cost.round() as u64);
+                    } else {
+                        entry.igp_cost = Some(0.0);
+                    }
+                    Some(entry)
+                })
+                .collect();
+            let optimal = match candidates.iter().max_by(|a, b| r.compare_routes(a, b).0) {
+                Some(optimal) => optimal,
+                None => continue,
+            };
+            let selected = r.get_selected_bgp_route(prefix);
+            let worse = match selected.as_ref() {
+                Some(selected) => matches!(
+                    r.compare_routes(selected, optimal),
+                    (Ordering::Less, step) if step != DecisionStep::Tiebreak
+                ),
+                None => true,
+            };
+            if worse {
+                violations.push(Violation {
+                    router: r.router_id(),
+                    selected: selected.map(|s| s.route.next_hop),
+                    optimal: optimal.route.next_hop,
+                });
+            }
+        }
+        violations.sort_by_key(|v| v.router);
+        Ok(violations)
+    }
+
+    /// # Compute a reduced set of iBGP sessions
+    ///
+    /// Greedily remove iBGP sessions, and keep each removal only if the network still converges to
+    /// the same forwarding state for all known prefixes, without any additional violation of
+    /// `check_ibgp_correctness`. The sessions are tried in the order of their router ids. The
+    /// network itself is not changed. The result contains all remaining iBGP sessions, in the same
+    /// form as the arguments of `add_ibgp_session`: source, target and whether the source is the
+    /// route reflector of the target. This is a heuristic, and the result is not necessarily the
+    /// smallest possible set.
+    pub fn minimal_ibgp_sessions(&self) -> Result<Vec<(RouterId, RouterId, bool)>, NetworkError> {
+        let prefixes = self.known_prefixes();
+        let state = |net: &Network| -> Result<_, NetworkError> {
+            let mut routers: Vec<RouterId> = net.routers.keys().copied().collect();
+            routers.sort();
+            let mut routes = Vec::new();
+            let mut violations = Vec::new();
+            for prefix in prefixes.iter() {
+                for router in routers.iter() {
+                    routes.push(net.get_route(*router, *prefix));
+                }
+                violations.push(net.check_ibgp_correctness(*prefix)?);
+            }
+            Ok((routes, violations))
+        };
+        let expected = state(self)?;
+
+        let mut sessions = self.get_ibgp_sessions();
+        let mut net = self.clone();
+        let mut idx = 0;
+        while idx < sessions.len() {
+            let (source, target, _) = sessions[idx];
+            let mut reduced = net.clone();
+            if reduced.remove_ibgp_session(source, target, true)? && state(&reduced)? == expected {
+                net = reduced;
+                sessions.remove(idx);
+            } else {
+                idx += 1;
+            }
+        }
+        Ok(sessions)
+    }
+
+    /// Returns all iBGP sessions, sorted by the router ids. Each session contains the source, the
+    /// target and whether the source is the route reflector of the target.
+    fn get_ibgp_sessions(&self) -> Vec<(RouterId, RouterId, bool)> {
+        let mut sessions: Vec<(RouterId, RouterId, bool)> = Vec::new();
+        for r in self.routers.values() {
+            for (peer, session_type) in r.get_bgp_sessions() {
+                match session_type {
+                    BgpSessionType::IBgpClient => sessions.push((r.router_id(), peer, true)),
+                    BgpSessionType::IBgpPeer => {
+                        let peer_is_client = self
+                            .routers
+                            .get(&peer)
+                            .and_then(|p| p.get_bgp_session_type(r.router_id()).ok())
+                            == Some(BgpSessionType::IBgpClient);
+                        if !peer_is_client && r.router_id() < peer {
+                            sessions.push((r.router_id(), peer, false));
+                        }
+                    }
+                    BgpSessionType::EBgp => {}
+                }
+            }
+        }
+        sessions.sort();
+        sessions
+    }
+
+    /// # Describe the network as a scenario
+    ///
+    /// Returns the topology, the sessions, the routes advertised by external routers, and the
+    /// forwarding state of all internal routers for all known prefixes, together with the BGP
+    /// configuration of every internal router. Links keep their `LinkId`, such that parallel links
+    /// survive the round trip. The network can be reconstructed with `from_scenario`.
+    pub fn to_scenario(&self) -> Scenario {
+        let mut ids: Vec<RouterId> = self
+            .routers
+            .keys()
+            .chain(self.external_routers.keys())
+            .copied()
+            .collect();
+        ids.sort();
+        let index = |id: &RouterId| id.index() as u32;
+
+        let mut routers = Vec::new();
+        for id in ids.iter() {
+            let router = match (self.routers.get(id), self.external_routers.get(id)) {
+                (Some(r), _) => ScenarioRouter {
+                    id: index(id),
+                    name: r.name().to_string(),
+                    kind: if r.is_p_router() {
+                        ScenarioRouterKind::PRouter
+                    } else {
+                        ScenarioRouterKind::Internal
+                    },
+                    as_id: r.as_id().0,
+                    config: r.get_scenario_config(),
+                },
+                (None, Some(r)) => ScenarioRouter {
+                    id: index(id),
+                    name: r.name().to_string(),
+                    kind: ScenarioRouterKind::External,
+                    as_id: r.as_id().0,
+                    config: ScenarioRouterConfig::default(),
+                },
+                (None, None) => unreachable!(),
+            };
+            routers.push(router);
+        }
+
+        let mut links: Vec<ScenarioLink> = self
+            .net
+            .edge_indices()
+            .filter_map(|e| {
+                let (a, b) = self.net.edge_endpoints(e)?;
+                Some(ScenarioLink {
+                    id: e.index() as u32,
+                    source: index(&a),
+                    target: index(&b),
+                    weight: *self.net.edge_weight(e)?,
+                    in_igp: !self.non_igp_links.contains(&(a, b)),
+                })
+            })
+            .collect();
+        links.sort_by_key(|link| link.id);
+
+        let ibgp_sessions = self
+            .get_ibgp_sessions()
+            .into_iter()
+            .map(|(a, b, rr)| (index(&a), index(&b), rr))
+            .collect();
+
+        let mut ebgp_sessions = Vec::new();
+        let mut advertisements = Vec::new();
+        for id in ids.iter() {
+            if let Some(r) = self.external_routers.get(id) {
+                let mut neighbors: Vec<RouterId> = r
+                    .neighbors
+                    .iter()
+                    .filter(|n| !self.external_routers.contains_key(n) || id < n)
+                    .copied()
+                    .collect();
+                neighbors.sort();
+                ebgp_sessions.extend(neighbors.iter().map(|n| (index(id), index(n))));
+                let mut routes: Vec<_> = r.advertised_prefixes().values().collect();
+                routes.sort_by_key(|route| route.prefix);
+                advertisements.extend(routes.into_iter().map(|route| ScenarioAdvertisement {
+                    router: index(id),
+                    prefix: route.prefix.0,
+                    as_path: route.as_path.iter().map(|as_id| as_id.0).collect(),
+                    med: route.med,
+                    communities: route.communities.iter().copied().collect(),
+                    origin: route.origin,
+                }));
+            }
+        }
+
+        let mut forwarding = Vec::new();
+        for prefix in self.known_prefixes() {
+            for id in ids.iter().filter(|id| self.routers.contains_key(id)) {
+                forwarding.push(ScenarioRoute {
+                    router: index(id),
+                    prefix: prefix.0,
+                    path: self
+                        .get_route(*id, prefix)
+                        .map(|path| path.iter().map(index).collect())
+                        .map_err(|e| e.to_string()),
+                });
             }
-            Err(e) => return Err(e),
         }
-        Ok(())
+
+        Scenario {
+            routers,
+            links,
+            ibgp_sessions,
+            ebgp_sessions,
+            advertisements,
+            forwarding,
+        }
     }
 
-    /// print the selected egress hop for a BGP origin at a router
-    pub fn print_egress_hop(&self, source: RouterId, prefix: Prefix) -> Result<(), NetworkError> {
-        let r = self
-            .routers
-            .get(&source)
-            .ok_or(NetworkError::DeviceNotFound(source))?;
-        println!(
-            "{} has chosen {} for {:?}",
-            r.name(),
-            r.get_selected_bgp_route(prefix)
-                .map(|e| self.get_router_name(e.route.next_hop))
-                .unwrap_or(Ok("None"))?,
-            prefix
-        );
-        Ok(())
+    /// Describe the network as a scenario in JSON format, see `to_scenario`.
+    pub fn to_scenario_json(&self) -> Result<String, NetworkError> {
+        serde_json::to_string_pretty(&self.to_scenario())
+            .map_err(|e| NetworkError::InvalidScenario(e.to_string()))
+    }
+
+    /// Describe the network as a scenario in a compact binary format, see `to_scenario`. This is
+    /// much smaller and faster than JSON for large topologies.
+    #[cfg(feature = "binary")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, NetworkError> {
+        bincode::serialize(&self.to_scenario())
+            .map_err(|e| NetworkError::InvalidScenario(e.to_string()))
     }
 
     /// print the bgp table (known and chosen routes)
     pub fn print_bgp_table(&self, source: RouterId, prefix: Prefix) -> Result<(), NetworkError> {
-        let r = self
-            .routers
-            .get(&source)
-            .ok_or(NetworkError::DeviceNotFound(source))?;
-        println!("BGP table of {} for {:?}", r.name(), prefix);
-        let selected_entry = r.get_selected_bgp_route(prefix);
-        let mut found = false;
-        for entry in r.get_known_bgp_routes(prefix)? {
-            if selected_entry.as_ref() == Some(&entry) {
-                print!("* ");
-                found = true;
-            } else {
-                print!("  ");
-            }
-            self.print_bgp_entry(&entry)?;
+        let table = self.get_bgp_table(source, prefix)?;
+        println!(
+            "BGP table of {} for {:?}",
+            self.get_router_name(source)?,
+            prefix
+        );
+        for (entry, selected) in table.entries.iter() {
+            print!("{}", if *selected { "* " } else { "  " });
+            self.print_bgp_entry(entry)?;
         }
-        if selected_entry.is_some() && !found {
+        if let Some(selected_entry) = table.invalid_selected.as_ref() {
             println!("E Invalid table!");
             print!("* ");
-            self.print_bgp_entry(&selected_entry.unwrap())?;
+            self.print_bgp_entry(selected_entry)?;
         }
-        println!("");
+        println!();
         Ok(())
     }
 
@@ -517,7 +2731,7 @@ impl Network {
                 println!("  {} unreachable!", self.get_router_name(target)?);
             }
         }
-        println!("");
+        println!();
         Ok(())
     }
 
@@ -532,6 +2746,219 @@ impl Network {
         }
     }
 
+    /// return the AS of the router
+    fn get_as_id(&self, router_id: RouterId) -> Result<AsId, NetworkError> {
+        if let Some(r) = self.routers.get(&router_id) {
+            Ok(r.as_id())
+        } else if let Some(r) = self.external_routers.get(&router_id) {
+            Ok(r.as_id())
+        } else {
+            Err(NetworkError::DeviceNotFound(router_id))
+        }
+    }
+
+    /// Recursively follow all next hops of `current`, and add the `share` of the traffic to the
+    /// load of each traversed link, until it leaves the network at an external router. `path`
+    /// contains the routers visited so far.
+    fn traffic_split_recursive(
+        &self,
+        current: RouterId,
+        prefix: Prefix,
+        bgp_next_hop: Option<RouterId>,
+        share: f64,
+        path: &mut Vec<RouterId>,
+        loads: &mut HashMap<(RouterId, RouterId), f64>,
+    ) -> Result<(), NetworkError> {
+        if self.external_routers.contains_key(&current) {
+            return Ok(());
+        }
+        let r = self
+            .routers
+            .get(&current)
+            .ok_or(NetworkError::DeviceNotFound(current))?;
+        let names = |path: &[RouterId]| {
+            path.iter()
+                .map(|r| self.routers.get(r).unwrap().name())
+                .collect()
+        };
+        path.push(current);
+        if path[..path.len() - 1].contains(&current) {
+            return Err(NetworkError::ForwardingLoop(names(path)));
+        }
+        let (split, bgp_next_hop) = if r.is_p_router() {
+            match bgp_next_hop.map(|target| r.get_igp_next_hop(target)) {
+                Some(NextHop::Via(next_hops)) => (vec![(next_hops[0], 1.0)], bgp_next_hop),
+                _ => (Vec::new(), bgp_next_hop),
+            }
+        } else {
+            (r.get_traffic_split(prefix), r.get_bgp_next_hop(prefix))
+        };
+        if split.is_empty() {
+            let reason = match (r.is_p_router(), bgp_next_hop) {
+                (false, _) => r.get_next_hop(prefix),
+                (true, Some(target)) => NextHop::Unreachable(target),
+                (true, None) => NextHop::NoRoute,
+            };
+            return Err(NetworkError::ForwardingBlackHole(names(path), reason));
+        }
+        for (next_hop, fraction) in split {
+            // the prefix is originated by the router itself
+            if next_hop == current {
+                continue;
+            }
+            *loads.entry((current, next_hop)).or_default() += share * fraction;
+            self.traffic_split_recursive(
+                next_hop,
+                prefix,
+                bgp_next_hop,
+                share * fraction,
+                path,
+                loads,
+            )?;
+        }
+        path.pop();
+        Ok(())
+    }
+
+    /// Execute the queue, but stop after `stop_after` events. The `observer` is called after
+    /// every `every` events.
+    fn run_queue<F>(
+        &mut self,
+        stop_after: Option<usize>,
+        every: usize,
+        mut observer: F,
+    ) -> Result<bool, NetworkError>
+    where
+        F: FnMut(&Network),
+    {
+        let mut remaining_iter = stop_after;
+        self.last_convergence_steps = 0;
+        while !self.queue.is_empty() {
+            if let Some(rem) = remaining_iter {
+                if rem == 0 {
+                    // the remaining events stay in the queue, such that it can be continued later
+                    return Ok(false);
+                }
+                remaining_iter = Some(rem - 1);
+            }
+            self.last_convergence_steps += 1;
+            self.step()?;
+            if self.last_convergence_steps.is_multiple_of(every) {
+                observer(self);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns true if `source` has a BGP session with `target`.
+    /// Check that an eBGP session can be established between source and target. At least one of
+    /// them must be an external router, and an internal router cannot peer with an external
+    /// router of its own AS. Two external routers may be in the same AS.
+    fn check_ebgp_session(&self, source: RouterId, target: RouterId) -> Result<(), NetworkError> {
+        let source_external = self.external_routers.contains_key(&source);
+        let target_external = self.external_routers.contains_key(&target);
+        if !source_external && !target_external {
+            return Err(NetworkError::DeviceIsInternalRouter(target));
+        }
+        let source_as = self.get_as_id(source)?;
+        let target_as = self.get_as_id(target)?;
+        if source_external != target_external && source_as == target_as {
+            return Err(DeviceError::NeighborInSameAs(target).into());
+        }
+        Ok(())
+    }
+
+    fn has_bgp_session(&self, source: RouterId, target: RouterId) -> bool {
+        match (
+            self.routers.get(&source),
+            self.external_routers.get(&source),
+        ) {
+            (Some(r), _) => r.get_bgp_session_type(target).is_ok(),
+            (None, Some(r)) => r.neighbors.contains(&target),
+            (None, None) => false,
+        }
+    }
+
+    /// Returns the graph used for computing the IGP forwarding tables, which contains all links
+    /// except the ones which are not advertised into the IGP (see `set_link_in_igp`).
+    fn igp_graph(&self) -> IgpNetwork {
+        let mut graph = self.net.clone();
+        graph.retain_edges(|g, e| match g.edge_endpoints(e) {
+            Some(link) => !self.non_igp_links.contains(&link),
+            None => true,
+        });
+        graph
+    }
+
+    /// Returns the next hop of the router for the prefix, while following a path. P routers forward
+    /// the traffic towards `bgp_next_hop`, the BGP next hop chosen by the last router on the path
+    /// which runs BGP. For all other routers, `bgp_next_hop` is updated.
+    fn get_next_hop_on_path(
+        &self,
+        r: &Router,
+        prefix: Prefix,
+        bgp_next_hop: &mut Option<RouterId>,
+    ) -> NextHop {
+        if r.is_p_router() {
+            match bgp_next_hop {
+                Some(target) => r.get_igp_next_hop(*target),
+                None => NextHop::NoRoute,
+            }
+        } else {
+            *bgp_next_hop = r.get_bgp_next_hop(prefix);
+            r.get_next_hop(prefix)
+        }
+    }
+
+    /// Execute a single event, and record it if recording is enabled
+    fn execute_event(&mut self, event: Event) -> Result<(), NetworkError> {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(event.clone());
+        }
+        // print the job
+        self.print_event(&event)?;
+        // execute the event
+        let (working_router_id, event_result) = match event {
+            Event::Bgp(from, to, bgp_event) => (
+                to,
+                if let Some(r) = self.routers.get_mut(&to) {
+                    r.handle_event(Event::Bgp(from, to, bgp_event), &mut self.queue)
+                        .map_err(NetworkError::DeviceError)
+                } else if let Some(r) = self.external_routers.get_mut(&to) {
+                    r.handle_event(Event::Bgp(from, to, bgp_event), &mut self.queue)
+                        .map_err(NetworkError::DeviceError)
+                } else {
+                    Err(NetworkError::DeviceNotFound(to))
+                },
+            ),
+        };
+
+        match event_result {
+            Ok(()) => {}
+            Err(NetworkError::DeviceError(DeviceError::NoBgpSession(target))) => {
+                eprintln!(
+                    "No BGP session active between {} and  {}!",
+                    self.get_router_name(working_router_id)?,
+                    self.get_router_name(target)?
+                );
+            }
+            Err(e) => return Err(e),
+        }
+
+        // record the propagation frontier
+        if let Some((prefix, snapshots)) = self.frontier.as_mut() {
+            let prefix = *prefix;
+            snapshots.push(
+                self.routers
+                    .values()
+                    .filter(|r| r.get_selected_bgp_route(prefix).is_some())
+                    .map(|r| r.router_id())
+                    .collect(),
+            );
+        }
+        Ok(())
+    }
+
     fn print_event(&self, event: &Event) -> Result<(), NetworkError> {
         match event {
             Event::Bgp(from, to, BgpEvent::Update(route)) => {
@@ -541,6 +2968,9 @@ impl Network {
                     self.get_router_name(*to)?
                 );
                 println!("    prefix: {}", route.prefix.0);
+                if route.path_id != 0 {
+                    println!("    path_id: {}", route.path_id);
+                }
                 println!("    as_path: {:?}", route.as_path);
                 println!("    next_hop: {}", self.get_router_name(route.next_hop)?);
                 println!("    local_pref: {:?}", route.local_pref);
@@ -556,7 +2986,350 @@ impl Network {
                 println!("    prefix: {}", prefix.0);
                 println!("}}\n");
             }
+            Event::Bgp(from, to, BgpEvent::WithdrawPath(prefix, path_id)) => {
+                println!(
+                    "BGP Widthdraw: {} => {} {{",
+                    self.get_router_name(*from)?,
+                    self.get_router_name(*to)?
+                );
+                println!("    prefix: {}", prefix.0);
+                println!("    path_id: {}", path_id);
+                println!("}}\n");
+            }
+            Event::Bgp(from, to, BgpEvent::Notification) => {
+                println!(
+                    "BGP Notification: {} => {}\n",
+                    self.get_router_name(*from)?,
+                    self.get_router_name(*to)?
+                );
+            }
         }
         Ok(())
     }
 }
+
+/// Configuration change of the BGP sessions or the link weights in the network, used for finding a
+/// safe reconfiguration order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigChange {
+    /// Add an iBGP session from `#0` to `#1`. If `#2` is true, `#0` is the route reflector of
+    /// `#1`. See `Network::add_ibgp_session`.
+    AddIbgpSession(RouterId, RouterId, bool),
+    /// Remove the iBGP session between `#0` and `#1`. See `Network::remove_ibgp_session`.
+    RemoveIbgpSession(RouterId, RouterId),
+    /// Change the type of the iBGP session between `#0` and `#1`. If `#2` is true, `#0` becomes
+    /// the route reflector of `#1`. See `Network::change_ibgp_session_type`.
+    ChangeIbgpSessionType(RouterId, RouterId, bool),
+    /// Set the weight of the directed link from `#0` to `#1` to `#2`. Unlike
+    /// `Network::update_edge_weight`, the reverse direction is not changed.
+    UpdateLinkWeight(RouterId, RouterId, LinkWeight),
+}
+
+/// Configuration of the iBGP sessions and the link weights of the network, see
+/// `Network::get_config` and `Network::plan_migration`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkConfig {
+    /// iBGP sessions, mapping the two routers to true if the first one is the route reflector of
+    /// the second one. For sessions between peers, the router with the smaller id comes first.
+    pub ibgp_sessions: HashMap<(RouterId, RouterId), bool>,
+    /// Weights of the directed links.
+    pub link_weights: HashMap<(RouterId, RouterId), LinkWeight>,
+}
+
+impl NetworkConfig {
+    /// Returns the configuration changes which transform `self` into `target`, sorted by the
+    /// routers involved. Sessions are removed, added, or changed in their type, and link weights
+    /// are updated. Links which do not exist in `self` are ignored, since they cannot be created
+    /// by a `ConfigChange`.
+    pub fn changes_to(&self, target: &NetworkConfig) -> Vec<ConfigChange> {
+        // map each session to the unordered pair of routers, and the route reflector (if any)
+        let normalize =
+            |config: &NetworkConfig| -> BTreeMap<(RouterId, RouterId), Option<RouterId>> {
+                config
+                    .ibgp_sessions
+                    .iter()
+                    .map(|((a, b), rr)| ((*a.min(b), *a.max(b)), if *rr { Some(*a) } else { None }))
+                    .collect()
+            };
+        let current = normalize(self);
+        let next = normalize(target);
+        let session_change = |(a, b): (RouterId, RouterId), rr: Option<RouterId>, add: bool| {
+            let (source, target, route_reflector) = match rr {
+                Some(rr) if rr == b => (b, a, true),
+                Some(_) => (a, b, true),
+                None => (a, b, false),
+            };
+            if add {
+                ConfigChange::AddIbgpSession(source, target, route_reflector)
+            } else {
+                ConfigChange::ChangeIbgpSessionType(source, target, route_reflector)
+            }
+        };
+
+        let mut changes = Vec::new();
+        for (pair, rr) in current.iter() {
+            match next.get(pair) {
+                None => changes.push(ConfigChange::RemoveIbgpSession(pair.0, pair.1)),
+                Some(new_rr) if new_rr != rr => changes.push(session_change(*pair, *new_rr, false)),
+                Some(_) => {}
+            }
+        }
+        for (pair, rr) in next.iter() {
+            if !current.contains_key(pair) {
+                changes.push(session_change(*pair, *rr, true));
+            }
+        }
+        let mut links: Vec<_> = target
+            .link_weights
+            .iter()
+            .filter(|(link, weight)| matches!(self.link_weights.get(link), Some(w) if w != *weight))
+            .collect();
+        links.sort_by_key(|(link, _)| **link);
+        changes.extend(
+            links
+                .into_iter()
+                .map(|((a, b), weight)| ConfigChange::UpdateLinkWeight(*a, *b, *weight)),
+        );
+        changes
+    }
+}
+
+/// Order of configuration changes, which migrates the network from one configuration to another
+/// without any forwarding loop or new black hole, see `Network::plan_migration`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationPlan {
+    /// The changes, in the order in which they are applied.
+    pub changes: Vec<ConfigChange>,
+}
+
+impl MigrationPlan {
+    /// Apply the changes one by one on the network, and let it converge after each change. After
+    /// each step, the forwarding state is checked to be free of forwarding loops and new black
+    /// holes. If a step does not converge or violates this, the migration is aborted, and
+    /// `NetworkError::UnsafeMigrationStep` with the index of the step is returned. Changes done
+    /// before are not undone.
+    pub fn execute(&self, net: &mut Network) -> Result<(), NetworkError> {
+        for (i, change) in self.changes.iter().enumerate() {
+            let black_holes = net.forwarding_black_holes();
+            if !net.apply_config_change(*change, true)? || !net.is_forwarding_safe(&black_holes) {
+                return Err(NetworkError::UnsafeMigrationStep(i));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Batch of changes applied to the network, created with `Network::transaction`. None of the
+/// methods update the network. Instead, the routers affected by the changes are remembered, and
+/// updated once the transaction is done.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    net: &'a mut Network,
+    touched: Vec<RouterId>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Create an edge, see `Network::add_edge`
+    pub fn add_edge(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        weight: LinkWeight,
+        rev_w: Option<LinkWeight>,
+    ) -> Result<(), NetworkError> {
+        self.net.add_edge(source, target, weight, rev_w)
+    }
+
+    /// Update the weight of an edge, see `Network::update_edge_weight`. Call
+    /// `write_igp_fw_tables` for the change to take effect.
+    pub fn update_edge_weight(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        weight: LinkWeight,
+        rev_w: Option<LinkWeight>,
+    ) {
+        self.net.update_edge_weight(source, target, weight, rev_w)
+    }
+
+    /// Add an iBGP session, see `Network::add_ibgp_session`
+    pub fn add_ibgp_session(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        route_reflector: bool,
+    ) -> Result<(), NetworkError> {
+        self.net
+            .add_ibgp_session(source, target, route_reflector, false)?;
+        self.touch(source);
+        self.touch(target);
+        Ok(())
+    }
+
+    /// Remove an iBGP session, see `Network::remove_ibgp_session`
+    pub fn remove_ibgp_session(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+    ) -> Result<(), NetworkError> {
+        self.net.remove_ibgp_session(source, target, false)?;
+        self.touch(source);
+        self.touch(target);
+        Ok(())
+    }
+
+    /// Change the type of an iBGP session, see `Network::change_ibgp_session_type`
+    pub fn change_ibgp_session_type(
+        &mut self,
+        source: RouterId,
+        target: RouterId,
+        route_reflector: bool,
+    ) -> Result<(), NetworkError> {
+        self.net
+            .change_ibgp_session_type(source, target, route_reflector, false)?;
+        self.touch(source);
+        self.touch(target);
+        Ok(())
+    }
+
+    /// Write the igp forwarding tables of all internal routers, see
+    /// `Network::write_igp_fw_tables`
+    pub fn write_igp_fw_tables(&mut self) -> Result<(), NetworkError> {
+        self.net.write_igp_fw_tables(false)?;
+        let routers: Vec<RouterId> = self.net.routers.keys().cloned().collect();
+        for router in routers {
+            self.touch(router);
+        }
+        Ok(())
+    }
+
+    /// Advertise an external route, see `Network::advertise_external_route`
+    pub fn advertise_external_route(
+        &mut self,
+        source: RouterId,
+        prefix: Prefix,
+        as_path: Vec<AsId>,
+        med: Option<u32>,
+    ) -> Result<(), NetworkError> {
+        self.net
+            .advertise_external_route(source, prefix, as_path, med, false)?;
+        Ok(())
+    }
+
+    /// Advertise an external route with additional path attributes, see
+    /// `Network::advertise_external_route_with`
+    pub fn advertise_external_route_with(
+        &mut self,
+        source: RouterId,
+        prefix: Prefix,
+        as_path: Vec<AsId>,
+        med: Option<u32>,
+        attrs: ExternalRouteAttrs,
+    ) -> Result<(), NetworkError> {
+        self.net
+            .advertise_external_route_with(source, prefix, as_path, med, attrs, false)?;
+        Ok(())
+    }
+
+    /// Retract an external route, see `Network::retract_external_route`
+    pub fn retract_external_route(
+        &mut self,
+        source: RouterId,
+        prefix: Prefix,
+    ) -> Result<(), NetworkError> {
+        self.net.retract_external_route(source, prefix, false)?;
+        Ok(())
+    }
+
+    /// Remember that the router needs to be updated at the end of the transaction
+    fn touch(&mut self, router: RouterId) {
+        if !self.touched.contains(&router) {
+            self.touched.push(router);
+        }
+    }
+}
+
+/// Change of the selected route of a router while tracing the propagation of a route, see
+/// `Network::trace_route_propagation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PropagationStep {
+    /// The router whose selected route changed
+    pub router: RouterId,
+    /// The neighbor which sent the update causing the change, i.e., the parent in the
+    /// propagation tree
+    pub parent: RouterId,
+    /// Depth of the router in the propagation tree
+    pub depth: usize,
+}
+
+/// BGP table of a router for a single prefix, containing all known and the selected route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BgpTableDump {
+    /// All known routes (processed routes of `bgp_rib_in`), together with a flag telling if the
+    /// route is selected.
+    pub entries: Vec<(RIBEntry, bool)>,
+    /// If the selected route is not among the known routes, the table is invalid, and the
+    /// selected route is stored here.
+    pub invalid_selected: Option<RIBEntry>,
+}
+
+impl BgpTableDump {
+    /// Returns the selected route, or None if no route is selected.
+    pub fn selected(&self) -> Option<&RIBEntry> {
+        self.entries
+            .iter()
+            .find(|(_, selected)| *selected)
+            .map(|(entry, _)| entry)
+            .or(self.invalid_selected.as_ref())
+    }
+
+    /// Returns true if the selected route is among the known routes, or if no route is selected.
+    pub fn is_valid(&self) -> bool {
+        self.invalid_selected.is_none()
+    }
+}
+
+/// Explanation of the route selection of a router for a single prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectionExplanation {
+    /// The selected route, or None if no route is selected.
+    pub selected: Option<RIBEntry>,
+    /// All known routes which are not selected, together with the step of the decision process,
+    /// which decided that the selected route is preferred over it.
+    pub rivals: Vec<(RIBEntry, DecisionStep)>,
+}
+
+impl SelectionExplanation {
+    /// Returns the step of the decision process which decided against the route received from
+    /// `neighbor`, or None if no such rival exists.
+    pub fn deciding_step(&self, neighbor: RouterId) -> Option<DecisionStep> {
+        self.rivals
+            .iter()
+            .find(|(entry, _)| entry.from_id == neighbor)
+            .map(|(_, step)| *step)
+    }
+}
+
+/// Router which does not select the optimal egress for a prefix, see
+/// `Network::check_ibgp_correctness`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The router which selects a suboptimal route
+    pub router: RouterId,
+    /// The BGP next hop of the selected route, or None if no route is selected
+    pub selected: Option<RouterId>,
+    /// The BGP next hop of the optimal route
+    pub optimal: RouterId,
+}
+
+/// Change of the forwarding state of a router between two networks, see
+/// `Network::diff_forwarding`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardingChange {
+    /// The router only exists in the other network, where it has the next hop `#0`.
+    Added(NextHop),
+    /// The router does not exist in the other network. It had the next hop `#0`.
+    Removed(NextHop),
+    /// The next hop changed from `#0` to `#1` in the other network.
+    Changed(NextHop, NextHop),
+}
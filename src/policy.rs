@@ -0,0 +1,61 @@
+//! Module containing shared BGP policy objects
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+
+/// Peer group, bundling BGP policies which are shared by multiple sessions of a router. Each
+/// session can be member of at most one peer group. Changing the peer group changes the policy
+/// of all members at once.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerGroup {
+    /// local_pref of routes received over eBGP from a member of the group. Configuration of
+    /// `policy_bgp_local_pref` for a specific neighbor takes precedence over the peer group.
+    pub local_pref: Option<u32>,
+    /// Routes learned from members of any of these peer groups are not exported to members of
+    /// this group. This way, business relationships can be implemented for a whole group of
+    /// neighbors, like prohibiting routes from a provider to be exported to a different provider.
+    pub no_export_from: HashSet<&'static str>,
+}
+
+impl PeerGroup {
+    /// Create a new, empty peer group.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// VRF (virtual routing and forwarding instance) of a router for L3VPN (RFC 4364). The VRF
+/// imports all VPN routes carrying any of its import route targets (see `BgpRoute::route_targets`),
+/// and routes originated in the VRF are tagged with its export route targets.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Vrf {
+    /// Route targets of the VPN routes imported into the VRF
+    pub import_rts: BTreeSet<(u32, u32)>,
+    /// Route targets attached to routes originated in the VRF
+    pub export_rts: BTreeSet<(u32, u32)>,
+}
+
+impl Vrf {
+    /// Create a new VRF, importing and exporting the given route targets.
+    pub fn new(import_rts: BTreeSet<(u32, u32)>, export_rts: BTreeSet<(u32, u32)>) -> Self {
+        Self {
+            import_rts,
+            export_rts,
+        }
+    }
+
+    /// Returns true if the VRF imports a route carrying the given route targets.
+    pub fn imports(&self, route_targets: &BTreeSet<(u32, u32)>) -> bool {
+        !self.import_rts.is_disjoint(route_targets)
+    }
+}
+
+/// Condition for conditional route advertisement (advertise-map), telling if a route is only
+/// advertised while the condition prefix exists, or while it does not exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConditionMode {
+    /// Only advertise the route if the condition prefix is present in the RIB (exist-map)
+    Exist,
+    /// Only advertise the route if the condition prefix is absent from the RIB (non-exist-map)
+    NonExist,
+}
@@ -1,13 +1,22 @@
 //! Module defining an internal router with BGP functionality.
 
-use crate::bgp::{BgpEvent, BgpRoute, BgpSessionType};
+use crate::bgp::{
+    ingress_community, BgpEvent, BgpRoute, BgpSessionType, PathId, RpkiValidation,
+    COMMUNITY_GRACEFUL_SHUTDOWN, COMMUNITY_NO_ADVERTISE, COMMUNITY_NO_EXPORT,
+};
+use crate::policy::{ConditionMode, PeerGroup, Vrf};
+use crate::scenario::{
+    ScenarioOriginatedRoute, ScenarioPeerGroup, ScenarioRouterConfig, ScenarioVrf,
+};
 use crate::{AsId, DeviceError, IgpNetwork, LinkWeight, NetworkDevice, Prefix, RouterId};
 use crate::{Event, EventQueue};
 use petgraph::algo::{bellman_ford, FloatMeasure};
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-#[derive(Debug)]
+/// Internal router running BGP
+#[derive(Debug, Clone)]
 pub struct Router {
     /// Name of the router
     name: &'static str,
@@ -15,6 +24,9 @@ pub struct Router {
     router_id: RouterId,
     /// AS Id of the router
     as_id: AsId,
+    /// The router is a P router, which only runs the IGP, but not BGP. Traffic is forwarded
+    /// towards the BGP next hop chosen by the previous router running BGP (label switching).
+    p_router: bool,
     /// forwarding table for IGP messages
     pub igp_forwarding_table: HashMap<RouterId, Option<(RouterId, LinkWeight)>>,
     /// Open iBGP connections to peers or other route reflectors
@@ -23,26 +35,118 @@ pub struct Router {
     ibgp_client_sessions: HashSet<RouterId>,
     /// Open eBGP connections
     ebgp_sessions: HashSet<RouterId>,
+    /// AS of the neighbor of each eBGP session.
+    ebgp_neighbor_as: HashMap<RouterId, AsId>,
+    /// BGP configuration to drop all routes received over an eBGP session whose AS path does not
+    /// start with the AS of the neighbor (see `set_ebgp_neighbor_as`).
+    pub enforce_first_as: bool,
+    /// All BGP peers (of any session type), sorted by their router id. This is updated whenever a
+    /// session is established or closed, and determines the order of route dissemination.
+    bgp_peers: Vec<RouterId>,
     /// Table containing all received entries. It is represented as a hashmap, mapping the prefixes
-    /// to another hashmap, which maps the received router id to the entry. This way, we can store
-    /// one entry for every prefix and every session.
-    bgp_rib_in: HashMap<Prefix, HashMap<RouterId, RIBEntry>>,
+    /// to another hashmap, which maps the received router id and path id to the entry. This way,
+    /// we can store one entry for every prefix and every session (or multiple entries, if the
+    /// session uses ADD-PATH).
+    bgp_rib_in: HashMap<Prefix, HashMap<(RouterId, PathId), RIBEntry>>,
     /// Table containing all selected best routes. It is represented as a hashmap, mapping the
-    /// prefixes to the table entry
-    bgp_rib: HashMap<Prefix, RIBEntry>,
+    /// prefixes to the table entries. The first entry is the selected best route. With BGP
+    /// multipath, the remaining entries are the routes which are equally good up to the IGP cost.
+    bgp_rib: HashMap<Prefix, Vec<RIBEntry>>,
+    /// Maximum number of routes installed in `bgp_rib` for each prefix (BGP multipath).
+    bgp_maximum_paths: usize,
+    /// Seed for breaking exact ties in the decision process randomly. If it is not set, ties are
+    /// broken by the lowest router id of the advertiser, next hop and neighbor.
+    pub tiebreak_seed: Option<u64>,
+    /// Order of the steps of the decision process (see `set_decision_steps`).
+    decision_steps: Vec<DecisionStep>,
     /// Table containing all exported routes, represented as a hashmap mapping the neighboring
-    /// RouterId (of a BGP session) to the table entries.
-    bgp_rib_out: HashMap<Prefix, HashMap<RouterId, RIBEntry>>,
+    /// RouterId (of a BGP session) and the path id to the table entries.
+    bgp_rib_out: HashMap<Prefix, HashMap<(RouterId, PathId), RIBEntry>>,
+    /// Number of paths advertised to each peer using ADD-PATH. Peers not in this map only receive
+    /// the best route.
+    bgp_add_path: HashMap<RouterId, usize>,
+    /// Maximum number of prefixes accepted from each peer. If a peer sends more prefixes, the
+    /// session is torn down. Peers not in this map have no limit.
+    bgp_maximum_prefix: HashMap<RouterId, usize>,
+    /// Maximum length of the AS path of routes accepted from each peer. Longer routes are dropped.
+    /// Peers not in this map have no limit.
+    bgp_max_as_path_len: HashMap<RouterId, usize>,
+    /// Routes originated by the router itself (network statement), mapping the prefix to the route.
+    bgp_local_routes: HashMap<Prefix, BgpRoute>,
+    /// Sequence number of the last update sent by the router (see `BgpRoute::seq`).
+    bgp_update_seq: u64,
+    /// Updates which were received out of order, and replaced a newer route of the same peer.
+    /// Each entry contains the peer and the prefix of the update.
+    bgp_stale_updates: Vec<(RouterId, Prefix)>,
+    /// Reason why the most recent route of each neighbor and prefix was rejected. Accepted routes
+    /// remove the entry.
+    bgp_reject_reasons: HashMap<(RouterId, Prefix), RejectReason>,
     /// Set of known bgp prefixes
     bgp_known_prefixes: HashSet<Prefix>,
     /// BGP configuration for tagging the local_pref of routes announced via eBGP, based on the
     /// router from which the route originates.
     pub policy_bgp_local_pref: HashMap<RouterId, u32>,
+    /// BGP configuration for tagging routes announced via eBGP with the link bandwidth extended
+    /// community (in Mbps), based on the router from which the route originates.
+    pub policy_bgp_link_bandwidth: HashMap<RouterId, u64>,
+    /// BGP configuration for tagging routes announced via eBGP with communities, based on the
+    /// router from which the route originates.
+    pub policy_bgp_communities: HashMap<RouterId, BTreeSet<u32>>,
+    /// Local preference assigned to routes announced via eBGP, if neither the policy nor the peer
+    /// group of the neighbor configures one. By default, it is 100.
+    pub default_local_pref: u32,
     /// BGP configuration for when to export routes to an eBGP peer, based on the next hop field of
     /// the route to be exported. This way, business relationships can be implemented, by
     /// prohibiting routes from a provider to be exported to a different provider.
     /// The tuple tells that a route, advertised by #0 should *not* be exported to the peer #1
     pub policy_bgp_route_no_export: HashSet<(RouterId, RouterId)>,
+    /// BGP configuration to mark all routes received via eBGP with an informational community
+    /// identifying the ingress peer (see `bgp::ingress_community`). Routes are not marked if the
+    /// AS id or the id of the peer does not fit into 16 bits.
+    pub mark_ingress_community: bool,
+    /// BGP configuration of a route reflector to reflect routes learned from a client to other
+    /// clients. It can be disabled if the clients are fully meshed. Routes from clients are
+    /// still reflected to non-clients.
+    pub client_to_client_reflection: bool,
+    /// BGP configuration to compare the MED of routes learned from different neighboring ASes. By
+    /// default, the MED is only compared among routes with the same first AS in the AS path: The
+    /// best route of each neighboring AS is selected including the MED, and those are compared
+    /// without the MED.
+    pub always_compare_med: bool,
+    /// Cluster id of a route reflector (RFC 4456). If set, the cluster id is added to the
+    /// CLUSTER_LIST of all reflected routes, and received routes whose CLUSTER_LIST already
+    /// contains the cluster id are dropped. Multiple route reflectors can serve the same cluster.
+    pub cluster_id: Option<u32>,
+    /// Sessions which are gracefully shut down (RFC 8326). Routes received over these sessions
+    /// are tagged with the GRACEFUL_SHUTDOWN community.
+    bgp_graceful_shutdown: HashSet<RouterId>,
+    /// eBGP sessions configured with next-hop-unchanged. Routes exported over these sessions keep
+    /// their original next hop, instead of the next hop being rewritten to the router itself.
+    bgp_next_hop_unchanged: HashSet<RouterId>,
+    /// Static routes, mapping the prefix to the next hop. Static routes take precedence over
+    /// the routes selected by BGP.
+    static_routes: HashMap<Prefix, RouterId>,
+    /// Route origin authorizations (ROA) known to the router, mapping each prefix to the set of
+    /// AS numbers which are authorized to originate it.
+    rpki_roas: HashMap<Prefix, HashSet<AsId>>,
+    /// BGP configuration to drop all received routes whose origin is invalid according to the
+    /// known ROAs.
+    pub rpki_drop_invalid: bool,
+    /// Peer groups configured on the router, referenced by their name.
+    peer_groups: HashMap<&'static str, PeerGroup>,
+    /// Peer group of the BGP neighbors, mapping the neighbor to the name of the peer group.
+    peer_group_members: HashMap<RouterId, &'static str>,
+    /// Conditional advertisement, mapping the prefix to be advertised to the condition prefix and
+    /// the mode. The prefix is only advertised to any peer if the condition is satisfied.
+    conditional_advertise: HashMap<Prefix, (Prefix, ConditionMode)>,
+    /// VRFs configured on the router, referenced by their name.
+    vrfs: HashMap<&'static str, Vrf>,
+    /// Selected VPN route of each VRF, mapping the name of the VRF to the selected route of each
+    /// prefix.
+    bgp_vrf_rib: HashMap<&'static str, HashMap<Prefix, RIBEntry>>,
+    /// Best VPN route of each prefix. It is advertised to the peers, but never used for
+    /// forwarding in the global table.
+    bgp_vpn_rib: HashMap<Prefix, RIBEntry>,
 }
 
 impl NetworkDevice for Router {
@@ -51,16 +155,48 @@ impl NetworkDevice for Router {
             name,
             router_id,
             as_id,
+            p_router: false,
             igp_forwarding_table: HashMap::new(),
             ibgp_peer_sessions: HashSet::new(),
             ibgp_client_sessions: HashSet::new(),
             ebgp_sessions: HashSet::new(),
+            ebgp_neighbor_as: HashMap::new(),
+            enforce_first_as: false,
+            bgp_peers: Vec::new(),
             bgp_rib_in: HashMap::new(),
             bgp_rib: HashMap::new(),
+            bgp_maximum_paths: 1,
+            tiebreak_seed: None,
+            decision_steps: DEFAULT_DECISION_STEPS.to_vec(),
             bgp_rib_out: HashMap::new(),
+            bgp_add_path: HashMap::new(),
+            bgp_maximum_prefix: HashMap::new(),
+            bgp_max_as_path_len: HashMap::new(),
+            bgp_local_routes: HashMap::new(),
+            bgp_update_seq: 0,
+            bgp_stale_updates: Vec::new(),
+            bgp_reject_reasons: HashMap::new(),
             bgp_known_prefixes: HashSet::new(),
             policy_bgp_local_pref: HashMap::new(),
+            policy_bgp_link_bandwidth: HashMap::new(),
+            policy_bgp_communities: HashMap::new(),
+            default_local_pref: 100,
             policy_bgp_route_no_export: HashSet::new(),
+            mark_ingress_community: false,
+            client_to_client_reflection: true,
+            always_compare_med: false,
+            cluster_id: None,
+            bgp_graceful_shutdown: HashSet::new(),
+            bgp_next_hop_unchanged: HashSet::new(),
+            static_routes: HashMap::new(),
+            rpki_roas: HashMap::new(),
+            rpki_drop_invalid: false,
+            peer_groups: HashMap::new(),
+            peer_group_members: HashMap::new(),
+            conditional_advertise: HashMap::new(),
+            vrfs: HashMap::new(),
+            bgp_vrf_rib: HashMap::new(),
+            bgp_vpn_rib: HashMap::new(),
         }
     }
 
@@ -85,14 +221,39 @@ impl NetworkDevice for Router {
             Event::Bgp(from, to, bgp_event) if to == self.router_id => {
                 // phase 1 of BGP protocol
                 let prefix = match bgp_event {
+                    // an identical route replaces itself, so nothing changes (implicit withdraw)
+                    BgpEvent::Update(route) if self.is_route_known(&route, from) => return Ok(()),
                     BgpEvent::Update(route) => self.insert_bgp_route(route, from)?,
-                    BgpEvent::Withdraw(prefix) => self.remove_bgp_route(prefix, from),
+                    BgpEvent::Withdraw(prefix) => self.remove_bgp_route(prefix, 0, from),
+                    BgpEvent::WithdrawPath(prefix, path_id) => {
+                        self.remove_bgp_route(prefix, path_id, from)
+                    }
+                    BgpEvent::Notification => {
+                        // the peer has closed the session
+                        self.close_bgp_session(from)?;
+                        self.bgp_decision_process()?;
+                        return self.bgp_route_dissemination(queue);
+                    }
                 };
                 self.bgp_known_prefixes.insert(prefix);
+                // tear down the session if the peer has sent too many prefixes
+                if self.is_maximum_prefix_exceeded(from) {
+                    self.close_bgp_session(from)?;
+                    self.bgp_reject_reasons
+                        .insert((from, prefix), RejectReason::MaximumPrefix);
+                    queue.push_back(Event::Bgp(self.router_id, from, BgpEvent::Notification));
+                    self.bgp_decision_process()?;
+                    return self.bgp_route_dissemination(queue);
+                }
                 // phase 2
                 self.run_bgp_decision_process_for_prefix(prefix)?;
                 // phase 3
-                self.run_bgp_route_dissemination_for_prefix(prefix, queue)
+                self.run_bgp_route_dissemination_for_prefix(prefix, queue)?;
+                // the change might affect prefixes which are conditionally advertised
+                for advertise_prefix in self.get_conditional_prefixes(prefix) {
+                    self.run_bgp_route_dissemination_for_prefix(advertise_prefix, queue)?;
+                }
+                Ok(())
             }
             _ => Ok(()),
         }
@@ -100,6 +261,19 @@ impl NetworkDevice for Router {
 }
 
 impl Router {
+    /// Create a new P router, which does not run BGP. It cannot establish any BGP session.
+    pub fn new_p_router(name: &'static str, router_id: RouterId, as_id: AsId) -> Router {
+        Router {
+            p_router: true,
+            ..Router::new(name, router_id, as_id)
+        }
+    }
+
+    /// Returns true if the router is a P router, which does not run BGP.
+    pub fn is_p_router(&self) -> bool {
+        self.p_router
+    }
+
     /// establish a bgp session with a peer
     /// `session_type` tells that `target` is in relation to `self`. If `session_type` is
     /// `BgpSessionType::IbgpClient`, then the `target` is added as client to `self`.
@@ -108,6 +282,9 @@ impl Router {
         target: RouterId,
         session_type: BgpSessionType,
     ) -> Result<(), DeviceError> {
+        if self.p_router {
+            return Err(DeviceError::BgpDisabled);
+        }
         if self.ebgp_sessions.contains(&target)
             || self.ibgp_peer_sessions.contains(&target)
             || self.ibgp_client_sessions.contains(&target)
@@ -120,6 +297,7 @@ impl Router {
             BgpSessionType::IBgpPeer => self.ibgp_peer_sessions.insert(target),
             BgpSessionType::IBgpClient => self.ibgp_client_sessions.insert(target),
         };
+        self.update_bgp_peers();
 
         Ok(())
     }
@@ -139,29 +317,411 @@ impl Router {
         if !removed {
             return Err(DeviceError::NoBgpSession(target));
         }
+        self.update_bgp_peers();
+        self.bgp_graceful_shutdown.remove(&target);
+        self.bgp_next_hop_unchanged.remove(&target);
+        self.ebgp_neighbor_as.remove(&target);
         for prefix in self.bgp_known_prefixes.clone() {
-            self.bgp_rib_in
-                .get_mut(&prefix)
-                .and_then(|rib| rib.remove(&target));
-            self.bgp_rib_out
-                .get_mut(&prefix)
-                .and_then(|rib| rib.remove(&target));
+            if let Some(rib) = self.bgp_rib_in.get_mut(&prefix) {
+                rib.retain(|(peer, _), _| *peer != target);
+            }
+            if let Some(rib) = self.bgp_rib_out.get_mut(&prefix) {
+                rib.retain(|(peer, _), _| *peer != target);
+            }
+        }
+        Ok(())
+    }
+
+    /// change the type of an existing bgp session, without closing it. All routes received from
+    /// and advertised to the peer are kept. `session_type` tells what `target` is in relation to
+    /// `self`, as in `establish_bgp_session`. The change is applied on the next decision process
+    /// and route dissemination.
+    pub fn change_bgp_session_type(
+        &mut self,
+        target: RouterId,
+        session_type: BgpSessionType,
+    ) -> Result<(), DeviceError> {
+        if !(self.ebgp_sessions.remove(&target)
+            | self.ibgp_peer_sessions.remove(&target)
+            | self.ibgp_client_sessions.remove(&target))
+        {
+            return Err(DeviceError::NoBgpSession(target));
+        }
+        self.establish_bgp_session(target, session_type)?;
+        if session_type != BgpSessionType::EBgp {
+            self.ebgp_neighbor_as.remove(&target);
+        }
+        // update the session type of the stored routes
+        for rib in self
+            .bgp_rib_in
+            .values_mut()
+            .chain(self.bgp_rib_out.values_mut())
+        {
+            for ((peer, _), entry) in rib.iter_mut() {
+                if *peer == target {
+                    entry.from_type = session_type;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Soft reconfiguration inbound: re-run the decision process on all routes received from the
+    /// peer, which are stored unprocessed in `bgp_rib_in`. This way, a changed import policy is
+    /// applied without the peer resending its routes. This does not execute route dissemination!
+    pub fn soft_reconfigure_in(&mut self, peer: RouterId) -> Result<(), DeviceError> {
+        self.get_bgp_session_type(peer)?;
+        let mut prefixes: Vec<Prefix> = self
+            .bgp_rib_in
+            .iter()
+            .filter(|(_, rib)| rib.keys().any(|(p, _)| *p == peer))
+            .map(|(prefix, _)| *prefix)
+            .collect();
+        prefixes.sort();
+        for prefix in prefixes {
+            self.run_bgp_decision_process_for_prefix(prefix)?;
+        }
+        Ok(())
+    }
+
+    /// Soft reconfiguration outbound: re-run the route dissemination towards the peer for all
+    /// known prefixes, such that a changed export policy is applied, and send the necessary
+    /// updates and withdraws to the peer.
+    pub fn soft_reconfigure_out(
+        &mut self,
+        peer: RouterId,
+        queue: &mut EventQueue,
+    ) -> Result<(), DeviceError> {
+        self.get_bgp_session_type(peer)?;
+        for prefix in self.sorted_known_prefixes() {
+            self.run_bgp_route_dissemination_for_prefix_to_peers(prefix, &[peer], queue)?;
+        }
+        Ok(())
+    }
+
+    /// Clear the BGP session with the neighbor (`clear ip bgp <neighbor>`), like a hard reset of
+    /// the session, but without changing its configuration. All routes advertised to the
+    /// neighbor are withdrawn, and all routes received from it are forgotten. Then, the decision
+    /// process is executed, and all best routes are advertised to the neighbor again. The neighbor
+    /// must clear the session as well, in order to send its routes again.
+    pub fn clear_bgp_neighbor(
+        &mut self,
+        neighbor: RouterId,
+        queue: &mut EventQueue,
+    ) -> Result<(), DeviceError> {
+        self.get_bgp_session_type(neighbor)?;
+        for prefix in self.sorted_known_prefixes() {
+            if let Some(rib) = self.bgp_rib_in.get_mut(&prefix) {
+                rib.retain(|(peer, _), _| *peer != neighbor);
+            }
+            if let Some(rib) = self.bgp_rib_out.get_mut(&prefix) {
+                let mut path_ids: Vec<PathId> = rib
+                    .keys()
+                    .filter(|(peer, _)| *peer == neighbor)
+                    .map(|(_, path_id)| *path_id)
+                    .collect();
+                path_ids.sort();
+                rib.retain(|(peer, _), _| *peer != neighbor);
+                for path_id in path_ids {
+                    queue.push_back(Event::Bgp(
+                        self.router_id,
+                        neighbor,
+                        withdraw_event(prefix, path_id),
+                    ));
+                }
+            }
+        }
+        self.bgp_decision_process()?;
+        self.bgp_route_dissemination(queue)
+    }
+
+    /// Configure the AS of the neighbor of an eBGP session. If `enforce_first_as` is set, all
+    /// routes received from the neighbor whose AS path does not start with `as_id` are dropped.
+    /// This function returns
+    /// an error if there is no eBGP session with the neighbor, or if the neighbor is in the same
+    /// AS as the router.
+    pub fn set_ebgp_neighbor_as(&mut self, peer: RouterId, as_id: AsId) -> Result<(), DeviceError> {
+        if !self.ebgp_sessions.contains(&peer) {
+            return Err(DeviceError::NoBgpSession(peer));
+        }
+        if as_id == self.as_id {
+            return Err(DeviceError::NeighborInSameAs(peer));
+        }
+        self.ebgp_neighbor_as.insert(peer, as_id);
+        Ok(())
+    }
+
+    /// Returns the configured AS of the neighbor of an eBGP session, or `None` if it is not
+    /// configured (see `set_ebgp_neighbor_as`).
+    pub fn get_ebgp_neighbor_as(&self, peer: RouterId) -> Option<AsId> {
+        self.ebgp_neighbor_as.get(&peer).copied()
+    }
+
+    /// Originate a route for the prefix on the router itself (network statement), and advertise it
+    /// to all peers. The next hop of the route is set to the router, and locally originated routes
+    /// are treated like routes learned via eBGP, without any IGP cost. A previously originated
+    /// route for the same prefix is replaced.
+    pub fn originate_route(
+        &mut self,
+        mut route: BgpRoute,
+        queue: &mut EventQueue,
+    ) -> Result<(), DeviceError> {
+        if self.p_router {
+            return Err(DeviceError::BgpDisabled);
+        }
+        let prefix = route.prefix;
+        route.path_id = 0;
+        route.next_hop = self.router_id;
+        self.bgp_local_routes.insert(prefix, route);
+        self.bgp_known_prefixes.insert(prefix);
+        self.run_bgp_decision_process_for_prefix(prefix)?;
+        self.run_bgp_route_dissemination_for_prefix(prefix, queue)
+    }
+
+    /// Add a static route for the prefix, which takes precedence over the route selected by BGP
+    /// when forwarding traffic. Traffic is forwarded towards `next_hop` along the IGP. If the next
+    /// hop is not reachable, traffic is dropped, instead of using the BGP route. BGP is not
+    /// affected by static routes.
+    pub fn add_static_route(&mut self, prefix: Prefix, next_hop: RouterId) {
+        self.static_routes.insert(prefix, next_hop);
+    }
+
+    /// Remove the static route for the prefix, and return its next hop.
+    pub fn remove_static_route(&mut self, prefix: Prefix) -> Option<RouterId> {
+        self.static_routes.remove(&prefix)
+    }
+
+    /// Add a route origin authorization (ROA), authorizing `origin_as` to originate the prefix.
+    /// ROAs are only checked when a route is received.
+    pub fn add_roa(&mut self, prefix: Prefix, origin_as: AsId) {
+        self.rpki_roas.entry(prefix).or_default().insert(origin_as);
+    }
+
+    /// Validate the origin of the route against the known ROAs. The origin AS is the last AS in
+    /// the AS path. Routes with an empty AS path have no origin AS, and are never invalid.
+    pub fn rpki_validate(&self, route: &BgpRoute) -> RpkiValidation {
+        match (self.rpki_roas.get(&route.prefix), route.as_path.last()) {
+            (None, _) | (_, None) => RpkiValidation::NotFound,
+            (Some(authorized), Some(origin)) if authorized.contains(origin) => {
+                RpkiValidation::Valid
+            }
+            (Some(_), Some(_)) => RpkiValidation::Invalid,
+        }
+    }
+
+    /// Enable or disable graceful shutdown (RFC 8326) of the session with the peer. While enabled,
+    /// all routes received from the peer are tagged with the GRACEFUL_SHUTDOWN community, and
+    /// imported with local_pref 0. The change is applied on the next decision process.
+    pub fn set_graceful_shutdown(
+        &mut self,
+        peer: RouterId,
+        enabled: bool,
+    ) -> Result<(), DeviceError> {
+        self.get_bgp_session_type(peer)?;
+        if enabled {
+            self.bgp_graceful_shutdown.insert(peer);
+        } else {
+            self.bgp_graceful_shutdown.remove(&peer);
+        }
+        Ok(())
+    }
+
+    /// Enable or disable next-hop-unchanged on the eBGP session with the peer. While enabled, all
+    /// routes exported to the peer keep their original next hop, instead of the router setting
+    /// itself as next hop. The change is applied on the next route dissemination.
+    pub fn set_next_hop_unchanged(
+        &mut self,
+        peer: RouterId,
+        enabled: bool,
+    ) -> Result<(), DeviceError> {
+        if !self.ebgp_sessions.contains(&peer) {
+            return Err(DeviceError::NoBgpSession(peer));
+        }
+        if enabled {
+            self.bgp_next_hop_unchanged.insert(peer);
+        } else {
+            self.bgp_next_hop_unchanged.remove(&peer);
+        }
+        Ok(())
+    }
+
+    /// Configure ADD-PATH towards a peer, such that the `n` best routes for each prefix are
+    /// advertised to the peer. Setting `n` to 1 disables ADD-PATH, and only the best route is
+    /// advertised. The change is applied on the next route dissemination.
+    pub fn set_add_path(&mut self, peer: RouterId, n: usize) {
+        if n == 1 {
+            self.bgp_add_path.remove(&peer);
+        } else {
+            self.bgp_add_path.insert(peer, n);
+        }
+    }
+
+    /// Configure BGP multipath, such that up to `n` routes are installed for each prefix, if they
+    /// are equally good up to the IGP cost. Setting `n` to 1 disables multipath. The change is
+    /// applied on the next decision process.
+    pub fn set_maximum_paths(&mut self, n: usize) {
+        self.bgp_maximum_paths = n.max(1);
+    }
+
+    /// Configure the order of the steps of the decision process, e.g., to ignore the IGP cost, or
+    /// to compare it before the AS path length. Steps which are not given are skipped. The final
+    /// tiebreak is always applied last, such that exactly one route is selected. The change is
+    /// applied on the next decision process. `DecisionStep::Tiebreak` and `DecisionStep::Equal`
+    /// only describe the result of a comparison, and cannot be configured.
+    pub fn set_decision_steps(&mut self, steps: Vec<DecisionStep>) -> Result<(), DeviceError> {
+        if let Some(step) = steps
+            .iter()
+            .find(|step| matches!(step, DecisionStep::Tiebreak | DecisionStep::Equal))
+        {
+            return Err(DeviceError::InvalidDecisionStep(*step));
+        }
+        self.decision_steps = steps;
+        Ok(())
+    }
+
+    /// Returns the order of the steps of the decision process (see `set_decision_steps`).
+    pub fn get_decision_steps(&self) -> &[DecisionStep] {
+        &self.decision_steps
+    }
+
+    /// Compare two routes with the configured steps of the decision process, and return the
+    /// ordering and the deciding step (see `RIBEntry::compare_with_steps`). Unless
+    /// `always_compare_med` is set, the MED is skipped for routes from different neighboring ASes.
+    pub fn compare_routes(&self, a: &RIBEntry, b: &RIBEntry) -> (Ordering, DecisionStep) {
+        if self.always_compare_med || a.neighbor_as() == b.neighbor_as() {
+            a.compare_with_steps(b, &self.decision_steps)
+        } else {
+            a.compare_with_steps(b, &steps_without_med(&self.decision_steps))
+        }
+    }
+
+    /// Limit the number of prefixes accepted from the peer (maximum-prefix). As soon as the peer
+    /// sends routes for more than `limit` prefixes, the session is closed, all routes of the
+    /// peer are removed, and the peer is notified. If `limit` is `None`, any number of prefixes is
+    /// accepted. The limit is checked whenever a route is received.
+    pub fn set_maximum_prefix(&mut self, peer: RouterId, limit: Option<usize>) {
+        match limit {
+            Some(limit) => self.bgp_maximum_prefix.insert(peer, limit),
+            None => self.bgp_maximum_prefix.remove(&peer),
+        };
+    }
+
+    /// Limit the length of the AS path of routes accepted from the peer. Received routes with a
+    /// longer AS path are dropped, and replace the previous route of the peer. If `limit` is
+    /// `None`, routes of any length are accepted. The limit is checked whenever a route is
+    /// received.
+    pub fn set_max_as_path_len(&mut self, peer: RouterId, limit: Option<usize>) {
+        match limit {
+            Some(limit) => self.bgp_max_as_path_len.insert(peer, limit),
+            None => self.bgp_max_as_path_len.remove(&peer),
+        };
+    }
+
+    /// Create a new peer group, or replace the configuration of an existing peer group. The
+    /// change is applied to all members of the group on the next decision process and route
+    /// dissemination.
+    pub fn set_peer_group(&mut self, name: &'static str, group: PeerGroup) {
+        self.peer_groups.insert(name, group);
+    }
+
+    /// Get a mutable reference to a peer group, in order to change the policy of all members.
+    pub fn get_peer_group_mut(&mut self, name: &'static str) -> Option<&mut PeerGroup> {
+        self.peer_groups.get_mut(name)
+    }
+
+    /// Remove a peer group. The members of the group are removed from the group.
+    pub fn remove_peer_group(&mut self, name: &'static str) -> Option<PeerGroup> {
+        self.peer_group_members.retain(|_, group| *group != name);
+        self.peer_groups.remove(name)
+    }
+
+    /// Make the neighbor a member of the peer group. If the neighbor was already member of a
+    /// different group, it is moved to the new group.
+    pub fn add_peer_group_member(
+        &mut self,
+        name: &'static str,
+        neighbor: RouterId,
+    ) -> Result<(), DeviceError> {
+        if !self.peer_groups.contains_key(name) {
+            return Err(DeviceError::PeerGroupNotFound(name));
         }
+        self.peer_group_members.insert(neighbor, name);
         Ok(())
     }
 
+    /// Remove the neighbor from its peer group.
+    pub fn remove_peer_group_member(&mut self, neighbor: RouterId) {
+        self.peer_group_members.remove(&neighbor);
+    }
+
+    /// Only advertise `advertise_prefix` to the peers if the condition on `condition_prefix` is
+    /// satisfied. With `ConditionMode::Exist`, the prefix is only advertised while the router has
+    /// selected a route for `condition_prefix`. With `ConditionMode::NonExist`, it is only
+    /// advertised while there is no such route. If the condition changes, the prefix is advertised
+    /// or withdrawn accordingly. The configuration is applied on the next route dissemination.
+    pub fn add_conditional_advertise(
+        &mut self,
+        advertise_prefix: Prefix,
+        condition_prefix: Prefix,
+        mode: ConditionMode,
+    ) {
+        self.conditional_advertise
+            .insert(advertise_prefix, (condition_prefix, mode));
+    }
+
+    /// Remove the conditional advertisement of the prefix, such that it is advertised
+    /// unconditionally. The change is applied on the next route dissemination.
+    pub fn remove_conditional_advertise(&mut self, advertise_prefix: Prefix) {
+        self.conditional_advertise.remove(&advertise_prefix);
+    }
+
+    /// Create a new VRF, or replace the configuration of an existing VRF. VPN routes received
+    /// while no VRF imported them were dropped, and are only imported once they are received
+    /// again. The change is applied on the next decision process.
+    pub fn set_vrf(&mut self, name: &'static str, vrf: Vrf) {
+        self.vrfs.insert(name, vrf);
+    }
+
+    /// Remove a VRF, together with all routes imported into it.
+    pub fn remove_vrf(&mut self, name: &'static str) -> Option<Vrf> {
+        self.bgp_vrf_rib.remove(name);
+        self.vrfs.remove(name)
+    }
+
+    /// Returns the VPN route for the prefix selected in the VRF, or `None` if the VRF does not
+    /// import any route for the prefix.
+    pub fn get_vrf_route(&self, name: &'static str, prefix: Prefix) -> Option<RIBEntry> {
+        self.bgp_vrf_rib
+            .get(name)
+            .and_then(|rib| rib.get(&prefix))
+            .cloned()
+    }
+
+    /// Originate a VPN route in the VRF, tagged with the export route targets of the VRF. The
+    /// route is treated like any other route originated by the router (see `originate_route`).
+    pub fn originate_vrf_route(
+        &mut self,
+        name: &'static str,
+        mut route: BgpRoute,
+        queue: &mut EventQueue,
+    ) -> Result<(), DeviceError> {
+        let vrf = self.vrfs.get(name).ok_or(DeviceError::VrfNotFound(name))?;
+        route.route_targets = vrf.export_rts.clone();
+        self.originate_route(route, queue)
+    }
+
     /// write forawrding table based on graph
     /// This function requres that all RouterIds are set to the GraphId.
     pub fn write_igp_forwarding_table(&mut self, graph: &IgpNetwork) -> Result<(), DeviceError> {
         // clear the forwarding table
         self.igp_forwarding_table = HashMap::new();
         // compute shortest path to all other nodes in the graph
-        let (path_weights, predecessors) = bellman_ford(graph, self.router_id.into()).unwrap();
+        let (path_weights, predecessors) = bellman_ford(graph, self.router_id).unwrap();
         let mut paths: Vec<(RouterId, LinkWeight, Option<RouterId>)> = path_weights
             .into_iter()
-            .zip(predecessors.into_iter())
+            .zip(predecessors)
             .enumerate()
-            .map(|(i, (w, p))| ((i as u32).into(), w, p.map(|x| x)))
+            .map(|(i, (w, p))| ((i as u32).into(), w, p))
             .collect();
         paths.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
         for (router, cost, predecessor) in paths {
@@ -192,7 +752,7 @@ impl Router {
     /// Run the bgp decision process, select the best route. This does not execute route
     /// dissemination!
     pub fn bgp_decision_process(&mut self) -> Result<(), DeviceError> {
-        for prefix in self.bgp_known_prefixes.clone() {
+        for prefix in self.sorted_known_prefixes() {
             self.run_bgp_decision_process_for_prefix(prefix)?
         }
         Ok(())
@@ -200,38 +760,504 @@ impl Router {
 
     /// Execute route dissemination, e.g. send all necessary updates to all peers
     pub fn bgp_route_dissemination(&mut self, queue: &mut EventQueue) -> Result<(), DeviceError> {
-        for prefix in self.bgp_known_prefixes.clone() {
+        for prefix in self.sorted_known_prefixes() {
             self.run_bgp_route_dissemination_for_prefix(prefix, queue)?
         }
         Ok(())
     }
 
-    /// get the IGP next hop for a prefix
-    pub fn get_next_hop(&self, prefix: Prefix) -> Option<RouterId> {
-        match self.bgp_rib.get(&prefix) {
-            Some(entry) => self
-                .igp_forwarding_table
-                .get(&entry.route.next_hop)
-                .unwrap()
-                .map(|e| e.0),
-            None => None,
+    /// get the IGP next hops for a prefix. If a static route is configured for the prefix, it is
+    /// used instead of BGP. The result tells if the prefix has no selected route,
+    /// if the BGP next hop of the selected route is not reachable, or to which neighbors the
+    /// traffic is forwarded. With BGP multipath, the traffic is forwarded to the next hops of all
+    /// installed routes, the first one being the next hop of the selected route.
+    pub fn get_next_hop(&self, prefix: Prefix) -> NextHop {
+        // static routes take precedence
+        if let Some(next_hop) = self.static_routes.get(&prefix) {
+            return match self.igp_forwarding_table.get(next_hop) {
+                Some(Some((router, _))) => NextHop::Via(vec![*router]),
+                _ => NextHop::Unreachable(*next_hop),
+            };
+        }
+        let entries = match self.bgp_rib.get(&prefix) {
+            Some(entries) => entries,
+            None => return NextHop::NoRoute,
+        };
+        let mut next_hops: Vec<RouterId> = Vec::new();
+        for entry in entries {
+            if let Some(Some((router, _))) = self.igp_forwarding_table.get(&entry.route.next_hop) {
+                if !next_hops.contains(router) {
+                    next_hops.push(*router);
+                }
+            }
+        }
+        if next_hops.is_empty() {
+            NextHop::Unreachable(entries[0].route.next_hop)
+        } else {
+            NextHop::Via(next_hops)
         }
     }
 
-    /// Return a list of all known bgp routes for a given origin
+    /// Returns the share of the traffic for the prefix forwarded to each IGP next hop, in the same
+    /// order as `get_next_hop`. With BGP multipath, the traffic is shared among the installed
+    /// routes proportionally to their link bandwidth, if all of them carry one. Otherwise, it is
+    /// shared equally. Routes whose next hop is not reachable are ignored. If the traffic is
+    /// dropped, the result is empty.
+    pub fn get_traffic_split(&self, prefix: Prefix) -> Vec<(RouterId, f64)> {
+        if self.static_routes.contains_key(&prefix) {
+            return match self.get_next_hop(prefix) {
+                NextHop::Via(next_hops) => vec![(next_hops[0], 1.0)],
+                _ => Vec::new(),
+            };
+        }
+        let entries = self.get_installed_bgp_routes(prefix);
+        let weighted = entries.iter().all(|e| e.route.link_bandwidth.is_some());
+        let mut split: Vec<(RouterId, f64)> = Vec::new();
+        for entry in entries.iter() {
+            let next_hop = match self.igp_forwarding_table.get(&entry.route.next_hop) {
+                Some(Some((router, _))) => *router,
+                _ => continue,
+            };
+            let weight = if weighted {
+                entry.route.link_bandwidth.unwrap_or(0) as f64
+            } else {
+                1.0
+            };
+            match split.iter_mut().find(|(r, _)| *r == next_hop) {
+                Some((_, w)) => *w += weight,
+                None => split.push((next_hop, weight)),
+            }
+        }
+        let total: f64 = split.iter().map(|(_, w)| w).sum();
+        if total <= 0.0 {
+            return Vec::new();
+        }
+        split.into_iter().map(|(r, w)| (r, w / total)).collect()
+    }
+
+    /// get the IGP next hop towards the `target` router. This is used to forward labeled traffic
+    /// through P routers, without looking at the prefix.
+    pub fn get_igp_next_hop(&self, target: RouterId) -> NextHop {
+        match self.igp_forwarding_table.get(&target) {
+            Some(Some((router, _))) => NextHop::Via(vec![*router]),
+            _ => NextHop::Unreachable(target),
+        }
+    }
+
+    /// Returns the BGP next hop used for the prefix, i.e., the next hop of the static route if
+    /// configured, or the next hop of the selected route.
+    pub fn get_bgp_next_hop(&self, prefix: Prefix) -> Option<RouterId> {
+        match self.static_routes.get(&prefix) {
+            Some(next_hop) => Some(*next_hop),
+            None => self
+                .get_selected_bgp_route(prefix)
+                .map(|entry| entry.route.next_hop),
+        }
+    }
+
+    /// Returns the IGP next hop used for the prefix, i.e., the neighbor to which the traffic is
+    /// forwarded after resolving the BGP next hop (see `get_bgp_next_hop`) in the IGP forwarding
+    /// table. With BGP multipath, this is the IGP next hop of the selected route. Returns `None` if
+    /// there is no route, or if the BGP next hop is not reachable.
+    pub fn get_igp_next_hop_for_prefix(&self, prefix: Prefix) -> Option<RouterId> {
+        match self.get_next_hop(prefix) {
+            NextHop::Via(next_hops) => next_hops.first().copied(),
+            NextHop::NoRoute | NextHop::Unreachable(_) => None,
+        }
+    }
+
+    /// Return a list of all known bgp routes for a given origin, sorted by the neighbor and the
+    /// path id. Locally originated routes are last.
     pub fn get_known_bgp_routes(&self, prefix: Prefix) -> Result<Vec<RIBEntry>, DeviceError> {
         let mut entries: Vec<RIBEntry> = Vec::new();
         if let Some(table) = self.bgp_rib_in.get(&prefix) {
-            for e in table.values() {
-                entries.push(self.process_bgp_rib_in_route(e)?);
+            let mut keys: Vec<&(RouterId, PathId)> = table.keys().collect();
+            keys.sort();
+            for key in keys {
+                entries.push(self.process_bgp_rib_in_route(&table[key])?);
             }
         }
+        if let Some(route) = self.bgp_local_routes.get(&prefix) {
+            let mut route = route.clone();
+            route.local_pref = Some(route.local_pref.unwrap_or(self.default_local_pref));
+            entries.push(RIBEntry {
+                route,
+                from_type: BgpSessionType::EBgp,
+                from_id: self.router_id,
+                igp_cost: Some(0.0),
+            });
+        }
         Ok(entries)
     }
 
+    /// Returns all known prefixes (see `known_prefixes`) for which the router cannot forward any
+    /// traffic, because no route is selected, or because the next hop is not reachable in the IGP.
+    /// The prefixes are sorted. Forwarding loops are not detected, as this requires the state of
+    /// the other routers (see `Network::get_unreachable_prefixes`).
+    pub fn unreachable_prefixes(&self) -> Vec<Prefix> {
+        self.sorted_known_prefixes()
+            .into_iter()
+            .filter(|prefix| {
+                matches!(
+                    self.get_next_hop(*prefix),
+                    NextHop::NoRoute | NextHop::Unreachable(_)
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the set of all prefixes for which the router has received any BGP message
+    pub fn known_prefixes(&self) -> &HashSet<Prefix> {
+        &self.bgp_known_prefixes
+    }
+
+    /// returns the BgpSessionType for a peer, or an error if no session is established
+    pub fn get_bgp_session_type(&self, peer: RouterId) -> Result<BgpSessionType, DeviceError> {
+        if self.ibgp_peer_sessions.contains(&peer) {
+            Ok(BgpSessionType::IBgpPeer)
+        } else if self.ibgp_client_sessions.contains(&peer) {
+            Ok(BgpSessionType::IBgpClient)
+        } else if self.ebgp_sessions.contains(&peer) {
+            Ok(BgpSessionType::EBgp)
+        } else {
+            Err(DeviceError::NoBgpSession(peer))
+        }
+    }
+
+    /// Returns all established BGP sessions, with the type of the neighbor in relation to `self`.
+    pub fn get_bgp_sessions(&self) -> Vec<(RouterId, BgpSessionType)> {
+        self.ibgp_peer_sessions
+            .iter()
+            .map(|peer| (*peer, BgpSessionType::IBgpPeer))
+            .chain(
+                self.ibgp_client_sessions
+                    .iter()
+                    .map(|peer| (*peer, BgpSessionType::IBgpClient)),
+            )
+            .chain(
+                self.ebgp_sessions
+                    .iter()
+                    .map(|peer| (*peer, BgpSessionType::EBgp)),
+            )
+            .collect()
+    }
+
+    /// Returns true if the advertisement of any prefix depends on the `condition_prefix` (see
+    /// `add_conditional_advertise`).
+    pub fn has_conditional_advertise(&self, condition_prefix: Prefix) -> bool {
+        !self.get_conditional_prefixes(condition_prefix).is_empty()
+    }
+
     /// Returns the selected bgp route for the prefix, or returns None
     pub fn get_selected_bgp_route(&self, prefix: Prefix) -> Option<RIBEntry> {
-        self.bgp_rib.get(&prefix).map(|r| r.clone())
+        self.bgp_rib
+            .get(&prefix)
+            .and_then(|entries| entries.first())
+            .cloned()
+    }
+
+    /// Returns the best VPN route for the prefix, or returns None. The route is advertised to the
+    /// peers, but it is not used for forwarding (see `get_vrf_route` for the route of a VRF).
+    pub fn get_selected_vpn_route(&self, prefix: Prefix) -> Option<RIBEntry> {
+        self.bgp_vpn_rib.get(&prefix).cloned()
+    }
+
+    /// Returns all known bgp routes for the prefix which are equally good as the selected route,
+    /// i.e., which are only decided by the final tiebreak (router id, next hop and neighbor). The
+    /// selected route is the first entry, followed by all others, sorted by the neighbor. If no
+    /// route is selected, the vector is empty.
+    pub fn get_equal_best_routes(&self, prefix: Prefix) -> Result<Vec<RIBEntry>, DeviceError> {
+        let selected = match self.get_selected_bgp_route(prefix) {
+            Some(selected) => selected,
+            None => return Ok(Vec::new()),
+        };
+        let mut others: Vec<RIBEntry> = self
+            .get_known_bgp_routes(prefix)?
+            .into_iter()
+            .filter(|entry| *entry != selected && entry.route.route_targets.is_empty())
+            .filter(|entry| {
+                matches!(
+                    self.compare_routes(&selected, entry).1,
+                    DecisionStep::Tiebreak | DecisionStep::Equal
+                )
+            })
+            .collect();
+        others.sort_by_key(|entry| (entry.from_id, entry.route.path_id));
+        Ok(std::iter::once(selected).chain(others).collect())
+    }
+
+    /// Returns all bgp routes installed for the prefix, sorted by preference. Without BGP
+    /// multipath, this contains at most the selected route.
+    pub fn get_installed_bgp_routes(&self, prefix: Prefix) -> Vec<RIBEntry> {
+        self.bgp_rib.get(&prefix).cloned().unwrap_or_default()
+    }
+
+    /// Returns the number of entries stored in the BGP tables of the router.
+    pub fn table_sizes(&self) -> TableSizes {
+        TableSizes {
+            rib_in: self.bgp_rib_in.values().map(|rib| rib.len()).sum(),
+            rib: self.bgp_rib.values().map(|entries| entries.len()).sum(),
+            rib_out: self.bgp_rib_out.values().map(|rib| rib.len()).sum(),
+            known_prefixes: self.bgp_known_prefixes.len(),
+        }
+    }
+
+    /// Returns all updates which were received out of order, i.e., which replaced a route of the
+    /// same peer with a higher sequence number (see `BgpRoute::seq`). Each entry contains the peer
+    /// and the prefix of the update.
+    pub fn get_stale_updates(&self) -> &[(RouterId, Prefix)] {
+        &self.bgp_stale_updates
+    }
+
+    /// Returns the reason why the most recent route for the prefix received from the peer was
+    /// dropped, or `None` if it was accepted, or if no route was received.
+    pub fn last_reject_reason(&self, peer: RouterId, prefix: Prefix) -> Option<RejectReason> {
+        self.bgp_reject_reasons.get(&(peer, prefix)).copied()
+    }
+
+    /// Returns the route for the prefix, which was last advertised to the peer, or `None` if no
+    /// route is advertised. The route contains all modifications done on export, like the
+    /// rewritten next hop. With ADD-PATH, this returns the best path (with path id 0).
+    pub fn get_advertised_route(&self, peer: RouterId, prefix: Prefix) -> Option<&BgpRoute> {
+        self.bgp_rib_out
+            .get(&prefix)
+            .and_then(|rib| rib.get(&(peer, 0)))
+            .map(|entry| &entry.route)
+    }
+
+    /// Returns the BGP configuration of the router, as stored in a `Scenario` (see
+    /// `Network::to_scenario`).
+    pub fn get_scenario_config(&self) -> ScenarioRouterConfig {
+        let index = |id: &RouterId| id.index() as u32;
+        fn sorted<T: Ord>(mut v: Vec<T>) -> Vec<T> {
+            v.sort();
+            v
+        }
+        let mut peer_groups: Vec<ScenarioPeerGroup> = self
+            .peer_groups
+            .iter()
+            .map(|(name, group)| ScenarioPeerGroup {
+                name: name.to_string(),
+                local_pref: group.local_pref,
+                no_export_from: sorted(
+                    group.no_export_from.iter().map(|g| g.to_string()).collect(),
+                ),
+            })
+            .collect();
+        peer_groups.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut vrfs: Vec<ScenarioVrf> = self
+            .vrfs
+            .iter()
+            .map(|(name, vrf)| ScenarioVrf {
+                name: name.to_string(),
+                import_rts: vrf.import_rts.iter().copied().collect(),
+                export_rts: vrf.export_rts.iter().copied().collect(),
+            })
+            .collect();
+        vrfs.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut originated_routes: Vec<ScenarioOriginatedRoute> = self
+            .bgp_local_routes
+            .values()
+            .map(|route| ScenarioOriginatedRoute {
+                prefix: route.prefix.0,
+                as_path: route.as_path.iter().map(|as_id| as_id.0).collect(),
+                local_pref: route.local_pref,
+                med: route.med,
+                aigp: route.aigp,
+                communities: route.communities.iter().copied().collect(),
+                link_bandwidth: route.link_bandwidth,
+                route_targets: route.route_targets.iter().copied().collect(),
+                origin: route.origin,
+            })
+            .collect();
+        originated_routes.sort_by_key(|route| route.prefix);
+
+        ScenarioRouterConfig {
+            local_pref: sorted(
+                self.policy_bgp_local_pref
+                    .iter()
+                    .map(|(n, lp)| (index(n), *lp))
+                    .collect(),
+            ),
+            default_local_pref: self.default_local_pref,
+            no_export: sorted(
+                self.policy_bgp_route_no_export
+                    .iter()
+                    .map(|(from, to)| (index(from), index(to)))
+                    .collect(),
+            ),
+            link_bandwidth: sorted(
+                self.policy_bgp_link_bandwidth
+                    .iter()
+                    .map(|(n, bw)| (index(n), *bw))
+                    .collect(),
+            ),
+            communities: sorted(
+                self.policy_bgp_communities
+                    .iter()
+                    .map(|(n, c)| (index(n), c.iter().copied().collect()))
+                    .collect(),
+            ),
+            mark_ingress_community: self.mark_ingress_community,
+            client_to_client_reflection: self.client_to_client_reflection,
+            always_compare_med: self.always_compare_med,
+            enforce_first_as: self.enforce_first_as,
+            cluster_id: self.cluster_id,
+            tiebreak_seed: self.tiebreak_seed,
+            rpki_drop_invalid: self.rpki_drop_invalid,
+            maximum_paths: self.bgp_maximum_paths,
+            decision_steps: self.decision_steps.clone(),
+            add_path: sorted(
+                self.bgp_add_path
+                    .iter()
+                    .map(|(n, x)| (index(n), *x))
+                    .collect(),
+            ),
+            maximum_prefix: sorted(
+                self.bgp_maximum_prefix
+                    .iter()
+                    .map(|(n, x)| (index(n), *x))
+                    .collect(),
+            ),
+            max_as_path_len: sorted(
+                self.bgp_max_as_path_len
+                    .iter()
+                    .map(|(n, x)| (index(n), *x))
+                    .collect(),
+            ),
+            graceful_shutdown: sorted(self.bgp_graceful_shutdown.iter().map(index).collect()),
+            next_hop_unchanged: sorted(self.bgp_next_hop_unchanged.iter().map(index).collect()),
+            static_routes: sorted(
+                self.static_routes
+                    .iter()
+                    .map(|(p, nh)| (p.0, index(nh)))
+                    .collect(),
+            ),
+            roas: sorted(
+                self.rpki_roas
+                    .iter()
+                    .map(|(p, ases)| (p.0, sorted(ases.iter().map(|a| a.0).collect())))
+                    .collect(),
+            ),
+            peer_groups,
+            peer_group_members: sorted(
+                self.peer_group_members
+                    .iter()
+                    .map(|(n, g)| (index(n), g.to_string()))
+                    .collect(),
+            ),
+            conditional_advertise: {
+                let mut c: Vec<(u32, u32, ConditionMode)> = self
+                    .conditional_advertise
+                    .iter()
+                    .map(|(p, (cond, mode))| (p.0, cond.0, *mode))
+                    .collect();
+                c.sort_by_key(|(p, cond, _)| (*p, *cond));
+                c
+            },
+            vrfs,
+            originated_routes,
+        }
+    }
+
+    /// Apply the BGP configuration of a `Scenario` (see `Network::from_scenario`). All sessions
+    /// must already be established. The originated routes are advertised to the peers.
+    pub fn apply_scenario_config(
+        &mut self,
+        config: &ScenarioRouterConfig,
+        queue: &mut EventQueue,
+    ) -> Result<(), DeviceError> {
+        // names are referenced during the whole lifetime of the router
+        let leak = |name: &String| -> &'static str { Box::leak(name.clone().into_boxed_str()) };
+        let id = |x: &u32| -> RouterId { (*x).into() };
+
+        for (n, lp) in config.local_pref.iter() {
+            self.policy_bgp_local_pref.insert(id(n), *lp);
+        }
+        self.default_local_pref = config.default_local_pref;
+        for (from, to) in config.no_export.iter() {
+            self.policy_bgp_route_no_export.insert((id(from), id(to)));
+        }
+        for (n, bw) in config.link_bandwidth.iter() {
+            self.policy_bgp_link_bandwidth.insert(id(n), *bw);
+        }
+        for (n, c) in config.communities.iter() {
+            self.policy_bgp_communities
+                .insert(id(n), c.iter().copied().collect());
+        }
+        self.mark_ingress_community = config.mark_ingress_community;
+        self.client_to_client_reflection = config.client_to_client_reflection;
+        self.always_compare_med = config.always_compare_med;
+        self.enforce_first_as = config.enforce_first_as;
+        self.cluster_id = config.cluster_id;
+        self.tiebreak_seed = config.tiebreak_seed;
+        self.rpki_drop_invalid = config.rpki_drop_invalid;
+        self.set_maximum_paths(config.maximum_paths);
+        self.set_decision_steps(config.decision_steps.clone())?;
+        for (n, x) in config.add_path.iter() {
+            self.set_add_path(id(n), *x);
+        }
+        for (n, x) in config.maximum_prefix.iter() {
+            self.set_maximum_prefix(id(n), Some(*x));
+        }
+        for (n, x) in config.max_as_path_len.iter() {
+            self.set_max_as_path_len(id(n), Some(*x));
+        }
+        for n in config.graceful_shutdown.iter() {
+            self.set_graceful_shutdown(id(n), true)?;
+        }
+        for n in config.next_hop_unchanged.iter() {
+            self.set_next_hop_unchanged(id(n), true)?;
+        }
+        for (p, nh) in config.static_routes.iter() {
+            self.add_static_route(Prefix(*p), id(nh));
+        }
+        for (p, ases) in config.roas.iter() {
+            for a in ases.iter() {
+                self.add_roa(Prefix(*p), AsId(*a));
+            }
+        }
+        for group in config.peer_groups.iter() {
+            let peer_group = PeerGroup {
+                local_pref: group.local_pref,
+                no_export_from: group.no_export_from.iter().map(leak).collect(),
+            };
+            self.set_peer_group(leak(&group.name), peer_group);
+        }
+        for (n, group) in config.peer_group_members.iter() {
+            self.add_peer_group_member(leak(group), id(n))?;
+        }
+        for (p, cond, mode) in config.conditional_advertise.iter() {
+            self.add_conditional_advertise(Prefix(*p), Prefix(*cond), *mode);
+        }
+        for vrf in config.vrfs.iter() {
+            let v = Vrf::new(
+                vrf.import_rts.iter().copied().collect(),
+                vrf.export_rts.iter().copied().collect(),
+            );
+            self.set_vrf(leak(&vrf.name), v);
+        }
+        for route in config.originated_routes.iter() {
+            let route = BgpRoute {
+                prefix: Prefix(route.prefix),
+                path_id: 0,
+                as_path: route.as_path.iter().map(|a| AsId(*a)).collect(),
+                next_hop: self.router_id,
+                local_pref: route.local_pref,
+                med: route.med,
+                aigp: route.aigp,
+                communities: route.communities.iter().copied().collect(),
+                link_bandwidth: route.link_bandwidth,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: route.route_targets.iter().copied().collect(),
+                origin: route.origin,
+            };
+            self.originate_route(route, queue)?;
+        }
+        Ok(())
     }
 
     // -----------------
@@ -240,130 +1266,235 @@ impl Router {
 
     /// only run bgp decision process (phase 2)
     fn run_bgp_decision_process_for_prefix(&mut self, prefix: Prefix) -> Result<(), DeviceError> {
-        // search the best route and compare
-        let old_entry = self.bgp_rib.get(&prefix);
-        let mut new_entry = None;
-
-        // find the new best route
-        if let Some(rib_in) = self.bgp_rib_in.get(&prefix) {
-            for entry_unprocessed in rib_in.values() {
-                let entry = self.process_bgp_rib_in_route(entry_unprocessed)?;
-                let mut better = true;
-                if let Some(current_best) = new_entry.as_ref() {
-                    better = &entry > current_best;
-                }
-                if better {
-                    new_entry = Some(entry)
+        // search the best routes and compare. VPN routes are kept apart from the global table.
+        let (mut vpn_candidates, mut candidates): (Vec<RIBEntry>, Vec<RIBEntry>) = self
+            .get_known_bgp_routes(prefix)?
+            .into_iter()
+            .partition(|entry| !entry.route.route_targets.is_empty());
+        let mut new_entries: Vec<RIBEntry> = Vec::new();
+
+        // select the best VPN route of each VRF
+        for (name, vrf) in self.vrfs.iter() {
+            let mut vrf_candidates: Vec<RIBEntry> = vpn_candidates
+                .iter()
+                .filter(|entry| vrf.imports(&entry.route.route_targets))
+                .cloned()
+                .collect();
+            let rib = self.bgp_vrf_rib.entry(name).or_default();
+            match pop_best_route(
+                &mut vrf_candidates,
+                &self.decision_steps,
+                self.tiebreak_seed,
+                self.always_compare_med,
+            ) {
+                Some(entry) => rib.insert(prefix, entry),
+                None => rib.remove(&prefix),
+            };
+        }
+
+        // select the best VPN route, which is advertised to the peers
+        match pop_best_route(
+            &mut vpn_candidates,
+            &self.decision_steps,
+            self.tiebreak_seed,
+            self.always_compare_med,
+        ) {
+            Some(entry) => self.bgp_vpn_rib.insert(prefix, entry),
+            None => self.bgp_vpn_rib.remove(&prefix),
+        };
+
+        // find the new best route, and all routes which are equally good up to the IGP cost
+        while new_entries.len() < self.bgp_maximum_paths {
+            let entry = match pop_best_route(
+                &mut candidates,
+                &self.decision_steps,
+                self.tiebreak_seed,
+                self.always_compare_med,
+            ) {
+                Some(entry) => entry,
+                None => break,
+            };
+            if let Some(best) = new_entries.first() {
+                if !best.is_multipath_equal(&entry) {
+                    break;
                 }
             }
+            new_entries.push(entry);
         }
 
-        // check if the entry will get changed
-        if new_entry.as_ref() != old_entry {
-            // replace the entry
-            if let Some(new_entry) = new_entry {
-                // insert the new entry
-                self.bgp_rib.insert(prefix, new_entry);
-            } else {
+        // check if the entries will get changed
+        if self.bgp_rib.get(&prefix) != Some(&new_entries) {
+            // replace the entries
+            if new_entries.is_empty() {
                 self.bgp_rib.remove(&prefix);
+            } else {
+                self.bgp_rib.insert(prefix, new_entries);
             }
         }
         Ok(())
     }
 
+    /// Recompute the sorted list of all BGP peers from the established sessions.
+    fn update_bgp_peers(&mut self) {
+        self.bgp_peers = self
+            .ibgp_client_sessions
+            .iter()
+            .chain(self.ibgp_peer_sessions.iter())
+            .chain(self.ebgp_sessions.iter())
+            .copied()
+            .collect();
+        self.bgp_peers.sort();
+    }
+
     /// only run bgp route dissemination (phase 3)
     fn run_bgp_route_dissemination_for_prefix(
         &mut self,
         prefix: Prefix,
         queue: &mut EventQueue,
     ) -> Result<(), DeviceError> {
-        if !self.bgp_rib_out.contains_key(&prefix) {
-            self.bgp_rib_out.insert(prefix, HashMap::new());
-        }
+        let bgp_peers = std::mem::take(&mut self.bgp_peers);
+        let result =
+            self.run_bgp_route_dissemination_for_prefix_to_peers(prefix, &bgp_peers, queue);
+        self.bgp_peers = bgp_peers;
+        result
+    }
 
-        let bgp_peers: HashSet<RouterId> = self
-            .ibgp_client_sessions
-            .union(&self.ibgp_peer_sessions)
-            .cloned()
-            .collect::<HashSet<_>>()
-            .union(&self.ebgp_sessions)
-            .cloned()
-            .collect::<HashSet<_>>();
+    /// only run bgp route dissemination (phase 3) towards the given peers
+    fn run_bgp_route_dissemination_for_prefix_to_peers(
+        &mut self,
+        prefix: Prefix,
+        bgp_peers: &[RouterId],
+        queue: &mut EventQueue,
+    ) -> Result<(), DeviceError> {
+        self.bgp_rib_out.entry(prefix).or_default();
 
-        for peer in bgp_peers {
-            // apply the route for the specific peer
-            let best_route: Option<RIBEntry> = self
-                .bgp_rib
-                .get(&prefix)
-                .map(|e| self.process_bgp_rib_out_route(e, peer))
-                .transpose()?;
-            // check if the current information is the same
-            let current_route: Option<RIBEntry> = self
+        // if the condition for advertising the prefix is not met, no path is advertised.
+        let advertise = self.is_advertise_condition_met(prefix);
+
+        for peer in bgp_peers.iter().copied() {
+            // number of paths advertised to the peer. Without ADD-PATH, only the best route is sent.
+            let num_paths = if advertise {
+                self.bgp_add_path.get(&peer).copied().unwrap_or(1)
+            } else {
+                0
+            };
+            let best_routes = self.get_best_routes(prefix, num_paths)?;
+            // also revisit paths that were advertised before, but are no longer used.
+            let num_old_paths = self
                 .bgp_rib_out
-                .get_mut(&prefix)
-                .and_then(|rib| rib.get(&peer).cloned());
-            let event = match (best_route, current_route) {
-                (Some(best_r), Some(current_r)) if best_r == current_r => {
-                    // Nothing to do, no new route received
-                    None
-                }
-                (Some(best_r), Some(_)) => {
-                    // Route information was changed
-                    if self.should_export_route(best_r.from_id, peer)? {
-                        // update the route
-                        let event = BgpEvent::Update(best_r.route.clone());
+                .get(&prefix)
+                .and_then(|rib| {
+                    rib.keys()
+                        .filter(|(p, _)| *p == peer)
+                        .map(|(_, path_id)| *path_id as usize + 1)
+                        .max()
+                })
+                .unwrap_or(0);
+
+            for path_id in 0..num_paths.max(num_old_paths) {
+                let key = (peer, path_id as PathId);
+                // apply the route for the specific peer. Routes which must not be exported to
+                // the peer are treated as if no route exists, such that the previously advertised
+                // route is withdrawn, even if the route itself did not change.
+                let best_route: Option<RIBEntry> = match best_routes.get(path_id) {
+                    Some(e) if self.should_export_route(e, peer)? => {
+                        Some(self.process_bgp_rib_out_route(e, peer, path_id as PathId)?)
+                    }
+                    _ => None,
+                };
+                // check if the current information is the same
+                let current_route: Option<RIBEntry> = self
+                    .bgp_rib_out
+                    .get_mut(&prefix)
+                    .and_then(|rib| rib.get(&key).cloned());
+                let event = match (best_route, current_route) {
+                    (Some(best_r), Some(current_r)) if best_r == current_r => {
+                        // Nothing to do, no new route received
+                        None
+                    }
+                    (Some(best_r), _) => {
+                        // New route information received, or route information was changed
+                        self.bgp_update_seq += 1;
+                        let mut route = best_r.route.clone();
+                        route.seq = Some(self.bgp_update_seq);
+                        let event = BgpEvent::Update(route);
                         self.bgp_rib_out
                             .get_mut(&prefix)
-                            .and_then(|rib| rib.insert(peer, best_r));
+                            .and_then(|rib| rib.insert(key, best_r));
                         Some(event)
-                    } else {
-                        // send a withdraw of the old route
-                        self.bgp_rib_out
-                            .get_mut(&prefix)
-                            .and_then(|rib| rib.remove(&peer));
-                        Some(BgpEvent::Withdraw(prefix))
                     }
-                }
-                (Some(best_r), None) => {
-                    // New route information received
-                    if self.should_export_route(best_r.from_id, peer)? {
-                        // send the route
-                        let event = BgpEvent::Update(best_r.route.clone());
+                    (None, Some(_)) => {
+                        // Current route must be WITHDRAWN, since we do no longer know any route
+                        // which may be exported to the peer
                         self.bgp_rib_out
                             .get_mut(&prefix)
-                            .and_then(|rib| rib.insert(peer, best_r));
-                        Some(event)
-                    } else {
+                            .and_then(|rib| rib.remove(&key));
+                        Some(withdraw_event(prefix, key.1))
+                    }
+                    (None, None) => {
+                        // Nothing to do
                         None
                     }
+                };
+                // add the event to the queue
+                if let Some(event) = event {
+                    queue.push_back(Event::Bgp(self.router_id, peer, event));
                 }
-                (None, Some(_)) => {
-                    // Current route must be WITHDRAWN, since we do no longer know any route
-                    self.bgp_rib_out
-                        .get_mut(&prefix)
-                        .and_then(|rib| rib.remove(&peer));
-                    Some(BgpEvent::Withdraw(prefix))
-                }
-                (None, None) => {
-                    // Nothing to do
-                    None
-                }
-            };
-            // add the event to the queue
-            if let Some(event) = event {
-                queue.push_back(Event::Bgp(self.router_id, peer, event));
             }
         }
 
         Ok(())
     }
 
+    /// Returns the `n` best routes for the prefix, sorted by preference. The first route is always
+    /// the selected route in `bgp_rib`, or the VPN route in `bgp_vpn_rib` if there is none. The
+    /// remaining ones are the next best routes of the same kind in `bgp_rib_in`, used for ADD-PATH.
+    fn get_best_routes(&self, prefix: Prefix, n: usize) -> Result<Vec<RIBEntry>, DeviceError> {
+        // without a route in the global table, the VPN route is advertised
+        let selected = self
+            .get_selected_bgp_route(prefix)
+            .or_else(|| self.get_selected_vpn_route(prefix));
+        let mut best_routes: Vec<RIBEntry> = match selected {
+            Some(entry) if n > 0 => vec![entry],
+            _ => return Ok(Vec::new()),
+        };
+        if n == 1 {
+            return Ok(best_routes);
+        }
+        let vpn = !best_routes[0].route.route_targets.is_empty();
+        let mut candidates: Vec<RIBEntry> = self
+            .get_known_bgp_routes(prefix)?
+            .into_iter()
+            .filter(|e| !best_routes.contains(e) && e.route.route_targets.is_empty() != vpn)
+            .collect();
+        while best_routes.len() < n {
+            match pop_best_route(
+                &mut candidates,
+                &self.decision_steps,
+                self.tiebreak_seed,
+                self.always_compare_med,
+            ) {
+                Some(entry) => best_routes.push(entry),
+                None => break,
+            }
+        }
+        Ok(best_routes)
+    }
+
     /// Tries to insert the route into the bgp_rib_in table. If the same route already exists in the table,
     /// replace the route. It returns the prefix for which the route was inserted
     fn insert_bgp_route(&mut self, route: BgpRoute, from: RouterId) -> Result<Prefix, DeviceError> {
         let prefix = route.prefix;
         let from_type = self.get_bgp_session_type(from)?;
 
+        // drop rejected routes. The route replaces the previous route from the same neighbor, so
+        // the previous route is removed.
+        if let Some(reason) = self.get_reject_reason(&route, from) {
+            self.bgp_reject_reasons.insert((from, prefix), reason);
+            return Ok(self.remove_bgp_route(prefix, route.path_id, from));
+        }
+        self.bgp_reject_reasons.remove(&(from, prefix));
+
         // the incoming bgp routes should not be processed here!
         // This is because when configuration chagnes, the routes should also change without needing
         // to receive them again.
@@ -375,27 +1506,57 @@ impl Router {
             igp_cost: None,
         };
 
-        let rib_in = if self.bgp_rib_in.contains_key(&new_entry.route.prefix) {
-            self.bgp_rib_in.get_mut(&new_entry.route.prefix).unwrap()
-        } else {
-            self.bgp_rib_in
-                .insert(new_entry.route.prefix, HashMap::new());
-            self.bgp_rib_in.get_mut(&new_entry.route.prefix).unwrap()
-        };
+        let rib_in = self.bgp_rib_in.entry(new_entry.route.prefix).or_default();
+
+        // detect updates which are older than the route they replace
+        let key = (from, new_entry.route.path_id);
+        if let (Some(seq), Some(old_seq)) = (
+            new_entry.route.seq,
+            rib_in.get(&key).and_then(|old| old.route.seq),
+        ) {
+            if seq < old_seq {
+                self.bgp_stale_updates.push((from, prefix));
+            }
+        }
 
         // insert the new route. If an old route was received, just ignore that one and drop it.
-        rib_in.insert(from, new_entry);
+        rib_in.insert(key, new_entry);
 
         Ok(prefix)
     }
 
+    /// Returns true if the same route was already received from the peer, and is stored in
+    /// bgp_rib_in.
+    fn is_route_known(&self, route: &BgpRoute, from: RouterId) -> bool {
+        self.bgp_rib_in
+            .get(&route.prefix)
+            .and_then(|rib| rib.get(&(from, route.path_id)))
+            .map(|entry| &entry.route == route)
+            .unwrap_or(false)
+    }
+
+    /// Returns true if the peer has sent routes for more prefixes than allowed by its
+    /// maximum-prefix limit.
+    fn is_maximum_prefix_exceeded(&self, peer: RouterId) -> bool {
+        match self.bgp_maximum_prefix.get(&peer) {
+            Some(limit) => {
+                self.bgp_rib_in
+                    .values()
+                    .filter(|rib| rib.keys().any(|(p, _)| *p == peer))
+                    .count()
+                    > *limit
+            }
+            None => false,
+        }
+    }
+
     /// remove an existing bgp route in bgp_rib_in and returns the prefix for which the route was
     /// inserted.
-    fn remove_bgp_route(&mut self, prefix: Prefix, from: RouterId) -> Prefix {
+    fn remove_bgp_route(&mut self, prefix: Prefix, path_id: PathId, from: RouterId) -> Prefix {
         // check if the prefix does exist in the table
         self.bgp_rib_in
             .get_mut(&prefix)
-            .and_then(|rib| rib.remove(&from));
+            .and_then(|rib| rib.remove(&(from, path_id)));
         prefix
     }
 
@@ -405,8 +1566,12 @@ impl Router {
             Some(
                 self.policy_bgp_local_pref
                     .get(&entry.from_id)
-                    .map(|x| *x) // copy the value received from the hashmap
-                    .unwrap_or(100), // if no value was received, use default of 100
+                    .copied() // copy the value received from the hashmap
+                    .or_else(|| {
+                        self.get_peer_group(entry.from_id)
+                            .and_then(|g| g.local_pref)
+                    })
+                    .unwrap_or(self.default_local_pref), // if no value was received, use default
             )
         } else {
             entry.route.local_pref
@@ -426,11 +1591,47 @@ impl Router {
         let mut new_route = entry.route.clone_default();
         new_route.local_pref = local_pref;
 
+        // the eBGP peer is the advertiser of the route
+        if entry.from_type.is_ebgp() {
+            new_route.router_id = Some(entry.from_id);
+        }
+
+        // tag the bandwidth of the link to the eBGP peer
+        if entry.from_type.is_ebgp() {
+            new_route.link_bandwidth = self.policy_bgp_link_bandwidth.get(&entry.from_id).copied();
+        }
+
+        // tag the communities configured for the eBGP peer
+        if entry.from_type.is_ebgp() {
+            if let Some(communities) = self.policy_bgp_communities.get(&entry.from_id) {
+                new_route.communities.extend(communities.iter().copied());
+            }
+        }
+
+        // mark the ingress peer of routes entering the AS, if it fits into a community
+        if self.mark_ingress_community && entry.from_type.is_ebgp() {
+            if let Some(community) = ingress_community(self.as_id, entry.from_id) {
+                new_route.communities.insert(community);
+            }
+        }
+
+        // routes of a session in graceful shutdown are tagged, and routes with the tag are not
+        // preferred.
+        if self.bgp_graceful_shutdown.contains(&entry.from_id) {
+            new_route.communities.insert(COMMUNITY_GRACEFUL_SHUTDOWN);
+        }
+        if new_route.communities.contains(&COMMUNITY_GRACEFUL_SHUTDOWN) {
+            new_route.local_pref = Some(0);
+        }
+
         // set the next hop to the egress from router if the message came from externally
         if entry.from_type.is_ebgp() {
             new_route.next_hop = entry.from_id;
         }
 
+        // accumulate the IGP cost to the next hop (RFC 7311)
+        new_route.aigp = new_route.aigp.map(|aigp| aigp + igp_cost.round() as u64);
+
         Ok(RIBEntry {
             route: new_route,
             from_type: entry.from_type,
@@ -445,11 +1646,38 @@ impl Router {
         &self,
         entry: &RIBEntry,
         target_peer: RouterId,
+        path_id: PathId,
     ) -> Result<RIBEntry, DeviceError> {
         let mut new_route = entry.route.clone();
+        new_route.path_id = path_id;
+        // the IGP cost to the next hop was accumulated when the route was received. Unless the
+        // next hop is rewritten, the peer accumulates its own IGP cost to the same next hop.
+        if !self.ebgp_sessions.contains(&target_peer)
+            || self.bgp_next_hop_unchanged.contains(&target_peer)
+        {
+            let igp_cost = entry.igp_cost.unwrap_or(0.0).round() as u64;
+            new_route.aigp = new_route.aigp.map(|aigp| aigp.saturating_sub(igp_cost));
+        }
         if self.ebgp_sessions.contains(&target_peer) {
-            new_route.next_hop = self.router_id;
             new_route.local_pref = None;
+            new_route.link_bandwidth = None;
+            new_route.cluster_list.clear();
+            new_route.originator_id = None;
+            new_route.router_id = None;
+            if !self.bgp_next_hop_unchanged.contains(&target_peer) {
+                new_route.next_hop = self.router_id;
+            }
+        } else if entry.from_type.is_ibgp() {
+            // the route is reflected. Only the first route reflector sets the ORIGINATOR_ID.
+            if new_route.originator_id.is_none() {
+                new_route.originator_id = Some(entry.from_id);
+            }
+            if let Some(cluster_id) = self.cluster_id {
+                new_route.cluster_list.insert(0, cluster_id);
+            }
+        } else {
+            // the route enters the iBGP, advertised by this router
+            new_route.router_id = Some(self.router_id);
         }
         Ok(RIBEntry {
             route: new_route,
@@ -459,41 +1687,308 @@ impl Router {
         })
     }
 
-    /// returns the BgpSessionType for a peer
-    fn get_bgp_session_type(&self, peer: RouterId) -> Result<BgpSessionType, DeviceError> {
-        if self.ibgp_peer_sessions.contains(&peer) {
-            Ok(BgpSessionType::IBgpPeer)
-        } else if self.ibgp_client_sessions.contains(&peer) {
-            Ok(BgpSessionType::IBgpClient)
-        } else if self.ebgp_sessions.contains(&peer) {
-            Ok(BgpSessionType::EBgp)
-        } else {
-            Err(DeviceError::NoBgpSession(peer))
+    /// Returns the reason why the route received from the neighbor must be dropped, or `None` if
+    /// it is accepted.
+    fn get_reject_reason(&self, route: &BgpRoute, from: RouterId) -> Option<RejectReason> {
+        // drop routes which already traversed the own AS
+        if self.ebgp_sessions.contains(&from) && route.as_path.contains(&self.as_id) {
+            return Some(RejectReason::AsPathLoop);
         }
+
+        // drop routes with an invalid origin.
+        if self.rpki_drop_invalid && self.rpki_validate(route) == RpkiValidation::Invalid {
+            return Some(RejectReason::RpkiInvalid);
+        }
+
+        // drop routes whose AS path is too long.
+        if let Some(limit) = self.bgp_max_as_path_len.get(&from) {
+            if route.as_path.len() > *limit {
+                return Some(RejectReason::MaxAsPathLength);
+            }
+        }
+
+        // drop routes whose AS path does not start with the AS of the eBGP neighbor.
+        if let (true, Some(neighbor_as)) = (self.enforce_first_as, self.ebgp_neighbor_as.get(&from))
+        {
+            if route.as_path.first() != Some(neighbor_as) {
+                return Some(RejectReason::FirstAsMismatch);
+            }
+        }
+
+        // drop VPN routes which are not imported into any VRF. Routers without VRFs, like route
+        // reflectors, keep all VPN routes.
+        if !route.route_targets.is_empty()
+            && !self.vrfs.is_empty()
+            && !self
+                .vrfs
+                .values()
+                .any(|vrf| vrf.imports(&route.route_targets))
+        {
+            return Some(RejectReason::NoVrfImport);
+        }
+
+        // drop routes which were already reflected by the own cluster.
+        if let Some(cluster_id) = self.cluster_id {
+            if route.cluster_list.contains(&cluster_id) {
+                return Some(RejectReason::ClusterListLoop);
+            }
+        }
+
+        // drop routes which were originally learned from the router itself.
+        if route.originator_id == Some(self.router_id) {
+            return Some(RejectReason::OriginatorLoop);
+        }
+
+        None
+    }
+
+    /// returns true if the prefix may be advertised, based on the conditional advertisement.
+    fn is_advertise_condition_met(&self, prefix: Prefix) -> bool {
+        match self.conditional_advertise.get(&prefix) {
+            Some((condition, ConditionMode::Exist)) => self.bgp_rib.contains_key(condition),
+            Some((condition, ConditionMode::NonExist)) => !self.bgp_rib.contains_key(condition),
+            None => true,
+        }
+    }
+
+    /// returns all prefixes whose advertisement depends on the condition prefix.
+    fn get_conditional_prefixes(&self, condition_prefix: Prefix) -> Vec<Prefix> {
+        let mut prefixes: Vec<Prefix> = self
+            .conditional_advertise
+            .iter()
+            .filter(|(_, (condition, _))| *condition == condition_prefix)
+            .map(|(prefix, _)| *prefix)
+            .collect();
+        prefixes.sort();
+        prefixes
+    }
+
+    /// Returns all known prefixes, sorted, such that they are always processed in the same order.
+    fn sorted_known_prefixes(&self) -> Vec<Prefix> {
+        let mut prefixes: Vec<Prefix> = self.bgp_known_prefixes.iter().copied().collect();
+        prefixes.sort();
+        prefixes
+    }
+
+    /// returns the peer group of which the neighbor is a member
+    fn get_peer_group(&self, neighbor: RouterId) -> Option<&PeerGroup> {
+        self.peer_group_members
+            .get(&neighbor)
+            .and_then(|name| self.peer_groups.get(name))
     }
 
     /// returns a bool which tells to export the route to the target, which was advertised by the
     /// source.
-    fn should_export_route(&self, from: RouterId, to: RouterId) -> Result<bool, DeviceError> {
+    fn should_export_route(&self, entry: &RIBEntry, to: RouterId) -> Result<bool, DeviceError> {
+        let from = entry.from_id;
         // never advertise a route to the receiver
         if from == to {
             return Ok(false);
         }
+        // check the well-known communities
+        let communities = &entry.route.communities;
+        if communities.contains(&COMMUNITY_NO_ADVERTISE)
+            || (communities.contains(&COMMUNITY_NO_EXPORT) && self.ebgp_sessions.contains(&to))
+        {
+            return Ok(false);
+        }
+        // VPN routes are only exchanged within the AS
+        if !entry.route.route_targets.is_empty() && self.ebgp_sessions.contains(&to) {
+            return Ok(false);
+        }
+        // locally originated routes are advertised to all peers
+        if from == self.router_id {
+            return Ok(true);
+        }
         // read the policy
         if self.policy_bgp_route_no_export.contains(&(from, to)) {
             return Ok(false);
         }
+        // read the policy of the peer group
+        if let (Some(from_group), Some(to_group)) =
+            (self.peer_group_members.get(&from), self.get_peer_group(to))
+        {
+            if to_group.no_export_from.contains(from_group) {
+                return Ok(false);
+            }
+        }
         // check the types
         let from_type = self.get_bgp_session_type(from)?;
         let to_type = self.get_bgp_session_type(to)?;
 
-        Ok(match (from_type, to_type) {
-            (BgpSessionType::EBgp, _) => true,
-            (BgpSessionType::IBgpClient, _) => true,
-            (_, BgpSessionType::EBgp) => true,
-            (_, BgpSessionType::IBgpClient) => true,
-            _ => false,
-        })
+        if !self.client_to_client_reflection
+            && from_type == BgpSessionType::IBgpClient
+            && to_type == BgpSessionType::IBgpClient
+        {
+            return Ok(false);
+        }
+
+        Ok(matches!(
+            (from_type, to_type),
+            (BgpSessionType::EBgp, _)
+                | (BgpSessionType::IBgpClient, _)
+                | (_, BgpSessionType::EBgp)
+                | (_, BgpSessionType::IBgpClient)
+        ))
+    }
+}
+
+/// Removes the most preferred route from the candidates and returns it. The order of the
+/// candidates is not total, so the routes are not sorted. Instead, the first route which is not
+/// beaten by any later one is chosen. Unless `always_compare_med` is set, the best route of each
+/// neighboring AS is chosen first, and the best of those is chosen without comparing the MED.
+fn pop_best_route(
+    candidates: &mut Vec<RIBEntry>,
+    steps: &[DecisionStep],
+    tiebreak_seed: Option<u64>,
+    always_compare_med: bool,
+) -> Option<RIBEntry> {
+    let best_idx = if always_compare_med {
+        best_route_index(candidates, 0..candidates.len(), steps, tiebreak_seed)?
+    } else {
+        let mut groups: Vec<(Option<AsId>, usize)> = Vec::new();
+        for (idx, entry) in candidates.iter().enumerate() {
+            let neighbor_as = entry.neighbor_as();
+            match groups.iter_mut().find(|(as_id, _)| *as_id == neighbor_as) {
+                Some((_, best)) => {
+                    if is_preferred(entry, &candidates[*best], steps, tiebreak_seed) {
+                        *best = idx;
+                    }
+                }
+                None => groups.push((neighbor_as, idx)),
+            }
+        }
+        best_route_index(
+            candidates,
+            groups.into_iter().map(|(_, idx)| idx),
+            &steps_without_med(steps),
+            tiebreak_seed,
+        )?
+    };
+    Some(candidates.remove(best_idx))
+}
+
+/// Returns the index of the most preferred route among the candidates with the given indices, or
+/// None if no index is given.
+fn best_route_index(
+    candidates: &[RIBEntry],
+    indices: impl IntoIterator<Item = usize>,
+    steps: &[DecisionStep],
+    tiebreak_seed: Option<u64>,
+) -> Option<usize> {
+    indices.into_iter().fold(None, |best, idx| match best {
+        Some(best) if !is_preferred(&candidates[idx], &candidates[best], steps, tiebreak_seed) => {
+            Some(best)
+        }
+        _ => Some(idx),
+    })
+}
+
+/// Returns the steps of the decision process without comparing the MED.
+fn steps_without_med(steps: &[DecisionStep]) -> Vec<DecisionStep> {
+    steps
+        .iter()
+        .copied()
+        .filter(|step| *step != DecisionStep::Med)
+        .collect()
+}
+
+/// Returns true if `a` is preferred over `b`, applying the steps of the decision process in the
+/// given order. If a `tiebreak_seed` is given, routes which are equally good up to the final
+/// tiebreak are decided randomly. The decision only depends on the seed and on the two routes,
+/// such that it is reproducible.
+fn is_preferred(
+    a: &RIBEntry,
+    b: &RIBEntry,
+    steps: &[DecisionStep],
+    tiebreak_seed: Option<u64>,
+) -> bool {
+    match (a.compare_with_steps(b, steps), tiebreak_seed) {
+        ((_, DecisionStep::Tiebreak), Some(seed)) => {
+            tiebreak_hash(a, seed) < tiebreak_hash(b, seed)
+        }
+        ((ordering, _), _) => ordering == Ordering::Greater,
+    }
+}
+
+/// Hash of the next hop and the neighbor of a route, used for random tiebreaking. The hash only
+/// depends on the seed and the route, such that the result is the same on every platform and
+/// with every Rust release.
+fn tiebreak_hash(entry: &RIBEntry, seed: u64) -> u64 {
+    let hash = splitmix64(seed ^ entry.route.next_hop.index() as u64);
+    splitmix64(hash ^ entry.from_id.index() as u64)
+}
+
+/// One step of the splitmix64 generator, used as a fixed mixing function.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Creates the withdraw event for a path of a prefix. The path 0 is withdrawn with a regular
+/// withdraw, such that sessions without ADD-PATH are not affected.
+fn withdraw_event(prefix: Prefix, path_id: PathId) -> BgpEvent {
+    if path_id == 0 {
+        BgpEvent::Withdraw(prefix)
+    } else {
+        BgpEvent::WithdrawPath(prefix, path_id)
+    }
+}
+
+/// Result of the next hop lookup for a prefix on a router
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NextHop {
+    /// No BGP route is selected for the prefix
+    NoRoute,
+    /// A route is selected, but its BGP next hop `#0` is not reachable in the IGP topology
+    Unreachable(RouterId),
+    /// Traffic for the prefix is forwarded to the neighbors `#0`. The first neighbor is the next
+    /// hop of the selected route. It contains multiple neighbors only with BGP multipath.
+    Via(Vec<RouterId>),
+}
+
+/// Reason why a received route is dropped by a router (see `Router::last_reject_reason`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The AS path of a route received via eBGP contains the own AS
+    AsPathLoop,
+    /// The origin of the route is invalid according to the known ROAs
+    RpkiInvalid,
+    /// The AS path is longer than the limit configured for the neighbor
+    MaxAsPathLength,
+    /// The AS path does not start with the AS of the eBGP neighbor
+    FirstAsMismatch,
+    /// The VPN route is not imported into any VRF
+    NoVrfImport,
+    /// The CLUSTER_LIST already contains the own cluster id
+    ClusterListLoop,
+    /// The ORIGINATOR_ID is the router itself
+    OriginatorLoop,
+    /// The neighbor has sent more prefixes than allowed, and the session was closed
+    MaximumPrefix,
+}
+
+/// Number of entries stored in the BGP tables of a router (see `Router::table_sizes`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableSizes {
+    /// Number of routes received from all peers (`bgp_rib_in`)
+    pub rib_in: usize,
+    /// Number of selected routes, including additional routes installed with BGP multipath
+    pub rib: usize,
+    /// Number of routes advertised to all peers (`bgp_rib_out`)
+    pub rib_out: usize,
+    /// Number of known prefixes
+    pub known_prefixes: usize,
+}
+
+impl std::ops::AddAssign for TableSizes {
+    fn add_assign(&mut self, other: Self) {
+        self.rib_in += other.rib_in;
+        self.rib += other.rib;
+        self.rib_out += other.rib_out;
+        self.known_prefixes += other.known_prefixes;
     }
 }
 
@@ -510,59 +2005,149 @@ pub struct RIBEntry {
     pub igp_cost: Option<LinkWeight>,
 }
 
-impl PartialEq for RIBEntry {
-    fn eq(&self, other: &Self) -> bool {
-        self.route == other.route && self.from_id == other.from_id
-    }
-}
-
-impl PartialOrd for RIBEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+impl RIBEntry {
+    /// Returns true if both routes are equally good up to the IGP cost, such that both can be
+    /// installed with BGP multipath.
+    fn is_multipath_equal(&self, other: &Self) -> bool {
         let s = self.route.clone_default();
         let o = other.route.clone_default();
+        let aigp_equal = match (s.aigp, o.aigp) {
+            (Some(s_aigp), Some(o_aigp)) => s_aigp == o_aigp,
+            _ => true,
+        };
+        s.local_pref == o.local_pref
+            && aigp_equal
+            && s.as_path.len() == o.as_path.len()
+            && s.med == o.med
+            && s.origin == o.origin
+            && self.from_type.is_ebgp() == other.from_type.is_ebgp()
+    }
 
-        if s.local_pref > o.local_pref {
-            return Some(Ordering::Greater);
-        } else if s.local_pref < o.local_pref {
-            return Some(Ordering::Less);
-        }
-
-        if s.as_path.len() < o.as_path.len() {
-            return Some(Ordering::Greater);
-        } else if s.as_path.len() > o.as_path.len() {
-            return Some(Ordering::Less);
-        }
+    /// Compare the two routes, and return the ordering together with the step of the decision
+    /// process which decided it. `Ordering::Greater` means that `self` is preferred over `other`.
+    /// The steps are applied in the default order (see `DEFAULT_DECISION_STEPS`).
+    pub fn compare(&self, other: &Self) -> (Ordering, DecisionStep) {
+        self.compare_with_steps(other, &DEFAULT_DECISION_STEPS)
+    }
 
-        if s.med < o.med {
-            return Some(Ordering::Greater);
-        } else if s.med > o.med {
-            return Some(Ordering::Less);
-        }
+    /// Returns the neighboring AS of the route, i.e., the first AS in the AS path, or None if the
+    /// AS path is empty.
+    pub fn neighbor_as(&self) -> Option<AsId> {
+        self.route.as_path.first().copied()
+    }
 
-        if self.from_type.is_ebgp() && other.from_type.is_ibgp() {
-            return Some(Ordering::Greater);
-        } else if self.from_type.is_ibgp() && self.from_type.is_ebgp() {
-            return Some(Ordering::Less);
+    /// Compare the two routes like `compare`, but apply the steps of the decision process in the
+    /// given order. Steps which are not given are skipped, except for the final tiebreak, which is
+    /// always applied last.
+    pub fn compare_with_steps(
+        &self,
+        other: &Self,
+        steps: &[DecisionStep],
+    ) -> (Ordering, DecisionStep) {
+        for step in steps
+            .iter()
+            .copied()
+            .chain(std::iter::once(DecisionStep::Tiebreak))
+        {
+            match self.compare_step(other, step) {
+                Ordering::Equal => {}
+                ordering => return (ordering, step),
+            }
         }
+        (Ordering::Equal, DecisionStep::Equal)
+    }
 
-        if self.igp_cost.unwrap() < other.igp_cost.unwrap() {
-            return Some(Ordering::Greater);
-        } else if self.igp_cost > other.igp_cost {
-            return Some(Ordering::Less);
-        }
+    /// Compare the two routes only based on a single step of the decision process.
+    /// `Ordering::Greater` means that `self` is preferred over `other`.
+    fn compare_step(&self, other: &Self, step: DecisionStep) -> Ordering {
+        let s = self.route.clone_default();
+        let o = other.route.clone_default();
 
-        if s.next_hop < o.next_hop {
-            return Some(Ordering::Greater);
-        } else if s.next_hop > o.next_hop {
-            return Some(Ordering::Less);
+        match step {
+            DecisionStep::LocalPref => s.local_pref.cmp(&o.local_pref),
+            // AIGP is only compared if both routes carry the attribute. It already contains the
+            // IGP cost to the next hop.
+            DecisionStep::Aigp => match (s.aigp, o.aigp) {
+                (Some(s_aigp), Some(o_aigp)) => o_aigp.cmp(&s_aigp),
+                _ => Ordering::Equal,
+            },
+            DecisionStep::AsPathLength => o.as_path.len().cmp(&s.as_path.len()),
+            DecisionStep::Med => o.med.cmp(&s.med),
+            DecisionStep::Origin => o.origin.cmp(&s.origin),
+            DecisionStep::EBgpOverIBgp => {
+                if self.from_type.is_ebgp() && other.from_type.is_ibgp() {
+                    Ordering::Greater
+                } else if self.from_type.is_ibgp() && other.from_type.is_ebgp() {
+                    Ordering::Less
+                } else {
+                    Ordering::Equal
+                }
+            }
+            DecisionStep::IgpCost => {
+                if self.igp_cost.unwrap() < other.igp_cost.unwrap() {
+                    Ordering::Greater
+                } else if self.igp_cost > other.igp_cost {
+                    Ordering::Less
+                } else {
+                    Ordering::Equal
+                }
+            }
+            // the router id is only compared if both routes carry it
+            DecisionStep::Tiebreak => match (s.router_id, o.router_id) {
+                (Some(s_id), Some(o_id)) => o_id.cmp(&s_id),
+                _ => Ordering::Equal,
+            }
+            .then_with(|| o.next_hop.cmp(&s.next_hop))
+            .then_with(|| other.from_id.cmp(&self.from_id)),
+            DecisionStep::Equal => Ordering::Equal,
         }
+    }
+}
 
-        if self.from_id < other.from_id {
-            return Some(Ordering::Greater);
-        } else if self.from_id > other.from_id {
-            return Some(Ordering::Less);
-        }
+impl PartialEq for RIBEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.route == other.route && self.from_id == other.from_id
+    }
+}
 
-        Some(Ordering::Equal)
+impl PartialOrd for RIBEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.compare(other).0)
     }
 }
+
+/// Step of the BGP decision process, which decides which of two routes is preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DecisionStep {
+    /// The route with the higher local preference is preferred
+    LocalPref,
+    /// The route with the lower accumulated IGP metric is preferred
+    Aigp,
+    /// The route with the shorter AS path is preferred
+    AsPathLength,
+    /// The route with the lower MED is preferred
+    Med,
+    /// The route with the lower origin is preferred
+    Origin,
+    /// The route learned over eBGP is preferred over the one learned over iBGP
+    EBgpOverIBgp,
+    /// The route with the lower IGP cost to the next hop is preferred
+    IgpCost,
+    /// The route with the lower router id of the advertiser (see `BgpRoute::router_id`), the lower
+    /// next hop, or received from the lower neighbor, is preferred
+    Tiebreak,
+    /// Both routes are equally preferred
+    Equal,
+}
+
+/// Default order of the steps of the BGP decision process, used unless the router is configured
+/// otherwise (see `Router::set_decision_steps`). The final tiebreak is always applied last.
+pub const DEFAULT_DECISION_STEPS: [DecisionStep; 7] = [
+    DecisionStep::LocalPref,
+    DecisionStep::Aigp,
+    DecisionStep::AsPathLength,
+    DecisionStep::Med,
+    DecisionStep::Origin,
+    DecisionStep::EBgpOverIBgp,
+    DecisionStep::IgpCost,
+];
@@ -0,0 +1,201 @@
+//! Module containing the serializable description of a network and its converged state
+
+use crate::bgp::Origin;
+use crate::policy::ConditionMode;
+use crate::router::DecisionStep;
+use serde::{Deserialize, Serialize};
+
+/// Complete description of an experiment: the topology, the configuration, the advertised routes
+/// and the resulting forwarding state. It is created with `Network::to_scenario`, and the network
+/// can be reconstructed with `Network::from_scenario`. All routers are referenced by their index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    /// All routers of the network, sorted by their index.
+    pub routers: Vec<ScenarioRouter>,
+    /// All directed links, sorted by their id.
+    pub links: Vec<ScenarioLink>,
+    /// All iBGP sessions, in the form of the arguments of `Network::add_ibgp_session`: source,
+    /// target and whether the source is the route reflector of the target.
+    pub ibgp_sessions: Vec<(u32, u32, bool)>,
+    /// All eBGP sessions, as `(external router, neighbor)`.
+    pub ebgp_sessions: Vec<(u32, u32)>,
+    /// All routes advertised by external routers.
+    pub advertisements: Vec<ScenarioAdvertisement>,
+    /// The forwarding state of all internal routers for all known prefixes.
+    pub forwarding: Vec<ScenarioRoute>,
+}
+
+/// Router of a `Scenario`, with its configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioRouter {
+    /// Index of the router
+    pub id: u32,
+    /// Name of the router
+    pub name: String,
+    /// Kind of the router
+    pub kind: ScenarioRouterKind,
+    /// AS of the router
+    pub as_id: u32,
+    /// BGP configuration of the router. It is empty for external routers and P routers.
+    pub config: ScenarioRouterConfig,
+}
+
+/// BGP configuration of an internal router in a `Scenario`. All lists are sorted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioRouterConfig {
+    /// Local preference assigned to routes from eBGP neighbors (`Router::policy_bgp_local_pref`).
+    pub local_pref: Vec<(u32, u32)>,
+    /// Local preference assigned to routes from eBGP neighbors without any policy
+    /// (`Router::default_local_pref`).
+    pub default_local_pref: u32,
+    /// Routes of the first neighbor which are not exported to the second one
+    /// (`Router::policy_bgp_route_no_export`).
+    pub no_export: Vec<(u32, u32)>,
+    /// Link bandwidth (in Mbps) tagged on routes from eBGP neighbors
+    /// (`Router::policy_bgp_link_bandwidth`).
+    pub link_bandwidth: Vec<(u32, u64)>,
+    /// Communities tagged on routes from eBGP neighbors (`Router::policy_bgp_communities`).
+    pub communities: Vec<(u32, Vec<u32>)>,
+    /// See `Router::mark_ingress_community`
+    pub mark_ingress_community: bool,
+    /// See `Router::client_to_client_reflection`
+    pub client_to_client_reflection: bool,
+    /// See `Router::always_compare_med`
+    pub always_compare_med: bool,
+    /// See `Router::enforce_first_as`
+    pub enforce_first_as: bool,
+    /// See `Router::cluster_id`
+    pub cluster_id: Option<u32>,
+    /// See `Router::tiebreak_seed`
+    pub tiebreak_seed: Option<u64>,
+    /// See `Router::rpki_drop_invalid`
+    pub rpki_drop_invalid: bool,
+    /// Maximum number of installed routes (`Router::set_maximum_paths`).
+    pub maximum_paths: usize,
+    /// Order of the decision process (`Router::set_decision_steps`).
+    pub decision_steps: Vec<DecisionStep>,
+    /// Number of paths advertised to each peer (`Router::set_add_path`).
+    pub add_path: Vec<(u32, usize)>,
+    /// Maximum number of prefixes accepted from each peer (`Router::set_maximum_prefix`).
+    pub maximum_prefix: Vec<(u32, usize)>,
+    /// Maximum AS path length accepted from each peer (`Router::set_max_as_path_len`).
+    pub max_as_path_len: Vec<(u32, usize)>,
+    /// Sessions in graceful shutdown (`Router::set_graceful_shutdown`).
+    pub graceful_shutdown: Vec<u32>,
+    /// eBGP sessions with next-hop-unchanged (`Router::set_next_hop_unchanged`).
+    pub next_hop_unchanged: Vec<u32>,
+    /// Static routes, as `(prefix, next hop)` (`Router::add_static_route`).
+    pub static_routes: Vec<(u32, u32)>,
+    /// Route origin authorizations, as `(prefix, authorized ASes)` (`Router::add_roa`).
+    pub roas: Vec<(u32, Vec<u32>)>,
+    /// Peer groups (`Router::set_peer_group`), sorted by their name.
+    pub peer_groups: Vec<ScenarioPeerGroup>,
+    /// Members of the peer groups, as `(neighbor, peer group)` (`Router::add_peer_group_member`).
+    pub peer_group_members: Vec<(u32, String)>,
+    /// Conditional advertisements, as `(advertised prefix, condition prefix, mode)`
+    /// (`Router::add_conditional_advertise`).
+    pub conditional_advertise: Vec<(u32, u32, ConditionMode)>,
+    /// VRFs (`Router::set_vrf`), sorted by their name.
+    pub vrfs: Vec<ScenarioVrf>,
+    /// Routes originated by the router (`Router::originate_route`), sorted by the prefix.
+    pub originated_routes: Vec<ScenarioOriginatedRoute>,
+}
+
+/// Peer group of a router in a `Scenario` (see `policy::PeerGroup`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioPeerGroup {
+    /// Name of the peer group
+    pub name: String,
+    /// local_pref of routes received from a member of the group
+    pub local_pref: Option<u32>,
+    /// Peer groups whose routes are not exported to members of this group, sorted.
+    pub no_export_from: Vec<String>,
+}
+
+/// VRF of a router in a `Scenario` (see `policy::Vrf`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioVrf {
+    /// Name of the VRF
+    pub name: String,
+    /// Route targets of the VPN routes imported into the VRF
+    pub import_rts: Vec<(u32, u32)>,
+    /// Route targets attached to routes originated in the VRF
+    pub export_rts: Vec<(u32, u32)>,
+}
+
+/// Route originated by an internal router in a `Scenario`. The next hop is the router itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioOriginatedRoute {
+    /// Originated prefix
+    pub prefix: u32,
+    /// AS path of the route
+    pub as_path: Vec<u32>,
+    /// LOCAL_PREF of the route
+    pub local_pref: Option<u32>,
+    /// MED of the route
+    pub med: Option<u32>,
+    /// AIGP of the route
+    pub aigp: Option<u64>,
+    /// Communities of the route
+    pub communities: Vec<u32>,
+    /// Link bandwidth of the route
+    pub link_bandwidth: Option<u64>,
+    /// Route targets of the route, which are set for VPN routes
+    pub route_targets: Vec<(u32, u32)>,
+    /// ORIGIN of the route
+    pub origin: Origin,
+}
+
+/// Directed link of a `Scenario`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioLink {
+    /// Id of the link (see `Network::add_parallel_edge`)
+    pub id: u32,
+    /// Index of the router at which the link starts
+    pub source: u32,
+    /// Index of the router at which the link ends
+    pub target: u32,
+    /// Weight of the link
+    pub weight: f32,
+    /// Whether the link is advertised into the IGP (see `Network::set_link_in_igp`)
+    pub in_igp: bool,
+}
+
+/// Kind of a router in a `Scenario`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScenarioRouterKind {
+    /// Internal router, running BGP and the IGP
+    Internal,
+    /// Internal P router, running only the IGP
+    PRouter,
+    /// External router
+    External,
+}
+
+/// Route advertised by an external router in a `Scenario`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioAdvertisement {
+    /// Index of the external router
+    pub router: u32,
+    /// Advertised prefix
+    pub prefix: u32,
+    /// AS path of the route
+    pub as_path: Vec<u32>,
+    /// MED of the route
+    pub med: Option<u32>,
+    /// Communities of the route
+    pub communities: Vec<u32>,
+    /// ORIGIN of the route
+    pub origin: Origin,
+}
+
+/// Forwarding state of a router for a prefix in a `Scenario`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioRoute {
+    /// Index of the router
+    pub router: u32,
+    /// Prefix
+    pub prefix: u32,
+    /// The path of the traffic (see `Network::get_route`), or the reason why it is dropped.
+    pub path: Result<Vec<u32>, String>,
+}
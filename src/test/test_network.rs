@@ -1,4 +1,20 @@
-use crate::{network::Network, AsId, NetworkError, Prefix, RouterId};
+use crate::bgp::{
+    ingress_community, BgpEvent, BgpRoute, BgpSessionType, Origin, RpkiValidation,
+    COMMUNITY_GRACEFUL_SHUTDOWN, COMMUNITY_NO_ADVERTISE, COMMUNITY_NO_EXPORT,
+};
+use crate::external_router::ExternalRouteAttrs;
+use crate::policy::{ConditionMode, PeerGroup, Vrf};
+use crate::router::{DecisionStep, NextHop, TableSizes};
+use crate::testing::{
+    assert_blackhole, assert_converged, assert_loop, assert_route, assert_route_bad,
+};
+use crate::{
+    network::{ConfigChange, ForwardingChange, MigrationPlan, Network, PropagationStep, Violation},
+    AsId, ConfigError, ConvergenceResult, DeviceError, Event, NetworkError, Prefix, RouterId,
+};
+use maplit::{btreeset, hashmap, hashset};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashSet};
 
 #[test]
 fn test_simple() {
@@ -42,10 +58,138 @@ fn test_simple() {
         .unwrap();
 
     // check that all routes are correct
-    assert_route_equal(&t, b0, prefix, vec![b0, e0]);
-    assert_route_equal(&t, r0, prefix, vec![r0, b0, e0]);
-    assert_route_equal(&t, r1, prefix, vec![r1, b1, e1]);
-    assert_route_equal(&t, b1, prefix, vec![b1, e1]);
+    assert_route(&t, b0, prefix, vec![b0, e0]);
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+    assert_route(&t, r1, prefix, vec![r1, b1, e1]);
+    assert_route(&t, b1, prefix, vec![b1, e1]);
+}
+
+/// Setup the network of `test_simple`, without any advertised routes. All weights are 1, and r0
+/// and b0 form an iBGP cluster, and so do r1 and b1. The returned routers are e0, b0, r0, r1, b1,
+/// e1.
+///
+/// ```text
+/// r0 ----- r1
+/// |        |
+/// b0       b1   internal
+/// |........|............
+/// e0       e1   external
+/// ```
+fn setup_simple() -> (Network, [RouterId; 6]) {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+    let b1 = t.add_router("B1");
+    let e1 = t.add_external_router("E1", AsId(1));
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_edge(r0, r1, 1.0, None).unwrap();
+    t.add_edge(r1, b1, 1.0, None).unwrap();
+    t.add_edge(b1, e1, 1.0, None).unwrap();
+
+    t.add_ibgp_session(r0, b0, true, true).unwrap();
+    t.add_ibgp_session(r1, b1, true, true).unwrap();
+    t.add_ibgp_session(r0, r1, false, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    (t, [e0, b0, r0, r1, b1, e1])
+}
+
+#[test]
+fn test_get_bgp_table() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+    let prefix = Prefix(0);
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    // the bgp table contains exactly one selected route
+    for router in [b0, r0, r1, b1] {
+        let table = t.get_bgp_table(router, prefix).unwrap();
+        assert!(table.is_valid());
+        assert_eq!(table.entries.iter().filter(|(_, s)| *s).count(), 1);
+        assert_eq!(
+            table.selected(),
+            t.get_router(router)
+                .unwrap()
+                .get_selected_bgp_route(prefix)
+                .as_ref()
+        );
+    }
+    let table = t.get_bgp_table(r0, prefix).unwrap();
+    assert_eq!(table.entries.len(), 2);
+    assert_eq!(table.selected().unwrap().from_id, b0);
+}
+
+#[test]
+fn test_get_selected_as_path() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+    let prefix = Prefix(0);
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    // the selected AS path is the advertised one
+    for router in [b0, r0, r1, b1] {
+        assert_eq!(
+            t.get_selected_as_path(router, prefix),
+            Some(vec![AsId(1), AsId(2), AsId(3)])
+        );
+    }
+    assert_eq!(t.get_selected_as_path(r0, Prefix(1)), None);
+    assert_eq!(t.get_selected_as_path(e0, prefix), None);
+}
+
+#[test]
+fn test_message_counts_per_session() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+    let prefix = Prefix(0);
+
+    t.record_trace(true);
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    let trace = t.take_trace();
+
+    // b0 -> r0 sends the route of e0, while r0 keeps it and sends nothing back. b1 -> r1 sends
+    // the route of e1, and r1 -> b1 sends the route of e0, which is withdrawn after the route of
+    // e1 is selected.
+    let counts = t.message_counts_per_session();
+    assert_eq!(counts.get(&(b0, r0)), Some(&1));
+    assert_eq!(counts.get(&(r0, b0)), None);
+    assert_eq!(counts.get(&(b1, r1)), Some(&1));
+    assert_eq!(counts.get(&(r1, b1)), Some(&2));
+    assert_eq!(counts.values().sum::<usize>(), trace.len());
+}
+
+#[test]
+fn test_export_trace_csv() {
+    let (mut t, [e0, _, _, _, _, e1]) = setup_simple();
+    let prefix = Prefix(0);
+
+    t.record_trace(true);
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    // the trace contains one row for each processed event
+    let csv = t.export_trace_csv();
+    let trace = t.take_trace();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("time,from,to,type,prefix"));
+    assert_eq!(lines.next(), Some("0,E0,B0,update,0"));
+    assert_eq!(lines.count(), trace.len() - 1);
 }
 
 #[test]
@@ -90,10 +234,10 @@ fn test_route_order1() {
         .unwrap();
 
     // check that all routes are correct
-    assert_route_equal(&t, b0, prefix, vec![b0, e0]);
-    assert_route_equal(&t, r0, prefix, vec![r0, r1, b0, e0]);
-    assert_route_equal(&t, r1, prefix, vec![r1, b0, e0]);
-    assert_route_equal(&t, b1, prefix, vec![b1, e1]);
+    assert_route(&t, b0, prefix, vec![b0, e0]);
+    assert_route(&t, r0, prefix, vec![r0, r1, b0, e0]);
+    assert_route(&t, r1, prefix, vec![r1, b0, e0]);
+    assert_route(&t, b1, prefix, vec![b1, e1]);
 }
 
 #[test]
@@ -138,14 +282,26 @@ fn test_route_order2() {
         .unwrap();
 
     // check that all routes are correct
-    assert_route_equal(&t, b0, prefix, vec![b0, e0]);
-    assert_route_equal(&t, r0, prefix, vec![r0, b1, e1]);
-    assert_route_equal(&t, r1, prefix, vec![r1, r0, b1, e1]);
-    assert_route_equal(&t, b1, prefix, vec![b1, e1]);
+    assert_route(&t, b0, prefix, vec![b0, e0]);
+    assert_route(&t, r0, prefix, vec![r0, b1, e1]);
+    assert_route(&t, r1, prefix, vec![r1, r0, b1, e1]);
+    assert_route(&t, b1, prefix, vec![b1, e1]);
 }
 
 #[test]
 fn test_bad_gadget() {
+    let (mut t, [e0, ..]) = setup_bad_gadget();
+    let prefix = Prefix(0);
+
+    assert_eq!(
+        t.advertise_external_route(e0, prefix, vec![AsId(0), AsId(1)], None, true),
+        Ok(false)
+    );
+}
+
+/// Setup the bad gadget, with the prefix advertised by e1 and e2, but not yet by e0. The queue is
+/// limited to 1000 events. The returned routers are e0, e1, e2, b0, b1, b2, r0, r1, r2.
+fn setup_bad_gadget() -> (Network, [RouterId; 9]) {
     // weights between ri and bi are 5, weights between ri and bi+1 are 1
     // ri and bi form a iBGP cluster
     //
@@ -202,10 +358,41 @@ fn test_bad_gadget() {
         t.advertise_external_route(e1, prefix, vec![AsId(0), AsId(1)], None, true),
         Ok(true)
     );
+
+    (t, [e0, e1, e2, b0, b1, b2, r0, r1, r2])
+}
+
+#[test]
+fn test_explain_selection() {
+    let (t, [_, _, _, _, b1, _, _, r1, r2]) = setup_bad_gadget();
+    let prefix = Prefix(0);
+
+    // r1 prefers the route of r2 over the one of its own client b1, because b2 is closer
+    let explanation = t.explain_selection(r1, prefix).unwrap();
+    assert_eq!(explanation.selected.as_ref().unwrap().from_id, r2);
+    assert_eq!(explanation.rivals.len(), 1);
+    assert_eq!(explanation.deciding_step(b1), Some(DecisionStep::IgpCost));
+}
+
+#[test]
+fn test_max_queue_len() {
+    let (mut t, [e0, ..]) = setup_bad_gadget();
+    let prefix = Prefix(0);
+
     assert_eq!(
         t.advertise_external_route(e0, prefix, vec![AsId(0), AsId(1)], None, true),
         Ok(false)
     );
+
+    // the queue never gets empty, and exceeds a small limit
+    t.set_max_queue_len(Some(10));
+    assert_eq!(t.do_queue(), Ok(false));
+    t.set_max_queue_len(Some(2));
+    assert!(matches!(
+        t.do_queue(),
+        Err(NetworkError::QueueOverflow { len }) if len > 2
+    ));
+    assert!(t.get_queue().len() > 2);
 }
 
 #[test]
@@ -299,10 +486,10 @@ fn change_ibgp_topology_1() {
         Ok(true)
     );
 
-    assert_route_equal(&n, r1, prefix, vec![r1, e1, p1]);
-    assert_route_equal(&n, r2, prefix, vec![r2, e1, p1]);
-    assert_route_equal(&n, r3, prefix, vec![r3, e2, p2]);
-    assert_route_equal(&n, rr, prefix, vec![rr, e1, p1]);
+    assert_route(&n, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&n, r2, prefix, vec![r2, e1, p1]);
+    assert_route(&n, r3, prefix, vec![r3, e2, p2]);
+    assert_route(&n, rr, prefix, vec![rr, e1, p1]);
 
     // change from the bottom up
     // modify e2
@@ -401,10 +588,10 @@ fn change_ibgp_topology_2() {
         Ok(true)
     );
 
-    assert_route_equal(&n, r1, prefix, vec![r1, e1, p1]);
-    assert_route_equal(&n, r2, prefix, vec![r2, e1, p1]);
-    assert_route_equal(&n, r3, prefix, vec![r3, e2, p2]);
-    assert_route_equal(&n, rr, prefix, vec![rr, e1, p1]);
+    assert_route(&n, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&n, r2, prefix, vec![r2, e1, p1]);
+    assert_route(&n, r3, prefix, vec![r3, e2, p2]);
+    assert_route(&n, rr, prefix, vec![rr, e1, p1]);
 
     // change from the middle routers first
     // modify r1
@@ -412,39 +599,69 @@ fn change_ibgp_topology_2() {
     assert_eq!(n.remove_ibgp_session(r1, r3, true), Ok(true));
     assert_eq!(n.remove_ibgp_session(rr, r1, false), Ok(true));
     assert_eq!(n.add_ibgp_session(rr, r1, true, true), Ok(true));
-    assert_route_equal(&n, r1, prefix, vec![r1, e1, p1]);
-    assert_route_equal(&n, r2, prefix, vec![r2, e1, p1]);
-    assert_route_equal(&n, r3, prefix, vec![r3, e2, p2]);
-    assert_route_equal(&n, rr, prefix, vec![rr, e1, p1]);
+    assert_route(&n, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&n, r2, prefix, vec![r2, e1, p1]);
+    assert_route(&n, r3, prefix, vec![r3, e2, p2]);
+    assert_route(&n, rr, prefix, vec![rr, e1, p1]);
 
     // modify r2
     assert_eq!(n.remove_ibgp_session(r2, r3, true), Ok(true));
     assert_eq!(n.remove_ibgp_session(rr, r2, false), Ok(true));
     assert_eq!(n.add_ibgp_session(rr, r2, true, true), Ok(true));
-    assert_route_equal(&n, r1, prefix, vec![r1, e1, p1]);
-    assert_route_equal(&n, r2, prefix, vec![r2, e1, p1]);
-    assert_route_equal(&n, r3, prefix, vec![r3, e2, p2]);
-    assert_route_equal(&n, rr, prefix, vec![rr, e1, p1]);
+    assert_route(&n, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&n, r2, prefix, vec![r2, e1, p1]);
+    assert_route(&n, r3, prefix, vec![r3, e2, p2]);
+    assert_route(&n, rr, prefix, vec![rr, e1, p1]);
 
     // modify r3
     assert_eq!(n.remove_ibgp_session(rr, r3, false), Ok(true));
     assert_eq!(n.add_ibgp_session(rr, r3, true, true), Ok(true));
-    assert_route_equal(&n, r1, prefix, vec![r1, e1, p1]);
-    assert_route_equal(&n, r2, prefix, vec![r2, e1, p1]);
-    assert_route_equal(&n, r3, prefix, vec![r3, e2, p2]);
-    assert_route_equal(&n, rr, prefix, vec![rr, e1, p1]);
+    assert_route(&n, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&n, r2, prefix, vec![r2, e1, p1]);
+    assert_route(&n, r3, prefix, vec![r3, e2, p2]);
+    assert_route(&n, rr, prefix, vec![rr, e1, p1]);
 
     // modify e2
     assert_eq!(n.remove_ibgp_session(e2, r3, true), Ok(true));
-    assert_route_equal(&n, r1, prefix, vec![r1, e1, p1]);
-    assert_route_equal(&n, r2, prefix, vec![r2, e1, p1]);
-    assert_route_equal(&n, r3, prefix, vec![r3, e3, p3]);
-    assert_route_equal(&n, rr, prefix, vec![rr, e1, p1]);
+    assert_route(&n, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&n, r2, prefix, vec![r2, e1, p1]);
+    assert_route(&n, r3, prefix, vec![r3, e3, p3]);
+    assert_route(&n, rr, prefix, vec![rr, e1, p1]);
 }
 
 #[test]
 fn test_pylon_gadget() {
     // Example from L. Vanbever bgpmig_ton, figure 5
+    let (mut n, [s, rr1, rr2, r1, r2, e0, e1, p0, p1, ps]) = setup_pylon_gadget();
+    let prefix = Prefix(0);
+
+    assert_route(&n, s, prefix, vec![s, ps]);
+    assert_route(&n, rr1, prefix, vec![rr1, e0, p0]);
+    assert_route(&n, rr2, prefix, vec![rr2, rr1, e0, p0]);
+    assert_route(&n, r1, prefix, vec![r1, r2, e0, p0]);
+    assert_route(&n, r2, prefix, vec![r2, e0, p0]);
+
+    // remove session r2 ---> e0
+    assert_eq!(n.remove_ibgp_session(r2, e0, true), Ok(true));
+
+    assert_route(&n, s, prefix, vec![s, ps]);
+    assert_route(&n, rr1, prefix, vec![rr1, e0, p0]);
+    assert_route(&n, rr2, prefix, vec![rr2, e1, p1]);
+    assert_route_bad(&n, r1, prefix, vec![r1, r2, r1]);
+    assert_route_bad(&n, r2, prefix, vec![r2, r1, r2]);
+
+    // add session r1 ---> e1
+    assert_eq!(n.add_ibgp_session(r1, e1, true, true), Ok(true));
+    assert_route(&n, s, prefix, vec![s, ps]);
+    assert_route(&n, rr1, prefix, vec![rr1, rr2, e1, p1]);
+    assert_route(&n, rr2, prefix, vec![rr2, e1, p1]);
+    assert_route(&n, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&n, r2, prefix, vec![r2, r1, e1, p1]);
+}
+
+/// Setup the pylon gadget from L. Vanbever bgpmig_ton, figure 5, with all routes advertised. The
+/// returned routers are s, rr1, rr2, r1, r2, e0, e1, p0, p1, ps.
+fn setup_pylon_gadget() -> (Network, [RouterId; 10]) {
     let mut n = Network::new();
     let prefix = Prefix(0);
 
@@ -496,33 +713,153 @@ fn test_pylon_gadget() {
         Ok(true)
     );
 
-    assert_route_equal(&n, s, prefix, vec![s, ps]);
-    assert_route_equal(&n, rr1, prefix, vec![rr1, e0, p0]);
-    assert_route_equal(&n, rr2, prefix, vec![rr2, rr1, e0, p0]);
-    assert_route_equal(&n, r1, prefix, vec![r1, r2, e0, p0]);
-    assert_route_equal(&n, r2, prefix, vec![r2, e0, p0]);
+    (n, [s, rr1, rr2, r1, r2, e0, e1, p0, p1, ps])
+}
+
+#[test]
+fn test_check_ibgp_correctness() {
+    let (mut n, [_, rr1, _, r1, r2, e0, e1, p0, p1, _]) = setup_pylon_gadget();
+    let prefix = Prefix(0);
 
     // remove session r2 ---> e0
     assert_eq!(n.remove_ibgp_session(r2, e0, true), Ok(true));
 
-    assert_route_equal(&n, s, prefix, vec![s, ps]);
-    assert_route_equal(&n, rr1, prefix, vec![rr1, e0, p0]);
-    assert_route_equal(&n, rr2, prefix, vec![rr2, e1, p1]);
-    assert_route_bad(&n, r1, prefix, vec![r1, r2, r1]);
-    assert_route_bad(&n, r2, prefix, vec![r2, r1, r2]);
+    // r1 and r2 would prefer the closer egress, but don't learn it
+    assert_eq!(
+        n.check_ibgp_correctness(prefix),
+        Ok(vec![
+            Violation {
+                router: r1,
+                selected: Some(p0),
+                optimal: p1
+            },
+            Violation {
+                router: r2,
+                selected: Some(p1),
+                optimal: p0
+            },
+        ])
+    );
 
     // add session r1 ---> e1
     assert_eq!(n.add_ibgp_session(r1, e1, true, true), Ok(true));
-    assert_route_equal(&n, s, prefix, vec![s, ps]);
-    assert_route_equal(&n, rr1, prefix, vec![rr1, rr2, e1, p1]);
-    assert_route_equal(&n, rr2, prefix, vec![rr2, e1, p1]);
-    assert_route_equal(&n, r1, prefix, vec![r1, e1, p1]);
-    assert_route_equal(&n, r2, prefix, vec![r2, r1, e1, p1]);
+    // rr1 and r2 no longer learn the route of e0
+    assert_eq!(
+        n.check_ibgp_correctness(prefix),
+        Ok(vec![
+            Violation {
+                router: rr1,
+                selected: Some(p1),
+                optimal: p0
+            },
+            Violation {
+                router: r2,
+                selected: Some(p1),
+                optimal: p0
+            },
+        ])
+    );
 }
 
 #[test]
-fn carousel_gadget() {
-    // Example from L. Vanbever bgpmig_ton, figure 6
+fn test_check_ibgp_correctness_decision_steps() {
+    let (mut t, [e0, _, r0, r1, b1, e1]) = setup_simple();
+    let prefix = Prefix(0);
+
+    // the route of e1 has a better origin, so the farther egress is optimal for r0
+    let attrs = ExternalRouteAttrs {
+        communities: BTreeSet::new(),
+        origin: Origin::Incomplete,
+    };
+    t.advertise_external_route_with(e0, prefix, vec![AsId(1), AsId(2)], None, attrs, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    assert_route(&t, r0, prefix, vec![r0, r1, b1, e1]);
+    assert_eq!(t.check_ibgp_correctness(prefix), Ok(vec![]));
+
+    // with the IGP cost compared first, the closer egress is optimal for r0. But b0 prefers the
+    // route of e1 due to its origin, and does not advertise the route of e0 to r0.
+    t.get_router_mut(r0)
+        .unwrap()
+        .set_decision_steps(vec![
+            DecisionStep::LocalPref,
+            DecisionStep::IgpCost,
+            DecisionStep::Origin,
+        ])
+        .unwrap();
+    t.schedule_update_router(r0).unwrap();
+    t.do_queue().unwrap();
+    assert_route(&t, r0, prefix, vec![r0, r1, b1, e1]);
+    assert_eq!(
+        t.check_ibgp_correctness(prefix),
+        Ok(vec![Violation {
+            router: r0,
+            selected: Some(e1),
+            optimal: e0
+        }])
+    );
+}
+
+#[test]
+fn test_explain_loop() {
+    let (mut n, [s, _, _, r1, r2, e0, _, p0, p1, _]) = setup_pylon_gadget();
+    let prefix = Prefix(0);
+
+    // remove session r2 ---> e0, which creates a forwarding loop between r1 and r2
+    assert_eq!(n.remove_ibgp_session(r2, e0, true), Ok(true));
+
+    // r1 selects the route of p0, which is reached via r2, and r2 selects the route of p1, which
+    // is reached via r1
+    let explain = |source| -> Vec<(RouterId, Option<RouterId>)> {
+        n.explain_loop(source, prefix)
+            .unwrap()
+            .into_iter()
+            .map(|(r, route)| (r, route.map(|route| route.next_hop)))
+            .collect()
+    };
+    assert_eq!(explain(s), vec![]);
+    assert_eq!(explain(r1), vec![(r1, Some(p0)), (r2, Some(p1))]);
+    assert_eq!(explain(r2), vec![(r2, Some(p1)), (r1, Some(p0))]);
+}
+
+#[test]
+fn test_get_unreachable_prefixes() {
+    let (mut n, [s, rr1, rr2, r1, r2, e0, e1, p0, p1, ps]) = setup_pylon_gadget();
+    let prefix = Prefix(0);
+
+    // remove session r2 ---> e0, which creates a forwarding loop between r1 and r2
+    assert_eq!(n.remove_ibgp_session(r2, e0, true), Ok(true));
+
+    // r1 and r2 cannot reach the prefix, even though both have a route towards a reachable next hop
+    assert_eq!(
+        n.get_unreachable_prefixes(),
+        Ok(hashmap! {r1 => vec![prefix], r2 => vec![prefix]})
+    );
+    assert!(n.get_router(r1).unwrap().unreachable_prefixes().is_empty());
+    assert!(n.get_router(r2).unwrap().unreachable_prefixes().is_empty());
+
+    // add session r1 ---> e1, which resolves the forwarding loop
+    assert_eq!(n.add_ibgp_session(r1, e1, true, true), Ok(true));
+    assert_eq!(n.get_unreachable_prefixes(), Ok(hashmap! {}));
+
+    // without any route, all routers report the prefix as unreachable
+    for p in [ps, p0, p1] {
+        assert_eq!(n.retract_external_route(p, prefix, true), Ok(true));
+    }
+    let unreachable = n.get_unreachable_prefixes().unwrap();
+    for r in [s, rr1, rr2, r1, r2, e0, e1] {
+        assert_eq!(unreachable.get(&r), Some(&vec![prefix]));
+        assert_eq!(
+            n.get_router(r).unwrap().unreachable_prefixes(),
+            vec![prefix]
+        );
+    }
+}
+
+/// Setup the carousel gadget from L. Vanbever bgpmig_ton, figure 6, with all routes advertised.
+/// The returned routers are rr, r1, r2, r3, r4, e1, e2, e3, e4, pr, p1, p2, p3, p4.
+fn setup_carousel_gadget() -> (Network, [RouterId; 14]) {
     let mut n = Network::new();
     let prefix1 = Prefix(1);
     let prefix2 = Prefix(2);
@@ -624,24 +961,34 @@ fn carousel_gadget() {
         Ok(true)
     );
 
-    assert_route_equal(&n, rr, prefix1, vec![rr, pr]);
-    assert_route_equal(&n, rr, prefix2, vec![rr, pr]);
-    assert_route_equal(&n, r1, prefix1, vec![r1, r2, e1, p1]);
-    assert_route_equal(&n, r1, prefix2, vec![r1, rr, pr]);
-    assert_route_equal(&n, r2, prefix1, vec![r2, e1, p1]);
-    assert_route_equal(&n, r2, prefix2, vec![r2, rr, pr]);
-    assert_route_equal(&n, r3, prefix1, vec![r3, rr, pr]);
-    assert_route_equal(&n, r3, prefix2, vec![r3, e4, p4]);
-    assert_route_equal(&n, r4, prefix1, vec![r4, rr, pr]);
-    assert_route_equal(&n, r4, prefix2, vec![r4, r3, e4, p4]);
-    assert_route_equal(&n, e1, prefix1, vec![e1, p1]);
-    assert_route_equal(&n, e1, prefix2, vec![e1, r2, rr, pr]);
-    assert_route_equal(&n, e2, prefix1, vec![e2, r1, r2, e1, p1]);
-    assert_route_equal(&n, e2, prefix2, vec![e2, r4, r3, e4, p4]);
-    assert_route_equal(&n, e3, prefix1, vec![e3, r1, r2, e1, p1]);
-    assert_route_equal(&n, e3, prefix2, vec![e3, r4, r3, e4, p4]);
-    assert_route_equal(&n, e4, prefix1, vec![e4, r3, rr, pr]);
-    assert_route_equal(&n, e4, prefix2, vec![e4, p4]);
+    (n, [rr, r1, r2, r3, r4, e1, e2, e3, e4, pr, p1, p2, p3, p4])
+}
+
+#[test]
+fn carousel_gadget() {
+    // Example from L. Vanbever bgpmig_ton, figure 6
+    let (mut n, [rr, r1, r2, r3, r4, e1, e2, e3, e4, pr, p1, p2, p3, p4]) = setup_carousel_gadget();
+    let prefix1 = Prefix(1);
+    let prefix2 = Prefix(2);
+
+    assert_route(&n, rr, prefix1, vec![rr, pr]);
+    assert_route(&n, rr, prefix2, vec![rr, pr]);
+    assert_route(&n, r1, prefix1, vec![r1, r2, e1, p1]);
+    assert_route(&n, r1, prefix2, vec![r1, rr, pr]);
+    assert_route(&n, r2, prefix1, vec![r2, e1, p1]);
+    assert_route(&n, r2, prefix2, vec![r2, rr, pr]);
+    assert_route(&n, r3, prefix1, vec![r3, rr, pr]);
+    assert_route(&n, r3, prefix2, vec![r3, e4, p4]);
+    assert_route(&n, r4, prefix1, vec![r4, rr, pr]);
+    assert_route(&n, r4, prefix2, vec![r4, r3, e4, p4]);
+    assert_route(&n, e1, prefix1, vec![e1, p1]);
+    assert_route(&n, e1, prefix2, vec![e1, r2, rr, pr]);
+    assert_route(&n, e2, prefix1, vec![e2, r1, r2, e1, p1]);
+    assert_route(&n, e2, prefix2, vec![e2, r4, r3, e4, p4]);
+    assert_route(&n, e3, prefix1, vec![e3, r1, r2, e1, p1]);
+    assert_route(&n, e3, prefix2, vec![e3, r4, r3, e4, p4]);
+    assert_route(&n, e4, prefix1, vec![e4, r3, rr, pr]);
+    assert_route(&n, e4, prefix2, vec![e4, p4]);
 
     // reconfigure e2
     n.get_router_mut(e2)
@@ -653,24 +1000,24 @@ fn carousel_gadget() {
     n.schedule_update_router(e2).unwrap();
     assert_eq!(n.do_queue(), Ok(true));
 
-    assert_route_equal(&n, rr, prefix1, vec![rr, pr]);
-    assert_route_equal(&n, rr, prefix2, vec![rr, pr]);
+    assert_route(&n, rr, prefix1, vec![rr, pr]);
+    assert_route(&n, rr, prefix2, vec![rr, pr]);
     assert_route_bad(&n, r1, prefix1, vec![r1, r2, r1]);
-    assert_route_equal(&n, r1, prefix2, vec![r1, rr, pr]);
+    assert_route(&n, r1, prefix2, vec![r1, rr, pr]);
     assert_route_bad(&n, r2, prefix1, vec![r2, r1, r2]);
-    assert_route_equal(&n, r2, prefix2, vec![r2, r1, rr, pr]);
-    assert_route_equal(&n, r3, prefix1, vec![r3, r4, e2, p2]);
-    assert_route_equal(&n, r3, prefix2, vec![r3, r4, e2, p2]);
-    assert_route_equal(&n, r4, prefix1, vec![r4, e2, p2]);
-    assert_route_equal(&n, r4, prefix2, vec![r4, e2, p2]);
-    assert_route_equal(&n, e1, prefix1, vec![e1, p1]);
-    assert_route_equal(&n, e1, prefix2, vec![e1, r2, r1, rr, pr]);
-    assert_route_equal(&n, e2, prefix1, vec![e2, p2]);
-    assert_route_equal(&n, e2, prefix2, vec![e2, p2]);
-    assert_route_equal(&n, e3, prefix1, vec![e3, r4, e2, p2]);
-    assert_route_equal(&n, e3, prefix2, vec![e3, r4, e2, p2]);
-    assert_route_equal(&n, e4, prefix1, vec![e4, r3, r4, e2, p2]);
-    assert_route_equal(&n, e4, prefix2, vec![e4, p4]);
+    assert_route(&n, r2, prefix2, vec![r2, r1, rr, pr]);
+    assert_route(&n, r3, prefix1, vec![r3, r4, e2, p2]);
+    assert_route(&n, r3, prefix2, vec![r3, r4, e2, p2]);
+    assert_route(&n, r4, prefix1, vec![r4, e2, p2]);
+    assert_route(&n, r4, prefix2, vec![r4, e2, p2]);
+    assert_route(&n, e1, prefix1, vec![e1, p1]);
+    assert_route(&n, e1, prefix2, vec![e1, r2, r1, rr, pr]);
+    assert_route(&n, e2, prefix1, vec![e2, p2]);
+    assert_route(&n, e2, prefix2, vec![e2, p2]);
+    assert_route(&n, e3, prefix1, vec![e3, r4, e2, p2]);
+    assert_route(&n, e3, prefix2, vec![e3, r4, e2, p2]);
+    assert_route(&n, e4, prefix1, vec![e4, r3, r4, e2, p2]);
+    assert_route(&n, e4, prefix2, vec![e4, p4]);
 
     // reconfigure e3
     n.get_router_mut(e3)
@@ -682,94 +1029,3839 @@ fn carousel_gadget() {
     n.schedule_update_router(e3).unwrap();
     assert_eq!(n.do_queue(), Ok(true));
 
-    assert_route_equal(&n, rr, prefix1, vec![rr, pr]);
-    assert_route_equal(&n, rr, prefix2, vec![rr, pr]);
-    assert_route_equal(&n, r1, prefix1, vec![r1, e3, p3]);
-    assert_route_equal(&n, r1, prefix2, vec![r1, e3, p3]);
-    assert_route_equal(&n, r2, prefix1, vec![r2, r1, e3, p3]);
-    assert_route_equal(&n, r2, prefix2, vec![r2, r1, e3, p3]);
-    assert_route_equal(&n, r3, prefix1, vec![r3, r4, e2, p2]);
-    assert_route_equal(&n, r3, prefix2, vec![r3, r4, e2, p2]);
-    assert_route_equal(&n, r4, prefix1, vec![r4, e2, p2]);
-    assert_route_equal(&n, r4, prefix2, vec![r4, e2, p2]);
-    assert_route_equal(&n, e1, prefix1, vec![e1, p1]);
-    assert_route_equal(&n, e1, prefix2, vec![e1, r2, r1, e3, p3]);
-    assert_route_equal(&n, e2, prefix1, vec![e2, p2]);
-    assert_route_equal(&n, e2, prefix2, vec![e2, p2]);
-    assert_route_equal(&n, e3, prefix1, vec![e3, p3]);
-    assert_route_equal(&n, e3, prefix2, vec![e3, p3]);
-    assert_route_equal(&n, e4, prefix1, vec![e4, r3, r4, e2, p2]);
-    assert_route_equal(&n, e4, prefix2, vec![e4, p4]);
-}
-
-fn assert_route_equal(n: &Network, source: RouterId, prefix: Prefix, exp: Vec<RouterId>) {
-    let acq = n.get_route(source, prefix);
-    let exp = exp
-        .iter()
-        .map(|r| n.get_router_name(*r).unwrap())
-        .collect::<Vec<&'static str>>();
-    if let Ok(acq) = acq {
-        let acq = acq
-            .iter()
-            .map(|r| n.get_router_name(*r).unwrap())
-            .collect::<Vec<&'static str>>();
-        assert_eq!(
-            acq,
-            exp,
-            "unexpected path on {} for prefix {}:\n        acq: {:?}, exp: {:?}\n",
-            n.get_router_name(source).unwrap(),
-            prefix.0,
-            acq,
-            exp
-        );
-    } else if let Err(acq) = acq {
-        assert_eq!(
-            Err(&acq),
-            Ok(&exp),
-            "unexpected path on {} for prefix {}: expected good path, but got bad path!\n        acq: {:?}, exp: {:?}\n",
-            n.get_router_name(source).unwrap(),
-            prefix.0,
-            &acq,
-            &exp
-        );
-    }
+    assert_route(&n, rr, prefix1, vec![rr, pr]);
+    assert_route(&n, rr, prefix2, vec![rr, pr]);
+    assert_route(&n, r1, prefix1, vec![r1, e3, p3]);
+    assert_route(&n, r1, prefix2, vec![r1, e3, p3]);
+    assert_route(&n, r2, prefix1, vec![r2, r1, e3, p3]);
+    assert_route(&n, r2, prefix2, vec![r2, r1, e3, p3]);
+    assert_route(&n, r3, prefix1, vec![r3, r4, e2, p2]);
+    assert_route(&n, r3, prefix2, vec![r3, r4, e2, p2]);
+    assert_route(&n, r4, prefix1, vec![r4, e2, p2]);
+    assert_route(&n, r4, prefix2, vec![r4, e2, p2]);
+    assert_route(&n, e1, prefix1, vec![e1, p1]);
+    assert_route(&n, e1, prefix2, vec![e1, r2, r1, e3, p3]);
+    assert_route(&n, e2, prefix1, vec![e2, p2]);
+    assert_route(&n, e2, prefix2, vec![e2, p2]);
+    assert_route(&n, e3, prefix1, vec![e3, p3]);
+    assert_route(&n, e3, prefix2, vec![e3, p3]);
+    assert_route(&n, e4, prefix1, vec![e4, r3, r4, e2, p2]);
+    assert_route(&n, e4, prefix2, vec![e4, p4]);
 }
 
-fn assert_route_bad(n: &Network, source: RouterId, prefix: Prefix, exp: Vec<RouterId>) {
-    let acq = n.get_route(source, prefix);
-    let exp = exp
-        .iter()
-        .map(|r| n.get_router_name(*r).unwrap())
-        .collect::<Vec<&'static str>>();
-    let acq_is_ok = acq.is_ok();
-    if acq_is_ok {
-        let acq = acq
-            .unwrap()
-            .iter()
-            .map(|r| n.get_router_name(*r).unwrap())
-            .collect::<Vec<&'static str>>();
-        assert_eq!(
-            acq, exp,
-            "Bad route expected on path on {} for prefix {}, but got a correct path:\n        acq: {:?}, exp: {:?}",
-            n.get_router_name(source).unwrap(),
-            prefix.0,
-            acq,
-            exp
-        );
-    } else {
-        let acq = match acq.unwrap_err() {
-            NetworkError::ForwardingLoop(x) => x,
-            NetworkError::ForwardingBlackHole(x) => x,
-            e => panic!("Unexpected return type: {:#?}", e),
-        };
-        assert_eq!(
-            &acq,
-            &exp,
-            "Unexpected path on {} for prefix {}:\n        acq: {:?}, exp: {:?}",
-            n.get_router_name(source).unwrap(),
-            prefix.0,
-            &acq,
-            &exp
-        )
+#[test]
+fn test_known_prefixes() {
+    let (mut n, [rr, ..]) = setup_carousel_gadget();
+
+    // both prefixes are known in the network, and on the route reflector
+    assert_eq!(n.known_prefixes(), btreeset! {Prefix(1), Prefix(2)});
+    assert_eq!(
+        n.get_router(rr).unwrap().known_prefixes(),
+        &hashset! {Prefix(1), Prefix(2)}
+    );
+}
+
+#[test]
+fn test_add_path() {
+    // All weights are 1
+    // rr is a route reflector with the clients b0, b1 and r. rr advertises two paths to r.
+    //
+    //        r
+    //        |
+    // b0 --- rr --- b1   internal
+    // |.............|............
+    // |             |    external
+    // e0            e1
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let rr = t.add_router("RR");
+    let r = t.add_router("R");
+    let b1 = t.add_router("B1");
+    let e1 = t.add_external_router("E1", AsId(1));
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, rr, 1.0, None).unwrap();
+    t.add_edge(rr, r, 1.0, None).unwrap();
+    t.add_edge(rr, b1, 1.0, None).unwrap();
+    t.add_edge(b1, e1, 1.0, None).unwrap();
+
+    t.add_ibgp_session(rr, b0, true, true).unwrap();
+    t.add_ibgp_session(rr, b1, true, true).unwrap();
+    t.add_ibgp_session(rr, r, true, true).unwrap();
+    t.get_router_mut(rr).unwrap().set_add_path(r, 2);
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    // r has received both paths from rr, while b1 only knows the best path from rr
+    let paths = t
+        .get_router(r)
+        .unwrap()
+        .get_known_bgp_routes(prefix)
+        .unwrap();
+    assert_eq!(paths.len(), 2);
+    assert!(paths.iter().all(|e| e.from_id == rr));
+    let mut next_hops: Vec<RouterId> = paths.iter().map(|e| e.route.next_hop).collect();
+    next_hops.sort();
+    assert_eq!(next_hops, vec![e0, e1]);
+    let known_b1 = t
+        .get_router(b1)
+        .unwrap()
+        .get_known_bgp_routes(prefix)
+        .unwrap();
+    assert_eq!(known_b1.iter().filter(|e| e.from_id == rr).count(), 1);
+
+    // retracting one of the routes withdraws the additional path
+    t.retract_external_route(e1, prefix, true).unwrap();
+    let paths = t
+        .get_router(r)
+        .unwrap()
+        .get_known_bgp_routes(prefix)
+        .unwrap();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].route.next_hop, e0);
+    assert_route(&t, r, prefix, vec![r, rr, b0, e0]);
+}
+
+#[test]
+fn test_capped_queue() {
+    // All weights are 1
+    // r0 and b0 form a iBGP cluster, and so does r1 and b1
+    //
+    // r0 ----- r1
+    // |        |
+    // |        |
+    // b0       b1   internal
+    // |........|............
+    // |        |    external
+    // e0       e1
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+
+    let prefix = Prefix(0);
+
+    // a tight cap for this single advertisement stops before convergence
+    assert_eq!(
+        t.advertise_external_route_capped(e0, prefix, vec![AsId(1), AsId(2)], None, Some(2)),
+        Ok(false)
+    );
+    // the global limit is unchanged, and the remaining events are processed
+    assert_eq!(t.do_queue(), Ok(true));
+    assert_eq!(
+        t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2)], None, true),
+        Ok(true)
+    );
+
+    assert_route(&t, b0, prefix, vec![b0, e0]);
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+    assert_route(&t, r1, prefix, vec![r1, b1, e1]);
+    assert_route(&t, b1, prefix, vec![b1, e1]);
+}
+
+#[test]
+fn test_validate_rib() {
+    // All weights are 1
+    // r0 and b0 form a iBGP cluster, and so does r1 and b1
+    //
+    // r0 ----- r1
+    // |        |
+    // |        |
+    // b0       b1   internal
+    // |........|............
+    // |        |    external
+    // e0       e1
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+
+    let prefix = Prefix(0);
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    for router in [b0, r0, r1, b1] {
+        assert_eq!(t.validate_rib(router, prefix), Ok(()));
     }
+
+    // change the policy without running the decision process
+    t.get_router_mut(b0)
+        .unwrap()
+        .policy_bgp_local_pref
+        .insert(e0, 200);
+    assert_eq!(
+        t.validate_rib(b0, prefix),
+        Err(NetworkError::RibInconsistency(b0, prefix))
+    );
+    assert!(!t.get_bgp_table(b0, prefix).unwrap().is_valid());
+
+    // after updating the router, the rib is consistent again
+    t.schedule_update_router(b0).unwrap();
+    assert_eq!(t.do_queue(), Ok(true));
+    assert_eq!(t.validate_rib(b0, prefix), Ok(()));
+}
+
+#[test]
+fn test_edge_bandwidth() {
+    // r0 is connected to r1 with a 10G link, and r1 is connected to r2 with a 1G link. The
+    // reverse direction from r2 to r1 has 100G, which is rounded up to a cost of 1.
+    //
+    // r0 --- r1 --- r2
+    let mut t = Network::new();
+
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+    let r2 = t.add_router("R2");
+
+    t.set_reference_bandwidth(10_000);
+    t.add_edge_bandwidth(r0, r1, 10_000, None).unwrap();
+    t.add_edge_bandwidth(r1, r2, 1_000, Some(100_000)).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    let r = t.get_router(r0).unwrap();
+    assert_eq!(r.igp_forwarding_table.get(&r1), Some(&Some((r1, 1.0))));
+    assert_eq!(r.igp_forwarding_table.get(&r2), Some(&Some((r1, 11.0))));
+    let r = t.get_router(r1).unwrap();
+    assert_eq!(r.igp_forwarding_table.get(&r2), Some(&Some((r2, 10.0))));
+    let r = t.get_router(r2).unwrap();
+    assert_eq!(r.igp_forwarding_table.get(&r1), Some(&Some((r1, 1.0))));
+    assert_eq!(r.igp_forwarding_table.get(&r0), Some(&Some((r1, 2.0))));
+}
+
+#[test]
+fn test_transaction() {
+    // Same network as in `test_simple`, but configured within a single transaction
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+    let b1 = t.add_router("B1");
+    let e1 = t.add_external_router("E1", AsId(1));
+
+    let result = t
+        .transaction(|tx| {
+            tx.add_edge(e0, b0, 1.0, None)?;
+            tx.add_edge(b0, r0, 1.0, None)?;
+            tx.add_edge(r0, r1, 1.0, None)?;
+            tx.add_edge(r1, b1, 1.0, None)?;
+            tx.add_edge(b1, e1, 1.0, None)?;
+
+            tx.add_ibgp_session(r0, b0, true)?;
+            tx.add_ibgp_session(r1, b1, true)?;
+            tx.add_ibgp_session(r0, r1, false)?;
+
+            tx.write_igp_fw_tables()?;
+
+            tx.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None)?;
+            tx.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None)
+        })
+        .unwrap();
+    assert_eq!(result, ConvergenceResult::Converged);
+    assert_converged(&t);
+
+    // check that all routes are the same as in `test_simple`
+    assert_route(&t, b0, prefix, vec![b0, e0]);
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+    assert_route(&t, r1, prefix, vec![r1, b1, e1]);
+    assert_route(&t, b1, prefix, vec![b1, e1]);
+
+    // errors are passed through, and the queue is not executed
+    let result = t.transaction(|tx| {
+        tx.retract_external_route(e0, prefix)?;
+        tx.add_ibgp_session(r0, r1, false)
+    });
+    assert_eq!(
+        result,
+        Err(NetworkError::DeviceError(
+            crate::DeviceError::SessionAlreadyExists(r1)
+        ))
+    );
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+    assert!(!t.get_queue().is_empty());
+
+    // the staged withdraw is executed with the next transaction
+    let result = t.transaction(|_| Ok(())).unwrap();
+    assert_eq!(result, ConvergenceResult::Converged);
+    assert_route(&t, b0, prefix, vec![b0, r0, r1, b1, e1]);
+    assert_route(&t, r0, prefix, vec![r0, r1, b1, e1]);
+}
+
+#[test]
+fn test_conditional_advertise() {
+    // All weights are 1
+    // r0 and r1 are iBGP peers, e0 advertises both prefixes.
+    //
+    // e0 ---- r0 ---- r1
+    //
+    // r0 only advertises `prefix` while it knows a route for `condition`.
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+    let condition = Prefix(1);
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+
+    t.add_edge(e0, r0, 1.0, None).unwrap();
+    t.add_edge(r0, r1, 1.0, None).unwrap();
+
+    t.add_ibgp_session(r0, r1, false, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.get_router_mut(r0).unwrap().add_conditional_advertise(
+        prefix,
+        condition,
+        ConditionMode::Exist,
+    );
+
+    // without the condition, the prefix is not advertised
+    t.advertise_external_route(e0, prefix, vec![AsId(1)], None, true)
+        .unwrap();
+    assert_route(&t, r0, prefix, vec![r0, e0]);
+    assert!(t
+        .get_router(r1)
+        .unwrap()
+        .get_selected_bgp_route(prefix)
+        .is_none());
+
+    // the condition appears, and the prefix is advertised
+    t.advertise_external_route(e0, condition, vec![AsId(1)], None, true)
+        .unwrap();
+    assert_route(&t, r1, prefix, vec![r1, r0, e0]);
+
+    // the condition disappears, and the prefix is withdrawn
+    t.retract_external_route(e0, condition, true).unwrap();
+    assert_route(&t, r0, prefix, vec![r0, e0]);
+    assert!(t
+        .get_router(r1)
+        .unwrap()
+        .get_selected_bgp_route(prefix)
+        .is_none());
+
+    // with the inverted condition, the prefix is advertised again once r0 is updated
+    t.get_router_mut(r0).unwrap().add_conditional_advertise(
+        prefix,
+        condition,
+        ConditionMode::NonExist,
+    );
+    t.schedule_update_router(r0).unwrap();
+    t.do_queue().unwrap();
+    assert_route(&t, r1, prefix, vec![r1, r0, e0]);
+}
+
+#[test]
+fn test_edge_no_session() {
+    // e0 ---- r0
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let r0 = t.add_router("R0");
+
+    t.add_edge_no_session(e0, r0, 1.0, None).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    // no session is created
+    assert_eq!(
+        t.get_router(r0).unwrap().get_bgp_session_type(e0),
+        Err(DeviceError::NoBgpSession(e0))
+    );
+    t.advertise_external_route(e0, prefix, vec![AsId(1)], None, true)
+        .unwrap();
+    assert_blackhole(&t, r0, prefix, vec![r0]);
+
+    // eBGP sessions need an external router
+    assert_eq!(
+        t.add_ebgp_session(r0, r0, true),
+        Err(NetworkError::DeviceIsInternalRouter(r0))
+    );
+
+    // create the session explicitly
+    t.add_ebgp_session(e0, r0, true).unwrap();
+    assert_eq!(
+        t.get_router(r0).unwrap().get_bgp_session_type(e0),
+        Ok(BgpSessionType::EBgp)
+    );
+    t.advertise_external_route(e0, prefix, vec![AsId(1)], None, true)
+        .unwrap();
+    assert_route(&t, r0, prefix, vec![r0, e0]);
+}
+
+#[test]
+fn test_withdraw_non_exportable() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+
+    let prefix = Prefix(0);
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    // r1 knows the route from r0
+    let known_from = |t: &mut Network, router: RouterId| -> Vec<RouterId> {
+        t.get_router(router)
+            .unwrap()
+            .get_known_bgp_routes(prefix)
+            .unwrap()
+            .iter()
+            .map(|e| e.from_id)
+            .collect()
+    };
+    assert!(known_from(&mut t, r1).contains(&r0));
+
+    // the route selected by r0 must no longer be exported to r1. The selected route of r0 does
+    // not change, but the route must still be withdrawn.
+    t.get_router_mut(r0)
+        .unwrap()
+        .policy_bgp_route_no_export
+        .insert((b0, r1));
+    t.schedule_update_router(r0).unwrap();
+    t.do_queue().unwrap();
+
+    assert!(!known_from(&mut t, r1).contains(&r0));
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+    assert_route(&t, r1, prefix, vec![r1, b1, e1]);
+
+    // removing the policy advertises the route again
+    t.get_router_mut(r0)
+        .unwrap()
+        .policy_bgp_route_no_export
+        .remove(&(b0, r1));
+    t.schedule_update_router(r0).unwrap();
+    t.do_queue().unwrap();
+
+    assert!(known_from(&mut t, r1).contains(&r0));
+}
+
+#[test]
+fn test_igp_path() {
+    // Same topology as in `test_igp_fw_table_complex`
+    //
+    //    3      8      8
+    // a ---- b ---- c ---- d
+    // |      |    / |      |
+    // |1    2|  --  |1     |1
+    // |      | / 1  |      |
+    // e ---- f ---- g ---- h
+    //    1      8      1
+    let mut t = Network::new();
+
+    let a = t.add_router("A");
+    let b = t.add_router("B");
+    let c = t.add_router("C");
+    let d = t.add_router("D");
+    let e = t.add_router("E");
+    let f = t.add_router("F");
+    let g = t.add_router("G");
+    let h = t.add_router("H");
+    let x = t.add_external_router("X", AsId(1));
+
+    t.add_edge(a, b, 3.0, None).unwrap();
+    t.add_edge(a, e, 1.0, None).unwrap();
+    t.add_edge(b, c, 8.0, None).unwrap();
+    t.add_edge(b, f, 2.0, None).unwrap();
+    t.add_edge(c, d, 8.0, None).unwrap();
+    t.add_edge(c, f, 1.0, None).unwrap();
+    t.add_edge(c, g, 1.0, None).unwrap();
+    t.add_edge(d, h, 1.0, None).unwrap();
+    t.add_edge(e, f, 1.0, None).unwrap();
+    t.add_edge(f, g, 8.0, None).unwrap();
+    t.add_edge(g, h, 1.0, None).unwrap();
+    t.add_edge(h, x, 1.0, None).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    // the link f -- g is expensive, so the path goes over c
+    assert_eq!(t.igp_path(a, h), Ok(vec![a, e, f, c, g, h]));
+    assert_eq!(t.igp_path(h, a), Ok(vec![h, g, c, f, e, a]));
+    assert_eq!(t.igp_path(b, d), Ok(vec![b, f, c, g, h, d]));
+    assert_eq!(t.igp_path(a, a), Ok(vec![a]));
+    assert_eq!(t.igp_path(a, x), Ok(vec![a, e, f, c, g, h, x]));
+    assert_eq!(
+        t.igp_path(x, a),
+        Err(NetworkError::DeviceIsExternalRouter(x))
+    );
+}
+
+#[test]
+fn test_return_path() {
+    // Link weights are shown next to the link, in the direction of the arrow. All other links
+    // are symmetric with weight 1.
+    //
+    //       1 -->
+    //  r0 --------- b0 ---- e0
+    //   \  <-- 10  /
+    //    \        /
+    //     '-- r1 -'
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+    let b0 = t.add_router("B0");
+    let e0 = t.add_external_router("E0", AsId(1));
+
+    t.add_edge(r0, b0, 1.0, Some(10.0)).unwrap();
+    t.add_edge(r0, r1, 1.0, None).unwrap();
+    t.add_edge(r1, b0, 1.0, None).unwrap();
+    t.add_edge(b0, e0, 1.0, None).unwrap();
+
+    t.add_ibgp_session(b0, r0, true, true).unwrap();
+    t.add_ibgp_session(b0, r1, true, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+    t.advertise_external_route(e0, prefix, vec![AsId(1)], None, true)
+        .unwrap();
+
+    // the forward path uses the direct link, but the traffic returns via r1
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+    assert_eq!(t.get_return_path(r0, prefix), Ok(vec![e0, b0, r1, r0]));
+
+    // for r1, both paths are symmetric
+    assert_route(&t, r1, prefix, vec![r1, b0, e0]);
+    assert_eq!(t.get_return_path(r1, prefix), Ok(vec![e0, b0, r1]));
+}
+
+#[test]
+fn test_return_path_originated() {
+    // r0 ---- r1 ---- e0
+    let mut t = Network::new();
+
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+    let e0 = t.add_external_router("E0", AsId(1));
+
+    t.add_edge(r0, r1, 1.0, None).unwrap();
+    t.add_edge(r1, e0, 1.0, None).unwrap();
+    t.add_ibgp_session(r0, r1, false, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    let route = BgpRoute {
+        prefix: Prefix(0),
+        path_id: 0,
+        as_path: vec![],
+        next_hop: r0,
+        local_pref: None,
+        med: None,
+        aigp: None,
+        communities: BTreeSet::new(),
+        link_bandwidth: None,
+        cluster_list: Vec::new(),
+        originator_id: None,
+        router_id: None,
+        seq: None,
+        route_targets: BTreeSet::new(),
+        origin: Origin::Igp,
+    };
+    t.originate_route(r0, route, true).unwrap();
+
+    // the originator itself has no return path through the network
+    assert_route(&t, r0, Prefix(0), vec![r0]);
+    assert_eq!(t.get_return_path(r0, Prefix(0)), Ok(vec![r0]));
+
+    // the traffic returns from the internal originator, and not from an external router
+    assert_route(&t, r1, Prefix(0), vec![r1, r0]);
+    assert_eq!(t.get_return_path(r1, Prefix(0)), Ok(vec![r0, r1]));
+
+    // external routers have no return path
+    assert_eq!(
+        t.get_return_path(e0, Prefix(0)),
+        Err(NetworkError::DeviceIsExternalRouter(e0))
+    );
+}
+
+#[test]
+fn test_change_ibgp_session_type() {
+    // Same network as in `change_ibgp_topology_2`. Reconfigure the session between rr and r1,
+    // such that r1 becomes a client of rr. Once by removing and adding the session, and once by
+    // changing the session type.
+    let prefix = Prefix(0);
+
+    let (mut n_readd, [rr, r1, r2, r3, e1, e2, _, p1, p2, _]) = setup_change_ibgp_topology();
+    n_readd.remove_ibgp_session(rr, r1, false).unwrap();
+    n_readd.add_ibgp_session(rr, r1, true, false).unwrap();
+    n_readd.schedule_update_router(rr).unwrap();
+    n_readd.schedule_update_router(r1).unwrap();
+    let churn_readd = count_events(&mut n_readd);
+
+    let (mut n_swap, _) = setup_change_ibgp_topology();
+    n_swap
+        .change_ibgp_session_type(rr, r1, true, false)
+        .unwrap();
+    n_swap.schedule_update_router(rr).unwrap();
+    n_swap.schedule_update_router(r1).unwrap();
+    let churn_swap = count_events(&mut n_swap);
+
+    // the swap causes less churn, since r1 does not need to send its routes again
+    assert!(churn_swap < churn_readd);
+
+    // both result in the same state
+    for n in [&n_readd, &n_swap] {
+        assert_route(n, r1, prefix, vec![r1, e1, p1]);
+        assert_route(n, r2, prefix, vec![r2, e1, p1]);
+        assert_route(n, r3, prefix, vec![r3, e2, p2]);
+        assert_route(n, rr, prefix, vec![rr, e1, p1]);
+    }
+    assert_eq!(
+        n_swap.get_router(rr).unwrap().get_bgp_session_type(r1),
+        Ok(BgpSessionType::IBgpClient)
+    );
+    assert_eq!(
+        n_swap.get_router(r1).unwrap().get_bgp_session_type(rr),
+        Ok(BgpSessionType::IBgpPeer)
+    );
+
+    // the session must exist
+    assert_eq!(
+        n_swap.change_ibgp_session_type(r1, e2, true, true),
+        Err(NetworkError::DeviceError(DeviceError::NoBgpSession(e2)))
+    );
+
+    // an eBGP session cannot be changed into an iBGP session
+    let mut n = Network::new();
+    let a = n.add_router("a");
+    let b = n.add_router("b");
+    n.add_edge(a, b, 1.0, None).unwrap();
+    n.get_router_mut(a)
+        .unwrap()
+        .establish_bgp_session(b, BgpSessionType::EBgp)
+        .unwrap();
+    n.get_router_mut(b)
+        .unwrap()
+        .establish_bgp_session(a, BgpSessionType::EBgp)
+        .unwrap();
+    assert_eq!(
+        n.change_ibgp_session_type(a, b, true, true),
+        Err(NetworkError::NotAnIBgpSession(a, b))
+    );
+    for (router, peer) in [(a, b), (b, a)] {
+        assert_eq!(
+            n.get_router(router).unwrap().get_bgp_session_type(peer),
+            Ok(BgpSessionType::EBgp)
+        );
+    }
+}
+
+#[test]
+fn test_safe_reconfig_order() {
+    // Same network as in `change_ibgp_topology_2`. Migrate from the full mesh to the route
+    // reflector topology.
+    let (n, [rr, r1, r2, r3, e1, e2, e3, p1, _, p3]) = setup_change_ibgp_topology();
+    let prefix = Prefix(0);
+
+    let changes = vec![
+        ConfigChange::RemoveIbgpSession(r3, e2),
+        ConfigChange::RemoveIbgpSession(r1, r2),
+        ConfigChange::RemoveIbgpSession(r1, r3),
+        ConfigChange::RemoveIbgpSession(r2, r3),
+        ConfigChange::ChangeIbgpSessionType(rr, r1, true),
+        ConfigChange::ChangeIbgpSessionType(rr, r2, true),
+        ConfigChange::ChangeIbgpSessionType(rr, r3, true),
+    ];
+
+    // removing the session between r3 and e2 first is not safe
+    assert!(!n.is_safe_reconfig_order(&changes));
+
+    let order = n.find_safe_reconfig_order(changes.clone()).unwrap();
+    assert_eq!(order.len(), changes.len());
+    assert!(changes.iter().all(|c| order.contains(c)));
+    assert!(n.is_safe_reconfig_order(&order));
+
+    // apply the order on the network
+    let mut n = n;
+    for change in order {
+        assert_eq!(n.apply_config_change(change, true), Ok(true));
+    }
+    assert_route(&n, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&n, r2, prefix, vec![r2, e1, p1]);
+    assert_route(&n, r3, prefix, vec![r3, e3, p3]);
+    assert_route(&n, rr, prefix, vec![rr, e1, p1]);
+}
+
+#[test]
+fn test_plan_migration() {
+    // Migrate from the start configuration of `change_ibgp_topology_1` to its end configuration.
+    let (n, [rr, r1, r2, r3, e1, e2, e3, p1, _, p3]) = setup_change_ibgp_topology();
+    let prefix = Prefix(0);
+
+    let from = n.get_config();
+    assert_eq!(from.ibgp_sessions.len(), 10);
+    assert_eq!(from.ibgp_sessions.get(&(r3, e2)), Some(&true));
+    assert_eq!(from.ibgp_sessions.get(&(rr, r1)), Some(&false));
+    assert_eq!(from.link_weights.get(&(rr, e2)), Some(&2.0));
+    assert_eq!(from.link_weights.get(&(e2, rr)), Some(&100.0));
+
+    let mut to = from.clone();
+    to.ibgp_sessions = hashmap! {
+        (rr, r1) => true,
+        (rr, r2) => true,
+        (rr, r3) => true,
+        (r1, e1) => true,
+        (r2, e2) => true,
+        (r3, e3) => true,
+    };
+    to.link_weights.insert((e3, rr), 50.0);
+
+    let changes = from.changes_to(&to);
+    assert_eq!(changes.len(), 8);
+    assert!(changes.contains(&ConfigChange::RemoveIbgpSession(r1, r2)));
+    assert!(changes.contains(&ConfigChange::ChangeIbgpSessionType(rr, r3, true)));
+    assert!(changes.contains(&ConfigChange::UpdateLinkWeight(e3, rr, 50.0)));
+    assert!(from.changes_to(&from).is_empty());
+
+    let plan = n.plan_migration(&from, &to).unwrap();
+    assert_eq!(plan.changes.len(), changes.len());
+    assert!(changes.iter().all(|c| plan.changes.contains(c)));
+    assert!(n.is_safe_reconfig_order(&plan.changes));
+
+    // execute the plan
+    let mut migrated = n.clone();
+    assert_eq!(plan.execute(&mut migrated), Ok(()));
+    assert_eq!(migrated.get_config(), to);
+    assert_route(&migrated, r1, prefix, vec![r1, e1, p1]);
+    assert_route(&migrated, r2, prefix, vec![r2, e1, p1]);
+    assert_route(&migrated, r3, prefix, vec![r3, e3, p3]);
+    assert_route(&migrated, rr, prefix, vec![rr, e1, p1]);
+
+    // removing the session between r3 and e2 first is aborted
+    let unsafe_plan = MigrationPlan {
+        changes: vec![
+            ConfigChange::RemoveIbgpSession(r3, e2),
+            ConfigChange::RemoveIbgpSession(r1, r2),
+        ],
+    };
+    let mut aborted = n.clone();
+    assert_eq!(
+        unsafe_plan.execute(&mut aborted),
+        Err(NetworkError::UnsafeMigrationStep(0))
+    );
+    assert_eq!(
+        aborted.get_config().ibgp_sessions.get(&(r1, r2)),
+        Some(&false)
+    );
+}
+
+/// Build the start topology of `change_ibgp_topology_2`, and advertise the prefix on all external
+/// routers. The routers are returned in the order rr, r1, r2, r3, e1, e2, e3, p1, p2, p3.
+fn setup_change_ibgp_topology() -> (Network, [RouterId; 10]) {
+    let mut n = Network::new();
+
+    let prefix = Prefix(0);
+
+    let rr = n.add_router("rr");
+    let r1 = n.add_router("r1");
+    let r2 = n.add_router("r2");
+    let r3 = n.add_router("r3");
+    let e1 = n.add_router("e1");
+    let e2 = n.add_router("e2");
+    let e3 = n.add_router("e3");
+    let p1 = n.add_external_router("p1", AsId(65101));
+    let p2 = n.add_external_router("p2", AsId(65102));
+    let p3 = n.add_external_router("p3", AsId(65103));
+
+    n.add_edge(r1, e1, 10.0, None).unwrap();
+    n.add_edge(r2, e2, 10.0, None).unwrap();
+    n.add_edge(r3, e3, 10.0, None).unwrap();
+    n.add_edge(e1, p1, 1.0, None).unwrap();
+    n.add_edge(e2, p2, 1.0, None).unwrap();
+    n.add_edge(e3, p3, 1.0, None).unwrap();
+    n.add_edge(e1, r2, 1.0, None).unwrap();
+    n.add_edge(e2, r3, 1.0, None).unwrap();
+    n.add_edge(e3, r1, 1.0, None).unwrap();
+    n.add_edge(rr, e1, 1.0, Some(100.0)).unwrap();
+    n.add_edge(rr, e2, 2.0, Some(100.0)).unwrap();
+    n.add_edge(rr, e3, 3.0, Some(100.0)).unwrap();
+
+    n.add_ibgp_session(rr, r1, false, true).unwrap();
+    n.add_ibgp_session(rr, r2, false, true).unwrap();
+    n.add_ibgp_session(rr, r3, false, true).unwrap();
+    n.add_ibgp_session(r1, r2, false, true).unwrap();
+    n.add_ibgp_session(r1, r3, false, true).unwrap();
+    n.add_ibgp_session(r2, r3, false, true).unwrap();
+    n.add_ibgp_session(r1, e1, true, true).unwrap();
+    n.add_ibgp_session(r2, e2, true, true).unwrap();
+    n.add_ibgp_session(r3, e3, true, true).unwrap();
+    n.add_ibgp_session(r3, e2, true, true).unwrap();
+
+    n.write_igp_fw_tables(true).unwrap();
+
+    n.advertise_external_route(p1, prefix, vec![AsId(1)], None, true)
+        .unwrap();
+    n.advertise_external_route(p2, prefix, vec![AsId(1)], None, true)
+        .unwrap();
+    n.advertise_external_route(p3, prefix, vec![AsId(1)], None, true)
+        .unwrap();
+
+    (n, [rr, r1, r2, r3, e1, e2, e3, p1, p2, p3])
+}
+
+/// Execute the queue one event at a time, and return the number of executed events.
+fn count_events(n: &mut Network) -> usize {
+    let mut count = 0;
+    while !n.get_queue().is_empty() {
+        n.do_queue_capped(Some(1)).unwrap();
+        count += 1;
+    }
+    count
+}
+
+#[test]
+fn test_trace_replay() {
+    // Same network as in `test_simple`. The second network is configured identically, but
+    // without executing any events. Then, the trace of the first network is replayed.
+    let build = |update: bool| {
+        let mut t = Network::new();
+        let e0 = t.add_external_router("E0", AsId(1));
+        let b0 = t.add_router("B0");
+        let r0 = t.add_router("R0");
+        let r1 = t.add_router("R1");
+        let b1 = t.add_router("B1");
+        let e1 = t.add_external_router("E1", AsId(1));
+
+        t.add_edge(e0, b0, 1.0, None).unwrap();
+        t.add_edge(b0, r0, 1.0, None).unwrap();
+        t.add_edge(r0, r1, 1.0, None).unwrap();
+        t.add_edge(r1, b1, 1.0, None).unwrap();
+        t.add_edge(b1, e1, 1.0, None).unwrap();
+
+        t.record_trace(true);
+        t.add_ibgp_session(r0, b0, true, update).unwrap();
+        t.add_ibgp_session(r1, b1, true, update).unwrap();
+        t.add_ibgp_session(r0, r1, false, update).unwrap();
+        t.write_igp_fw_tables(update).unwrap();
+        (t, [e0, b0, r0, r1, b1, e1])
+    };
+
+    let prefix = Prefix(0);
+
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = build(true);
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    let trace = t.take_trace();
+    assert!(!trace.is_empty());
+
+    let (mut replayed, _) = build(false);
+    replayed.replay(&trace).unwrap();
+    assert_converged(&replayed);
+    assert_eq!(replayed.take_trace(), trace);
+
+    // check that both networks have the same RIBs
+    for router in [b0, r0, r1, b1] {
+        let selected =
+            |n: &mut Network| n.get_router(router).unwrap().get_selected_bgp_route(prefix);
+        let known = |n: &mut Network| {
+            let mut routes = n
+                .get_router(router)
+                .unwrap()
+                .get_known_bgp_routes(prefix)
+                .unwrap();
+            routes.sort_by_key(|e| e.from_id);
+            routes
+        };
+        assert_eq!(selected(&mut t), selected(&mut replayed));
+        assert_eq!(known(&mut t), known(&mut replayed));
+    }
+    assert_route(&replayed, b0, prefix, vec![b0, e0]);
+    assert_route(&replayed, r0, prefix, vec![r0, b0, e0]);
+    assert_route(&replayed, r1, prefix, vec![r1, b1, e1]);
+    assert_route(&replayed, b1, prefix, vec![b1, e1]);
+}
+
+#[test]
+fn test_graceful_shutdown() {
+    // All weights are 1, except r -- b1, which has weight 2. All internal routers are iBGP peers.
+    //
+    // e0 ---- b0 ---- r ---- b1 ---- e1
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let r = t.add_router("R");
+    let b1 = t.add_router("B1");
+    let e1 = t.add_external_router("E1", AsId(2));
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, r, 1.0, None).unwrap();
+    t.add_edge(r, b1, 2.0, None).unwrap();
+    t.add_edge(b1, e1, 1.0, None).unwrap();
+
+    t.add_ibgp_session(b0, r, false, true).unwrap();
+    t.add_ibgp_session(b1, r, false, true).unwrap();
+    t.add_ibgp_session(b0, b1, false, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    assert_route(&t, b0, prefix, vec![b0, e0]);
+    assert_route(&t, r, prefix, vec![r, b0, e0]);
+    assert_route(&t, b1, prefix, vec![b1, e1]);
+
+    // drain the session between b0 and e0, while it is still established
+    t.graceful_shutdown_session(e0, b0, true).unwrap();
+
+    assert_route(&t, b0, prefix, vec![b0, r, b1, e1]);
+    assert_route(&t, r, prefix, vec![r, b1, e1]);
+    assert_route(&t, b1, prefix, vec![b1, e1]);
+    let table = t.get_bgp_table(b0, prefix).unwrap();
+    let drained = table.entries.iter().find(|(e, _)| e.from_id == e0).unwrap();
+    assert_eq!(drained.0.route.local_pref, Some(0));
+    assert!(drained
+        .0
+        .route
+        .communities
+        .contains(&COMMUNITY_GRACEFUL_SHUTDOWN));
+    assert!(!drained.1);
+
+    // the session is still used if there is no alternative
+    t.retract_external_route(e1, prefix, true).unwrap();
+    assert_route(&t, r, prefix, vec![r, b0, e0]);
+}
+
+#[test]
+fn test_static_route() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+
+    let prefix = Prefix(0);
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    assert_route(&t, b0, prefix, vec![b0, e0]);
+
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+
+    // pin the traffic of r0 towards b1
+    t.get_router_mut(r0).unwrap().add_static_route(prefix, b1);
+    assert_route(&t, r0, prefix, vec![r0, r1, b1, e1]);
+    assert_route(&t, b0, prefix, vec![b0, e0]);
+
+    // pinning b0 towards b1 causes a loop, since r0 still follows BGP
+    t.get_router_mut(r0).unwrap().remove_static_route(prefix);
+    t.get_router_mut(b0).unwrap().add_static_route(prefix, b1);
+    assert_loop(&t, b0, prefix, vec![b0, r0, b0]);
+
+    // a static route to an unknown next hop drops the traffic
+    t.get_router_mut(b0)
+        .unwrap()
+        .add_static_route(prefix, 100.into());
+    assert_blackhole(&t, b0, prefix, vec![b0]);
+    assert_blackhole(&t, r0, prefix, vec![r0, b0]);
+}
+
+#[test]
+fn test_diff_forwarding() {
+    let (mut t, [e0, _, r0, r1, b1, e1]) = setup_simple();
+
+    let prefix = Prefix(0);
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    // the same network has no difference
+    let mut after = t.clone();
+    assert!(t.diff_forwarding(&after, prefix).is_empty());
+
+    // only r1 and b1 change their next hop
+    after.retract_external_route(e1, prefix, true).unwrap();
+    assert_eq!(
+        t.diff_forwarding(&after, prefix),
+        vec![
+            (
+                r1,
+                ForwardingChange::Changed(NextHop::Via(vec![b1]), NextHop::Via(vec![r0]))
+            ),
+            (
+                b1,
+                ForwardingChange::Changed(NextHop::Via(vec![e1]), NextHop::Via(vec![r1]))
+            ),
+        ]
+    );
+
+    // routers which exist in only one network
+    let x = after.add_router("X");
+    assert_eq!(
+        t.diff_forwarding(&after, prefix).last(),
+        Some(&(x, ForwardingChange::Added(NextHop::NoRoute)))
+    );
+    assert_eq!(
+        after.diff_forwarding(&t, prefix).last(),
+        Some(&(x, ForwardingChange::Removed(NextHop::NoRoute)))
+    );
+}
+
+#[test]
+fn test_session_validation() {
+    // e0 ---- r0 ---- r1
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+
+    t.add_edge(e0, r0, 1.0, None).unwrap();
+    t.add_edge(r0, r1, 1.0, None).unwrap();
+
+    // iBGP sessions with external routers
+    assert_eq!(
+        t.add_ibgp_session(r0, e0, false, true),
+        Err(NetworkError::DeviceIsExternalRouter(e0))
+    );
+    assert_eq!(
+        t.add_ibgp_session(e0, r0, true, true),
+        Err(NetworkError::DeviceIsExternalRouter(e0))
+    );
+    assert_eq!(
+        t.remove_ibgp_session(r0, e0, true),
+        Err(NetworkError::DeviceIsExternalRouter(e0))
+    );
+    assert_eq!(
+        t.change_ibgp_session_type(e0, r0, true, true),
+        Err(NetworkError::DeviceIsExternalRouter(e0))
+    );
+
+    // the eBGP session created by add_edge is not modified
+    assert_eq!(
+        t.get_router(r0).unwrap().get_bgp_session_type(e0),
+        Ok(BgpSessionType::EBgp)
+    );
+
+    // unknown routers
+    assert_eq!(
+        t.add_ibgp_session(r0, 100.into(), false, true),
+        Err(NetworkError::DeviceNotFound(100.into()))
+    );
+    assert_eq!(
+        t.get_router(r0).unwrap().get_bgp_session_type(100.into()),
+        Err(DeviceError::NoBgpSession(100.into()))
+    );
+
+    // eBGP sessions between internal routers
+    assert_eq!(
+        t.add_ebgp_session(r0, r1, true),
+        Err(NetworkError::DeviceIsInternalRouter(r1))
+    );
+    assert_eq!(
+        t.get_router(r0).unwrap().get_bgp_session_type(r1),
+        Err(DeviceError::NoBgpSession(r1))
+    );
+}
+
+#[test]
+fn test_rpki() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+
+    let valid = Prefix(0);
+    let invalid = Prefix(1);
+    let unknown = Prefix(2);
+
+    t.add_roa(valid, AsId(3));
+    t.add_roa(invalid, AsId(4));
+    t.get_router_mut(b0).unwrap().rpki_drop_invalid = true;
+
+    // e0 originates all prefixes from AS 3
+    for prefix in [valid, invalid, unknown] {
+        t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+            .unwrap();
+    }
+
+    // the invalid route is dropped on b0, and never reaches the other routers
+    assert_route(&t, r1, valid, vec![r1, r0, b0, e0]);
+    assert_route(&t, r1, unknown, vec![r1, r0, b0, e0]);
+    assert_blackhole(&t, b0, invalid, vec![b0]);
+    assert_blackhole(&t, r1, invalid, vec![r1]);
+
+    // b1 does not drop invalid routes, but still validates them
+    t.advertise_external_route(e1, invalid, vec![AsId(1), AsId(3)], None, true)
+        .unwrap();
+    assert_route(&t, r0, invalid, vec![r0, r1, b1, e1]);
+    let b1_router = t.get_router(b1).unwrap();
+    let route = b1_router.get_selected_bgp_route(invalid).unwrap().route;
+    assert_eq!(b1_router.rpki_validate(&route), RpkiValidation::Invalid);
+    let route = b1_router.get_selected_bgp_route(valid).unwrap().route;
+    assert_eq!(b1_router.rpki_validate(&route), RpkiValidation::Valid);
+    let route = b1_router.get_selected_bgp_route(unknown).unwrap().route;
+    assert_eq!(b1_router.rpki_validate(&route), RpkiValidation::NotFound);
+}
+
+#[test]
+fn test_last_convergence_steps() {
+    // Same network as in `test_simple`, built multiple times. The number of steps to converge
+    // must be the same for every run.
+    let prefix = Prefix(0);
+    let mut steps: Option<Vec<usize>> = None;
+    for _ in 0..10 {
+        let (mut t, [e0, _, _, _, _, e1]) = setup_simple();
+
+        let mut run_steps = Vec::new();
+        t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+            .unwrap();
+        run_steps.push(t.last_convergence_steps());
+        t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+            .unwrap();
+        run_steps.push(t.last_convergence_steps());
+
+        assert!(run_steps.iter().all(|s| *s > 0));
+        match steps.as_ref() {
+            Some(steps) => assert_eq!(steps, &run_steps),
+            None => steps = Some(run_steps),
+        }
+    }
+}
+
+#[test]
+fn test_srlg() {
+    // All iBGP sessions are peers (full mesh), and all weights are 1 except the diagonal ones.
+    //
+    // e0 - b0 ----- r0
+    //      |  `.5 .'|
+    //      |    X   |
+    //      |  .'5 `.|
+    //      r1 ----- b1 - e1
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+    let b1 = t.add_router("B1");
+    let e1 = t.add_external_router("E1", AsId(1));
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_edge(b0, r1, 1.0, None).unwrap();
+    t.add_edge(r0, b1, 1.0, None).unwrap();
+    t.add_edge(r1, b1, 1.0, None).unwrap();
+    t.add_edge(b0, b1, 5.0, None).unwrap();
+    t.add_edge(r0, r1, 5.0, None).unwrap();
+    t.add_edge(b1, e1, 1.0, None).unwrap();
+
+    for (a, b) in [(b0, r0), (b0, r1), (b0, b1), (r0, r1), (r0, b1), (r1, b1)] {
+        t.add_ibgp_session(a, b, false, false).unwrap();
+    }
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    // e0 is preferred because of the shorter AS path
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    assert_route(&t, b1, prefix, vec![b1, r0, b0, e0]);
+    let original = t.clone();
+
+    // fail both links of b0 to r0 and r1 at once
+    let srlg = [(b0, r0), (b0, r1)];
+    let mut simultaneous = t.clone();
+    assert!(simultaneous.fail_srlg(&srlg, true).unwrap());
+    assert_route(&simultaneous, b1, prefix, vec![b1, b0, e0]);
+    assert_route(&simultaneous, r0, prefix, vec![r0, b1, b0, e0]);
+
+    // failing them one by one passes through a different state
+    let mut sequential = t.clone();
+    assert!(sequential.fail_srlg(&srlg[..1], true).unwrap());
+    assert_route(&sequential, r0, prefix, vec![r0, b1, r1, b0, e0]);
+    assert!(!sequential.diff_forwarding(&simultaneous, prefix).is_empty());
+    assert!(sequential.fail_srlg(&srlg[1..], true).unwrap());
+    assert_eq!(sequential.diff_forwarding(&simultaneous, prefix), vec![]);
+
+    // links can only be failed once, and only failed links can be restored
+    assert_eq!(
+        simultaneous.fail_srlg(&srlg, true),
+        Err(NetworkError::LinkNotFound(b0, r0))
+    );
+    assert_eq!(
+        simultaneous.restore_srlg(&[(r0, r1)], true),
+        Err(NetworkError::LinkNotFound(r0, r1))
+    );
+
+    // restoring the group results in the original state
+    assert!(simultaneous.restore_srlg(&srlg, true).unwrap());
+    assert_eq!(simultaneous.diff_forwarding(&original, prefix), vec![]);
+    assert_route(&simultaneous, b1, prefix, vec![b1, r0, b0, e0]);
+}
+
+/// Setup the evil twin gadget from L. Vanbever: Improving Network Agility with Seamless BGP
+/// reconfigurations (the same network as in `main`), with all routes advertised. The returned
+/// routers are r1, r2, r3, r4, ra, rb, e1, ex, e2, e3, e4, x1, x2, x3, x4, x5, x6.
+fn setup_evil_twin_gadget() -> (Network, [RouterId; 17]) {
+    let mut n = Network::new();
+
+    // router declaration
+    let r1 = n.add_router("R1");
+    let r2 = n.add_router("R2");
+    let r3 = n.add_router("R3");
+    let r4 = n.add_router("R4");
+    let ra = n.add_router("RA");
+    let rb = n.add_router("RB");
+    let e1 = n.add_router("E1");
+    let ex = n.add_router("EX");
+    let e2 = n.add_router("E2");
+    let e3 = n.add_router("E3");
+    let e4 = n.add_router("E4");
+    let x1 = n.add_external_router("X1", AsId(65101));
+    let x2 = n.add_external_router("X2", AsId(65102));
+    let x3 = n.add_external_router("X3", AsId(65103));
+    let x4 = n.add_external_router("X4", AsId(65104));
+    let x5 = n.add_external_router("X5", AsId(65105));
+    let x6 = n.add_external_router("X6", AsId(65106));
+
+    // IGP topology
+    n.add_edge(r1, e1, 2.0, None).unwrap();
+    n.add_edge(r1, e2, 1.0, None).unwrap();
+    n.add_edge(ra, e1, 4.0, None).unwrap();
+    n.add_edge(ra, ex, 2.0, None).unwrap();
+    n.add_edge(ra, e2, 3.0, None).unwrap();
+    n.add_edge(r2, ex, 4.0, None).unwrap();
+    n.add_edge(r2, e2, 6.0, None).unwrap();
+    n.add_edge(r2, e3, 5.0, None).unwrap();
+    n.add_edge(r2, e4, 3.0, None).unwrap();
+    n.add_edge(rb, e1, 3.0, None).unwrap();
+    n.add_edge(rb, e3, 1.0, None).unwrap();
+    n.add_edge(rb, e4, 2.0, None).unwrap();
+    n.add_edge(r3, e1, 8.0, None).unwrap();
+    n.add_edge(r3, ex, 7.0, None).unwrap();
+    n.add_edge(r3, e3, 9.0, None).unwrap();
+    n.add_edge(r4, e1, 8.0, None).unwrap();
+    n.add_edge(r4, e4, 9.0, None).unwrap();
+    n.add_edge(r1, x1, 0.0, None).unwrap();
+    n.add_edge(e1, x2, 0.0, None).unwrap();
+    n.add_edge(ex, x3, 0.0, None).unwrap();
+    n.add_edge(e2, x4, 0.0, None).unwrap();
+    n.add_edge(e3, x5, 0.0, None).unwrap();
+    n.add_edge(e4, x6, 0.0, None).unwrap();
+
+    n.write_igp_fw_tables(true).unwrap();
+
+    // iBGP topology
+    n.add_ibgp_session(r1, e1, true, true).unwrap();
+    n.add_ibgp_session(r1, ex, true, true).unwrap();
+    n.add_ibgp_session(ra, e1, true, true).unwrap();
+    n.add_ibgp_session(ra, ex, true, true).unwrap();
+    n.add_ibgp_session(ra, e2, true, true).unwrap();
+    n.add_ibgp_session(r2, ra, true, true).unwrap();
+    n.add_ibgp_session(r2, e2, true, true).unwrap();
+    n.add_ibgp_session(rb, e1, true, true).unwrap();
+    n.add_ibgp_session(rb, e3, true, true).unwrap();
+    n.add_ibgp_session(rb, e4, true, true).unwrap();
+    n.add_ibgp_session(r3, rb, true, true).unwrap();
+    n.add_ibgp_session(r3, e3, true, true).unwrap();
+    n.add_ibgp_session(r4, e4, true, true).unwrap();
+    n.add_ibgp_session(r1, r2, false, true).unwrap();
+    n.add_ibgp_session(r1, r3, false, true).unwrap();
+    n.add_ibgp_session(r1, r4, false, true).unwrap();
+    n.add_ibgp_session(r2, r3, false, true).unwrap();
+    n.add_ibgp_session(r2, r4, false, true).unwrap();
+    n.add_ibgp_session(r3, r4, false, true).unwrap();
+
+    // advertise all external sources
+    n.advertise_external_route(x1, Prefix(2), vec![AsId(65101), AsId(65202)], None, true)
+        .unwrap();
+    n.advertise_external_route(x2, Prefix(1), vec![AsId(65102), AsId(65201)], None, true)
+        .unwrap();
+    n.advertise_external_route(x2, Prefix(2), vec![AsId(65102), AsId(65202)], None, true)
+        .unwrap();
+    n.advertise_external_route(x3, Prefix(1), vec![AsId(65103), AsId(65201)], None, true)
+        .unwrap();
+    n.advertise_external_route(x3, Prefix(2), vec![AsId(65103), AsId(65202)], None, true)
+        .unwrap();
+    n.advertise_external_route(x4, Prefix(1), vec![AsId(65104), AsId(65201)], None, true)
+        .unwrap();
+    n.advertise_external_route(x5, Prefix(1), vec![AsId(65105), AsId(65201)], None, true)
+        .unwrap();
+    n.advertise_external_route(x6, Prefix(2), vec![AsId(65106), AsId(65202)], None, true)
+        .unwrap();
+
+    (
+        n,
+        [
+            r1, r2, r3, r4, ra, rb, e1, ex, e2, e3, e4, x1, x2, x3, x4, x5, x6,
+        ],
+    )
+}
+
+#[test]
+fn test_ingress_routers_for_egress() {
+    let (t, [r1, r2, r3, r4, ra, rb, e1, ex, e2, e3, e4, x1, x2, x3, x4, x5, x6]) =
+        setup_evil_twin_gadget();
+    let internal = btreeset! {r1, r2, r3, r4, ra, rb, e1, ex, e2, e3, e4};
+
+    for prefix in [Prefix(1), Prefix(2)] {
+        // The selection of some routers depends on the order in which the events are processed,
+        // and may even result in forwarding loops. However, each router reaches at most one
+        // external router.
+        let mut seen = BTreeSet::new();
+        for x in [x1, x2, x3, x4, x5, x6] {
+            for r in t.ingress_routers_for_egress(x, prefix).unwrap() {
+                assert!(seen.insert(r));
+            }
+        }
+        assert!(seen.is_subset(&internal));
+    }
+
+    // X2 carries the traffic of E1 and of R4 for prefix 1
+    let carried = t.ingress_routers_for_egress(x2, Prefix(1)).unwrap();
+    assert!(carried.contains(&e1));
+    assert!(carried.contains(&r4));
+    // X3 carries the traffic of EX and of E2 for prefix 2
+    let carried = t.ingress_routers_for_egress(x3, Prefix(2)).unwrap();
+    assert!(carried.contains(&ex));
+    assert!(carried.contains(&e2));
+    // X1 only carries the traffic of R1 for prefix 2, and nothing for prefix 1
+    assert_eq!(t.ingress_routers_for_egress(x1, Prefix(2)), Ok(vec![r1]));
+    assert_eq!(t.ingress_routers_for_egress(x1, Prefix(1)), Ok(vec![]));
+
+    assert_eq!(
+        t.ingress_routers_for_egress(ra, Prefix(1)),
+        Err(NetworkError::DeviceIsInternalRouter(ra))
+    );
+}
+
+#[test]
+fn test_maximum_prefix() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+
+    // b0 accepts at most 2 prefixes from e0
+    t.get_router_mut(b0)
+        .unwrap()
+        .set_maximum_prefix(e0, Some(2));
+
+    // e1 advertises the first prefix as a backup, with a longer AS path
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(1), AsId(2), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    t.advertise_external_route(e0, Prefix(1), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    assert_route(&t, r1, Prefix(0), vec![r1, r0, b0, e0]);
+    assert_route(&t, r1, Prefix(1), vec![r1, r0, b0, e0]);
+    assert!(t.get_router(b0).unwrap().get_bgp_session_type(e0).is_ok());
+
+    // the third prefix exceeds the limit, and the session is torn down
+    t.record_trace(true);
+    t.advertise_external_route(e0, Prefix(2), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    assert!(t
+        .take_trace()
+        .contains(&Event::Bgp(b0, e0, BgpEvent::Notification)));
+    assert_eq!(
+        t.get_router(b0).unwrap().get_bgp_session_type(e0),
+        Err(DeviceError::NoBgpSession(e0))
+    );
+
+    // all routes from e0 are withdrawn, and the network uses the backup route from e1
+    assert_route(&t, r0, Prefix(0), vec![r0, r1, b1, e1]);
+    assert_route(&t, b0, Prefix(0), vec![b0, r0, r1, b1, e1]);
+    assert_blackhole(&t, r1, Prefix(1), vec![r1]);
+    assert_blackhole(&t, b0, Prefix(2), vec![b0]);
+
+    // e0 no longer sends any updates to b0
+    assert!(t.get_external_router(e0).unwrap().neighbors.is_empty());
+    t.advertise_external_route(e0, Prefix(4), vec![AsId(1), AsId(2)], None, false)
+        .unwrap();
+    assert!(t.get_queue().is_empty());
+
+    // the limit also applies to iBGP sessions, where both sides close the session
+    t.get_router_mut(r1)
+        .unwrap()
+        .set_maximum_prefix(b1, Some(0));
+    t.advertise_external_route(e1, Prefix(3), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    assert!(t.get_router(r1).unwrap().get_bgp_session_type(b1).is_err());
+    assert!(t.get_router(b1).unwrap().get_bgp_session_type(r1).is_err());
+    assert_blackhole(&t, r0, Prefix(0), vec![r0]);
+    assert_route(&t, b1, Prefix(0), vec![b1, e1]);
+}
+
+#[test]
+fn test_client_to_client_reflection() {
+    // rr is the route reflector of c1 and c2, and a regular peer of p. All weights are 1.
+    //
+    //       p
+    //       |
+    // c1 -- rr -- c2
+    // |
+    // e1
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let rr = t.add_router("RR");
+    let c1 = t.add_router("C1");
+    let c2 = t.add_router("C2");
+    let p = t.add_router("P");
+    let e1 = t.add_external_router("E1", AsId(1));
+
+    t.add_edge(c1, rr, 1.0, None).unwrap();
+    t.add_edge(rr, c2, 1.0, None).unwrap();
+    t.add_edge(rr, p, 1.0, None).unwrap();
+    t.add_edge(c1, e1, 1.0, None).unwrap();
+
+    t.add_ibgp_session(rr, c1, true, true).unwrap();
+    t.add_ibgp_session(rr, c2, true, true).unwrap();
+    t.add_ibgp_session(rr, p, false, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    assert_route(&t, c2, prefix, vec![c2, rr, c1, e1]);
+    assert_route(&t, p, prefix, vec![p, rr, c1, e1]);
+
+    // disable the reflection between clients. c2 no longer learns the route of c1, but p does.
+    t.get_router_mut(rr).unwrap().client_to_client_reflection = false;
+    t.schedule_update_router(rr).unwrap();
+    t.do_queue().unwrap();
+    assert_blackhole(&t, c2, prefix, vec![c2]);
+    assert_route(&t, p, prefix, vec![p, rr, c1, e1]);
+    assert_route(&t, rr, prefix, vec![rr, c1, e1]);
+
+    // enable it again
+    t.get_router_mut(rr).unwrap().client_to_client_reflection = true;
+    t.schedule_update_router(rr).unwrap();
+    t.do_queue().unwrap();
+    assert_route(&t, c2, prefix, vec![c2, rr, c1, e1]);
+}
+
+#[test]
+fn test_igp_recomputation() {
+    let (mut t, [rr, r1, r2, r3, e1, _, _, _, _, _]) = setup_change_ibgp_topology();
+    let prefix = Prefix(0);
+    // rr, r1, r2, r3, e1, e2 and e3
+    let num_routers = 7;
+
+    // the tables are already up to date, so they are not computed again
+    let computations = t.igp_computations();
+    assert!(computations > 0);
+    t.write_igp_fw_tables(true).unwrap();
+    assert_eq!(t.igp_computations(), computations);
+
+    // reading the route does not compute anything
+    let route = t.get_route(r2, prefix).unwrap();
+    assert_eq!(t.get_route(r2, prefix).unwrap(), route);
+    assert_eq!(t.igp_computations(), computations);
+
+    // a topology change requires all tables to be computed once
+    t.update_edge_weight(rr, e1, 5.0, Some(100.0));
+    t.write_igp_fw_tables(true).unwrap();
+    assert_eq!(t.igp_computations(), computations + num_routers);
+    t.write_igp_fw_tables(true).unwrap();
+    assert_eq!(t.igp_computations(), computations + num_routers);
+
+    // the same holds for new links and failed links
+    t.add_edge(r1, r3, 1.0, None).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+    assert_eq!(t.igp_computations(), computations + 2 * num_routers);
+    t.fail_srlg(&[(r1, r3)], true).unwrap();
+    assert_eq!(t.igp_computations(), computations + 3 * num_routers);
+}
+
+#[test]
+fn test_default_local_pref() {
+    // b0 has two eBGP neighbors, e0 and e1, and r0 is its client.
+    //
+    // e0 -- b0 -- e1
+    //       |
+    //       r0
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let b0 = t.add_router("B0");
+    let r0 = t.add_router("R0");
+    let e0 = t.add_external_router("E0", AsId(1));
+    let e1 = t.add_external_router("E1", AsId(2));
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, e1, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+
+    t.add_ibgp_session(b0, r0, true, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    // the route of e0 is tagged explicitly, the route of e1 gets the default local pref
+    t.get_router_mut(b0)
+        .unwrap()
+        .policy_bgp_local_pref
+        .insert(e0, 150);
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, prefix, vec![AsId(2), AsId(3)], None, true)
+        .unwrap();
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+
+    // with a higher default, b0 prefers the route from e1
+    t.get_router_mut(b0).unwrap().default_local_pref = 200;
+    t.schedule_update_router(b0).unwrap();
+    t.do_queue().unwrap();
+    assert_route(&t, r0, prefix, vec![r0, b0, e1]);
+    assert_eq!(
+        t.get_router(r0)
+            .unwrap()
+            .get_selected_bgp_route(prefix)
+            .unwrap()
+            .route
+            .local_pref,
+        Some(200)
+    );
+}
+
+#[test]
+fn test_ingress_community() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+
+    let prefix = Prefix(0);
+
+    // only b0 marks the ingress peer
+    t.get_router_mut(b0).unwrap().mark_ingress_community = true;
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(1), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+
+    // the community is propagated to all routers via iBGP
+    let community = ingress_community(AsId(65001), e0).unwrap();
+    for router in [b0, r0, r1, b1] {
+        let route = t
+            .get_router(router)
+            .unwrap()
+            .get_selected_bgp_route(prefix)
+            .unwrap()
+            .route;
+        assert_eq!(route.communities, btreeset! {community});
+    }
+
+    // routes entering via b1 are not marked
+    let route = t
+        .get_router(r0)
+        .unwrap()
+        .get_selected_bgp_route(Prefix(1))
+        .unwrap()
+        .route;
+    assert!(route.communities.is_empty());
+
+    // ids which do not fit into 16 bits cannot be encoded
+    assert_eq!(ingress_community(AsId(70000), e0), None);
+    assert_eq!(ingress_community(AsId(65001), RouterId::new(0x10000)), None);
+}
+
+#[test]
+fn test_advertised_route() {
+    let (mut t, [e0, b0, r0, _, b1, e1]) = setup_simple();
+
+    // e1 advertises a prefix, which is then advertised by b0 to e0
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+
+    let b0_router = t.get_router(b0).unwrap();
+    let route = b0_router.get_advertised_route(e0, Prefix(0)).unwrap();
+    assert_eq!(route.next_hop, b0);
+    assert_eq!(route.local_pref, None);
+    assert_eq!(route.as_path, vec![AsId(1), AsId(2)]);
+
+    // internally, the next hop is kept and the local pref is set
+    let route = b0_router.get_advertised_route(r0, Prefix(0));
+    assert_eq!(route, None);
+    let route = t
+        .get_router(r0)
+        .unwrap()
+        .get_advertised_route(b0, Prefix(0))
+        .unwrap();
+    assert_eq!(route.next_hop, e1);
+    assert_eq!(route.local_pref, Some(100));
+
+    // nothing is advertised back to the peer from which the route was learned
+    let route = t
+        .get_router(b1)
+        .unwrap()
+        .get_advertised_route(e1, Prefix(0));
+    assert_eq!(route, None);
+}
+
+#[test]
+fn test_predict_affected_routers() {
+    // Same network as in `test_pylon_gadget`
+    let mut n = Network::new();
+    let prefix = Prefix(0);
+
+    let s = n.add_router("s");
+    let rr1 = n.add_router("rr1");
+    let rr2 = n.add_router("rr2");
+    let r1 = n.add_router("r1");
+    let r2 = n.add_router("r2");
+    let e0 = n.add_router("e0");
+    let e1 = n.add_router("e1");
+    let p0 = n.add_external_router("p0", AsId(65100));
+    let p1 = n.add_external_router("p1", AsId(65101));
+    let ps = n.add_external_router("ps", AsId(65102));
+
+    n.add_edge(s, r1, 100.0, None).unwrap();
+    n.add_edge(s, r2, 100.0, None).unwrap();
+    n.add_edge(s, rr1, 100.0, None).unwrap();
+    n.add_edge(s, rr2, 100.0, None).unwrap();
+    n.add_edge(rr1, rr2, 1.0, None).unwrap();
+    n.add_edge(rr1, e0, 1.0, None).unwrap();
+    n.add_edge(rr2, e1, 1.0, None).unwrap();
+    n.add_edge(r1, r2, 1.0, None).unwrap();
+    n.add_edge(r1, e1, 1.0, None).unwrap();
+    n.add_edge(r2, e0, 1.0, None).unwrap();
+    n.add_edge(e0, p0, 1.0, None).unwrap();
+    n.add_edge(e1, p1, 1.0, None).unwrap();
+    n.add_edge(s, ps, 1.0, None).unwrap();
+
+    n.add_ibgp_session(s, rr1, true, true).unwrap();
+    n.add_ibgp_session(s, rr2, true, true).unwrap();
+    n.add_ibgp_session(rr1, r1, true, true).unwrap();
+    n.add_ibgp_session(rr2, r2, true, true).unwrap();
+    n.add_ibgp_session(r1, e0, true, true).unwrap();
+    n.add_ibgp_session(r2, e0, true, true).unwrap();
+    n.add_ibgp_session(r2, e1, true, true).unwrap();
+
+    n.write_igp_fw_tables(true).unwrap();
+
+    // the route of p1 is sent up the hierarchy to s, and reflected down to all routers.
+    let predicted = n.predict_affected_routers(p1, prefix).unwrap();
+    assert_eq!(predicted, hashset! {e1, r2, rr2, s, rr1, r1, e0});
+
+    let routers = hashset! {s, rr1, rr2, r1, r2, e0, e1};
+    let check = |n: &mut Network, source: RouterId, withdraw: bool| {
+        let predicted = n.predict_affected_routers(source, prefix).unwrap();
+        n.record_trace(true);
+        if withdraw {
+            n.retract_external_route(source, prefix, true).unwrap();
+        } else {
+            n.advertise_external_route(source, prefix, vec![AsId(1)], None, true)
+                .unwrap();
+        }
+        let churn: HashSet<RouterId> = n
+            .take_trace()
+            .into_iter()
+            .map(|Event::Bgp(_, to, _)| to)
+            .filter(|r| routers.contains(r))
+            .collect();
+        n.record_trace(false);
+        assert!(!churn.is_empty());
+        assert!(
+            churn.is_subset(&predicted),
+            "predicted: {:?}, actual: {:?}",
+            predicted,
+            churn
+        );
+    };
+
+    check(&mut n, p1, false);
+    check(&mut n, ps, false);
+    check(&mut n, p0, false);
+    check(&mut n, p1, true);
+    check(&mut n, p0, true);
+
+    assert_eq!(
+        n.predict_affected_routers(s, prefix),
+        Err(NetworkError::DeviceIsInternalRouter(s))
+    );
+}
+
+#[test]
+fn test_p_router() {
+    // pe1 and pe2 are iBGP peers, and p is a P router between them, without BGP.
+    //
+    // pe1 -- p -- pe2 -- e2
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let pe1 = t.add_router("PE1");
+    let p = t.add_p_router("P");
+    let pe2 = t.add_router("PE2");
+    let e2 = t.add_external_router("E2", AsId(1));
+
+    t.add_edge(pe1, p, 1.0, None).unwrap();
+    t.add_edge(p, pe2, 1.0, None).unwrap();
+    t.add_edge(pe2, e2, 1.0, None).unwrap();
+
+    t.add_ibgp_session(pe1, pe2, false, true).unwrap();
+    assert_eq!(
+        t.add_ibgp_session(pe1, p, false, true),
+        Err(NetworkError::DeviceError(DeviceError::BgpDisabled))
+    );
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e2, prefix, vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+
+    // the traffic of pe1 is forwarded through p, which holds no route
+    assert_route(&t, pe1, prefix, vec![pe1, p, pe2, e2]);
+    assert!(t.get_router(p).unwrap().known_prefixes().is_empty());
+    assert_eq!(
+        t.get_router(p).unwrap().get_next_hop(prefix),
+        NextHop::NoRoute
+    );
+
+    // traffic starting at p has no BGP next hop, and is dropped
+    assert_blackhole(&t, p, prefix, vec![p]);
+}
+
+#[test]
+fn test_link_bandwidth_traffic_split() {
+    // r0 uses multipath over b1 and b2, which are connected to e1 and e2. All weights are 1, and
+    // all iBGP sessions are peers (full mesh).
+    //
+    //    .-- b1 -- e1
+    // r0
+    //    '-- b2 -- e2
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let r0 = t.add_router("R0");
+    let b1 = t.add_router("B1");
+    let b2 = t.add_router("B2");
+    let e1 = t.add_external_router("E1", AsId(1));
+    let e2 = t.add_external_router("E2", AsId(2));
+
+    t.add_edge(r0, b1, 1.0, None).unwrap();
+    t.add_edge(r0, b2, 1.0, None).unwrap();
+    t.add_edge(b1, e1, 1.0, None).unwrap();
+    t.add_edge(b2, e2, 1.0, None).unwrap();
+
+    t.add_ibgp_session(r0, b1, false, false).unwrap();
+    t.add_ibgp_session(r0, b2, false, false).unwrap();
+    t.add_ibgp_session(b1, b2, false, false).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+    t.get_router_mut(r0).unwrap().set_maximum_paths(2);
+
+    t.advertise_external_route(e1, prefix, vec![AsId(1), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e2, prefix, vec![AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    // without link bandwidth, the traffic is shared equally
+    assert_eq!(
+        t.get_router(r0).unwrap().get_traffic_split(prefix),
+        vec![(b1, 0.5), (b2, 0.5)]
+    );
+
+    // the link to e1 has three times the bandwidth of the link to e2
+    t.get_router_mut(b1)
+        .unwrap()
+        .policy_bgp_link_bandwidth
+        .insert(e1, 3000);
+    t.get_router_mut(b2)
+        .unwrap()
+        .policy_bgp_link_bandwidth
+        .insert(e2, 1000);
+    for router in [b1, b2] {
+        t.schedule_update_router(router).unwrap();
+    }
+    t.do_queue().unwrap();
+
+    assert_eq!(
+        t.get_router(r0).unwrap().get_traffic_split(prefix),
+        vec![(b1, 0.75), (b2, 0.25)]
+    );
+    assert_eq!(
+        t.get_traffic_split(r0, prefix),
+        Ok(hashmap! {e1 => 0.75, e2 => 0.25})
+    );
+    // the border routers send all traffic to their own peer
+    assert_eq!(t.get_traffic_split(b1, prefix), Ok(hashmap! {e1 => 1.0}));
+
+    // the bandwidth is advertised via iBGP, but not to eBGP peers
+    t.advertise_external_route(e2, Prefix(1), vec![AsId(2), AsId(3)], None, true)
+        .unwrap();
+    let b1_router = t.get_router(b1).unwrap();
+    let route = b1_router.get_selected_bgp_route(Prefix(1)).unwrap().route;
+    assert_eq!(route.link_bandwidth, Some(1000));
+    let route = b1_router.get_advertised_route(e1, Prefix(1)).unwrap();
+    assert_eq!(route.link_bandwidth, None);
+}
+
+#[test]
+fn test_from_edges() {
+    // Same network as in `test_simple`, built once manually and once from the edges
+    let mut manual = Network::new();
+    let e0 = manual.add_external_router("E0", AsId(1));
+    let b0 = manual.add_router("B0");
+    let r0 = manual.add_router("R0");
+    let r1 = manual.add_router("R1");
+    let b1 = manual.add_router("B1");
+    let e1 = manual.add_external_router("E1", AsId(1));
+    manual.add_edge(e0, b0, 1.0, None).unwrap();
+    manual.add_edge(b0, r0, 1.0, None).unwrap();
+    manual.add_edge(r0, r1, 1.0, None).unwrap();
+    manual.add_edge(r1, b1, 1.0, None).unwrap();
+    manual.add_edge(b1, e1, 1.0, None).unwrap();
+    let manual_ids =
+        hashmap! {"E0" => e0, "B0" => b0, "R0" => r0, "R1" => r1, "B1" => b1, "E1" => e1};
+
+    let (built, ids) = Network::from_edges(
+        &["B0", "R0", "R1", "B1"],
+        &[("E0", AsId(1)), ("E1", AsId(1))],
+        &[
+            ("E0", "B0", 1.0),
+            ("B0", "R0", 1.0),
+            ("R0", "R1", 1.0),
+            ("R1", "B1", 1.0),
+            ("B1", "E1", 1.0),
+        ],
+    )
+    .unwrap();
+    assert_eq!(ids.len(), 6);
+
+    let prefix = Prefix(0);
+    let mut routes = Vec::new();
+    for (mut t, ids) in [(manual, manual_ids), (built, ids)] {
+        t.add_ibgp_session(ids["R0"], ids["B0"], true, true)
+            .unwrap();
+        t.add_ibgp_session(ids["R1"], ids["B1"], true, true)
+            .unwrap();
+        t.add_ibgp_session(ids["R0"], ids["R1"], false, true)
+            .unwrap();
+        t.write_igp_fw_tables(true).unwrap();
+        t.advertise_external_route(
+            ids["E0"],
+            prefix,
+            vec![AsId(1), AsId(2), AsId(3)],
+            None,
+            true,
+        )
+        .unwrap();
+        t.advertise_external_route(
+            ids["E1"],
+            prefix,
+            vec![AsId(1), AsId(2), AsId(3)],
+            None,
+            true,
+        )
+        .unwrap();
+
+        // the routes of all routers, by name
+        routes.push(
+            ["B0", "R0", "R1", "B1"]
+                .iter()
+                .map(|name| {
+                    t.get_route(ids[name], prefix)
+                        .unwrap()
+                        .into_iter()
+                        .map(|r| t.get_router_name(r).unwrap())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+    assert_eq!(routes[0], routes[1]);
+    assert_eq!(routes[1][0], vec!["B0", "E0"]);
+    assert_eq!(routes[1][1], vec!["R0", "B0", "E0"]);
+    assert_eq!(routes[1][2], vec!["R1", "B1", "E1"]);
+
+    // unknown routers are reported by their name
+    assert_eq!(
+        Network::from_edges(&["A"], &[], &[("A", "B", 1.0)]).map(|_| ()),
+        Err(NetworkError::DeviceNameNotFound("B"))
+    );
+}
+
+#[test]
+fn test_transient_forwarding_states() {
+    let (mut n, [rr, r1, r2, r3, r4, e1, e2, e3, e4, _, _, p2, p3, _]) = setup_carousel_gadget();
+    let routers = [rr, r1, r2, r3, r4, e1, e2, e3, e4];
+    let prefixes = [Prefix(1), Prefix(2)];
+
+    // reconfigure e2 (see `carousel_gadget`), and check every 5th state
+    n.get_router_mut(e2)
+        .unwrap()
+        .policy_bgp_local_pref
+        .remove(&p2);
+    n.schedule_update_router(e2).unwrap();
+    let mut observed = 0;
+    assert_eq!(n.do_queue_observed(5, |_| observed += 1), Ok(true));
+    assert_eq!(observed, n.last_convergence_steps() / 5);
+
+    // reconfigure e3, and record the forwarding state after every event
+    n.get_router_mut(e3)
+        .unwrap()
+        .policy_bgp_local_pref
+        .remove(&p3);
+    n.schedule_update_router(e3).unwrap();
+    let mut states = Vec::new();
+    let result = n.do_queue_observed(1, |n| {
+        let state: Vec<_> = routers
+            .iter()
+            .flat_map(|r| prefixes.iter().map(move |p| (*r, *p)))
+            .map(|(r, p)| n.get_route(r, p))
+            .collect();
+        states.push(state);
+    });
+    assert_eq!(result, Ok(true));
+    assert_eq!(states.len(), n.last_convergence_steps());
+
+    // the last observed state is the converged one, and the states before differ from it
+    let final_state: Vec<_> = routers
+        .iter()
+        .flat_map(|r| prefixes.iter().map(move |p| (*r, *p)))
+        .map(|(r, p)| n.get_route(r, p))
+        .collect();
+    assert!(final_state.iter().all(|route| route.is_ok()));
+    assert_eq!(states.last(), Some(&final_state));
+    assert!(states.iter().any(|state| state != &final_state));
+}
+
+#[test]
+fn test_ebgp_session_same_as() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let e1 = t.add_external_router("E1", AsId(1));
+    let b0 = t.add_router("B0");
+    let e2 = t.add_external_router("E2", AsId(65001));
+
+    // two external routers of the same AS may be connected
+    t.add_edge(e0, e1, 1.0, None).unwrap();
+    assert!(t.get_topology().find_edge(e0, e1).is_some());
+
+    // an internal router cannot peer with an external router of its own AS, and the link is not
+    // added to the topology
+    assert_eq!(
+        t.add_edge(b0, e2, 1.0, None),
+        Err(NetworkError::DeviceError(DeviceError::NeighborInSameAs(e2)))
+    );
+    assert!(t.get_topology().find_edge(b0, e2).is_none());
+    assert!(t.get_topology().find_edge(e2, b0).is_none());
+}
+
+#[test]
+fn test_enforce_first_as() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let b1 = t.add_router("B1");
+    let e1 = t.add_external_router("E1", AsId(2));
+    let e2 = t.add_external_router("E2", AsId(65001));
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, b1, 1.0, None).unwrap();
+    t.add_edge(b1, e1, 1.0, None).unwrap();
+    t.add_edge_no_session(b1, e2, 1.0, None).unwrap();
+
+    // an eBGP session cannot be established with a neighbor in the same AS
+    assert_eq!(
+        t.add_ebgp_session(b1, e2, false),
+        Err(NetworkError::DeviceError(DeviceError::NeighborInSameAs(e2)))
+    );
+    assert_eq!(
+        t.get_router(b0).unwrap().get_ebgp_neighbor_as(e0),
+        Some(AsId(1))
+    );
+    assert_eq!(
+        t.get_router(b1).unwrap().get_ebgp_neighbor_as(e1),
+        Some(AsId(2))
+    );
+
+    t.add_ibgp_session(b0, b1, false, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+    t.get_router_mut(b0).unwrap().enforce_first_as = true;
+
+    // e0 pretends to be in AS 2, and announces a shorter path than e1
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(2), AsId(3), AsId(4)], None, true)
+        .unwrap();
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(2), AsId(4)], None, true)
+        .unwrap();
+
+    // b0 drops the spoofed route
+    assert_route(&t, b0, Prefix(0), vec![b0, b1, e1]);
+    assert_route(&t, b1, Prefix(0), vec![b1, e1]);
+
+    // the route with the correct AS is accepted, and replaces the route of e1
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(4)], None, true)
+        .unwrap();
+    assert_route(&t, b0, Prefix(0), vec![b0, e0]);
+    assert_route(&t, b1, Prefix(0), vec![b1, b0, e0]);
+}
+
+#[test]
+fn test_table_sizes() {
+    let (mut n, [rr, r1, r2, r3, r4, e1, e2, e3, e4, _, _, _, _, _]) = setup_carousel_gadget();
+    let sizes = |n: &mut Network, r: RouterId| n.get_router(r).unwrap().table_sizes();
+
+    // rr receives both prefixes from pr, and four more routes from its clients. It selects the
+    // route from pr, and advertises it to all four clients.
+    assert_eq!(
+        sizes(&mut n, rr),
+        TableSizes {
+            rib_in: 6,
+            rib: 2,
+            rib_out: 8,
+            known_prefixes: 2
+        }
+    );
+    // e2 receives both prefixes from p2 and from its three route reflectors. It prefers the
+    // routes from the route reflectors (due to the lower local pref of p2), and only advertises
+    // them to p2.
+    assert_eq!(
+        sizes(&mut n, e2),
+        TableSizes {
+            rib_in: 8,
+            rib: 2,
+            rib_out: 2,
+            known_prefixes: 2
+        }
+    );
+
+    let mut total = TableSizes::default();
+    for r in [rr, r1, r2, r3, r4, e1, e2, e3, e4] {
+        total += sizes(&mut n, r);
+    }
+    assert_eq!(total, n.total_table_sizes());
+    assert_eq!(
+        total,
+        TableSizes {
+            rib_in: 40,
+            rib: 18,
+            rib_out: 38,
+            known_prefixes: 18
+        }
+    );
+}
+
+#[test]
+fn test_well_known_communities() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let e1 = t.add_external_router("E1", AsId(2));
+    let b0 = t.add_router("B0");
+    let b1 = t.add_router("B1");
+    let e2 = t.add_external_router("E2", AsId(3));
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(e1, b0, 1.0, None).unwrap();
+    t.add_edge(b0, b1, 1.0, None).unwrap();
+    t.add_edge(b1, e2, 1.0, None).unwrap();
+    t.add_ibgp_session(b0, b1, false, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    // tag all routes from e0 with NO_EXPORT, and all routes from e1 with NO_ADVERTISE
+    let b0_router = t.get_router_mut(b0).unwrap();
+    b0_router
+        .policy_bgp_communities
+        .insert(e0, btreeset! {COMMUNITY_NO_EXPORT});
+    b0_router
+        .policy_bgp_communities
+        .insert(e1, btreeset! {COMMUNITY_NO_ADVERTISE});
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(1), vec![AsId(2)], None, true)
+        .unwrap();
+
+    // the route tagged with NO_EXPORT is propagated over iBGP, but not over eBGP
+    assert_route(&t, b0, Prefix(0), vec![b0, e0]);
+    assert_route(&t, b1, Prefix(0), vec![b1, b0, e0]);
+    assert!(t
+        .get_router(b1)
+        .unwrap()
+        .get_selected_bgp_route(Prefix(0))
+        .unwrap()
+        .route
+        .communities
+        .contains(&COMMUNITY_NO_EXPORT));
+    assert_eq!(
+        t.get_router(b1)
+            .unwrap()
+            .get_advertised_route(e2, Prefix(0)),
+        None
+    );
+    assert_eq!(
+        t.get_router(b0)
+            .unwrap()
+            .get_advertised_route(e1, Prefix(0)),
+        None
+    );
+
+    // the route tagged with NO_ADVERTISE is used by b0, but not advertised to any peer
+    assert_route(&t, b0, Prefix(1), vec![b0, e1]);
+    assert_blackhole(&t, b1, Prefix(1), vec![b1]);
+    assert_eq!(
+        t.get_router(b0)
+            .unwrap()
+            .get_advertised_route(b1, Prefix(1)),
+        None
+    );
+}
+
+#[test]
+fn test_link_loads() {
+    let (mut t, [e0, b0, r0, r1, b1, _]) = setup_simple();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+
+    // the load appears on exactly the links of the chosen path
+    assert_eq!(
+        t.compute_link_loads(&[(b1, Prefix(0), 10.0)]),
+        Ok(hashmap! {
+            (b1, r1) => 10.0,
+            (r1, r0) => 10.0,
+            (r0, b0) => 10.0,
+            (b0, e0) => 10.0,
+        })
+    );
+
+    // the load of multiple demands is added up
+    assert_eq!(
+        t.compute_link_loads(&[(b1, Prefix(0), 10.0), (r0, Prefix(0), 5.0)]),
+        Ok(hashmap! {
+            (b1, r1) => 10.0,
+            (r1, r0) => 10.0,
+            (r0, b0) => 15.0,
+            (b0, e0) => 15.0,
+        })
+    );
+
+    // demands towards an unknown prefix are dropped
+    assert_eq!(
+        t.compute_link_loads(&[(b1, Prefix(1), 10.0)]),
+        Err(NetworkError::ForwardingBlackHole(
+            vec!["B1"],
+            NextHop::NoRoute
+        ))
+    );
+}
+
+#[test]
+fn test_black_hole_reason() {
+    // e0 ---- b0 ---- r0
+    let mut t = Network::new();
+    let prefix = Prefix(0);
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let r0 = t.add_router("R0");
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_ibgp_session(b0, r0, false, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    assert_route(&t, r0, prefix, vec![r0, b0, e0]);
+
+    // no router knows a route for an unknown prefix
+    assert_eq!(
+        t.get_route(r0, Prefix(1)),
+        Err(NetworkError::ForwardingBlackHole(
+            vec!["R0"],
+            NextHop::NoRoute
+        ))
+    );
+
+    // remove the link towards e0 from the IGP, without updating BGP. The route stays selected,
+    // but its next hop is no longer reachable.
+    t.set_link_in_igp(b0, e0, false, false).unwrap();
+    assert_eq!(
+        t.get_route(r0, prefix),
+        Err(NetworkError::ForwardingBlackHole(
+            vec!["R0"],
+            NextHop::Unreachable(e0)
+        ))
+    );
+}
+
+#[test]
+fn test_do_queue_uncapped() {
+    let (mut n, [_, _, _, _, _, _, e2, _, _, _, _, p2, _, _]) = setup_carousel_gadget();
+    n.stop_after_queue(Some(10));
+
+    // reconfigure e2 (see `carousel_gadget`), which takes more than 10 events to converge
+    n.get_router_mut(e2)
+        .unwrap()
+        .policy_bgp_local_pref
+        .remove(&p2);
+    n.schedule_update_router(e2).unwrap();
+    assert_eq!(n.do_queue_uncapped(), Ok(true));
+    assert!(n.last_convergence_steps() > 10);
+    assert_converged(&n);
+
+    // the configured limit is still used afterwards
+    n.get_router_mut(e2)
+        .unwrap()
+        .policy_bgp_local_pref
+        .insert(p2, 50);
+    n.schedule_update_router(e2).unwrap();
+    assert_eq!(n.do_queue(), Ok(false));
+    assert_eq!(n.last_convergence_steps(), 10);
+}
+
+#[test]
+fn test_route_reflector_clusters() {
+    let mut t = Network::new();
+
+    let e1 = t.add_external_router("E1", AsId(1));
+    let b1 = t.add_router("B1");
+    let c1 = t.add_router("C1");
+    let rr1a = t.add_router("RR1a");
+    let rr1b = t.add_router("RR1b");
+    let rr2 = t.add_router("RR2");
+    let c2 = t.add_router("C2");
+
+    t.add_edge(e1, b1, 1.0, None).unwrap();
+    t.add_edge(b1, rr1a, 1.0, None).unwrap();
+    t.add_edge(b1, rr1b, 1.0, None).unwrap();
+    t.add_edge(c1, rr1a, 1.0, None).unwrap();
+    t.add_edge(c1, rr1b, 1.0, None).unwrap();
+    t.add_edge(rr1a, rr2, 1.0, None).unwrap();
+    t.add_edge(rr1b, rr2, 2.0, None).unwrap();
+    t.add_edge(rr2, c2, 1.0, None).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    // two clusters, the first one with two redundant route reflectors
+    assert_eq!(t.add_cluster(1, &[rr1a, rr1b], &[b1, c1], true), Ok(true));
+    assert_eq!(t.add_cluster(2, &[rr2], &[c2], true), Ok(true));
+    assert_eq!(
+        t.get_router(rr2).unwrap().get_bgp_session_type(rr1a),
+        Ok(BgpSessionType::IBgpPeer)
+    );
+    assert_eq!(
+        t.get_router(rr1a).unwrap().get_bgp_session_type(c1),
+        Ok(BgpSessionType::IBgpClient)
+    );
+
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+
+    // a client in the second cluster reaches the prefix originated in the first cluster
+    assert_route(&t, c2, Prefix(0), vec![c2, rr2, rr1a, b1, e1]);
+    assert_route(&t, c1, Prefix(0), vec![c1, rr1a, b1, e1]);
+    let route = t
+        .get_router(c2)
+        .unwrap()
+        .get_selected_bgp_route(Prefix(0))
+        .unwrap()
+        .route;
+    assert_eq!(route.cluster_list, vec![2, 1]);
+
+    // the route reflectors of the first cluster only know the route from the client, since the
+    // reflected routes already contain their cluster id
+    for rr in [rr1a, rr1b] {
+        let known = t
+            .get_router(rr)
+            .unwrap()
+            .get_known_bgp_routes(Prefix(0))
+            .unwrap();
+        assert_eq!(known.len(), 1);
+        assert_eq!(known[0].from_id, b1);
+    }
+}
+
+#[test]
+fn test_originate_route() {
+    let mut t = Network::new();
+
+    let b0 = t.add_router("B0");
+    let r0 = t.add_router("R0");
+    let b1 = t.add_router("B1");
+    let e1 = t.add_external_router("E1", AsId(1));
+
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_edge(r0, b1, 1.0, None).unwrap();
+    t.add_edge(b1, e1, 1.0, None).unwrap();
+
+    t.add_ibgp_session(r0, b0, true, true).unwrap();
+    t.add_ibgp_session(r0, b1, true, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    let route = BgpRoute {
+        prefix: Prefix(0),
+        path_id: 0,
+        as_path: vec![],
+        next_hop: e1,
+        local_pref: None,
+        med: Some(10),
+        aigp: None,
+        communities: btreeset! {100},
+        link_bandwidth: None,
+        cluster_list: Vec::new(),
+        originator_id: None,
+        router_id: None,
+        seq: None,
+        route_targets: BTreeSet::new(),
+        origin: Origin::Igp,
+    };
+    assert_eq!(t.originate_route(r0, route, true), Ok(true));
+
+    // all peers receive the route with the next hop set to the originator
+    for r in [b0, b1] {
+        let selected = t
+            .get_router(r)
+            .unwrap()
+            .get_selected_bgp_route(Prefix(0))
+            .unwrap();
+        assert_eq!(selected.from_id, r0);
+        assert_eq!(selected.route.next_hop, r0);
+        assert_eq!(selected.route.med, Some(10));
+    }
+    assert_route(&t, b0, Prefix(0), vec![b0, r0]);
+    assert_route(&t, r0, Prefix(0), vec![r0]);
+
+    // the route is advertised to the external neighbor with the next hop rewritten
+    let advertised = t
+        .get_router(b1)
+        .unwrap()
+        .get_advertised_route(e1, Prefix(0))
+        .cloned()
+        .unwrap();
+    assert_eq!(advertised.next_hop, b1);
+    assert_eq!(advertised.communities, btreeset! {100});
+
+    // external routers cannot originate routes
+    assert_eq!(
+        t.originate_route(e1, advertised, true),
+        Err(NetworkError::DeviceIsExternalRouter(e1))
+    );
+}
+
+#[test]
+fn test_external_advertised_prefixes() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], Some(5), true)
+        .unwrap();
+    t.advertise_external_route(e0, Prefix(1), vec![AsId(1)], None, true)
+        .unwrap();
+
+    // the external router reports the advertised routes
+    let advertised = t.get_external_router(e0).unwrap().advertised_prefixes();
+    assert_eq!(advertised.len(), 2);
+    assert_eq!(advertised[&Prefix(0)].as_path, vec![AsId(1), AsId(2)]);
+    assert_eq!(advertised[&Prefix(0)].med, Some(5));
+    assert_eq!(advertised[&Prefix(1)].as_path, vec![AsId(1)]);
+
+    // withdrawing a prefix which is not advertised does nothing
+    t.retract_external_route(e0, Prefix(2), false).unwrap();
+    assert!(t.get_queue().is_empty());
+
+    t.retract_external_route(e0, Prefix(1), true).unwrap();
+    assert_eq!(
+        t.get_external_router(e0)
+            .unwrap()
+            .advertised_prefixes()
+            .keys()
+            .collect::<Vec<_>>(),
+        vec![&Prefix(0)]
+    );
+    assert_route(&t, b0, Prefix(0), vec![b0, e0]);
+    assert_blackhole(&t, b0, Prefix(1), vec![b0]);
+}
+
+#[test]
+fn test_detect_stale_overwrites() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let r0 = t.add_router("R0");
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_ibgp_session(b0, r0, false, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    // updates executed in order are never stale
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+    assert_eq!(t.detect_stale_overwrites(), vec![]);
+
+    // send two updates, and execute them in reverse order
+    t.advertise_external_route(e0, Prefix(1), vec![AsId(1), AsId(2)], None, false)
+        .unwrap();
+    t.advertise_external_route(e0, Prefix(1), vec![AsId(1)], None, false)
+        .unwrap();
+    let mut reordered: Vec<Event> = t.get_queue().iter().cloned().collect();
+    reordered.reverse();
+    t.replay(&reordered).unwrap();
+
+    // the older update has overwritten the newer one on b0
+    assert_eq!(t.detect_stale_overwrites(), vec![(b0, e0, Prefix(1))]);
+    assert_eq!(
+        t.get_router(b0)
+            .unwrap()
+            .get_known_bgp_routes(Prefix(1))
+            .unwrap()[0]
+            .route
+            .as_path,
+        vec![AsId(1), AsId(2)]
+    );
+}
+
+#[test]
+fn test_random_tiebreak() {
+    // r0 receives the same route from b1 and b2, with the same IGP cost
+    let selection = |seed: Option<u64>| {
+        let mut t = Network::new();
+        let e1 = t.add_external_router("E1", AsId(1));
+        let e2 = t.add_external_router("E2", AsId(2));
+        let b1 = t.add_router("B1");
+        let b2 = t.add_router("B2");
+        let r0 = t.add_router("R0");
+        t.add_edge(e1, b1, 1.0, None).unwrap();
+        t.add_edge(e2, b2, 1.0, None).unwrap();
+        t.add_edge(b1, r0, 1.0, None).unwrap();
+        t.add_edge(b2, r0, 1.0, None).unwrap();
+        t.add_ibgp_session(r0, b1, true, true).unwrap();
+        t.add_ibgp_session(r0, b2, true, true).unwrap();
+        t.write_igp_fw_tables(true).unwrap();
+        t.set_tiebreak_seed(seed);
+        t.advertise_external_route(e1, Prefix(0), vec![AsId(1), AsId(3)], None, true)
+            .unwrap();
+        t.advertise_external_route(e2, Prefix(0), vec![AsId(2), AsId(3)], None, true)
+            .unwrap();
+        let route = t.get_route(r0, Prefix(0)).unwrap();
+        (route[1] == b1, route)
+    };
+
+    // without a seed, the route with the lower next hop is selected
+    assert!(selection(None).0);
+
+    // the same seed always results in the same selection
+    for seed in 0..10 {
+        assert_eq!(selection(Some(seed)), selection(Some(seed)));
+    }
+
+    // different seeds can result in different selections
+    let selected: HashSet<bool> = (0..10).map(|seed| selection(Some(seed)).0).collect();
+    assert_eq!(selected.len(), 2);
+
+    // the selection does not depend on the platform or the Rust release
+    let selected: Vec<bool> = (0..10).map(|seed| selection(Some(seed)).0).collect();
+    assert_eq!(
+        selected,
+        vec![false, true, true, true, false, true, true, true, false, false]
+    );
+}
+
+#[test]
+fn test_shutdown_router_bgp() {
+    let (mut t, [e0, b0, r0, r1, b1, _]) = setup_simple();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    assert_route(&t, b1, Prefix(0), vec![b1, r1, r0, b0, e0]);
+
+    // shutting down BGP on the transit route reflector r0 isolates r1 and b1
+    assert_eq!(t.shutdown_router_bgp(r0, true), Ok(true));
+    assert!(t.get_router(r0).unwrap().get_bgp_sessions().is_empty());
+    assert_route(&t, b0, Prefix(0), vec![b0, e0]);
+    assert_blackhole(&t, r0, Prefix(0), vec![r0]);
+    assert_blackhole(&t, r1, Prefix(0), vec![r1]);
+    assert_blackhole(&t, b1, Prefix(0), vec![b1]);
+
+    // shutting down BGP again does nothing
+    assert_eq!(t.shutdown_router_bgp(r0, true), Ok(true));
+
+    // re-enabling BGP restores all sessions and routes
+    assert_eq!(t.no_shutdown_router_bgp(r0, true), Ok(true));
+    let r0_router = t.get_router(r0).unwrap();
+    assert_eq!(
+        r0_router.get_bgp_session_type(b0),
+        Ok(BgpSessionType::IBgpClient)
+    );
+    assert_eq!(
+        r0_router.get_bgp_session_type(r1),
+        Ok(BgpSessionType::IBgpPeer)
+    );
+    assert_eq!(
+        t.get_router(b0).unwrap().get_bgp_session_type(r0),
+        Ok(BgpSessionType::IBgpPeer)
+    );
+    assert_route(&t, r0, Prefix(0), vec![r0, b0, e0]);
+    assert_route(&t, b1, Prefix(0), vec![b1, r1, r0, b0, e0]);
+
+    // shutting down BGP on a border router removes the routes of its external neighbor
+    assert_eq!(t.shutdown_router_bgp(b0, true), Ok(true));
+    assert_blackhole(&t, b1, Prefix(0), vec![b1]);
+    assert_eq!(t.no_shutdown_router_bgp(b0, true), Ok(true));
+    assert_route(&t, b0, Prefix(0), vec![b0, e0]);
+    assert_route(&t, b1, Prefix(0), vec![b1, r1, r0, b0, e0]);
+}
+
+#[test]
+fn test_minimal_ibgp_sessions() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let e1 = t.add_external_router("E1", AsId(2));
+    let b0 = t.add_router("B0");
+    let b1 = t.add_router("B1");
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(e1, b1, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_edge(r0, r1, 1.0, None).unwrap();
+    t.add_edge(r1, b1, 1.0, None).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    // full mesh
+    let routers = [b0, b1, r0, r1];
+    for (i, a) in routers.iter().enumerate() {
+        for b in routers[i + 1..].iter() {
+            t.add_ibgp_session(*a, *b, false, true).unwrap();
+        }
+    }
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(1), vec![AsId(2)], None, true)
+        .unwrap();
+    t.advertise_external_route(e0, Prefix(2), vec![AsId(1)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(2), vec![AsId(2)], None, true)
+        .unwrap();
+
+    // only the sessions with the border routers are needed
+    let sessions = t.minimal_ibgp_sessions().unwrap();
+    assert_eq!(
+        sessions,
+        vec![
+            (b0, b1, false),
+            (b0, r0, false),
+            (b0, r1, false),
+            (b1, r0, false),
+            (b1, r1, false)
+        ]
+    );
+
+    // the network is not changed
+    assert!(t.get_router(r0).unwrap().get_bgp_session_type(r1).is_ok());
+
+    // the reduced set still passes the correctness check
+    t.remove_ibgp_session(r0, r1, true).unwrap();
+    for prefix in [Prefix(0), Prefix(1), Prefix(2)] {
+        assert_eq!(t.check_ibgp_correctness(prefix), Ok(vec![]));
+    }
+    assert_route(&t, r0, Prefix(2), vec![r0, b0, e0]);
+    assert_route(&t, r1, Prefix(2), vec![r1, b1, e1]);
+}
+
+#[test]
+fn test_scenario_round_trip() {
+    let (n, [rr, _, _, _, _, _, _, _, e4, pr, _, _, _, p4]) = setup_carousel_gadget();
+    let json = n.to_scenario_json().unwrap();
+    let scenario = n.to_scenario();
+    assert_eq!(scenario.routers.len(), 14);
+    assert_eq!(scenario.forwarding.len(), 2 * 9);
+
+    // the reconstructed network has the same forwarding state
+    let reloaded = Network::from_scenario_json(&json).unwrap();
+    assert_eq!(reloaded.to_scenario().forwarding, scenario.forwarding);
+    assert_eq!(reloaded.to_scenario(), scenario);
+    assert_eq!(
+        reloaded.get_route(rr, Prefix(1)),
+        n.get_route(rr, Prefix(1))
+    );
+    assert_eq!(
+        reloaded.get_route(e4, Prefix(2)),
+        n.get_route(e4, Prefix(2))
+    );
+    assert_eq!(reloaded.get_router_name(pr), Ok("pr"));
+    assert_eq!(reloaded.get_router_name(p4), Ok("p4"));
+
+    // invalid scenarios are rejected
+    assert!(matches!(
+        Network::from_scenario_json("{\"routers\": []}"),
+        Err(NetworkError::InvalidScenario(_))
+    ));
+}
+
+#[test]
+fn test_scenario_round_trip_config() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+    let m = t.add_router("M");
+    t.add_edge(r1, m, 1.0, None).unwrap();
+    t.set_link_in_igp(r1, m, false, true).unwrap();
+    let parallel = t.add_parallel_edge(r0, r1, 5.0).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.get_router_mut(r0).unwrap().cluster_id = Some(7);
+    t.get_router_mut(r1).unwrap().always_compare_med = true;
+    t.get_router_mut(r1)
+        .unwrap()
+        .set_decision_steps(vec![
+            DecisionStep::LocalPref,
+            DecisionStep::IgpCost,
+            DecisionStep::AsPathLength,
+        ])
+        .unwrap();
+    t.get_router_mut(b1)
+        .unwrap()
+        .set_maximum_prefix(e1, Some(10));
+    let router = t.get_router_mut(b0).unwrap();
+    router.set_peer_group("upstream", PeerGroup::new());
+    router.get_peer_group_mut("upstream").unwrap().local_pref = Some(200);
+    router.add_peer_group_member("upstream", e0).unwrap();
+    router.set_vrf("red", Vrf::new(btreeset! {(1, 1)}, btreeset! {(1, 1)}));
+    let route = BgpRoute {
+        prefix: Prefix(5),
+        path_id: 0,
+        as_path: vec![],
+        next_hop: b0,
+        local_pref: None,
+        med: None,
+        aigp: None,
+        communities: BTreeSet::new(),
+        link_bandwidth: None,
+        cluster_list: Vec::new(),
+        originator_id: None,
+        router_id: None,
+        seq: None,
+        route_targets: BTreeSet::new(),
+        origin: Origin::Igp,
+    };
+    t.originate_vrf_route(b0, "red", route, true).unwrap();
+
+    let attrs = ExternalRouteAttrs {
+        communities: btreeset! {42},
+        origin: Origin::Egp,
+    };
+    t.advertise_external_route_with(e0, Prefix(0), vec![AsId(1), AsId(2)], None, attrs, true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+
+    let scenario = t.to_scenario();
+    let mut reloaded = Network::from_scenario_json(&t.to_scenario_json().unwrap()).unwrap();
+    assert_eq!(reloaded.to_scenario(), scenario);
+
+    // links keep their ids and whether they are part of the IGP
+    let topo = reloaded.get_topology();
+    assert_eq!(topo.edge_endpoints(parallel), Some((r0, r1)));
+    assert_eq!(topo.edge_weight(parallel), Some(&5.0));
+    assert!(scenario
+        .links
+        .iter()
+        .any(|l| (l.source, l.target, l.in_igp) == (r1.index() as u32, m.index() as u32, false)));
+
+    // the configuration and the selected routes are the same
+    let (old, new) = (t.get_router(r1).unwrap(), reloaded.get_router(r1).unwrap());
+    assert_eq!(new.get_decision_steps(), old.get_decision_steps());
+    assert!(new.always_compare_med);
+    assert_eq!(reloaded.get_router(r0).unwrap().cluster_id, Some(7));
+    for router in [b0, r0, r1, b1] {
+        let (old, new) = (
+            t.get_router(router).unwrap(),
+            reloaded.get_router(router).unwrap(),
+        );
+        assert_eq!(
+            new.get_selected_bgp_route(Prefix(0)),
+            old.get_selected_bgp_route(Prefix(0))
+        );
+    }
+    let selected = reloaded
+        .get_router(b0)
+        .unwrap()
+        .get_selected_bgp_route(Prefix(0))
+        .unwrap();
+    assert_eq!(selected.route.communities, btreeset! {42});
+    assert_eq!(selected.route.origin, Origin::Egp);
+    assert_eq!(selected.route.local_pref, Some(200));
+    assert_eq!(
+        reloaded
+            .get_router(b0)
+            .unwrap()
+            .get_vrf_route("red", Prefix(5)),
+        t.get_router(b0).unwrap().get_vrf_route("red", Prefix(5))
+    );
+    assert!(reloaded
+        .get_router(b0)
+        .unwrap()
+        .get_vrf_route("red", Prefix(5))
+        .is_some());
+}
+
+#[cfg(feature = "binary")]
+#[test]
+fn test_scenario_bytes_round_trip() {
+    // generate a network with 90 internal routers in a ring with chords, and 10 external routers
+    let mut n = Network::new();
+    let name = |prefix: &str, i: usize| -> &'static str {
+        Box::leak(format!("{}{}", prefix, i).into_boxed_str())
+    };
+    let routers: Vec<RouterId> = (0..90).map(|i| n.add_router(name("r", i))).collect();
+    let externals: Vec<RouterId> = (0..10)
+        .map(|i| n.add_external_router(name("e", i), AsId(100 + i as u32)))
+        .collect();
+    let mut rand: u32 = 42;
+    let mut next_weight = || {
+        rand = rand.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (1 + (rand >> 16) % 10) as f32
+    };
+    for i in 0..90 {
+        for j in [(i + 1) % 90, (i + 7) % 90] {
+            let (w, rev_w) = (next_weight(), next_weight());
+            n.add_edge(routers[i], routers[j], w, Some(rev_w)).unwrap();
+        }
+    }
+    for (i, e) in externals.iter().enumerate() {
+        n.add_edge(*e, routers[i * 9], 1.0, None).unwrap();
+    }
+    for r in routers.iter().skip(1) {
+        n.add_ibgp_session(routers[0], *r, true, true).unwrap();
+    }
+    n.write_igp_fw_tables(true).unwrap();
+    for (i, e) in externals.iter().enumerate() {
+        for p in 0..5 {
+            let as_path = vec![AsId(100 + i as u32); 1 + (i + p) % 3];
+            n.advertise_external_route(*e, Prefix(p as u32), as_path, None, true)
+                .unwrap();
+        }
+    }
+
+    let bytes = n.to_bytes().unwrap();
+    assert!(bytes.len() < n.to_scenario_json().unwrap().len());
+
+    // the reconstructed network has the same forwarding state
+    let reloaded = Network::from_bytes(&bytes).unwrap();
+    assert_eq!(reloaded.to_scenario(), n.to_scenario());
+    for r in routers.iter() {
+        for p in 0..5 {
+            assert_eq!(
+                reloaded.get_route(*r, Prefix(p)),
+                n.get_route(*r, Prefix(p))
+            );
+        }
+    }
+
+    // invalid bytes are rejected
+    assert!(matches!(
+        Network::from_bytes(&bytes[..bytes.len() / 2]),
+        Err(NetworkError::InvalidScenario(_))
+    ));
+}
+
+#[test]
+fn test_equal_best_routes() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let e1 = t.add_external_router("E1", AsId(1));
+    let b0 = t.add_router("B0");
+    let b1 = t.add_router("B1");
+    let r0 = t.add_router("R0");
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(e1, b1, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_edge(b1, r0, 1.0, None).unwrap();
+
+    t.add_ibgp_session(r0, b0, true, true).unwrap();
+    t.add_ibgp_session(r0, b1, true, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+
+    // both egresses are equally good for r0
+    let r = t.get_router(r0).unwrap();
+    let selected = r.get_selected_bgp_route(Prefix(0)).unwrap();
+    let equal = r.get_equal_best_routes(Prefix(0)).unwrap();
+    assert_eq!(equal.len(), 2);
+    assert_eq!(equal[0], selected);
+    assert_eq!(
+        equal
+            .iter()
+            .map(|e| e.route.next_hop)
+            .collect::<HashSet<_>>(),
+        hashset![e0, e1]
+    );
+
+    // the border routers prefer their own eBGP route
+    let r = t.get_router(b0).unwrap();
+    let equal = r.get_equal_best_routes(Prefix(0)).unwrap();
+    assert_eq!(equal.len(), 1);
+    assert_eq!(equal[0].route.next_hop, e0);
+
+    // with a longer AS path, the route of e1 is no longer equally good
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(1), AsId(3), AsId(2)], None, true)
+        .unwrap();
+    let r = t.get_router(r0).unwrap();
+    let equal = r.get_equal_best_routes(Prefix(0)).unwrap();
+    assert_eq!(equal.len(), 1);
+    assert_eq!(equal[0].route.next_hop, e0);
+
+    // no route is known for other prefixes
+    assert!(r.get_equal_best_routes(Prefix(1)).unwrap().is_empty());
+}
+
+#[test]
+fn test_vrf_route_targets() {
+    let mut t = Network::new();
+
+    let pe1 = t.add_router("PE1");
+    let rr = t.add_router("RR");
+    let pe2 = t.add_router("PE2");
+    let pe3 = t.add_router("PE3");
+    let e0 = t.add_external_router("E0", AsId(1));
+
+    t.add_edge(pe1, rr, 1.0, None).unwrap();
+    t.add_edge(rr, pe2, 1.0, None).unwrap();
+    t.add_edge(rr, pe3, 1.0, None).unwrap();
+    t.add_edge(pe2, e0, 1.0, None).unwrap();
+
+    t.add_ibgp_session(rr, pe1, true, true).unwrap();
+    t.add_ibgp_session(rr, pe2, true, true).unwrap();
+    t.add_ibgp_session(rr, pe3, true, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.get_router_mut(pe1)
+        .unwrap()
+        .set_vrf("red", Vrf::new(btreeset! {(100, 1)}, btreeset! {(100, 1)}));
+    t.get_router_mut(pe2)
+        .unwrap()
+        .set_vrf("red", Vrf::new(btreeset! {(100, 1)}, btreeset! {(100, 1)}));
+    t.get_router_mut(pe2)
+        .unwrap()
+        .set_vrf("blue", Vrf::new(btreeset! {(200, 1)}, btreeset! {(200, 1)}));
+    t.get_router_mut(pe3).unwrap().set_vrf(
+        "green",
+        Vrf::new(btreeset! {(300, 1)}, btreeset! {(300, 1)}),
+    );
+
+    let route = BgpRoute {
+        prefix: Prefix(0),
+        path_id: 0,
+        as_path: vec![],
+        next_hop: pe1,
+        local_pref: None,
+        med: None,
+        aigp: None,
+        communities: BTreeSet::new(),
+        link_bandwidth: None,
+        cluster_list: Vec::new(),
+        originator_id: None,
+        router_id: None,
+        seq: None,
+        route_targets: BTreeSet::new(),
+        origin: Origin::Igp,
+    };
+    assert_eq!(
+        t.originate_vrf_route(pe1, "blue", route.clone(), true),
+        Err(NetworkError::DeviceError(DeviceError::VrfNotFound("blue")))
+    );
+    assert_eq!(t.originate_vrf_route(pe1, "red", route, true), Ok(true));
+
+    // the route is tagged with RT 100:1, and only enters the VRFs importing it
+    let r = t.get_router(pe2).unwrap();
+    let imported = r.get_vrf_route("red", Prefix(0)).unwrap();
+    assert_eq!(imported.route.next_hop, pe1);
+    assert_eq!(imported.route.route_targets, btreeset! {(100, 1)});
+    assert_eq!(r.get_vrf_route("blue", Prefix(0)), None);
+
+    // PE3 does not import the route target, so the route is dropped
+    let r = t.get_router(pe3).unwrap();
+    assert_eq!(r.get_vrf_route("green", Prefix(0)), None);
+    assert!(r.get_known_bgp_routes(Prefix(0)).unwrap().is_empty());
+
+    // the route reflector has no VRFs, and keeps the route apart from the global table
+    let r = t.get_router(rr).unwrap();
+    assert_eq!(r.get_selected_vpn_route(Prefix(0)).unwrap().from_id, pe1);
+    assert_eq!(r.get_selected_bgp_route(Prefix(0)), None);
+
+    // VPN routes are not advertised via eBGP
+    assert_eq!(
+        t.get_router(pe2)
+            .unwrap()
+            .get_advertised_route(e0, Prefix(0)),
+        None
+    );
+
+    // removing the VRF removes the imported routes
+    let r = t.get_router_mut(pe2).unwrap();
+    assert!(r.remove_vrf("red").is_some());
+    assert_eq!(r.get_vrf_route("red", Prefix(0)), None);
+}
+
+#[test]
+fn test_vrf_route_not_forwarded() {
+    let mut t = Network::new();
+
+    let pe1 = t.add_router("PE1");
+    let pe2 = t.add_router("PE2");
+    t.add_edge(pe1, pe2, 1.0, None).unwrap();
+    t.add_ibgp_session(pe1, pe2, false, true).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    for pe in [pe1, pe2] {
+        t.get_router_mut(pe)
+            .unwrap()
+            .set_vrf("red", Vrf::new(btreeset! {(100, 1)}, btreeset! {(100, 1)}));
+    }
+
+    let route = BgpRoute {
+        prefix: Prefix(0),
+        path_id: 0,
+        as_path: vec![],
+        next_hop: pe1,
+        local_pref: None,
+        med: None,
+        aigp: None,
+        communities: BTreeSet::new(),
+        link_bandwidth: None,
+        cluster_list: Vec::new(),
+        originator_id: None,
+        router_id: None,
+        seq: None,
+        route_targets: BTreeSet::new(),
+        origin: Origin::Igp,
+    };
+    assert_eq!(t.originate_vrf_route(pe1, "red", route, true), Ok(true));
+
+    // PE2 imports the route into the VRF, but global traffic is not forwarded along it
+    let r = t.get_router(pe2).unwrap();
+    assert_eq!(r.get_vrf_route("red", Prefix(0)).unwrap().from_id, pe1);
+    assert_eq!(r.get_selected_bgp_route(Prefix(0)), None);
+    assert_eq!(r.get_next_hop(Prefix(0)), NextHop::NoRoute);
+    assert_blackhole(&t, pe2, Prefix(0), vec![pe2]);
+}
+
+#[test]
+fn test_step() {
+    let (mut t, [e0, b0, r0, r1, b1, _]) = setup_simple();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], None, false)
+        .unwrap();
+
+    // the routers learn the route one after the other, and never lose it again
+    let routers = [b0, r0, r1, b1];
+    let num_routes = |t: &Network| {
+        routers
+            .iter()
+            .filter(|r| t.get_route(**r, Prefix(0)).is_ok())
+            .count()
+    };
+    let mut last = num_routes(&t);
+    assert_eq!(last, 0);
+    let mut steps = 0;
+    while let Some(event) = t.step().unwrap() {
+        steps += 1;
+        let Event::Bgp(_, to, _) = event;
+        let current = num_routes(&t);
+        assert!(current >= last);
+        assert!(current == last || routers.contains(&to));
+        last = current;
+    }
+    assert!(steps > 0);
+    assert_eq!(last, routers.len());
+    assert!(t.get_queue().is_empty());
+    assert_eq!(t.step(), Ok(None));
+    assert_route(&t, b1, Prefix(0), vec![b1, r1, r0, b0, e0]);
+}
+
+#[test]
+fn test_bgp_and_igp_next_hop() {
+    let (mut t, [e0, b0, _, r1, b1, _]) = setup_simple();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+
+    // the BGP next hop is the remote egress, reached via the neighbor r1
+    let r = t.get_router(b1).unwrap();
+    assert_eq!(r.get_bgp_next_hop(Prefix(0)), Some(e0));
+    assert_eq!(r.get_igp_next_hop_for_prefix(Prefix(0)), Some(r1));
+
+    // at the egress, both are the same
+    let r = t.get_router(b0).unwrap();
+    assert_eq!(r.get_bgp_next_hop(Prefix(0)), Some(e0));
+    assert_eq!(r.get_igp_next_hop_for_prefix(Prefix(0)), Some(e0));
+
+    // no route for an unknown prefix
+    assert_eq!(r.get_bgp_next_hop(Prefix(1)), None);
+    assert_eq!(r.get_igp_next_hop_for_prefix(Prefix(1)), None);
+}
+
+#[test]
+fn test_link_not_in_igp() {
+    let mut t = Network::new();
+
+    let a = t.add_router("A");
+    let b = t.add_router("B");
+    let c = t.add_router("C");
+
+    t.add_edge(a, b, 1.0, None).unwrap();
+    t.add_edge(a, c, 5.0, None).unwrap();
+    t.add_edge(c, b, 5.0, None).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+    assert_eq!(
+        t.get_router(a).unwrap().get_igp_next_hop(b),
+        NextHop::Via(vec![b])
+    );
+
+    // the cheap link is not advertised into the IGP, so the longer path is used
+    assert_eq!(t.set_link_in_igp(a, b, false, true), Ok(true));
+    let r = t.get_router(a).unwrap();
+    assert_eq!(r.get_igp_next_hop(b), NextHop::Via(vec![c]));
+    assert_eq!(r.igp_forwarding_table.get(&b), Some(&Some((c, 10.0))));
+    assert_eq!(
+        t.get_router(b).unwrap().get_igp_next_hop(a),
+        NextHop::Via(vec![c])
+    );
+    assert_eq!(t.igp_path(a, b), Ok(vec![a, c, b]));
+
+    // advertising the link again restores the short path
+    assert_eq!(t.set_link_in_igp(b, a, true, true), Ok(true));
+    assert_eq!(
+        t.get_router(a).unwrap().get_igp_next_hop(b),
+        NextHop::Via(vec![b])
+    );
+
+    assert_eq!(
+        t.set_link_in_igp(b, b, false, true),
+        Err(NetworkError::LinkNotFound(b, b))
+    );
+}
+
+#[test]
+fn test_with_seed() {
+    fn build(seed: u64) -> (Network, Vec<Event>) {
+        let mut t = Network::with_seed(seed);
+        t.record_trace(true);
+
+        let e0 = t.add_external_router("E0", AsId(1));
+        let e1 = t.add_external_router("E1", AsId(2));
+        let b0 = t.add_router("B0");
+        let b1 = t.add_router("B1");
+        let r0 = t.add_router("R0");
+
+        t.add_edge(e0, b0, 1.0, None).unwrap();
+        t.add_edge(e1, b1, 1.0, None).unwrap();
+        t.add_edge(b0, r0, 1.0, None).unwrap();
+        t.add_edge(b1, r0, 1.0, None).unwrap();
+
+        t.add_ibgp_session(r0, b0, true, true).unwrap();
+        t.add_ibgp_session(r0, b1, true, true).unwrap();
+
+        t.write_igp_fw_tables(true).unwrap();
+
+        // all prefixes are advertised by both egresses with equally good routes
+        for p in 0..10 {
+            for e in [e0, e1] {
+                assert_eq!(
+                    t.advertise_external_route(e, Prefix(p), vec![AsId(p)], None, true),
+                    Ok(true)
+                );
+            }
+        }
+        // changing the IGP affects many prefixes at once
+        t.update_edge_weight(b0, r0, 5.0, None);
+        assert_eq!(t.write_igp_fw_tables(true), Ok(true));
+        t.update_edge_weight(b1, r0, 10.0, None);
+        assert_eq!(t.write_igp_fw_tables(true), Ok(true));
+        let trace = t.take_trace();
+        (t, trace)
+    }
+
+    // identical networks with the same seed converge with the same events to the same state
+    let (a, trace_a) = build(42);
+    let (b, trace_b) = build(42);
+    assert!(!trace_a.is_empty());
+    assert_eq!(trace_a, trace_b);
+    assert_eq!(a.to_scenario_json().unwrap(), b.to_scenario_json().unwrap());
+
+    // routers added to the network use the seed
+    let mut a = a;
+    let r = a.add_router("R2");
+    assert_eq!(a.get_router(r).unwrap().tiebreak_seed, Some(42));
+}
+
+#[test]
+fn test_update_external_route() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let e1 = t.add_external_router("E1", AsId(1));
+    let b0 = t.add_router("B0");
+    let b1 = t.add_router("B1");
+    let r0 = t.add_router("R0");
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(e1, b1, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_edge(b1, r0, 1.0, None).unwrap();
+
+    t.add_ibgp_session(r0, b0, true, true).unwrap();
+    t.add_ibgp_session(r0, b1, true, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], Some(10), true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(1), AsId(2)], Some(20), true)
+        .unwrap();
+    assert_route(&t, r0, Prefix(0), vec![r0, b0, e0]);
+
+    // increasing the MED of e0 moves the traffic to e1
+    assert_eq!(
+        t.update_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], Some(30), true),
+        Ok(true)
+    );
+    assert_route(&t, r0, Prefix(0), vec![r0, b1, e1]);
+    // MED is compared before preferring eBGP over iBGP
+    assert_route(&t, b0, Prefix(0), vec![b0, r0, b1, e1]);
+    assert_eq!(
+        t.get_external_router(e0).unwrap().advertised_prefixes()[&Prefix(0)].med,
+        Some(30)
+    );
+
+    // a longer AS path of e1 moves it back
+    assert_eq!(
+        t.update_external_route(
+            e1,
+            Prefix(0),
+            vec![AsId(1), AsId(3), AsId(2)],
+            Some(0),
+            true
+        ),
+        Ok(true)
+    );
+    assert_route(&t, r0, Prefix(0), vec![r0, b0, e0]);
+
+    // only advertised routes can be updated
+    assert_eq!(
+        t.update_external_route(e0, Prefix(1), vec![AsId(1)], None, true),
+        Err(NetworkError::PrefixNotAdvertised(e0, Prefix(1)))
+    );
+}
+
+#[test]
+fn test_parallel_external_links() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+
+    // both parallel links are added, but only one eBGP session
+    assert_eq!(t.add_edge(e0, b0, 1.0, None), Ok(()));
+    assert_eq!(t.add_edge(b0, e0, 2.0, None), Ok(()));
+    let links = t
+        .get_topology()
+        .edge_indices()
+        .filter_map(|e| t.get_topology().edge_endpoints(e))
+        .filter(|link| *link == (e0, b0))
+        .count();
+    assert_eq!(links, 2);
+    assert_eq!(
+        t.get_router(b0).unwrap().get_bgp_sessions(),
+        vec![(e0, BgpSessionType::EBgp)]
+    );
+    assert_eq!(t.get_external_router(e0).unwrap().neighbors, hashset! {b0});
+
+    t.write_igp_fw_tables(true).unwrap();
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+    assert_route(&t, b0, Prefix(0), vec![b0, e0]);
+}
+
+#[test]
+fn test_frontier() {
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+    let r2 = t.add_router("R2");
+    let r3 = t.add_router("R3");
+
+    // tree: b0 - r0, with r0 - r1 - r3 and r0 - r2
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_edge(r0, r1, 1.0, None).unwrap();
+    t.add_edge(r0, r2, 1.0, None).unwrap();
+    t.add_edge(r1, r3, 1.0, None).unwrap();
+
+    t.add_ibgp_session(r0, b0, true, true).unwrap();
+    t.add_ibgp_session(r0, r1, true, true).unwrap();
+    t.add_ibgp_session(r0, r2, true, true).unwrap();
+    t.add_ibgp_session(r1, r3, true, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    // recording is disabled by default
+    assert!(t.take_frontier().is_empty());
+
+    t.record_frontier(Some(Prefix(0)));
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+    let frontier = t.take_frontier();
+
+    // the frontier only grows
+    assert!(!frontier.is_empty());
+    assert!(frontier.windows(2).all(|w| w[0].is_subset(&w[1])));
+    assert_eq!(frontier.last().unwrap(), &hashset! {b0, r0, r1, r2, r3});
+    assert!(t.take_frontier().is_empty());
+
+    // disabling the recording
+    t.record_frontier(None);
+    t.retract_external_route(e0, Prefix(0), true).unwrap();
+    assert!(t.take_frontier().is_empty());
+}
+
+#[test]
+fn test_originator_id() {
+    // two-level route reflector hierarchy: rr2 is the route reflector of rr1, which is the route
+    // reflector of b0
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let b0 = t.add_router("B0");
+    let rr1 = t.add_router("RR1");
+    let rr2 = t.add_router("RR2");
+    let r0 = t.add_router("R0");
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(b0, rr1, 1.0, None).unwrap();
+    t.add_edge(rr1, rr2, 1.0, None).unwrap();
+    t.add_edge(rr2, r0, 1.0, None).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.get_router_mut(rr1).unwrap().cluster_id = Some(1);
+    t.get_router_mut(rr2).unwrap().cluster_id = Some(2);
+    t.add_ibgp_session(rr1, b0, true, true).unwrap();
+    t.add_ibgp_session(rr2, rr1, true, true).unwrap();
+    t.add_ibgp_session(rr2, r0, true, true).unwrap();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+    assert_route(&t, r0, Prefix(0), vec![r0, rr2, rr1, b0, e0]);
+
+    // the originator is set by the first route reflector, and preserved by the second one
+    let originator = |t: &mut Network, r: RouterId| {
+        t.get_router(r)
+            .unwrap()
+            .get_selected_bgp_route(Prefix(0))
+            .unwrap()
+            .route
+            .originator_id
+    };
+    assert_eq!(originator(&mut t, b0), None);
+    assert_eq!(originator(&mut t, rr1), None);
+    assert_eq!(originator(&mut t, rr2), Some(b0));
+    assert_eq!(originator(&mut t, r0), Some(b0));
+    let route = t
+        .get_router(r0)
+        .unwrap()
+        .get_selected_bgp_route(Prefix(0))
+        .unwrap()
+        .route;
+    assert_eq!(route.cluster_list, vec![2, 1]);
+}
+
+#[test]
+fn test_path_diversity() {
+    // r0 is dual-homed to b0 and b1
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let e1 = t.add_external_router("E1", AsId(2));
+    let b0 = t.add_router("B0");
+    let b1 = t.add_router("B1");
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+
+    t.add_edge(e0, b0, 1.0, None).unwrap();
+    t.add_edge(e1, b1, 1.0, None).unwrap();
+    t.add_edge(b0, r0, 1.0, None).unwrap();
+    t.add_edge(b1, r0, 1.0, None).unwrap();
+    t.add_edge(r0, r1, 1.0, None).unwrap();
+
+    for (a, b) in [(b0, b1), (b0, r0), (b1, r0), (b0, r1), (b1, r1), (r0, r1)] {
+        t.add_ibgp_session(a, b, false, true).unwrap();
+    }
+    t.write_igp_fw_tables(true).unwrap();
+
+    // no route is known yet
+    assert_eq!(t.path_diversity(r0, Prefix(0)), Ok(0));
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(0), vec![AsId(2)], None, true)
+        .unwrap();
+    assert_eq!(t.path_diversity(r0, Prefix(0)), Ok(2));
+    assert_eq!(t.path_diversity(b0, Prefix(0)), Ok(2));
+    // r1 is single-homed to r0, so both paths share the same link
+    assert_eq!(t.path_diversity(r1, Prefix(0)), Ok(1));
+
+    // with only a single egress left, r0 has no alternative
+    t.retract_external_route(e1, Prefix(0), true).unwrap();
+    assert_eq!(t.path_diversity(r0, Prefix(0)), Ok(1));
+
+    assert_eq!(
+        t.path_diversity(e0, Prefix(0)),
+        Err(NetworkError::DeviceIsExternalRouter(e0))
+    );
+}
+
+#[test]
+fn test_validate() {
+    let (mut t, [_, b0, _, _, b1, _]) = setup_simple();
+
+    assert_eq!(t.validate(), Ok(()));
+    assert_eq!(setup_carousel_gadget().0.validate(), Ok(()));
+
+    // a session which is only configured on one end
+    t.get_router_mut(b0)
+        .unwrap()
+        .establish_bgp_session(b1, BgpSessionType::IBgpPeer)
+        .unwrap();
+    assert_eq!(
+        t.validate(),
+        Err(vec![ConfigError::OneSidedSession(b0, b1)])
+    );
+
+    // both ends treat the other one as their client
+    t.get_router_mut(b0).unwrap().close_bgp_session(b1).unwrap();
+    t.get_router_mut(b1)
+        .unwrap()
+        .establish_bgp_session(b0, BgpSessionType::IBgpClient)
+        .unwrap();
+    t.get_router_mut(b0)
+        .unwrap()
+        .establish_bgp_session(b1, BgpSessionType::IBgpClient)
+        .unwrap();
+    assert_eq!(
+        t.validate(),
+        Err(vec![ConfigError::IncompatibleSessionTypes(b0, b1)])
+    );
+    t.get_router_mut(b0).unwrap().close_bgp_session(b1).unwrap();
+    t.get_router_mut(b0)
+        .unwrap()
+        .establish_bgp_session(b1, BgpSessionType::IBgpPeer)
+        .unwrap();
+    assert_eq!(t.validate(), Ok(()));
+
+    // an external router without any link
+    let e2 = t.add_external_router("E2", AsId(2));
+    assert_eq!(
+        t.validate(),
+        Err(vec![
+            ConfigError::ExternalRouterWithoutNeighbor(e2),
+            ConfigError::Disconnected(e2)
+        ])
+    );
+}
+
+#[test]
+fn test_clear_bgp_neighbor() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+    t.advertise_external_route(e1, Prefix(1), vec![AsId(1), AsId(2)], None, true)
+        .unwrap();
+
+    // number of messages sent from `from` to `to`, and whether they contain a withdraw
+    let messages = |trace: &[Event], from: RouterId, to: RouterId| -> (usize, bool) {
+        let sent: Vec<&BgpEvent> = trace
+            .iter()
+            .filter_map(|Event::Bgp(f, t, e)| {
+                if (*f, *t) == (from, to) {
+                    Some(e)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let withdraw = sent.iter().any(|e| matches!(e, BgpEvent::Withdraw(_)));
+        (sent.len(), withdraw)
+    };
+
+    // both ends withdraw and re-advertise their routes
+    t.record_trace(true);
+    assert_eq!(t.clear_bgp_neighbor(r0, r1), Ok(true));
+    let trace = t.take_trace();
+    assert_eq!(messages(&trace, r0, r1), (2, true));
+    assert_eq!(messages(&trace, r1, r0), (2, true));
+    assert_route(&t, r0, Prefix(1), vec![r0, r1, b1, e1]);
+    assert_route(&t, r1, Prefix(0), vec![r1, r0, b0, e0]);
+    assert_eq!(
+        t.get_router(r0).unwrap().get_bgp_session_type(r1),
+        Ok(BgpSessionType::IBgpPeer)
+    );
+
+    // the external neighbor advertises its routes again
+    assert_eq!(t.clear_bgp_neighbor(b0, e0), Ok(true));
+    let trace = t.take_trace();
+    assert_eq!(messages(&trace, e0, b0), (1, false));
+    assert_eq!(messages(&trace, b0, e0), (2, true));
+    assert_route(&t, r1, Prefix(0), vec![r1, r0, b0, e0]);
+
+    assert_eq!(
+        t.clear_bgp_neighbor(b0, b1),
+        Err(NetworkError::DeviceError(DeviceError::NoBgpSession(b1)))
+    );
+}
+
+#[test]
+fn test_parallel_edges() {
+    let mut t = Network::new();
+
+    let r0 = t.add_router("R0");
+    let r1 = t.add_router("R1");
+    let r2 = t.add_router("R2");
+
+    // two parallel links of equal weight between r0 and r1, and a more expensive one
+    let a = t.add_parallel_edge(r0, r1, 1.0).unwrap();
+    let b = t.add_parallel_edge(r0, r1, 1.0).unwrap();
+    t.add_parallel_edge(r0, r1, 5.0).unwrap();
+    assert_ne!(a, b);
+    for _ in 0..2 {
+        t.add_parallel_edge(r1, r0, 1.0).unwrap();
+    }
+    t.add_edge(r1, r2, 1.0, None).unwrap();
+    // the direct links between r0 and r2 are more expensive
+    t.add_parallel_edge(r0, r2, 5.0).unwrap();
+    t.add_parallel_edge(r0, r2, 3.0).unwrap();
+    t.add_parallel_edge(r2, r0, 3.0).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    assert_eq!(t.get_igp_ecmp_next_hops(r0, r1), Ok(vec![r1, r1]));
+    assert_eq!(t.get_igp_ecmp_next_hops(r0, r2), Ok(vec![r1, r1]));
+    assert_eq!(t.get_igp_ecmp_next_hops(r2, r0), Ok(vec![r1]));
+    assert_eq!(t.get_igp_ecmp_next_hops(r0, r0), Ok(vec![]));
+    assert_eq!(
+        t.get_router(r0).unwrap().igp_forwarding_table.get(&r2),
+        Some(&Some((r1, 2.0)))
+    );
+
+    // a direct link of equal cost is used as well
+    t.add_parallel_edge(r0, r2, 2.0).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+    assert_eq!(t.get_igp_ecmp_next_hops(r0, r2), Ok(vec![r1, r1, r2]));
+
+    assert_eq!(
+        t.add_parallel_edge(r0, 10.into(), 1.0),
+        Err(NetworkError::DeviceNotFound(10.into()))
+    );
+}
+
+#[test]
+fn test_subnetwork() {
+    // Same network as in `test_pylon_gadget`, with an additional router and prefix
+    let mut n = Network::new();
+    let prefix = Prefix(0);
+
+    let s = n.add_router("s");
+    let rr1 = n.add_router("rr1");
+    let rr2 = n.add_router("rr2");
+    let r1 = n.add_router("r1");
+    let r2 = n.add_router("r2");
+    let e0 = n.add_router("e0");
+    let e1 = n.add_router("e1");
+    let p0 = n.add_external_router("p0", AsId(65100));
+    let p1 = n.add_external_router("p1", AsId(65101));
+    let ps = n.add_external_router("ps", AsId(65102));
+    let x = n.add_router("x");
+    let px = n.add_external_router("px", AsId(65103));
+
+    n.add_edge(s, r1, 100.0, None).unwrap();
+    n.add_edge(s, r2, 100.0, None).unwrap();
+    n.add_edge(s, rr1, 100.0, None).unwrap();
+    n.add_edge(s, rr2, 100.0, None).unwrap();
+    n.add_edge(rr1, rr2, 1.0, None).unwrap();
+    n.add_edge(rr1, e0, 1.0, None).unwrap();
+    n.add_edge(rr2, e1, 1.0, None).unwrap();
+    n.add_edge(r1, r2, 1.0, None).unwrap();
+    n.add_edge(r1, e1, 1.0, None).unwrap();
+    n.add_edge(r2, e0, 1.0, None).unwrap();
+    n.add_edge(e0, p0, 1.0, None).unwrap();
+    n.add_edge(e1, p1, 1.0, None).unwrap();
+    n.add_edge(s, ps, 1.0, None).unwrap();
+    n.add_edge(rr1, x, 1.0, None).unwrap();
+    n.add_edge(x, px, 1.0, None).unwrap();
+
+    n.add_ibgp_session(s, rr1, true, true).unwrap();
+    n.add_ibgp_session(s, rr2, true, true).unwrap();
+    n.add_ibgp_session(rr1, r1, true, true).unwrap();
+    n.add_ibgp_session(rr2, r2, true, true).unwrap();
+    n.add_ibgp_session(r1, e0, true, true).unwrap();
+    n.add_ibgp_session(r2, e0, true, true).unwrap();
+    n.add_ibgp_session(r2, e1, true, true).unwrap();
+    n.add_ibgp_session(rr1, x, true, true).unwrap();
+
+    n.write_igp_fw_tables(true).unwrap();
+
+    for p in [ps, p0, p1] {
+        n.advertise_external_route(p, prefix, vec![AsId(1)], None, true)
+            .unwrap();
+    }
+    n.advertise_external_route(px, Prefix(1), vec![AsId(2)], None, true)
+        .unwrap();
+    assert_route(&n, r1, Prefix(1), vec![r1, r2, e0, rr1, x, px]);
+
+    // extract the pylon gadget
+    let pylon = [s, rr1, rr2, r1, r2, e0, e1];
+    let sub = n.subnetwork(&pylon).unwrap();
+    for r in pylon {
+        assert_eq!(sub.get_route(r, prefix), n.get_route(r, prefix));
+        assert_eq!(sub.get_router_name(r), n.get_router_name(r));
+    }
+    assert_route(&sub, r1, prefix, vec![r1, r2, e0, p0]);
+    assert_eq!(sub.get_router_name(p0), Ok("p0"));
+
+    // the additional router and its prefix are gone
+    assert_eq!(sub.get_router_name(x), Err(NetworkError::DeviceNotFound(x)));
+    assert_eq!(
+        sub.get_router_name(px),
+        Err(NetworkError::DeviceNotFound(px))
+    );
+    for r in pylon {
+        assert!(matches!(
+            sub.get_route(r, Prefix(1)),
+            Err(NetworkError::ForwardingBlackHole(..))
+        ));
+    }
+    assert_eq!(sub.validate(), Ok(()));
+
+    assert_eq!(
+        n.subnetwork(&[s, 100.into()]).map(|_| ()),
+        Err(NetworkError::DeviceNotFound(100.into()))
+    );
+
+    // the withdrawals of the routes via x do not fit into the limit of the queue
+    n.stop_after_queue(Some(1));
+    assert_eq!(
+        n.subnetwork(&pylon).map(|_| ()),
+        Err(NetworkError::MaxIterExceeded)
+    );
+}
+
+#[test]
+fn test_trace_route_propagation() {
+    // same topology as `test_simple`
+    let (mut t, [e0, b0, r0, r1, b1, _]) = setup_simple();
+
+    let prefix = Prefix(0);
+
+    let tree = t.trace_route_propagation(e0, prefix).unwrap();
+    assert!(t.get_queue().is_empty());
+    assert_eq!(
+        tree,
+        vec![
+            PropagationStep {
+                router: b0,
+                parent: e0,
+                depth: 1
+            },
+            PropagationStep {
+                router: r0,
+                parent: b0,
+                depth: 2
+            },
+            PropagationStep {
+                router: r1,
+                parent: r0,
+                depth: 3
+            },
+            PropagationStep {
+                router: b1,
+                parent: r1,
+                depth: 4
+            },
+        ]
+    );
+
+    // the route crosses one eBGP session and the three iBGP sessions from b0 to b1
+    let ibgp_hops = 3;
+    assert_eq!(tree.iter().map(|s| s.depth).max(), Some(ibgp_hops + 1));
+    assert_route(&t, b1, prefix, vec![b1, r1, r0, b0, e0]);
+
+    // advertising an unknown router fails
+    assert_eq!(
+        t.trace_route_propagation(r0, prefix),
+        Err(NetworkError::DeviceNotFound(r0))
+    );
+}
+
+#[test]
+fn test_next_hop_unchanged() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+    let prefix = Prefix(0);
+
+    t.advertise_external_route(e0, prefix, vec![AsId(1)], None, true)
+        .unwrap();
+    assert_route(&t, b1, prefix, vec![b1, r1, r0, b0, e0]);
+
+    // by default, b1 sets itself as next hop towards e1
+    let advertised = |t: &mut Network| {
+        t.get_router(b1)
+            .unwrap()
+            .get_advertised_route(e1, prefix)
+            .map(|route| route.next_hop)
+    };
+    assert_eq!(advertised(&mut t), Some(b1));
+
+    // only eBGP sessions can be configured with next-hop-unchanged
+    assert_eq!(
+        t.get_router_mut(b1)
+            .unwrap()
+            .set_next_hop_unchanged(r1, true),
+        Err(DeviceError::NoBgpSession(r1))
+    );
+
+    // with next-hop-unchanged, e1 receives the original next hop, which is e0
+    t.get_router_mut(b1)
+        .unwrap()
+        .set_next_hop_unchanged(e1, true)
+        .unwrap();
+    t.schedule_update_router(b1).unwrap();
+    t.do_queue().unwrap();
+    assert_eq!(advertised(&mut t), Some(e0));
+
+    // disabling it restores the default
+    t.get_router_mut(b1)
+        .unwrap()
+        .set_next_hop_unchanged(e1, false)
+        .unwrap();
+    t.schedule_update_router(b1).unwrap();
+    t.do_queue().unwrap();
+    assert_eq!(advertised(&mut t), Some(b1));
+}
+
+#[test]
+fn test_advertise_with_communities() {
+    let (mut t, [e0, b0, r0, r1, b1, e1]) = setup_simple();
+    t.record_trace(true);
+
+    // Prefix(0) is tagged with NO_EXPORT (65535:65281), Prefix(1) carries a regular community
+    t.advertise_external_route_with(
+        e0,
+        Prefix(0),
+        vec![AsId(1)],
+        None,
+        ExternalRouteAttrs {
+            communities: btreeset! {COMMUNITY_NO_EXPORT},
+            ..Default::default()
+        },
+        true,
+    )
+    .unwrap();
+    t.advertise_external_route_with(
+        e0,
+        Prefix(1),
+        vec![AsId(1)],
+        None,
+        ExternalRouteAttrs {
+            communities: btreeset! {100},
+            ..Default::default()
+        },
+        true,
+    )
+    .unwrap();
+
+    // both routes are propagated in the AS, together with their communities
+    for prefix in [Prefix(0), Prefix(1)] {
+        assert_route(&t, b1, prefix, vec![b1, r1, r0, b0, e0]);
+    }
+    let selected = |t: &mut Network, prefix| {
+        t.get_router(b1)
+            .unwrap()
+            .get_selected_bgp_route(prefix)
+            .unwrap()
+            .route
+    };
+    assert_eq!(
+        selected(&mut t, Prefix(0)).communities,
+        btreeset! {COMMUNITY_NO_EXPORT}
+    );
+    assert_eq!(selected(&mut t, Prefix(1)).communities, btreeset! {100});
+
+    // routes differing only in the communities are different
+    let mut route = selected(&mut t, Prefix(1));
+    let other = route.clone();
+    route.communities.insert(200);
+    assert_ne!(route, other);
+
+    // the route tagged with NO_EXPORT never reaches e1
+    assert_eq!(
+        t.get_router(b1)
+            .unwrap()
+            .get_advertised_route(e1, Prefix(0)),
+        None
+    );
+    assert!(t
+        .get_router(b1)
+        .unwrap()
+        .get_advertised_route(e1, Prefix(1))
+        .is_some());
+    let trace = t.take_trace();
+    let reaches_e1 = |prefix| {
+        trace.iter().any(|event| match event {
+            Event::Bgp(_, to, BgpEvent::Update(route)) => *to == e1 && route.prefix == prefix,
+            _ => false,
+        })
+    };
+    assert!(!reaches_e1(Prefix(0)));
+    assert!(reaches_e1(Prefix(1)));
+}
+
+#[test]
+fn test_advertise_with_origin() {
+    // e0 -- r0
+    let mut t = Network::new();
+
+    let e0 = t.add_external_router("E0", AsId(1));
+    let r0 = t.add_router("R0");
+    t.add_edge(e0, r0, 1.0, None).unwrap();
+    t.write_igp_fw_tables(true).unwrap();
+
+    // routes are advertised with the origin IGP by default
+    t.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+        .unwrap();
+    t.advertise_external_route_with(
+        e0,
+        Prefix(1),
+        vec![AsId(1)],
+        None,
+        ExternalRouteAttrs {
+            origin: Origin::Incomplete,
+            ..Default::default()
+        },
+        true,
+    )
+    .unwrap();
+
+    let origin = |t: &mut Network, prefix| {
+        t.get_router(r0)
+            .unwrap()
+            .get_selected_bgp_route(prefix)
+            .unwrap()
+            .route
+            .origin
+    };
+    assert_eq!(origin(&mut t, Prefix(0)), Origin::Igp);
+    assert_eq!(origin(&mut t, Prefix(1)), Origin::Incomplete);
+}
+
+#[test]
+fn test_router_id_tiebreak() {
+    // b_lo and b_hi learn the route from their own external neighbor, and advertise it to their
+    // route reflectors rr_b and rr_a. r is a client of both route reflectors, and has the same
+    // IGP cost to both next hops. The next hop e_lo of b_hi, and the route reflector rr_a of b_hi
+    // both have the lower id, but b_lo has the lower router id.
+    //
+    //   e_hi -- b_lo --.
+    //                   r -- rr_a
+    //   e_lo -- b_hi --'  \
+    //                      rr_b
+    let mut t = Network::new();
+
+    let prefix = Prefix(0);
+
+    let e_lo = t.add_external_router("E_lo", AsId(1));
+    let e_hi = t.add_external_router("E_hi", AsId(2));
+    let b_lo = t.add_router("B_lo");
+    let rr_a = t.add_router("RR_a");
+    let rr_b = t.add_router("RR_b");
+    let b_hi = t.add_router("B_hi");
+    let r = t.add_router("R");
+
+    t.add_edge(e_hi, b_lo, 1.0, None).unwrap();
+    t.add_edge(e_lo, b_hi, 1.0, None).unwrap();
+    t.add_edge(b_lo, r, 1.0, None).unwrap();
+    t.add_edge(b_hi, r, 1.0, None).unwrap();
+    t.add_edge(rr_a, r, 1.0, None).unwrap();
+    t.add_edge(rr_b, r, 1.0, None).unwrap();
+
+    t.add_ibgp_session(rr_a, b_hi, true, true).unwrap();
+    t.add_ibgp_session(rr_b, b_lo, true, true).unwrap();
+    t.add_ibgp_session(rr_a, r, true, true).unwrap();
+    t.add_ibgp_session(rr_b, r, true, true).unwrap();
+
+    t.write_igp_fw_tables(true).unwrap();
+
+    t.advertise_external_route(e_lo, prefix, vec![AsId(1), AsId(3)], None, true)
+        .unwrap();
+    t.advertise_external_route(e_hi, prefix, vec![AsId(2), AsId(3)], None, true)
+        .unwrap();
+
+    // routes learned over eBGP carry the router id of the external peer
+    let selected = |t: &mut Network, router| {
+        t.get_router(router)
+            .unwrap()
+            .get_selected_bgp_route(prefix)
+            .unwrap()
+    };
+    assert_eq!(selected(&mut t, b_lo).route.router_id, Some(e_hi));
+    assert_eq!(selected(&mut t, rr_a).route.router_id, Some(b_hi));
+
+    // the route advertised by the lower router id wins, even though it has the higher next hop,
+    // and it is received from the higher neighbor
+    let entry = selected(&mut t, r);
+    assert_eq!(entry.from_id, rr_b);
+    assert_eq!(entry.route.router_id, Some(b_lo));
+    assert_eq!(entry.route.next_hop, e_hi);
+    assert_eq!(
+        t.explain_selection(r, prefix).unwrap().deciding_step(rr_a),
+        Some(DecisionStep::Tiebreak)
+    );
+    assert_route(&t, r, prefix, vec![r, b_lo, e_hi]);
+
+    // without the router ids, the next hop decides
+    let mut other = t
+        .get_router(r)
+        .unwrap()
+        .get_known_bgp_routes(prefix)
+        .unwrap()
+        .into_iter()
+        .find(|e| e.from_id == rr_a)
+        .unwrap();
+    assert_eq!(other.route.next_hop, e_lo);
+    let mut entry = entry;
+    assert_eq!(entry.compare(&other).0, Ordering::Greater);
+    entry.route.router_id = None;
+    other.route.router_id = None;
+    assert_eq!(entry.compare(&other).0, Ordering::Less);
 }
@@ -1,10 +1,14 @@
 use crate::bgp::BgpSessionType::{EBgp, IBgpClient, IBgpPeer};
-use crate::bgp::{BgpEvent, BgpRoute};
+use crate::bgp::{BgpEvent, BgpRoute, Origin};
 use crate::event::{Event, EventQueue};
+use crate::policy::PeerGroup;
 use crate::router::*;
-use crate::{AsId, Prefix};
+use crate::{AsId, DeviceError, Prefix};
 use crate::{IgpNetwork, NetworkDevice};
 use maplit::{hashmap, hashset};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::time::Instant;
 
 #[test]
 fn test_bgp_single() {
@@ -40,10 +44,20 @@ fn test_bgp_single() {
             0.into(),
             BgpEvent::Update(BgpRoute {
                 prefix: Prefix(200),
+                path_id: 0,
                 as_path: vec![AsId(1), AsId(2), AsId(3), AsId(4), AsId(5)],
                 next_hop: 100.into(),
                 local_pref: None,
                 med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
             }),
         ),
         &mut queue,
@@ -62,7 +76,7 @@ fn test_bgp_single() {
                 assert_eq!(from, 0.into());
                 assert_eq!(r.next_hop, 100.into());
             }
-            _ => assert!(false),
+            _ => unreachable!(),
         }
     }
     // used for later
@@ -80,10 +94,20 @@ fn test_bgp_single() {
             0.into(),
             BgpEvent::Update(BgpRoute {
                 prefix: Prefix(201),
+                path_id: 0,
                 as_path: vec![AsId(1), AsId(2), AsId(3)],
                 next_hop: 11.into(),
                 local_pref: Some(50),
                 med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
             }),
         ),
         &mut queue,
@@ -100,14 +124,14 @@ fn test_bgp_single() {
         match job {
             Event::Bgp(from, to, BgpEvent::Update(r)) => {
                 assert_eq!(from, 0.into());
-                assert!(hashset![4, 5, 6, 100].contains(&(to.index() as usize)));
+                assert!(hashset![4, 5, 6, 100].contains(&to.index()));
                 if to == 100.into() {
                     assert_eq!(r.next_hop, 0.into());
                 } else {
                     assert_eq!(r.next_hop, 11.into());
                 }
             }
-            _ => assert!(false),
+            _ => unreachable!(),
         }
     }
 
@@ -123,10 +147,20 @@ fn test_bgp_single() {
             0.into(),
             BgpEvent::Update(BgpRoute {
                 prefix: Prefix(200),
+                path_id: 0,
                 as_path: vec![AsId(1), AsId(2), AsId(3), AsId(4), AsId(5)],
                 next_hop: 10.into(),
                 local_pref: None,
                 med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
             }),
         ),
         &mut queue,
@@ -151,6 +185,7 @@ fn test_bgp_single() {
             0.into(),
             BgpEvent::Update(BgpRoute {
                 prefix: Prefix(200),
+                path_id: 0,
                 as_path: vec![
                     AsId(1),
                     AsId(2),
@@ -166,6 +201,15 @@ fn test_bgp_single() {
                 next_hop: 5.into(),
                 local_pref: Some(150),
                 med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
             }),
         ),
         &mut queue,
@@ -182,7 +226,7 @@ fn test_bgp_single() {
         match job {
             Event::Bgp(from, to, BgpEvent::Update(r)) => {
                 assert_eq!(from, 0.into());
-                assert!(hashset![1, 2, 3, 4, 6, 100].contains(&(to.index() as usize)));
+                assert!(hashset![1, 2, 3, 4, 6, 100].contains(&to.index()));
                 if to == 100.into() {
                     assert_eq!(r.next_hop, 0.into());
                     assert_eq!(r.local_pref, None);
@@ -196,6 +240,7 @@ fn test_bgp_single() {
                 assert_eq!(to, 5.into());
                 assert_eq!(prefix, Prefix(200));
             }
+            _ => unreachable!(),
         }
     }
 
@@ -233,7 +278,7 @@ fn test_bgp_single() {
         match job {
             Event::Bgp(from, to, BgpEvent::Update(r)) => {
                 assert_eq!(from, 0.into());
-                assert!(hashset![1, 2, 3, 4, 5, 6].contains(&(to.index() as usize)));
+                assert!(hashset![1, 2, 3, 4, 5, 6].contains(&to.index()));
                 assert_eq!(r.next_hop, 100.into());
                 assert_eq!(r.local_pref, Some(100));
             }
@@ -242,6 +287,7 @@ fn test_bgp_single() {
                 assert_eq!(to, 100.into());
                 assert_eq!(prefix, Prefix(200));
             }
+            _ => unreachable!(),
         }
     }
 
@@ -262,13 +308,584 @@ fn test_bgp_single() {
         match job {
             Event::Bgp(from, to, BgpEvent::Withdraw(Prefix(200))) => {
                 assert_eq!(from, 0.into());
-                assert!(hashset![1, 2, 3, 4, 5, 6].contains(&(to.index() as usize)));
+                assert!(hashset![1, 2, 3, 4, 5, 6].contains(&to.index()));
             }
             _ => unreachable!(),
         }
     }
 }
 
+#[test]
+fn test_next_hop_unreachable() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        1.into()  => Some((1.into(), 1.0)),
+        10.into() => Some((1.into(), 6.0)),
+    };
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    assert_eq!(r.get_next_hop(Prefix(200)), NextHop::NoRoute);
+
+    r.handle_event(
+        Event::Bgp(
+            1.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(200),
+                path_id: 0,
+                as_path: vec![AsId(1), AsId(2), AsId(3)],
+                next_hop: 10.into(),
+                local_pref: None,
+                med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        ),
+        &mut queue,
+    )
+    .unwrap();
+
+    assert_eq!(r.get_next_hop(Prefix(200)), NextHop::Via(vec![1.into()]));
+
+    // the next hop becomes unreachable, but the route stays selected
+    r.igp_forwarding_table.insert(10.into(), None);
+    assert_eq!(r.get_next_hop(Prefix(200)), NextHop::Unreachable(10.into()));
+    assert_eq!(r.get_next_hop(Prefix(201)), NextHop::NoRoute);
+}
+
+#[test]
+fn test_static_route() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        1.into()  => Some((1.into(), 1.0)),
+        2.into()  => Some((2.into(), 1.0)),
+        10.into() => Some((1.into(), 6.0)),
+        11.into() => None,
+    };
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    r.handle_event(
+        Event::Bgp(
+            1.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(200),
+                path_id: 0,
+                as_path: vec![AsId(1), AsId(2), AsId(3)],
+                next_hop: 10.into(),
+                local_pref: None,
+                med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        ),
+        &mut queue,
+    )
+    .unwrap();
+    assert_eq!(r.get_next_hop(Prefix(200)), NextHop::Via(vec![1.into()]));
+
+    // the static route takes precedence, and also works without any BGP route
+    r.add_static_route(Prefix(200), 2.into());
+    r.add_static_route(Prefix(201), 2.into());
+    assert_eq!(r.get_next_hop(Prefix(200)), NextHop::Via(vec![2.into()]));
+    assert_eq!(r.get_next_hop(Prefix(201)), NextHop::Via(vec![2.into()]));
+
+    // a static route to an unreachable next hop drops the traffic
+    r.add_static_route(Prefix(200), 11.into());
+    assert_eq!(r.get_next_hop(Prefix(200)), NextHop::Unreachable(11.into()));
+
+    // without the static route, BGP is used again
+    assert_eq!(r.remove_static_route(Prefix(200)), Some(11.into()));
+    assert_eq!(r.get_next_hop(Prefix(200)), NextHop::Via(vec![1.into()]));
+}
+
+#[test]
+fn test_aigp() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(100.into(), EBgp).unwrap();
+    r.establish_bgp_session(101.into(), EBgp).unwrap();
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        100.into() => Some((100.into(), 0.0)),
+        101.into() => Some((101.into(), 0.0)),
+        1.into()   => Some((1.into(), 1.0)),
+    };
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    // short AS path, but large AIGP
+    r.handle_event(
+        Event::Bgp(
+            100.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(200),
+                path_id: 0,
+                as_path: vec![AsId(1), AsId(2)],
+                next_hop: 100.into(),
+                local_pref: None,
+                med: None,
+                aigp: Some(50),
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        ),
+        &mut queue,
+    )
+    .unwrap();
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        100.into()
+    );
+
+    // long AS path, but small AIGP
+    r.handle_event(
+        Event::Bgp(
+            101.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(200),
+                path_id: 0,
+                as_path: vec![AsId(3), AsId(4), AsId(5), AsId(6)],
+                next_hop: 101.into(),
+                local_pref: None,
+                med: None,
+                aigp: Some(10),
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        ),
+        &mut queue,
+    )
+    .unwrap();
+
+    // the route with the smaller AIGP is selected, despite its longer AS path
+    let entry = r.get_selected_bgp_route(Prefix(200)).unwrap();
+    assert_eq!(entry.from_id, 101.into());
+    assert_eq!(entry.route.aigp, Some(10));
+
+    // without AIGP on the long route, the AS path length decides again
+    r.handle_event(
+        Event::Bgp(
+            101.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(200),
+                path_id: 0,
+                as_path: vec![AsId(3), AsId(4), AsId(5), AsId(6)],
+                next_hop: 101.into(),
+                local_pref: None,
+                med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        ),
+        &mut queue,
+    )
+    .unwrap();
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        100.into()
+    );
+
+    // the IGP cost to the next hop is added when the route is received over iBGP
+    queue.clear();
+    r.handle_event(
+        Event::Bgp(
+            1.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(200),
+                path_id: 0,
+                as_path: vec![AsId(1), AsId(2)],
+                next_hop: 1.into(),
+                local_pref: None,
+                med: None,
+                aigp: Some(5),
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        ),
+        &mut queue,
+    )
+    .unwrap();
+    let entry = r.get_selected_bgp_route(Prefix(200)).unwrap();
+    assert_eq!(entry.from_id, 1.into());
+    assert_eq!(entry.route.aigp, Some(6));
+
+    // the accumulated value is advertised with the rewritten next hop, and not increased again
+    assert_eq!(queue.len(), 3);
+    while let Some(job) = queue.pop_front() {
+        match job {
+            Event::Bgp(_, to, BgpEvent::Update(r)) => {
+                assert!(hashset![100, 101].contains(&to.index()));
+                assert_eq!(r.next_hop, 0.into());
+                assert_eq!(r.aigp, Some(6));
+            }
+            Event::Bgp(_, to, BgpEvent::Withdraw(_)) => assert_eq!(to, 1.into()),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn test_multipath() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(100.into(), EBgp).unwrap();
+    r.establish_bgp_session(101.into(), EBgp).unwrap();
+    r.establish_bgp_session(102.into(), EBgp).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        100.into() => Some((100.into(), 0.0)),
+        101.into() => Some((101.into(), 0.0)),
+        102.into() => Some((102.into(), 0.0)),
+    };
+    r.set_maximum_paths(4);
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    // two equal routes, and one with a longer AS path
+    for (neighbor, as_path) in [
+        (100, vec![AsId(1), AsId(2)]),
+        (101, vec![AsId(3), AsId(4)]),
+        (102, vec![AsId(5), AsId(6), AsId(7)]),
+    ] {
+        r.handle_event(
+            Event::Bgp(
+                neighbor.into(),
+                0.into(),
+                BgpEvent::Update(BgpRoute {
+                    prefix: Prefix(200),
+                    path_id: 0,
+                    as_path,
+                    next_hop: neighbor.into(),
+                    local_pref: None,
+                    med: None,
+                    aigp: None,
+                    communities: BTreeSet::new(),
+                    link_bandwidth: None,
+                    cluster_list: Vec::new(),
+                    originator_id: None,
+                    router_id: None,
+                    seq: None,
+                    route_targets: BTreeSet::new(),
+                    origin: Origin::Igp,
+                }),
+            ),
+            &mut queue,
+        )
+        .unwrap();
+    }
+
+    // both equal routes are installed, the longer one is not
+    let installed: Vec<_> = r
+        .get_installed_bgp_routes(Prefix(200))
+        .iter()
+        .map(|e| e.from_id)
+        .collect();
+    assert_eq!(installed, vec![100.into(), 101.into()]);
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        100.into()
+    );
+    assert_eq!(
+        r.get_next_hop(Prefix(200)),
+        NextHop::Via(vec![100.into(), 101.into()])
+    );
+
+    // only one route is advertised to the peers
+    assert!(queue.iter().all(|e| match e {
+        Event::Bgp(_, _, BgpEvent::Update(route)) => route.as_path == vec![AsId(1), AsId(2)],
+        _ => true,
+    }));
+
+    // disabling multipath only installs the best route
+    r.set_maximum_paths(1);
+    r.bgp_decision_process().unwrap();
+    assert_eq!(r.get_installed_bgp_routes(Prefix(200)).len(), 1);
+    assert_eq!(r.get_next_hop(Prefix(200)), NextHop::Via(vec![100.into()]));
+}
+
+#[test]
+fn test_soft_reconfiguration() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(100.into(), EBgp).unwrap();
+    r.establish_bgp_session(101.into(), EBgp).unwrap();
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        100.into() => Some((100.into(), 0.0)),
+        101.into() => Some((101.into(), 0.0)),
+        1.into()   => Some((1.into(), 1.0)),
+    };
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    for (neighbor, as_path) in [
+        (100, vec![AsId(1), AsId(2)]),
+        (101, vec![AsId(3), AsId(4), AsId(5)]),
+    ] {
+        r.handle_event(
+            Event::Bgp(
+                neighbor.into(),
+                0.into(),
+                BgpEvent::Update(BgpRoute {
+                    prefix: Prefix(200),
+                    path_id: 0,
+                    as_path,
+                    next_hop: neighbor.into(),
+                    local_pref: None,
+                    med: None,
+                    aigp: None,
+                    communities: BTreeSet::new(),
+                    link_bandwidth: None,
+                    cluster_list: Vec::new(),
+                    originator_id: None,
+                    router_id: None,
+                    seq: None,
+                    route_targets: BTreeSet::new(),
+                    origin: Origin::Igp,
+                }),
+            ),
+            &mut queue,
+        )
+        .unwrap();
+    }
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        100.into()
+    );
+    queue.clear();
+
+    // prefer routes from 101. The policy is only applied after soft reconfiguration.
+    r.policy_bgp_local_pref.insert(101.into(), 200);
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        100.into()
+    );
+    r.soft_reconfigure_in(101.into()).unwrap();
+    let entry = r.get_selected_bgp_route(Prefix(200)).unwrap();
+    assert_eq!(entry.from_id, 101.into());
+    assert_eq!(entry.route.local_pref, Some(200));
+    assert!(queue.is_empty());
+
+    // outbound soft reconfiguration only sends the new route to the given peer
+    r.soft_reconfigure_out(1.into(), &mut queue).unwrap();
+    assert_eq!(queue.len(), 1);
+    match queue.pop_front() {
+        Some(Event::Bgp(from, to, BgpEvent::Update(route))) => {
+            assert_eq!(from, 0.into());
+            assert_eq!(to, 1.into());
+            assert_eq!(route.local_pref, Some(200));
+            assert_eq!(route.next_hop, 101.into());
+        }
+        _ => unreachable!(),
+    }
+
+    // soft reconfiguration requires a session
+    assert_eq!(
+        r.soft_reconfigure_in(2.into()),
+        Err(DeviceError::NoBgpSession(2.into()))
+    );
+}
+
+#[test]
+fn test_duplicate_update() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(100.into(), EBgp).unwrap();
+    r.establish_bgp_session(101.into(), EBgp).unwrap();
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        100.into() => Some((100.into(), 0.0)),
+        101.into() => Some((101.into(), 0.0)),
+        1.into()   => Some((1.into(), 1.0)),
+    };
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    let route = |neighbor: u32, as_path: Vec<AsId>| {
+        Event::Bgp(
+            neighbor.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(200),
+                path_id: 0,
+                as_path,
+                next_hop: neighbor.into(),
+                local_pref: None,
+                med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        )
+    };
+
+    r.handle_event(route(100, vec![AsId(1), AsId(2)]), &mut queue)
+        .unwrap();
+    r.handle_event(route(101, vec![AsId(3), AsId(4), AsId(5)]), &mut queue)
+        .unwrap();
+    assert_eq!(queue.len(), 2);
+    queue.clear();
+
+    // prefer routes from 101, but without soft reconfiguration.
+    r.policy_bgp_local_pref.insert(101.into(), 200);
+
+    // an identical update does not change anything, and the decision process is not executed.
+    r.handle_event(route(101, vec![AsId(3), AsId(4), AsId(5)]), &mut queue)
+        .unwrap();
+    assert!(queue.is_empty());
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        100.into()
+    );
+
+    // a different update replaces the old route, and the new policy is applied
+    r.handle_event(route(101, vec![AsId(3), AsId(5)]), &mut queue)
+        .unwrap();
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        101.into()
+    );
+    // update to 100 and 1, and withdraw towards 101
+    assert_eq!(queue.len(), 3);
+}
+
+#[test]
+fn test_peer_group() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(100.into(), EBgp).unwrap();
+    r.establish_bgp_session(101.into(), EBgp).unwrap();
+    r.establish_bgp_session(102.into(), EBgp).unwrap();
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        100.into() => Some((100.into(), 0.0)),
+        101.into() => Some((101.into(), 0.0)),
+        102.into() => Some((102.into(), 0.0)),
+        1.into()   => Some((1.into(), 1.0)),
+    };
+
+    // routes from a provider must not be exported to another provider
+    let mut providers = PeerGroup::new();
+    providers.no_export_from.insert("providers");
+    r.set_peer_group("providers", providers);
+    r.add_peer_group_member("providers", 100.into()).unwrap();
+    r.add_peer_group_member("providers", 101.into()).unwrap();
+    assert_eq!(
+        r.add_peer_group_member("customers", 102.into()),
+        Err(DeviceError::PeerGroupNotFound("customers"))
+    );
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    for (from, as_path) in [
+        (100, vec![AsId(1), AsId(2)]),
+        (101, vec![AsId(3), AsId(4)]),
+        (102, vec![AsId(5), AsId(6), AsId(7)]),
+    ] {
+        r.handle_event(
+            Event::Bgp(
+                from.into(),
+                0.into(),
+                BgpEvent::Update(BgpRoute {
+                    prefix: Prefix(200),
+                    path_id: 0,
+                    as_path,
+                    next_hop: from.into(),
+                    local_pref: None,
+                    med: None,
+                    aigp: None,
+                    communities: BTreeSet::new(),
+                    link_bandwidth: None,
+                    cluster_list: Vec::new(),
+                    originator_id: None,
+                    router_id: None,
+                    seq: None,
+                    route_targets: BTreeSet::new(),
+                    origin: Origin::Igp,
+                }),
+            ),
+            &mut queue,
+        )
+        .unwrap();
+        if from == 100 {
+            // the route is only sent to the iBGP peer and to the neighbor outside the group
+            let mut receivers: Vec<usize> = queue
+                .drain(..)
+                .map(|Event::Bgp(_, to, _)| to.index())
+                .collect();
+            receivers.sort();
+            assert_eq!(receivers, vec![1, 102]);
+        }
+    }
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        100.into()
+    );
+
+    // lowering the local_pref of the group affects both members
+    r.get_peer_group_mut("providers").unwrap().local_pref = Some(50);
+    r.bgp_decision_process().unwrap();
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        102.into()
+    );
+    for entry in r.get_known_bgp_routes(Prefix(200)).unwrap() {
+        if entry.from_id == 102.into() {
+            assert_eq!(entry.route.local_pref, Some(100));
+        } else {
+            assert_eq!(entry.route.local_pref, Some(50));
+        }
+    }
+}
+
 #[test]
 fn test_fw_table_simple() {
     let mut net: IgpNetwork = IgpNetwork::new();
@@ -311,7 +928,7 @@ fn test_fw_table_simple() {
     let exp = &expected_forwarding_table;
     let acq = &a.igp_forwarding_table;
 
-    for target in vec![&a, &b, &c, &d, &e] {
+    for target in [&a, &b, &c, &d, &e] {
         assert_eq!(exp.get(&target.router_id()), acq.get(&target.router_id()));
     }
 
@@ -328,7 +945,7 @@ fn test_fw_table_simple() {
     let exp = &expected_forwarding_table;
     let acq = &b.igp_forwarding_table;
 
-    for target in vec![&a, &b, &c, &d, &e] {
+    for target in [&a, &b, &c, &d, &e] {
         assert_eq!(exp.get(&target.router_id()), acq.get(&target.router_id()));
     }
 
@@ -345,7 +962,7 @@ fn test_fw_table_simple() {
     let exp = &expected_forwarding_table;
     let acq = &c.igp_forwarding_table;
 
-    for target in vec![&a, &b, &c, &d, &e] {
+    for target in [&a, &b, &c, &d, &e] {
         assert_eq!(exp.get(&target.router_id()), acq.get(&target.router_id()));
     }
 }
@@ -411,7 +1028,7 @@ fn test_igp_fw_table_complex() {
     let exp = &expected_forwarding_table;
     let acq = &a.igp_forwarding_table;
 
-    for target in vec![&a, &b, &c, &d, &e, &f, &g, &h] {
+    for target in [&a, &b, &c, &d, &e, &f, &g, &h] {
         assert_eq!(exp.get(&target.router_id()), acq.get(&target.router_id()));
     }
 
@@ -431,7 +1048,546 @@ fn test_igp_fw_table_complex() {
     let exp = &expected_forwarding_table;
     let acq = &c.igp_forwarding_table;
 
-    for target in vec![&a, &b, &c, &d, &e, &f, &g, &h] {
+    for target in [&a, &b, &c, &d, &e, &f, &g, &h] {
         assert_eq!(exp.get(&target.router_id()), acq.get(&target.router_id()));
     }
 }
+
+#[test]
+fn test_dissemination_order() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    // establish the sessions in an order different from the router ids
+    let peers: Vec<u32> = (1..=100).map(|i| (i * 7919) % 100 + 1).collect();
+    for peer in peers.iter() {
+        let session_type = if peer % 2 == 0 { IBgpPeer } else { IBgpClient };
+        r.establish_bgp_session((*peer).into(), session_type)
+            .unwrap();
+        r.igp_forwarding_table
+            .insert((*peer).into(), Some(((*peer).into(), 1.0)));
+    }
+    r.establish_bgp_session(2000.into(), EBgp).unwrap();
+    r.igp_forwarding_table
+        .insert(2000.into(), Some((2000.into(), 0.0)));
+
+    let mut queue: EventQueue = EventQueue::new();
+    r.handle_event(
+        Event::Bgp(
+            2000.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(200),
+                path_id: 0,
+                as_path: vec![AsId(1)],
+                next_hop: 2000.into(),
+                local_pref: None,
+                med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        ),
+        &mut queue,
+    )
+    .unwrap();
+
+    // the route is sent to all iBGP peers, sorted by their router id
+    let targets: Vec<u32> = queue
+        .iter()
+        .map(|event| match event {
+            Event::Bgp(_, to, _) => to.index() as u32,
+        })
+        .collect();
+    assert_eq!(targets, (1..=100).collect::<Vec<u32>>());
+}
+
+/// Micro-benchmark of the route dissemination on a router with many peers. Run it with
+/// `cargo test --release bench_dissemination_many_peers -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_dissemination_many_peers() {
+    const PEERS: u32 = 1000;
+    const PREFIXES: u32 = 100;
+
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    for peer in 1..=PEERS {
+        r.establish_bgp_session(peer.into(), IBgpClient).unwrap();
+        r.igp_forwarding_table
+            .insert(peer.into(), Some((peer.into(), 1.0)));
+    }
+    r.establish_bgp_session(2000.into(), EBgp).unwrap();
+    r.igp_forwarding_table
+        .insert(2000.into(), Some((2000.into(), 0.0)));
+
+    let mut queue: EventQueue = EventQueue::new();
+    let start = Instant::now();
+    for prefix in 0..PREFIXES {
+        r.handle_event(
+            Event::Bgp(
+                2000.into(),
+                0.into(),
+                BgpEvent::Update(BgpRoute {
+                    prefix: Prefix(prefix),
+                    path_id: 0,
+                    as_path: vec![AsId(1)],
+                    next_hop: 2000.into(),
+                    local_pref: None,
+                    med: None,
+                    aigp: None,
+                    communities: BTreeSet::new(),
+                    link_bandwidth: None,
+                    cluster_list: Vec::new(),
+                    originator_id: None,
+                    router_id: None,
+                    seq: None,
+                    route_targets: BTreeSet::new(),
+                    origin: Origin::Igp,
+                }),
+            ),
+            &mut queue,
+        )
+        .unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    assert_eq!(queue.len(), (PEERS * PREFIXES) as usize);
+    println!(
+        "disseminated {} prefixes to {} peers in {:?} ({:?} per prefix)",
+        PREFIXES,
+        PEERS,
+        elapsed,
+        elapsed / PREFIXES
+    );
+}
+
+#[test]
+fn test_max_as_path_len() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(100.into(), EBgp).unwrap();
+    r.establish_bgp_session(101.into(), EBgp).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        100.into() => Some((100.into(), 0.0)),
+        101.into() => Some((101.into(), 0.0)),
+    };
+    r.set_max_as_path_len(100.into(), Some(3));
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    let route = |neighbor: u32, prefix: u32, as_path_len: u32| {
+        Event::Bgp(
+            neighbor.into(),
+            0.into(),
+            BgpEvent::Update(BgpRoute {
+                prefix: Prefix(prefix),
+                path_id: 0,
+                as_path: (1..=as_path_len).map(AsId).collect(),
+                next_hop: neighbor.into(),
+                local_pref: None,
+                med: None,
+                aigp: None,
+                communities: BTreeSet::new(),
+                link_bandwidth: None,
+                cluster_list: Vec::new(),
+                originator_id: None,
+                router_id: None,
+                seq: None,
+                route_targets: BTreeSet::new(),
+                origin: Origin::Igp,
+            }),
+        )
+    };
+
+    // a short path is kept, and a very long path is dropped
+    r.handle_event(route(100, 200, 3), &mut queue).unwrap();
+    r.handle_event(route(100, 201, 50), &mut queue).unwrap();
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        100.into()
+    );
+    assert!(r.get_known_bgp_routes(Prefix(201)).unwrap().is_empty());
+
+    // a long path replaces the previous route of the peer
+    r.handle_event(route(100, 200, 4), &mut queue).unwrap();
+    assert!(r.get_selected_bgp_route(Prefix(200)).is_none());
+
+    // other peers have no limit
+    r.handle_event(route(101, 201, 50), &mut queue).unwrap();
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(201)).unwrap().from_id,
+        101.into()
+    );
+}
+
+#[test]
+fn test_bgp_origin() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.establish_bgp_session(2.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        1.into()  => Some((1.into(), 1.0)),
+        2.into()  => Some((2.into(), 1.0)),
+        10.into() => Some((1.into(), 2.0)),
+    };
+
+    let mut queue: EventQueue = EventQueue::new();
+    let route = |origin| BgpRoute {
+        prefix: Prefix(200),
+        path_id: 0,
+        as_path: vec![AsId(1), AsId(2)],
+        next_hop: 10.into(),
+        local_pref: None,
+        med: None,
+        aigp: None,
+        communities: BTreeSet::new(),
+        link_bandwidth: None,
+        cluster_list: Vec::new(),
+        originator_id: None,
+        router_id: None,
+        seq: None,
+        route_targets: BTreeSet::new(),
+        origin,
+    };
+
+    // both routes only differ in the origin. Without it, the route of 1 would win the tiebreak.
+    r.handle_event(
+        Event::Bgp(
+            1.into(),
+            0.into(),
+            BgpEvent::Update(route(Origin::Incomplete)),
+        ),
+        &mut queue,
+    )
+    .unwrap();
+    r.handle_event(
+        Event::Bgp(2.into(), 0.into(), BgpEvent::Update(route(Origin::Igp))),
+        &mut queue,
+    )
+    .unwrap();
+
+    let entry = r.get_selected_bgp_route(Prefix(200)).unwrap();
+    assert_eq!(entry.from_id, 2.into());
+    assert_eq!(entry.route.origin, Origin::Igp);
+    let other = r
+        .get_known_bgp_routes(Prefix(200))
+        .unwrap()
+        .into_iter()
+        .find(|e| e.from_id == 1.into())
+        .unwrap();
+    assert_eq!(
+        entry.compare(&other),
+        (Ordering::Greater, DecisionStep::Origin)
+    );
+    assert_eq!(
+        other.compare(&entry),
+        (Ordering::Less, DecisionStep::Origin)
+    );
+}
+
+#[test]
+fn test_decision_steps() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.establish_bgp_session(2.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        1.into() => Some((1.into(), 10.0)),
+        2.into() => Some((2.into(), 1.0)),
+    };
+
+    let mut queue: EventQueue = EventQueue::new();
+
+    // the route of 1 has a shorter AS path, and the route of 2 a lower IGP cost
+    for (neighbor, as_path) in [(1, vec![AsId(1)]), (2, vec![AsId(2), AsId(3)])] {
+        r.handle_event(
+            Event::Bgp(
+                neighbor.into(),
+                0.into(),
+                BgpEvent::Update(BgpRoute {
+                    prefix: Prefix(200),
+                    path_id: 0,
+                    as_path,
+                    next_hop: neighbor.into(),
+                    local_pref: None,
+                    med: None,
+                    aigp: None,
+                    communities: BTreeSet::new(),
+                    link_bandwidth: None,
+                    cluster_list: Vec::new(),
+                    originator_id: None,
+                    router_id: None,
+                    seq: None,
+                    route_targets: BTreeSet::new(),
+                    origin: Origin::Igp,
+                }),
+            ),
+            &mut queue,
+        )
+        .unwrap();
+    }
+    assert_eq!(r.get_decision_steps(), &DEFAULT_DECISION_STEPS);
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        1.into()
+    );
+
+    // comparing the IGP cost before the AS path length flips the selection
+    r.set_decision_steps(vec![
+        DecisionStep::LocalPref,
+        DecisionStep::IgpCost,
+        DecisionStep::AsPathLength,
+    ])
+    .unwrap();
+    r.bgp_decision_process().unwrap();
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        2.into()
+    );
+    let routes = r.get_known_bgp_routes(Prefix(200)).unwrap();
+    assert_eq!(
+        routes[1].compare_with_steps(&routes[0], r.get_decision_steps()),
+        (Ordering::Greater, DecisionStep::IgpCost)
+    );
+    assert_eq!(
+        routes[1].compare(&routes[0]),
+        (Ordering::Less, DecisionStep::AsPathLength)
+    );
+
+    // without any step, only the final tiebreak is left
+    r.set_decision_steps(Vec::new()).unwrap();
+    r.bgp_decision_process().unwrap();
+    assert_eq!(
+        r.get_selected_bgp_route(Prefix(200)).unwrap().from_id,
+        1.into()
+    );
+    assert_eq!(
+        routes[1].compare_with_steps(&routes[0], r.get_decision_steps()),
+        (Ordering::Less, DecisionStep::Tiebreak)
+    );
+
+    // the result markers cannot be configured, and leave the steps unchanged
+    for step in [DecisionStep::Tiebreak, DecisionStep::Equal] {
+        assert_eq!(
+            r.set_decision_steps(vec![DecisionStep::IgpCost, step]),
+            Err(DeviceError::InvalidDecisionStep(step))
+        );
+        assert!(r.get_decision_steps().is_empty());
+    }
+}
+
+#[test]
+fn test_reject_reason() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(100.into(), EBgp).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        100.into() => Some((100.into(), 0.0)),
+    };
+    r.set_max_as_path_len(100.into(), Some(3));
+
+    let mut queue: EventQueue = EventQueue::new();
+    let mut update = |r: &mut Router, as_path: Vec<AsId>| {
+        r.handle_event(
+            Event::Bgp(
+                100.into(),
+                0.into(),
+                BgpEvent::Update(BgpRoute {
+                    prefix: Prefix(200),
+                    path_id: 0,
+                    as_path,
+                    next_hop: 100.into(),
+                    local_pref: None,
+                    med: None,
+                    aigp: None,
+                    communities: BTreeSet::new(),
+                    link_bandwidth: None,
+                    cluster_list: Vec::new(),
+                    originator_id: None,
+                    router_id: None,
+                    seq: None,
+                    route_targets: BTreeSet::new(),
+                    origin: Origin::Igp,
+                }),
+            ),
+            &mut queue,
+        )
+        .unwrap();
+    };
+
+    // no route received yet
+    assert_eq!(r.last_reject_reason(100.into(), Prefix(200)), None);
+
+    // the AS path already contains the own AS
+    update(&mut r, vec![AsId(1), AsId(65001), AsId(2)]);
+    assert_eq!(
+        r.last_reject_reason(100.into(), Prefix(200)),
+        Some(RejectReason::AsPathLoop)
+    );
+    assert!(r.get_selected_bgp_route(Prefix(200)).is_none());
+
+    // the AS path is too long
+    update(&mut r, vec![AsId(1), AsId(2), AsId(3), AsId(4)]);
+    assert_eq!(
+        r.last_reject_reason(100.into(), Prefix(200)),
+        Some(RejectReason::MaxAsPathLength)
+    );
+    assert!(r.get_selected_bgp_route(Prefix(200)).is_none());
+
+    // accepting a route clears the reason
+    update(&mut r, vec![AsId(1), AsId(2)]);
+    assert_eq!(r.last_reject_reason(100.into(), Prefix(200)), None);
+    assert!(r.get_selected_bgp_route(Prefix(200)).is_some());
+}
+
+#[test]
+fn test_bgp_ebgp_over_ibgp() {
+    // both routes are equal up to the session type. Regardless of the order in which they arrive,
+    // the eBGP route is selected by the eBGP-over-iBGP step, which is applied before the IGP cost.
+    let igp_forwarding_table = hashmap! {
+        100.into() => Some((100.into(), 10.0)),
+        1.into()   => Some((1.into(), 1.0)),
+        11.into()  => Some((1.into(), 1.0)),
+    };
+    let route = |next_hop: u32| BgpRoute {
+        prefix: Prefix(200),
+        path_id: 0,
+        as_path: vec![AsId(1), AsId(2)],
+        next_hop: next_hop.into(),
+        local_pref: None,
+        med: None,
+        aigp: None,
+        communities: BTreeSet::new(),
+        link_bandwidth: None,
+        cluster_list: Vec::new(),
+        originator_id: None,
+        router_id: None,
+        seq: None,
+        route_targets: BTreeSet::new(),
+        origin: Origin::Igp,
+    };
+    let ebgp_event = Event::Bgp(100.into(), 0.into(), BgpEvent::Update(route(100)));
+    let ibgp_event = Event::Bgp(1.into(), 0.into(), BgpEvent::Update(route(11)));
+
+    for events in [
+        [ebgp_event.clone(), ibgp_event.clone()],
+        [ibgp_event.clone(), ebgp_event.clone()],
+    ] {
+        let mut r = Router::new("test", 0.into(), AsId(65001));
+        r.establish_bgp_session(100.into(), EBgp).unwrap();
+        r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+        r.igp_forwarding_table = igp_forwarding_table.clone();
+
+        let mut queue: EventQueue = EventQueue::new();
+        for event in events {
+            r.handle_event(event, &mut queue).unwrap();
+        }
+
+        let entry = r.get_selected_bgp_route(Prefix(200)).unwrap();
+        assert_eq!(entry.from_id, 100.into());
+        assert_eq!(entry.from_type, EBgp);
+
+        // the comparison is antisymmetric
+        let other = r
+            .get_known_bgp_routes(Prefix(200))
+            .unwrap()
+            .into_iter()
+            .find(|e| e.from_id == 1.into())
+            .unwrap();
+        assert_eq!(
+            entry.compare(&other),
+            (Ordering::Greater, DecisionStep::EBgpOverIBgp)
+        );
+        assert_eq!(
+            other.compare(&entry),
+            (Ordering::Less, DecisionStep::EBgpOverIBgp)
+        );
+    }
+}
+
+#[test]
+fn test_always_compare_med() {
+    let mut r = Router::new("test", 0.into(), AsId(65001));
+    r.establish_bgp_session(1.into(), IBgpPeer).unwrap();
+    r.establish_bgp_session(2.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table = hashmap! {
+        1.into()  => Some((1.into(), 1.0)),
+        2.into()  => Some((2.into(), 1.0)),
+        10.into() => Some((1.into(), 1.0)),
+        20.into() => Some((2.into(), 5.0)),
+    };
+
+    let mut queue: EventQueue = EventQueue::new();
+    let route = |neighbor_as, med, next_hop: u32| BgpRoute {
+        prefix: Prefix(200),
+        path_id: 0,
+        as_path: vec![AsId(neighbor_as), AsId(3)],
+        next_hop: next_hop.into(),
+        local_pref: None,
+        med: Some(med),
+        aigp: None,
+        communities: BTreeSet::new(),
+        link_bandwidth: None,
+        cluster_list: Vec::new(),
+        originator_id: None,
+        router_id: None,
+        seq: None,
+        route_targets: BTreeSet::new(),
+        origin: Origin::Igp,
+    };
+
+    // the route of 2 has the lower MED, but the higher IGP cost
+    r.handle_event(
+        Event::Bgp(1.into(), 0.into(), BgpEvent::Update(route(1, 20, 10))),
+        &mut queue,
+    )
+    .unwrap();
+    r.handle_event(
+        Event::Bgp(2.into(), 0.into(), BgpEvent::Update(route(2, 10, 20))),
+        &mut queue,
+    )
+    .unwrap();
+    let selected = |r: &Router| r.get_selected_bgp_route(Prefix(200)).unwrap();
+    let other = |r: &Router, from: u32| {
+        r.get_known_bgp_routes(Prefix(200))
+            .unwrap()
+            .into_iter()
+            .find(|e| e.from_id == from.into())
+            .unwrap()
+    };
+
+    // the routes are from different neighboring ASes, so the MED is not compared
+    assert!(!r.always_compare_med);
+    assert_eq!(selected(&r).from_id, 1.into());
+    assert_eq!(
+        r.compare_routes(&selected(&r), &other(&r, 2)),
+        (Ordering::Greater, DecisionStep::IgpCost)
+    );
+
+    // when always comparing the MED, the route of 2 wins
+    r.always_compare_med = true;
+    r.bgp_decision_process().unwrap();
+    assert_eq!(selected(&r).from_id, 2.into());
+    assert_eq!(
+        r.compare_routes(&selected(&r), &other(&r, 1)),
+        (Ordering::Greater, DecisionStep::Med)
+    );
+
+    // a route of the same neighboring AS with a lower MED beats the route of 1 even without
+    // always comparing the MED. It is then compared with the route of 2 without the MED.
+    r.always_compare_med = false;
+    r.establish_bgp_session(3.into(), IBgpPeer).unwrap();
+    r.igp_forwarding_table
+        .insert(30.into(), Some((1.into(), 3.0)));
+    r.handle_event(
+        Event::Bgp(3.into(), 0.into(), BgpEvent::Update(route(1, 5, 30))),
+        &mut queue,
+    )
+    .unwrap();
+    assert_eq!(selected(&r).from_id, 3.into());
+    assert_eq!(
+        r.compare_routes(&other(&r, 3), &other(&r, 1)),
+        (Ordering::Greater, DecisionStep::Med)
+    );
+}
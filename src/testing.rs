@@ -0,0 +1,147 @@
+//! Module containing assertions for testing the routing behavior of a network. Each assertion
+//! panics with a readable message, containing the router names, if the network does not behave as
+//! expected. The module is only available in tests, or with the feature `testing`.
+//!
+//! ```
+//! use bgpsim::network::Network;
+//! use bgpsim::testing::{assert_converged, assert_route};
+//! use bgpsim::{AsId, Prefix};
+//!
+//! let mut n = Network::new();
+//! let e0 = n.add_external_router("E0", AsId(1));
+//! let r0 = n.add_router("R0");
+//! let r1 = n.add_router("R1");
+//! n.add_edge(e0, r0, 1.0, None).unwrap();
+//! n.add_edge(r0, r1, 1.0, None).unwrap();
+//! n.add_ibgp_session(r0, r1, false, true).unwrap();
+//! n.write_igp_fw_tables(true).unwrap();
+//! n.advertise_external_route(e0, Prefix(0), vec![AsId(1)], None, true)
+//!     .unwrap();
+//!
+//! assert_converged(&n);
+//! assert_route(&n, r1, Prefix(0), vec![r1, r0, e0]);
+//! ```
+
+use crate::network::Network;
+use crate::{NetworkError, Prefix, RouterId};
+
+/// Assert that the traffic for the prefix, starting at `source`, follows the path `exp`. The path
+/// starts at `source` and ends at the external router, where the traffic leaves the network.
+pub fn assert_route(n: &Network, source: RouterId, prefix: Prefix, exp: Vec<RouterId>) {
+    let acq = n.get_route(source, prefix);
+    let exp = names(n, &exp);
+    match acq {
+        Ok(acq) => {
+            let acq = names(n, &acq);
+            assert_eq!(
+                acq,
+                exp,
+                "unexpected path on {} for prefix {}:\n        acq: {:?}, exp: {:?}\n",
+                n.get_router_name(source).unwrap(),
+                prefix.0,
+                acq,
+                exp
+            );
+        }
+        Err(acq) => {
+            assert_eq!(
+                Err(&acq),
+                Ok(&exp),
+                "unexpected path on {} for prefix {}: expected good path, but got bad path!\n        acq: {:?}, exp: {:?}\n",
+                n.get_router_name(source).unwrap(),
+                prefix.0,
+                &acq,
+                &exp
+            );
+        }
+    }
+}
+
+/// Assert that the traffic for the prefix, starting at `source`, is either dropped or caught in a
+/// forwarding loop, along the path `exp`.
+pub fn assert_route_bad(n: &Network, source: RouterId, prefix: Prefix, exp: Vec<RouterId>) {
+    match n.get_route(source, prefix) {
+        Ok(acq) => {
+            let acq = names(n, &acq);
+            let exp = names(n, &exp);
+            panic!(
+                "Bad route expected on path on {} for prefix {}, but got a correct path:\n        acq: {:?}, exp: {:?}",
+                n.get_router_name(source).unwrap(),
+                prefix.0,
+                acq,
+                exp
+            );
+        }
+        Err(NetworkError::ForwardingLoop(acq)) | Err(NetworkError::ForwardingBlackHole(acq, _)) => {
+            assert_bad_path(n, source, prefix, acq, exp)
+        }
+        Err(e) => panic!("Unexpected return type: {:#?}", e),
+    }
+}
+
+/// Assert that the traffic for the prefix, starting at `source`, is caught in a forwarding loop.
+/// `exp` is the path until the first router is visited the second time.
+pub fn assert_loop(n: &Network, source: RouterId, prefix: Prefix, exp: Vec<RouterId>) {
+    match n.get_route(source, prefix) {
+        Err(NetworkError::ForwardingLoop(acq)) => assert_bad_path(n, source, prefix, acq, exp),
+        acq => panic!(
+            "Forwarding loop expected on {} for prefix {}, but got: {:?}",
+            n.get_router_name(source).unwrap(),
+            prefix.0,
+            acq
+        ),
+    }
+}
+
+/// Assert that the traffic for the prefix, starting at `source`, is dropped at the end of the
+/// path `exp`.
+pub fn assert_blackhole(n: &Network, source: RouterId, prefix: Prefix, exp: Vec<RouterId>) {
+    match n.get_route(source, prefix) {
+        Err(NetworkError::ForwardingBlackHole(acq, _)) => {
+            assert_bad_path(n, source, prefix, acq, exp)
+        }
+        acq => panic!(
+            "Black hole expected on {} for prefix {}, but got: {:?}",
+            n.get_router_name(source).unwrap(),
+            prefix.0,
+            acq
+        ),
+    }
+}
+
+/// Assert that the network has converged, i.e., that no event is left in the queue.
+pub fn assert_converged(n: &Network) {
+    assert!(
+        n.get_queue().is_empty(),
+        "Network has not converged, {} events are left in the queue: {:?}",
+        n.get_queue().len(),
+        n.get_queue()
+    );
+}
+
+/// Compare the path of a forwarding loop or black hole with the expected path.
+fn assert_bad_path(
+    n: &Network,
+    source: RouterId,
+    prefix: Prefix,
+    acq: Vec<&'static str>,
+    exp: Vec<RouterId>,
+) {
+    let exp = names(n, &exp);
+    assert_eq!(
+        &acq,
+        &exp,
+        "Unexpected path on {} for prefix {}:\n        acq: {:?}, exp: {:?}",
+        n.get_router_name(source).unwrap(),
+        prefix.0,
+        &acq,
+        &exp
+    )
+}
+
+/// Translate the path into router names.
+fn names(n: &Network, path: &[RouterId]) -> Vec<&'static str> {
+    path.iter()
+        .map(|r| n.get_router_name(*r).unwrap())
+        .collect()
+}
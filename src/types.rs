@@ -1,5 +1,6 @@
 //! Module containing all type definitions
 
+use crate::router::{DecisionStep, NextHop};
 use crate::{Event, EventQueue};
 use petgraph::prelude::*;
 use petgraph::stable_graph::StableGraph;
@@ -8,6 +9,8 @@ use thiserror::Error;
 type IndexType = u32;
 /// Router Identification (and index into the graph)
 pub type RouterId = NodeIndex<IndexType>;
+/// Link Identification (index into the graph), only needed to distinguish parallel links
+pub type LinkId = EdgeIndex<IndexType>;
 /// IP Prefix (simple representation)
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
 pub struct Prefix(pub u32);
@@ -19,6 +22,15 @@ pub type LinkWeight = f32;
 /// IGP Network graph
 pub type IgpNetwork = StableGraph<(), LinkWeight, Directed, IndexType>;
 
+/// Result of running the event queue until the network has converged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvergenceResult {
+    /// All events are handled, and the network has converged
+    Converged,
+    /// The maximum number of iterations is exceeded. The remaining events stay in the queue.
+    MaxIterExceeded,
+}
+
 /// Trait for a network device
 pub trait NetworkDevice {
     /// Create a new NetworkDevice instance
@@ -48,6 +60,21 @@ pub enum DeviceError {
     /// Router is marked as not reachable in the IGP forwarding table.
     #[error("Router {0:?} is not reachable in IGP topology")]
     RouterNotReachable(RouterId),
+    /// BGP is disabled on the router (P router)
+    #[error("BGP is disabled on the router")]
+    BgpDisabled,
+    /// The eBGP neighbor is in the same AS as the router
+    #[error("eBGP neighbor {0:?} is in the same AS")]
+    NeighborInSameAs(RouterId),
+    /// The peer group is not configured on the router
+    #[error("Peer group {0} is not configured")]
+    PeerGroupNotFound(&'static str),
+    /// The VRF is not configured on the router
+    #[error("VRF {0} is not configured")]
+    VrfNotFound(&'static str),
+    /// The step cannot be configured in the decision process
+    #[error("Decision step {0:?} cannot be configured")]
+    InvalidDecisionStep(DecisionStep),
 }
 
 /// Network Errors
@@ -59,13 +86,70 @@ pub enum NetworkError {
     /// Device is not present in the topology
     #[error("Network device was not found in topology: {0:?}")]
     DeviceNotFound(RouterId),
+    /// No device with the given name is present in the topology
+    #[error("Network device was not found in topology: {0}")]
+    DeviceNameNotFound(&'static str),
     /// Device must be an internal router, but an external router was passed
-    #[error("Netowrk device cannot be an external router: {0:?}")]
+    #[error("Network device {0:?} is an external router, but an internal router is required")]
     DeviceIsExternalRouter(RouterId),
+    /// Device must be an external router, but an internal router was passed
+    #[error("Network device {0:?} is an internal router, but an external router is required")]
+    DeviceIsInternalRouter(RouterId),
+    /// The link between the two devices does not exist (or is not failed)
+    #[error("Link from {0:?} to {1:?} was not found")]
+    LinkNotFound(RouterId, RouterId),
     /// Forwarding loop detected
     #[error("Forwarding Loop occurred! path: {0:?}")]
     ForwardingLoop(Vec<&'static str>),
-    /// Black hole detected
-    #[error("Black hole occurred! path: {0:?}")]
-    ForwardingBlackHole(Vec<&'static str>),
+    /// Black hole detected along the path `#0`. The last router of the path drops the traffic
+    /// for the reason `#1` (either `NextHop::NoRoute` or `NextHop::Unreachable`).
+    #[error("Black hole occurred! path: {0:?}, reason: {1:?}")]
+    ForwardingBlackHole(Vec<&'static str>, NextHop),
+    /// The selected route of router `#0` for prefix `#1` cannot be reconstructed from the
+    /// received routes.
+    #[error("Inconsistent RIB on {0:?} for {1:?}: selected route is not among the known routes")]
+    RibInconsistency(RouterId, Prefix),
+    /// The scenario cannot be parsed, or does not describe a valid network
+    #[error("Invalid scenario: {0}")]
+    InvalidScenario(String),
+    /// The external router `#0` does not advertise the prefix `#1`
+    #[error("Prefix {1:?} is not advertised by {0:?}")]
+    PrefixNotAdvertised(RouterId, Prefix),
+    /// Step `#0` of a migration plan did not converge, or caused a forwarding loop or a new black
+    /// hole
+    #[error("Migration step {0} is not safe")]
+    UnsafeMigrationStep(usize),
+    /// The session between `#0` and `#1` is an eBGP session, but an iBGP session is required
+    #[error("BGP session between {0:?} and {1:?} is an eBGP session")]
+    NotAnIBgpSession(RouterId, RouterId),
+    /// The network did not converge within the limit configured with `stop_after_queue`
+    #[error("Network did not converge")]
+    MaxIterExceeded,
+    /// The event queue has grown beyond the configured limit
+    #[error("Event queue overflow: {len} events")]
+    QueueOverflow {
+        /// Number of events in the queue
+        len: usize,
+    },
+}
+
+/// Inconsistencies in the configuration of a network, found by `Network::validate`
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// Router `#0` has a BGP session with `#1`, but `#1` has no session with `#0`
+    #[error("BGP session from {0:?} to {1:?} is not configured on {1:?}")]
+    OneSidedSession(RouterId, RouterId),
+    /// The session types configured on both ends of the iBGP session do not match, e.g., both
+    /// routers treat the other one as their client
+    #[error("iBGP session between {0:?} and {1:?} has incompatible types")]
+    IncompatibleSessionTypes(RouterId, RouterId),
+    /// The internal routers `#0` and `#1` have an eBGP session
+    #[error("eBGP session between the internal routers {0:?} and {1:?}")]
+    InternalEbgpSession(RouterId, RouterId),
+    /// The external router has no neighbor
+    #[error("External router {0:?} has no neighbor")]
+    ExternalRouterWithoutNeighbor(RouterId),
+    /// The router is not connected to the rest of the topology
+    #[error("Router {0:?} is not connected to the rest of the topology")]
+    Disconnected(RouterId),
 }